@@ -88,9 +88,16 @@ pub async fn get_balance(principal: Principal) -> Result<u64, String> {
 }
 
 /// Transfer ckBTC from caller to recipient
-/// TODO: Expose as public endpoint when implementing fee payment system
-#[allow(dead_code)]
-pub async fn transfer(to: Principal, amount: u64, memo: Option<Vec<u8>>) -> Result<u64, String> {
+///
+/// With `created_at_time` set the ledger deduplicates identical transfers
+/// (same memo, amount and timestamp) for 24 hours; a duplicate resolves to the
+/// block of the original transfer instead of paying twice.
+pub async fn transfer(
+    to: Principal,
+    amount: u64,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+) -> Result<u64, String> {
     let ledger = Principal::from_text(CKBTC_LEDGER_MAINNET)
         .map_err(|e| format!("Invalid ledger principal: {}", e))?;
 
@@ -103,7 +110,7 @@ pub async fn transfer(to: Principal, amount: u64, memo: Option<Vec<u8>>) -> Resu
         amount: Nat::from(amount),
         fee: None, // Let ledger calculate fee
         memo,
-        created_at_time: None,
+        created_at_time,
     };
 
     let (result,): (TransferResult,) = call(ledger, "icrc1_transfer", (args,))
@@ -118,34 +125,71 @@ pub async fn transfer(to: Principal, amount: u64, memo: Option<Vec<u8>>) -> Resu
                 .map_err(|_| "Block index overflow".to_string())?;
             Ok(block_u64)
         }
+        Err(TransferError::Duplicate { duplicate_of }) => duplicate_of
+            .0
+            .try_into()
+            .map_err(|_| "Block index overflow".to_string()),
         Err(e) => Err(format!("Transfer failed: {:?}", e)),
     }
 }
 
 /// Charge etching fee from user to canister
-/// TODO: Integrate with etching flow when implementing fee system
-#[allow(dead_code)]
-pub async fn charge_etching_fee(from: Principal, amount: u64) -> Result<u64, String> {
+///
+/// The user must have approved this canister via `icrc2_approve`. A repeated
+/// charge with the same memo and `created_at_time` resolves to the original
+/// block instead of charging twice.
+pub async fn charge_etching_fee(
+    from: Principal,
+    amount: u64,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+) -> Result<u64, String> {
     let canister_id = ic_cdk::api::id();
 
-    // User must have approved canister to spend their ckBTC
-    // This uses ICRC-2 approve/transferFrom pattern
     transfer_from(
         from,
         canister_id,
         amount,
-        Some(b"Rune etching fee".to_vec()),
+        memo.or_else(|| Some(b"Rune etching fee".to_vec())),
+        created_at_time,
     )
     .await
 }
 
+/// Move ckBTC from `from` straight to `to` out of `from`'s allowance
+///
+/// Used to settle ckBTC-priced OTC fills without routing the payment through
+/// this canister. Deduplicated like `charge_etching_fee`.
+pub async fn pay_from(
+    from: Principal,
+    to: Principal,
+    amount: u64,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+) -> Result<u64, String> {
+    transfer_from(from, to, amount, memo, created_at_time).await
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
 /// Transfer ckBTC from one account to another (requires approval)
-#[allow(dead_code)]
 async fn transfer_from(
     from: Principal,
     to: Principal,
     amount: u64,
     memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
 ) -> Result<u64, String> {
     let ledger = Principal::from_text(CKBTC_LEDGER_MAINNET)
         .map_err(|e| format!("Invalid ledger principal: {}", e))?;
@@ -172,25 +216,62 @@ async fn transfer_from(
         amount: Nat::from(amount),
         fee: None,
         memo,
-        created_at_time: None,
+        created_at_time,
     };
 
-    let (result,): (TransferResult,) = call(ledger, "icrc2_transfer_from", (args,))
+    let (result,): (Result<Nat, TransferFromError>,) = call(ledger, "icrc2_transfer_from", (args,))
         .await
         .map_err(|(code, msg)| format!("TransferFrom call failed: {} - {}", code as u32, msg))?;
 
     match result {
-        Ok(block_index) => {
-            let block_u64: u64 = block_index
-                .0
-                .try_into()
-                .map_err(|_| "Block index overflow".to_string())?;
-            Ok(block_u64)
-        }
+        Ok(block_index) | Err(TransferFromError::Duplicate { duplicate_of: block_index }) => block_index
+            .0
+            .try_into()
+            .map_err(|_| "Block index overflow".to_string()),
         Err(e) => Err(format!("TransferFrom failed: {:?}", e)),
     }
 }
 
+/// ckBTC this canister may still draw from `owner` via `icrc2_transfer_from`
+pub async fn get_allowance(owner: Principal) -> Result<u64, String> {
+    let ledger = Principal::from_text(CKBTC_LEDGER_MAINNET)
+        .map_err(|e| format!("Invalid ledger principal: {}", e))?;
+
+    #[derive(CandidType)]
+    struct AllowanceArgs {
+        account: Account,
+        spender: Account,
+    }
+
+    #[derive(CandidType, Deserialize)]
+    struct Allowance {
+        allowance: Nat,
+        #[allow(dead_code)]
+        expires_at: Option<u64>,
+    }
+
+    let args = AllowanceArgs {
+        account: Account {
+            owner,
+            subaccount: None,
+        },
+        spender: Account {
+            owner: ic_cdk::api::id(),
+            subaccount: None,
+        },
+    };
+
+    let (result,): (Allowance,) = call(ledger, "icrc2_allowance", (args,))
+        .await
+        .map_err(|(code, msg)| format!("Allowance call failed: {} - {}", code as u32, msg))?;
+
+    result
+        .allowance
+        .0
+        .try_into()
+        .map_err(|_| "Allowance overflow".to_string())
+}
+
 /// Request ckBTC withdrawal to Bitcoin address
 pub async fn withdraw_to_bitcoin(address: String, amount: u64) -> Result<String, String> {
     let minter = Principal::from_text(CKBTC_MINTER_MAINNET)
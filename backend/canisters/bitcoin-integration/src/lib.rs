@@ -1,7 +1,7 @@
 use candid::{CandidType, Deserialize, Principal};
 use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::DefaultMemoryImpl;
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
 use std::cell::RefCell;
 use std::str::FromStr;
 
@@ -24,15 +24,23 @@ pub struct Config {
     pub ckbtc_ledger_id: Principal,
 }
 
+/// Maximum UTXOs spent by a single rune transfer
+const MAX_TRANSFER_INPUTS: usize = 50;
+
 thread_local! {
     static CONFIG: RefCell<Option<Config>> = const { RefCell::new(None) };
 
+    /// Principals allowed to move runes from the canister address (MemoryId 1)
+    static TRANSFER_CALLERS: RefCell<Option<StableBTreeMap<Principal, (), Memory>>> = const { RefCell::new(None) };
+
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
         RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
 }
 
+/// `transfer_callers` seeds the canisters allowed to move runes and ckBTC
+/// (normally rune-engine); more can be added with `authorize_transfer_caller`
 #[init]
-fn init(network: BitcoinNetwork, ckbtc_ledger_id: Principal) {
+fn init(network: BitcoinNetwork, ckbtc_ledger_id: Principal, transfer_callers: Option<Vec<Principal>>) {
     CONFIG.with(|config| {
         *config.borrow_mut() = Some(Config {
             network,
@@ -44,6 +52,16 @@ fn init(network: BitcoinNetwork, ckbtc_ledger_id: Principal) {
     let confirmation_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0)));
     confirmation_tracker::init_confirmation_storage(confirmation_memory);
 
+    // Initialize rune transfer authorization (MemoryId 1)
+    init_transfer_callers();
+    TRANSFER_CALLERS.with(|c| {
+        if let Some(ref mut callers) = *c.borrow_mut() {
+            for principal in transfer_callers.unwrap_or_default() {
+                callers.insert(principal, ());
+            }
+        }
+    });
+
     ic_cdk::println!("Bitcoin Integration canister initialized");
     config::log_config();
 
@@ -68,6 +86,9 @@ fn post_upgrade() {
     let confirmation_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0)));
     confirmation_tracker::reinit_confirmation_storage(confirmation_memory);
 
+    // Restore rune transfer authorization (MemoryId 1)
+    init_transfer_callers();
+
     // Config needs to be re-initialized after upgrade
     // Call configure() after upgrade to set the config
     ic_cdk::println!("Bitcoin Integration canister upgraded - call configure() to set network");
@@ -194,6 +215,112 @@ async fn build_and_sign_etching_tx(
     Ok(tx_bytes)
 }
 
+/// Build and sign a rune transfer (edict) from the canister's address
+///
+/// Spends the canister's UTXOs (up to `MAX_TRANSFER_INPUTS`), sends `amount`
+/// of `rune_id` (`block:tx`) to `destination` and returns every other rune
+/// and the BTC change to the canister. Restricted to authorized callers.
+#[update]
+async fn build_and_sign_rune_transfer_tx(
+    rune_id: String,
+    amount: u128,
+    destination: String,
+    fee_rate: u64,
+) -> Result<Vec<u8>, String> {
+    let caller = ic_cdk::caller();
+    if !is_transfer_caller(&caller) {
+        return Err("Caller is not authorized to transfer runes".to_string());
+    }
+
+    let rune_id = runes_utils::RuneId::from_str(&rune_id).map_err(|e| e.to_string())?;
+    let network = get_network()?;
+
+    let destination = bitcoin::Address::from_str(&destination)
+        .map_err(|e| format!("Invalid destination address: {}", e))?
+        .require_network(convert_network(network))
+        .map_err(|e| format!("Destination network mismatch: {}", e))?;
+
+    let address_info = get_p2tr_address().await?;
+    let change_address = bitcoin::Address::from_str(&address_info.address)
+        .map_err(|e| format!("Invalid address: {}", e))?
+        .require_network(convert_network(network))
+        .map_err(|e| format!("Address network mismatch: {}", e))?;
+
+    // Spend every UTXO (rune-bearing outputs are usually dust) so the edict
+    // can draw on whatever runes the canister holds
+    let utxos = utxo::get_canister_utxos(network).await?;
+    if utxos.is_empty() {
+        return Err("No UTXOs available".to_string());
+    }
+    let inputs = utxos
+        .iter()
+        .take(MAX_TRANSFER_INPUTS)
+        .map(|utxo| {
+            Ok(transaction::PreviousOutput {
+                outpoint: bitcoin::OutPoint {
+                    txid: bitcoin::Txid::from_str(&hex::encode(&utxo.outpoint.txid))
+                        .map_err(|e| format!("Invalid txid: {}", e))?,
+                    vout: utxo.outpoint.vout,
+                },
+                amount: utxo.value,
+                script_pubkey: change_address.script_pubkey(),
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let tx_data = transaction::build_rune_transfer_transaction(
+        rune_id,
+        amount,
+        inputs,
+        &destination,
+        &change_address,
+        fee_rate.max(1),
+    )?;
+
+    // Sign every input with threshold Schnorr
+    let mut signed_tx = tx_data.unsigned_tx;
+    for (index, sighash) in tx_data.sighashes.into_iter().enumerate() {
+        let signature = schnorr::sign_message(sighash, address_info.derivation_path.clone())
+            .await
+            .map_err(|e| format!("Failed to sign input {}: {}", index, e))?;
+        signed_tx = transaction::finalize_transaction(signed_tx, index, &signature)?;
+    }
+
+    use bitcoin::consensus::Encodable;
+    let mut tx_bytes = Vec::new();
+    signed_tx
+        .consensus_encode(&mut tx_bytes)
+        .map_err(|e| format!("Failed to encode transaction: {}", e))?;
+
+    Ok(tx_bytes)
+}
+
+/// Allow a canister (e.g. rune-engine) to request rune transfers (controllers only)
+#[update]
+fn authorize_transfer_caller(principal: Principal) -> Result<(), String> {
+    require_controller()?;
+    TRANSFER_CALLERS.with(|c| {
+        c.borrow_mut()
+            .as_mut()
+            .ok_or("Canister not initialized")?
+            .insert(principal, ());
+        Ok(())
+    })
+}
+
+/// Revoke a rune transfer caller (controllers only)
+#[update]
+fn revoke_transfer_caller(principal: Principal) -> Result<(), String> {
+    require_controller()?;
+    TRANSFER_CALLERS.with(|c| {
+        c.borrow_mut()
+            .as_mut()
+            .ok_or("Canister not initialized")?
+            .remove(&principal);
+        Ok(())
+    })
+}
+
 /// Broadcast a signed Bitcoin transaction
 #[update]
 async fn broadcast_transaction(tx_bytes: Vec<u8>) -> Result<String, String> {
@@ -237,19 +364,76 @@ async fn get_ckbtc_balance(principal: Principal) -> Result<u64, String> {
         .map_err(|e| format!("Failed to get ckBTC balance: {}", e))
 }
 
-/// Transfer ckBTC to a recipient
-/// Used for refunds and other transfers
+/// Transfer ckBTC to a recipient (authorized callers only)
+/// Used for refunds and other transfers. Pass `created_at_time` with a stable
+/// memo to make retries idempotent.
 #[update]
 async fn transfer_ckbtc(
     to: Principal,
     amount: u64,
     memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
 ) -> Result<u64, String> {
-    ckbtc::transfer(to, amount, memo)
+    if !is_transfer_caller(&ic_cdk::caller()) {
+        return Err("Caller is not authorized to transfer ckBTC".to_string());
+    }
+
+    ckbtc::transfer(to, amount, memo, created_at_time)
         .await
         .map_err(|e| format!("Failed to transfer ckBTC: {}", e))
 }
 
+/// Draw an etching fee from a user's ICRC-2 allowance (authorized callers only)
+///
+/// The user approves this canister with `icrc2_approve`; rune-engine draws the
+/// fee once the etching transaction has been broadcast.
+#[update]
+async fn charge_etching_fee(
+    from: Principal,
+    amount: u64,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+) -> Result<u64, String> {
+    if !is_transfer_caller(&ic_cdk::caller()) {
+        return Err("Caller is not authorized to charge fees".to_string());
+    }
+
+    ckbtc::charge_etching_fee(from, amount, memo, created_at_time)
+        .await
+        .map_err(|e| format!("Failed to charge etching fee: {}", e))
+}
+
+/// Pay ckBTC from one user to another out of the payer's ICRC-2 allowance
+/// (authorized callers only)
+///
+/// rune-engine settles ckBTC-priced OTC fills with this. Pass a stable memo
+/// and `created_at_time` so a retried payment resolves to the original block.
+#[update]
+async fn pay_ckbtc_from(
+    from: Principal,
+    to: Principal,
+    amount: u64,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+) -> Result<u64, String> {
+    if !is_transfer_caller(&ic_cdk::caller()) {
+        return Err("Caller is not authorized to transfer ckBTC".to_string());
+    }
+
+    ckbtc::pay_from(from, to, amount, memo, created_at_time)
+        .await
+        .map_err(|e| format!("Failed to pay ckBTC: {}", e))
+}
+
+/// ckBTC a user has approved this canister to draw for etching fees
+/// MUST be update (not query) because it makes inter-canister calls
+#[update]
+async fn get_etching_allowance(owner: Principal) -> Result<u64, String> {
+    ckbtc::get_allowance(owner)
+        .await
+        .map_err(|e| format!("Failed to get ckBTC allowance: {}", e))
+}
+
 // ============================================================================
 // Confirmation Tracking APIs
 // ============================================================================
@@ -306,6 +490,30 @@ fn untrack_transaction(txid: String) -> Result<(), String> {
 
 // Helper functions
 
+fn init_transfer_callers() {
+    let memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)));
+    TRANSFER_CALLERS.with(|c| {
+        *c.borrow_mut() = Some(StableBTreeMap::init(memory));
+    });
+}
+
+fn is_transfer_caller(principal: &Principal) -> bool {
+    ic_cdk::api::is_controller(principal)
+        || TRANSFER_CALLERS.with(|c| {
+            c.borrow()
+                .as_ref()
+                .map(|callers| callers.contains_key(principal))
+                .unwrap_or(false)
+        })
+}
+
+fn require_controller() -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        return Err("Only controllers can perform this action".to_string());
+    }
+    Ok(())
+}
+
 fn get_network() -> Result<BitcoinNetwork, String> {
     CONFIG.with(|config| {
         config
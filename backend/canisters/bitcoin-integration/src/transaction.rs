@@ -98,7 +98,10 @@ use bitcoin::{
     Address, Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
 };
 use quri_types::{BitcoinNetwork, RuneEtching};
-use runes_utils::build_runestone;
+use runes_utils::{build_runestone, build_transfer_runestone, Edict, RuneId};
+
+/// Valor de los outputs que reciben runes (dust limit estándar)
+pub const RUNE_OUTPUT_VALUE: u64 = 546;
 
 /// Resultado de construcción de transacción para etching
 #[derive(Debug, Clone)]
@@ -231,6 +234,131 @@ pub fn build_etching_transaction(
     })
 }
 
+// ========================================================================
+// 🎓 IMPLEMENTACIÓN 1b: Construir Transacción de Transferencia (Edict)
+// ========================================================================
+
+/// Resultado de construcción de una transferencia de runes
+///
+/// A diferencia del etching, puede gastar varios inputs: cada uno
+/// necesita su propia firma.
+#[derive(Debug, Clone)]
+pub struct TransferTransaction {
+    /// Transacción sin firmar
+    pub unsigned_tx: Transaction,
+    /// Sighash por input, en el mismo orden que `unsigned_tx.input`
+    pub sighashes: Vec<Vec<u8>>,
+}
+
+/// Construye una transacción que transfiere runes con un edict
+///
+/// ## Outputs:
+/// - Output 0: OP_RETURN con el runestone (edict → output 1, pointer → 2)
+/// - Output 1: Destino, recibe `amount` del rune
+/// - Output 2: Change al canister, recibe el resto de runes y BTC
+///
+/// Todos los inputs se gastan, así que los runes que contengan (del rune
+/// transferido o de otros) terminan en el output de change salvo el edict.
+pub fn build_rune_transfer_transaction(
+    rune_id: RuneId,
+    amount: u128,
+    inputs: Vec<PreviousOutput>,
+    destination: &Address,
+    change_address: &Address,
+    fee_rate: u64,
+) -> Result<TransferTransaction, String> {
+    if inputs.is_empty() {
+        return Err("No inputs to spend".to_string());
+    }
+    if amount == 0 {
+        return Err("Transfer amount must be greater than 0".to_string());
+    }
+
+    // 🎓 PASO 1: Runestone con un edict hacia el output 1
+    let edict = Edict {
+        id: rune_id,
+        amount,
+        output: 1,
+    };
+    let runestone_bytes = build_transfer_runestone(&[edict], Some(2))
+        .map_err(|e| format!("Failed to build runestone: {}", e))?;
+    let runestone_script = create_runestone_script(&runestone_bytes)?;
+
+    // 🎓 PASO 2: Fee según número de inputs y script del destino
+    let destination_script = destination.script_pubkey();
+    let estimated_vsize = estimate_transfer_vsize(
+        &runestone_bytes,
+        inputs.len(),
+        destination_script.len(),
+    );
+    let fee = estimated_vsize * fee_rate;
+
+    let total_in: u64 = inputs.iter().map(|input| input.amount).sum();
+    let needed = fee + RUNE_OUTPUT_VALUE * 2;
+    if total_in < needed {
+        return Err(format!(
+            "Insufficient funds: have {} sats, need {} sats for fee and outputs",
+            total_in, needed
+        ));
+    }
+    let change_amount = total_in - fee - RUNE_OUTPUT_VALUE;
+
+    // 🎓 PASO 3: Inputs y outputs
+    let tx_inputs = inputs
+        .iter()
+        .map(|input| TxIn {
+            previous_output: input.outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        })
+        .collect();
+
+    let outputs = vec![
+        TxOut {
+            value: Amount::from_sat(0),
+            script_pubkey: runestone_script,
+        },
+        TxOut {
+            value: Amount::from_sat(RUNE_OUTPUT_VALUE),
+            script_pubkey: destination_script,
+        },
+        TxOut {
+            value: Amount::from_sat(change_amount),
+            script_pubkey: change_address.script_pubkey(),
+        },
+    ];
+
+    let unsigned_tx = Transaction {
+        version: Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: tx_inputs,
+        output: outputs,
+    };
+
+    // 🎓 PASO 4: Un sighash por input (BIP-341 commits a todos los prevouts)
+    let prevouts: Vec<TxOut> = inputs
+        .iter()
+        .map(|input| TxOut {
+            value: Amount::from_sat(input.amount),
+            script_pubkey: input.script_pubkey.clone(),
+        })
+        .collect();
+    let mut sighash_cache = SighashCache::new(&unsigned_tx);
+    let mut sighashes = Vec::with_capacity(inputs.len());
+    for index in 0..inputs.len() {
+        let sighash = sighash_cache
+            .taproot_key_spend_signature_hash(index, &Prevouts::All(&prevouts), TapSighashType::Default)
+            .map_err(|e| format!("Failed to compute taproot sighash: {}", e))?;
+        sighashes.push(sighash.as_byte_array().to_vec());
+    }
+
+    Ok(TransferTransaction {
+        unsigned_tx,
+        sighashes,
+    })
+}
+
 // ========================================================================
 // 🎓 IMPLEMENTACIÓN 2: Crear Script OP_RETURN para Runestone
 // ========================================================================
@@ -388,6 +516,25 @@ fn estimate_transaction_vsize(runestone_bytes: &[u8]) -> u64 {
     (weight + 3) / 4
 }
 
+/// Estima el vsize de una transferencia con `input_count` inputs P2TR
+fn estimate_transfer_vsize(runestone_bytes: &[u8], input_count: usize, destination_script_len: usize) -> u64 {
+    let inputs = input_count as u64;
+    let base_size: u64 = 4 // version
+        + 1 // input count
+        + 41 * inputs // inputs
+        + 1 // output count
+        + 8 + 1 + 2 + runestone_bytes.len() as u64 // OP_RETURN output
+        + 8 + 1 + destination_script_len as u64 // destination output
+        + 8 + 1 + 34 // change output (P2TR)
+        + 4; // locktime
+
+    // Una firma Schnorr por input
+    let witness_size: u64 = 2 + inputs * 66;
+
+    let weight = base_size * 4 + witness_size;
+    weight.div_ceil(4)
+}
+
 // ========================================================================
 // 🎓 HELPER: Agregar Signature a Transacción
 // ========================================================================
@@ -462,6 +609,44 @@ mod tests {
         assert!(script.as_bytes().contains(&0x5D)); // OP_13
     }
 
+    /// Test: transferencia con varios inputs
+    #[test]
+    fn test_build_rune_transfer_transaction() {
+        use std::str::FromStr;
+
+        let address = Address::from_str("bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr")
+            .unwrap()
+            .require_network(Network::Bitcoin)
+            .unwrap();
+        let input = |vout| PreviousOutput {
+            outpoint: OutPoint {
+                txid: bitcoin::Txid::all_zeros(),
+                vout,
+            },
+            amount: 5_000,
+            script_pubkey: address.script_pubkey(),
+        };
+
+        let tx = build_rune_transfer_transaction(
+            RuneId::new(840_000, 1),
+            1_000,
+            vec![input(0), input(1)],
+            &address,
+            &address,
+            2,
+        )
+        .unwrap();
+
+        assert_eq!(tx.unsigned_tx.input.len(), 2);
+        assert_eq!(tx.sighashes.len(), 2);
+        assert_eq!(tx.unsigned_tx.output.len(), 3);
+        assert_eq!(tx.unsigned_tx.output[1].value, Amount::from_sat(RUNE_OUTPUT_VALUE));
+
+        // Not enough BTC for fee and the two rune outputs
+        let poor = PreviousOutput { amount: 600, ..input(0) };
+        assert!(build_rune_transfer_transaction(RuneId::new(840_000, 1), 1_000, vec![poor], &address, &address, 2).is_err());
+    }
+
     /// Test: estimación de tamaño
     #[test]
     fn test_estimate_vsize() {
@@ -0,0 +1,117 @@
+//! Rune Archive Canister
+//!
+//! Stores ICRC-3 blocks spilled from the rune engine's transaction log.
+//! Blocks are appended in order by the engine only and served read-only
+//! through `icrc3_get_blocks` (the engine's archive callback).
+
+use candid::{Nat, Principal};
+use ic_cdk_macros::{init, query, update};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
+use quri_types::{BlockWithId, GetBlocksArgs, GetBlocksResult, ICRC3Value};
+use std::cell::RefCell;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Maximum blocks returned by a single `icrc3_get_blocks` call
+const MAX_BLOCKS_PER_RESPONSE: u64 = 1_000;
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    /// Engine principal allowed to append blocks (MemoryId 0)
+    static ENGINE: RefCell<StableCell<Vec<u8>, Memory>> = RefCell::new(
+        StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))), Vec::new())
+            .expect("Failed to initialize engine cell")
+    );
+
+    /// Archived blocks by index, candid-encoded (MemoryId 1)
+    static BLOCKS: RefCell<StableBTreeMap<u64, Vec<u8>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1))))
+    );
+}
+
+#[init]
+fn init(engine: Principal) {
+    ENGINE.with(|e| {
+        e.borrow_mut()
+            .set(engine.as_slice().to_vec())
+            .expect("Failed to store engine principal");
+    });
+}
+
+fn engine() -> Option<Principal> {
+    ENGINE.with(|e| Principal::try_from_slice(e.borrow().get()).ok())
+}
+
+/// Index of the next block expected from the engine
+fn next_index() -> u64 {
+    BLOCKS.with(|b| b.borrow().last_key_value().map(|(index, _)| index + 1).unwrap_or(0))
+}
+
+/// Append blocks starting at `start` (engine only)
+///
+/// Blocks already stored are skipped, so the engine can safely retry a
+/// batch whose response it never received.
+#[update]
+fn append_blocks(start: u64, blocks: Vec<ICRC3Value>) -> Result<(), String> {
+    if Some(ic_cdk::caller()) != engine() {
+        return Err("Only the rune engine can append blocks".to_string());
+    }
+
+    let mut next = next_index();
+    let is_first_batch = BLOCKS.with(|b| b.borrow().is_empty());
+    if is_first_batch {
+        next = start;
+    }
+    if start > next {
+        return Err(format!("Gap in archived blocks: expected {}, got {}", next, start));
+    }
+
+    BLOCKS.with(|b| {
+        let mut map = b.borrow_mut();
+        for (offset, block) in blocks.into_iter().enumerate() {
+            let index = start + offset as u64;
+            if index < next {
+                continue;
+            }
+            let bytes = candid::encode_one(&block).map_err(|e| format!("Failed to encode block: {}", e))?;
+            map.insert(index, bytes);
+        }
+        Ok(())
+    })
+}
+
+/// Serve archived blocks
+#[query]
+fn icrc3_get_blocks(args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+    let log_length = next_index();
+    let mut blocks = Vec::new();
+
+    BLOCKS.with(|b| {
+        let map = b.borrow();
+        for range in args {
+            let start = u64::try_from(&range.start.0).unwrap_or(u64::MAX);
+            let length = u64::try_from(&range.length.0).unwrap_or(u64::MAX);
+            let budget = MAX_BLOCKS_PER_RESPONSE.saturating_sub(blocks.len() as u64);
+            let end = start.saturating_add(length.min(budget)).min(log_length);
+            if start >= end {
+                continue;
+            }
+            for (id, bytes) in map.range(start..end) {
+                if let Ok(block) = candid::decode_one::<ICRC3Value>(&bytes) {
+                    blocks.push(BlockWithId { id: Nat::from(id), block });
+                }
+            }
+        }
+    });
+
+    GetBlocksResult {
+        log_length: Nat::from(log_length),
+        blocks,
+        archived_blocks: vec![],
+    }
+}
+
+ic_cdk::export_candid!();
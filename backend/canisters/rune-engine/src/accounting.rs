@@ -0,0 +1,737 @@
+/*!
+ * Accounting Module - Double-Entry Ledger
+ *
+ * Single source of truth for every balance the engine owes:
+ * - User ICP and rune balances (available and locked)
+ * - V1 and V2 pool reserves
+ * - Pending protocol fees
+ * - ckBTC etching fees held in escrow
+ *
+ * Every movement is a journal entry that debits one account and credits
+ * another for the same asset and amount. `Custody` is the only asset
+ * account (tokens the engine actually holds, or rune supply it has issued);
+ * every other account is a liability.
+ *
+ * Custody equals the sum of liability balances by construction, so it
+ * proves nothing on its own; invariant checks compare the liabilities with
+ * what the asset's own ledger says the engine holds.
+ */
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::memory_manager::VirtualMemory;
+use ic_stable_structures::{storable::Bound, DefaultMemoryImpl, StableBTreeMap, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Maximum length of string identifiers embedded in account keys
+const MAX_KEY_ID_LENGTH: usize = 255;
+
+// ============================================================================
+// TYPES
+// ============================================================================
+
+/// Asset tracked by the ledger
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Asset {
+    /// ICP (e8s)
+    Icp,
+    /// ckBTC (satoshis)
+    CkBtc,
+    /// Virtual rune by rune ID
+    Rune(String),
+}
+
+/// Ledger account
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LedgerAccount {
+    /// Asset side: tokens held by the engine / rune supply issued
+    Custody,
+    /// User balance that can be traded, transferred or withdrawn
+    Available(Principal),
+    /// User balance reserved for a pending operation
+    Locked(Principal),
+    /// V2 pool reserves, by rune ID
+    Pool(String),
+    /// V1 pool reserves, by rune ID
+    LegacyPool(String),
+    /// Protocol fees awaiting collection to the treasury
+    ProtocolFees,
+    /// Etching fee escrow, by process ID
+    Escrow(String),
+    /// User balance held on a non-default ICRC subaccount (32 bytes)
+    Subaccount(Principal, Vec<u8>),
+}
+
+impl LedgerAccount {
+    /// Asset accounts grow on debit; liability accounts grow on credit
+    pub fn is_asset(&self) -> bool {
+        matches!(self, LedgerAccount::Custody)
+    }
+}
+
+/// Why a journal entry was posted
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum EntryReason {
+    Deposit,
+    Withdrawal,
+    WithdrawalRefund,
+    ManualCredit,
+    Premine,
+    PoolCreation,
+    Trade,
+    ProtocolFee,
+    LiquidityAdd,
+    LiquidityRemove,
+    FeeCollection,
+    FeeCollectionRefund,
+    Transfer,
+    Lock,
+    Unlock,
+    LockConsumed,
+    EscrowHold,
+    EscrowConsume,
+    EscrowRefund,
+    Migration,
+    LpFeeClaim,
+}
+
+/// A single posting: `amount` of `asset` moved from `debit` to `credit`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct JournalEntry {
+    pub id: u64,
+    pub asset: Asset,
+    pub debit: LedgerAccount,
+    pub credit: LedgerAccount,
+    pub amount: u64,
+    pub reason: EntryReason,
+    pub reference: Option<String>,
+    pub timestamp: u64,
+}
+
+impl Storable for JournalEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("Failed to encode JournalEntry"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).expect("Failed to decode JournalEntry")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Per-asset result of an invariant check
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AssetTotals {
+    pub asset: Asset,
+    pub liabilities: u128,
+    /// Balance reported by the asset's ledger, where it can be queried
+    pub held: Option<u128>,
+    /// Whether `held` covers the liabilities (`None` without a ledger figure)
+    pub covered: Option<bool>,
+}
+
+/// Result of a full ledger invariant check
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct InvariantReport {
+    pub assets: Vec<AssetTotals>,
+    /// Cross-checks against pool state that failed
+    pub violations: Vec<String>,
+    pub journal_entries: u64,
+    pub ok: bool,
+}
+
+// ============================================================================
+// ACCOUNT KEY - prefix-ordered (account, asset) key
+// ============================================================================
+
+/// Storage key for a balance: account bytes followed by asset bytes
+///
+/// Accounts that belong to a principal encode the principal first, so all
+/// balances of a user can be read with a prefix range.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AccountKey(Vec<u8>);
+
+impl AccountKey {
+    pub fn new(account: &LedgerAccount, asset: &Asset) -> Self {
+        let mut bytes = Self::account_prefix(account);
+        match asset {
+            Asset::Icp => bytes.push(0),
+            Asset::CkBtc => bytes.push(1),
+            Asset::Rune(rune_id) => {
+                bytes.push(2);
+                push_str(&mut bytes, rune_id);
+            }
+        }
+        Self(bytes)
+    }
+
+    /// Encoded account, shared by every asset balance of that account
+    pub(crate) fn account_prefix(account: &LedgerAccount) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(48);
+        match account {
+            LedgerAccount::Custody => bytes.push(0),
+            LedgerAccount::Available(owner) => {
+                bytes.push(1);
+                push_bytes(&mut bytes, owner.as_slice());
+            }
+            LedgerAccount::Locked(owner) => {
+                bytes.push(2);
+                push_bytes(&mut bytes, owner.as_slice());
+            }
+            LedgerAccount::Pool(rune_id) => {
+                bytes.push(3);
+                push_str(&mut bytes, rune_id);
+            }
+            LedgerAccount::LegacyPool(rune_id) => {
+                bytes.push(4);
+                push_str(&mut bytes, rune_id);
+            }
+            LedgerAccount::ProtocolFees => bytes.push(5),
+            LedgerAccount::Escrow(process_id) => {
+                bytes.push(6);
+                push_str(&mut bytes, process_id);
+            }
+            LedgerAccount::Subaccount(owner, subaccount) => {
+                bytes.push(7);
+                push_bytes(&mut bytes, owner.as_slice());
+                push_bytes(&mut bytes, subaccount);
+            }
+        }
+        bytes
+    }
+
+    /// Decode back into (account, asset)
+    pub fn decode(&self) -> Option<(LedgerAccount, Asset)> {
+        let bytes = &self.0;
+        let (account, rest) = match *bytes.first()? {
+            0 => (LedgerAccount::Custody, &bytes[1..]),
+            1 => {
+                let (owner, rest) = read_bytes(&bytes[1..])?;
+                (LedgerAccount::Available(Principal::try_from_slice(owner).ok()?), rest)
+            }
+            2 => {
+                let (owner, rest) = read_bytes(&bytes[1..])?;
+                (LedgerAccount::Locked(Principal::try_from_slice(owner).ok()?), rest)
+            }
+            3 => {
+                let (id, rest) = read_str(&bytes[1..])?;
+                (LedgerAccount::Pool(id), rest)
+            }
+            4 => {
+                let (id, rest) = read_str(&bytes[1..])?;
+                (LedgerAccount::LegacyPool(id), rest)
+            }
+            5 => (LedgerAccount::ProtocolFees, &bytes[1..]),
+            6 => {
+                let (id, rest) = read_str(&bytes[1..])?;
+                (LedgerAccount::Escrow(id), rest)
+            }
+            7 => {
+                let (owner, rest) = read_bytes(&bytes[1..])?;
+                let (subaccount, rest) = read_bytes(rest)?;
+                (
+                    LedgerAccount::Subaccount(Principal::try_from_slice(owner).ok()?, subaccount.to_vec()),
+                    rest,
+                )
+            }
+            _ => return None,
+        };
+        let asset = match *rest.first()? {
+            0 => Asset::Icp,
+            1 => Asset::CkBtc,
+            2 => Asset::Rune(read_str(&rest[1..])?.0),
+            _ => return None,
+        };
+        Some((account, asset))
+    }
+}
+
+fn push_bytes(bytes: &mut Vec<u8>, value: &[u8]) {
+    bytes.push(value.len() as u8);
+    bytes.extend_from_slice(value);
+}
+
+fn push_str(bytes: &mut Vec<u8>, value: &str) {
+    push_bytes(bytes, value.as_bytes());
+}
+
+fn read_bytes(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = *bytes.first()? as usize;
+    if bytes.len() < 1 + len {
+        return None;
+    }
+    Some((&bytes[1..1 + len], &bytes[1 + len..]))
+}
+
+fn read_str(bytes: &[u8]) -> Option<(String, &[u8])> {
+    let (value, rest) = read_bytes(bytes)?;
+    Some((String::from_utf8(value.to_vec()).ok()?, rest))
+}
+
+impl Storable for AccountKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self(bytes.into_owned())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// ============================================================================
+// STORAGE
+// ============================================================================
+
+thread_local! {
+    /// Balance per (account, asset)
+    static BALANCES: RefCell<Option<StableBTreeMap<AccountKey, u64, Memory>>> = const { RefCell::new(None) };
+
+    /// Append-only journal
+    static JOURNAL: RefCell<Option<StableBTreeMap<u64, JournalEntry, Memory>>> = const { RefCell::new(None) };
+}
+
+// Time provider for testing
+#[cfg(not(test))]
+fn get_time() -> u64 {
+    ic_cdk::api::time()
+}
+
+#[cfg(test)]
+fn get_time() -> u64 {
+    0
+}
+
+/// Initialize accounting storage (also restores it after upgrade)
+pub fn init_accounting_storage(balances_memory: Memory, journal_memory: Memory) {
+    BALANCES.with(|b| {
+        *b.borrow_mut() = Some(StableBTreeMap::init(balances_memory));
+    });
+    JOURNAL.with(|j| {
+        *j.borrow_mut() = Some(StableBTreeMap::init(journal_memory));
+    });
+}
+
+// ============================================================================
+// POSTING
+// ============================================================================
+
+/// Post a journal entry moving `amount` of `asset` from `from` to `to`
+///
+/// Debits `from` and credits `to`. Posting from `Custody` records tokens
+/// entering the engine (deposit, mint); posting to `Custody` records tokens
+/// leaving it (withdrawal, fee spent). Zero amounts are a no-op.
+pub fn post(
+    asset: Asset,
+    from: LedgerAccount,
+    to: LedgerAccount,
+    amount: u64,
+    reason: EntryReason,
+    reference: Option<String>,
+) -> Result<(), String> {
+    if amount == 0 {
+        return Ok(());
+    }
+    if from == to {
+        return Err("Cannot post an entry to the same account".to_string());
+    }
+    validate_account(&from)?;
+    validate_account(&to)?;
+    if let Asset::Rune(ref rune_id) = asset {
+        if rune_id.is_empty() || rune_id.len() > MAX_KEY_ID_LENGTH {
+            return Err("Invalid rune ID length".to_string());
+        }
+    }
+
+    let from_key = AccountKey::new(&from, &asset);
+    let to_key = AccountKey::new(&to, &asset);
+
+    BALANCES.with(|b| {
+        let mut b = b.borrow_mut();
+        let map = b.as_mut().ok_or("Accounting storage not initialized")?;
+
+        let from_balance = map.get(&from_key).unwrap_or(0);
+        let to_balance = map.get(&to_key).unwrap_or(0);
+
+        // Debit: asset accounts grow, liability accounts shrink
+        let new_from = if from.is_asset() {
+            from_balance.checked_add(amount).ok_or("Custody balance overflow")?
+        } else {
+            from_balance.checked_sub(amount).ok_or_else(|| {
+                format!(
+                    "Insufficient balance in {:?}: have {}, need {}",
+                    from, from_balance, amount
+                )
+            })?
+        };
+        // Credit: liability accounts grow, asset accounts shrink
+        let new_to = if to.is_asset() {
+            to_balance.checked_sub(amount).ok_or_else(|| {
+                format!("Insufficient custody of {:?}: have {}, need {}", asset, to_balance, amount)
+            })?
+        } else {
+            to_balance.checked_add(amount).ok_or("Balance overflow")?
+        };
+
+        set_balance(map, from_key, new_from);
+        set_balance(map, to_key, new_to);
+        Ok::<(), String>(())
+    })?;
+
+    let entry = JOURNAL.with(|j| {
+        let mut j = j.borrow_mut();
+        let journal = j.as_mut()?;
+        let id = journal.last_key_value().map(|(id, _)| id + 1).unwrap_or(0);
+        let entry = JournalEntry {
+            id,
+            asset,
+            debit: from,
+            credit: to,
+            amount,
+            reason,
+            reference,
+            timestamp: get_time(),
+        };
+        journal.insert(id, entry.clone());
+        Some(entry)
+    });
+
+    // Rune movements are also appended to the ICRC-3 block log
+    if let Some(entry) = entry {
+        crate::icrc3::record_posting(&entry);
+        if let Asset::Rune(ref rune_id) = entry.asset {
+            for account in [&entry.debit, &entry.credit] {
+                if let LedgerAccount::Available(owner) | LedgerAccount::Locked(owner) = account {
+                    crate::trading_v2::sync_user_rune_index(*owner, rune_id);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_account(account: &LedgerAccount) -> Result<(), String> {
+    let id = match account {
+        LedgerAccount::Pool(id) | LedgerAccount::LegacyPool(id) | LedgerAccount::Escrow(id) => id,
+        LedgerAccount::Subaccount(_, subaccount) if subaccount.len() != 32 => {
+            return Err("Subaccount must be 32 bytes".to_string());
+        }
+        _ => return Ok(()),
+    };
+    if id.is_empty() || id.len() > MAX_KEY_ID_LENGTH {
+        return Err(format!("Invalid account identifier length: {}", id.len()));
+    }
+    Ok(())
+}
+
+fn set_balance(map: &mut StableBTreeMap<AccountKey, u64, Memory>, key: AccountKey, balance: u64) {
+    if balance == 0 {
+        map.remove(&key);
+    } else {
+        map.insert(key, balance);
+    }
+}
+
+// ============================================================================
+// QUERIES
+// ============================================================================
+
+/// Balance of an account in an asset
+pub fn balance_of(account: &LedgerAccount, asset: &Asset) -> u64 {
+    let key = AccountKey::new(account, asset);
+    BALANCES.with(|b| b.borrow().as_ref().and_then(|map| map.get(&key)).unwrap_or(0))
+}
+
+/// User's (available, locked) balance in an asset
+pub fn user_balance(owner: Principal, asset: &Asset) -> (u64, u64) {
+    (
+        balance_of(&LedgerAccount::Available(owner), asset),
+        balance_of(&LedgerAccount::Locked(owner), asset),
+    )
+}
+
+/// All non-zero balances of an account, by asset
+#[allow(dead_code)]
+pub fn account_balances(account: &LedgerAccount) -> Vec<(Asset, u64)> {
+    let prefix = AccountKey::account_prefix(account);
+    BALANCES.with(|b| {
+        if let Some(ref map) = *b.borrow() {
+            map.range(AccountKey(prefix.clone())..)
+                .take_while(|(key, _)| key.0.starts_with(&prefix))
+                .filter_map(|(key, balance)| key.decode().map(|(_, asset)| (asset, balance)))
+                .collect()
+        } else {
+            vec![]
+        }
+    })
+}
+
+/// All non-zero balances held in an asset, by account
+pub fn asset_holders(asset: &Asset) -> Vec<(LedgerAccount, u64)> {
+    BALANCES.with(|b| {
+        if let Some(ref map) = *b.borrow() {
+            map.iter()
+                .filter_map(|(key, balance)| key.decode().map(|(account, a)| (account, a, balance)))
+                .filter(|(_, a, _)| a == asset)
+                .map(|(account, _, balance)| (account, balance))
+                .collect()
+        } else {
+            vec![]
+        }
+    })
+}
+
+/// Total (available + locked) balance of every user in every asset
+pub fn user_balances() -> BTreeMap<(Asset, Principal), u64> {
+    let mut totals = BTreeMap::new();
+    BALANCES.with(|b| {
+        if let Some(ref map) = *b.borrow() {
+            // Available (tag 1) and Locked (tag 2) accounts
+            for (key, balance) in map.range(AccountKey(vec![1])..AccountKey(vec![3])) {
+                if let Some((LedgerAccount::Available(owner) | LedgerAccount::Locked(owner), asset)) = key.decode() {
+                    let total: &mut u64 = totals.entry((asset, owner)).or_default();
+                    *total = total.saturating_add(balance);
+                }
+            }
+        }
+    });
+    totals
+}
+
+/// Every (user, rune) pair with a non-zero available or locked balance
+pub fn user_rune_holdings() -> Vec<(Principal, String)> {
+    user_balances()
+        .into_keys()
+        .filter_map(|(asset, owner)| match asset {
+            Asset::Rune(rune_id) => Some((owner, rune_id)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Number of journal entries
+pub fn journal_len() -> u64 {
+    JOURNAL.with(|j| j.borrow().as_ref().map(|journal| journal.len()).unwrap_or(0))
+}
+
+/// Journal entries in posting order
+pub fn get_journal(offset: u64, limit: u64) -> Vec<JournalEntry> {
+    JOURNAL.with(|j| {
+        if let Some(ref journal) = *j.borrow() {
+            journal
+                .range(offset..)
+                .take(limit as usize)
+                .map(|(_, entry)| entry)
+                .collect()
+        } else {
+            vec![]
+        }
+    })
+}
+
+// ============================================================================
+// INVARIANTS
+// ============================================================================
+
+/// Recompute per-asset liabilities and check pool state against the ledger
+///
+/// Coverage is only known once the caller adds external balances with
+/// `InvariantReport::record_held`.
+pub fn check_invariants() -> InvariantReport {
+    let mut totals: BTreeMap<Asset, u128> = BTreeMap::new();
+    BALANCES.with(|b| {
+        if let Some(ref map) = *b.borrow() {
+            for (key, balance) in map.iter() {
+                if let Some((account, asset)) = key.decode() {
+                    if !account.is_asset() {
+                        *totals.entry(asset).or_default() += balance as u128;
+                    }
+                }
+            }
+        }
+    });
+
+    let assets: Vec<AssetTotals> = totals
+        .into_iter()
+        .map(|(asset, liabilities)| AssetTotals {
+            asset,
+            liabilities,
+            held: None,
+            covered: None,
+        })
+        .collect();
+
+    let violations = pool_violations();
+    let ok = violations.is_empty();
+
+    InvariantReport {
+        assets,
+        violations,
+        journal_entries: journal_len(),
+        ok,
+    }
+}
+
+impl InvariantReport {
+    /// Record what `asset`'s ledger says the engine holds
+    pub fn record_held(&mut self, asset: &Asset, held: u128) {
+        if let Some(totals) = self.assets.iter_mut().find(|t| &t.asset == asset) {
+            totals.held = Some(held);
+            totals.covered = Some(held >= totals.liabilities);
+        }
+        self.ok = self.violations.is_empty() && self.assets.iter().all(|t| t.covered != Some(false));
+    }
+}
+
+/// Pool state must be backed by the ledger
+fn pool_violations() -> Vec<String> {
+    let mut violations = Vec::new();
+    let mut pending_protocol_fees: u128 = 0;
+
+    for pool in crate::trading_v2::list_pools(0, u64::MAX) {
+        let account = LedgerAccount::Pool(pool.rune_id.clone());
+        let icp = balance_of(&account, &Asset::Icp);
+        let runes = balance_of(&account, &Asset::Rune(pool.rune_id.clone()));
+        if icp < pool.icp_reserve {
+            violations.push(format!(
+                "Pool {} ICP reserve {} exceeds ledger balance {}",
+                pool.rune_id, pool.icp_reserve, icp
+            ));
+        }
+        if runes < pool.rune_reserve {
+            violations.push(format!(
+                "Pool {} rune reserve {} exceeds ledger balance {}",
+                pool.rune_id, pool.rune_reserve, runes
+            ));
+        }
+        pending_protocol_fees += pool.protocol_fees_pending as u128;
+    }
+
+    let recorded = balance_of(&LedgerAccount::ProtocolFees, &Asset::Icp) as u128;
+    if recorded != pending_protocol_fees {
+        violations.push(format!(
+            "Pending protocol fees {} differ from ledger balance {}",
+            pending_protocol_fees, recorded
+        ));
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+
+    fn init_test_storage() {
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        init_accounting_storage(manager.get(MemoryId::new(0)), manager.get(MemoryId::new(1)));
+    }
+
+    fn user() -> Principal {
+        Principal::from_text("aaaaa-aa").unwrap()
+    }
+
+    fn user_2() -> Principal {
+        Principal::from_text("2vxsx-fae").unwrap()
+    }
+
+    #[test]
+    fn test_account_key_roundtrip() {
+        let accounts = [
+            LedgerAccount::Custody,
+            LedgerAccount::Available(user()),
+            LedgerAccount::Locked(user_2()),
+            LedgerAccount::Pool("840000:1".to_string()),
+            LedgerAccount::LegacyPool("840000:1".to_string()),
+            LedgerAccount::ProtocolFees,
+            LedgerAccount::Escrow("process".to_string()),
+            LedgerAccount::Subaccount(user(), vec![7; 32]),
+        ];
+        let assets = [Asset::Icp, Asset::CkBtc, Asset::Rune("840000:1".to_string())];
+
+        for account in &accounts {
+            for asset in &assets {
+                let key = AccountKey::new(account, asset);
+                assert_eq!(key.decode(), Some((account.clone(), asset.clone())));
+            }
+        }
+    }
+
+    #[test]
+    fn test_deposit_trade_and_withdraw() {
+        init_test_storage();
+        let rune = Asset::Rune("840000:1".to_string());
+        let pool = LedgerAccount::Pool("840000:1".to_string());
+
+        // Deposit
+        post(Asset::Icp, LedgerAccount::Custody, LedgerAccount::Available(user()), 1_000_000, EntryReason::Deposit, None).unwrap();
+        assert_eq!(user_balance(user(), &Asset::Icp), (1_000_000, 0));
+
+        // Premine seeds the pool
+        post(rune.clone(), LedgerAccount::Custody, pool.clone(), 5_000, EntryReason::Premine, None).unwrap();
+
+        // Buy: ICP to pool and protocol fees, runes to user
+        post(Asset::Icp, LedgerAccount::Available(user()), pool.clone(), 299_000, EntryReason::Trade, None).unwrap();
+        post(Asset::Icp, LedgerAccount::Available(user()), LedgerAccount::ProtocolFees, 1_000, EntryReason::ProtocolFee, None).unwrap();
+        post(rune.clone(), pool.clone(), LedgerAccount::Available(user()), 100, EntryReason::Trade, None).unwrap();
+
+        assert_eq!(balance_of(&LedgerAccount::Available(user()), &Asset::Icp), 700_000);
+        assert_eq!(balance_of(&LedgerAccount::Available(user()), &rune), 100);
+        assert_eq!(balance_of(&pool, &Asset::Icp), 299_000);
+
+        // Over-withdrawal fails without side effects
+        assert!(post(Asset::Icp, LedgerAccount::Available(user()), LedgerAccount::Custody, 700_001, EntryReason::Withdrawal, None).is_err());
+        assert_eq!(balance_of(&LedgerAccount::Available(user()), &Asset::Icp), 700_000);
+
+        // Withdrawal
+        post(Asset::Icp, LedgerAccount::Available(user()), LedgerAccount::Custody, 700_000, EntryReason::Withdrawal, None).unwrap();
+        assert_eq!(balance_of(&LedgerAccount::Custody, &Asset::Icp), 300_000);
+        assert_eq!(journal_len(), 6);
+
+        let balances = account_balances(&LedgerAccount::Available(user()));
+        assert_eq!(balances, vec![(rune, 100)]);
+    }
+
+    #[test]
+    fn test_lock_and_transfer() {
+        init_test_storage();
+        let rune = Asset::Rune("r".to_string());
+
+        post(rune.clone(), LedgerAccount::Custody, LedgerAccount::Available(user()), 1_000, EntryReason::Premine, None).unwrap();
+        post(rune.clone(), LedgerAccount::Available(user()), LedgerAccount::Locked(user()), 400, EntryReason::Lock, None).unwrap();
+        assert_eq!(user_balance(user(), &rune), (600, 400));
+
+        // Locked balance cannot be transferred
+        assert!(post(rune.clone(), LedgerAccount::Available(user()), LedgerAccount::Available(user_2()), 601, EntryReason::Transfer, None).is_err());
+        post(rune.clone(), LedgerAccount::Available(user()), LedgerAccount::Available(user_2()), 600, EntryReason::Transfer, None).unwrap();
+        assert_eq!(user_balance(user_2(), &rune), (600, 0));
+
+        // Entries must move value between two different accounts
+        assert!(post(rune, LedgerAccount::Custody, LedgerAccount::Custody, 1, EntryReason::Transfer, None).is_err());
+    }
+
+    #[test]
+    fn test_invariants_compare_ledger_holdings() {
+        init_test_storage();
+        post(Asset::Icp, LedgerAccount::Custody, LedgerAccount::Available(user()), 1_000, EntryReason::Deposit, None).unwrap();
+
+        let mut report = check_invariants();
+        assert!(report.ok);
+        assert_eq!(report.assets[0].covered, None);
+
+        // The ICP ledger holds less than the engine owes
+        report.record_held(&Asset::Icp, 999);
+        assert_eq!(report.assets[0].covered, Some(false));
+        assert!(!report.ok);
+
+        report.record_held(&Asset::Icp, 1_000);
+        assert!(report.ok);
+    }
+}
@@ -9,11 +9,15 @@
  * - Credit/debit operations
  * - Transfer between users
  * - Lock/unlock for trading
+ *
+ * Balances are stored in the accounting ledger; this module keeps the
+ * rune-level API and a change history for display.
  */
 
 use candid::{CandidType, Deserialize, Principal};
 use std::cell::RefCell;
-use std::collections::HashMap;
+
+use crate::accounting::{self, Asset, EntryReason, LedgerAccount};
 
 /// User balance for a specific rune
 #[derive(CandidType, Deserialize, Clone, Debug, Default)]
@@ -34,9 +38,6 @@ impl RuneBalance {
     }
 }
 
-/// Key for balance storage: (Principal, RuneId)
-type BalanceKey = (Principal, String);
-
 /// Balance change record for audit trail
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct BalanceChange {
@@ -73,11 +74,23 @@ pub enum BalanceChangeType {
     PoolWithdraw,
 }
 
-// Thread-local storage for balances
-thread_local! {
-    /// User balances: (Principal, RuneId) -> RuneBalance
-    static BALANCES: RefCell<HashMap<BalanceKey, RuneBalance>> = RefCell::new(HashMap::new());
+impl BalanceChangeType {
+    /// Journal reason recorded in the accounting ledger
+    pub fn entry_reason(&self) -> EntryReason {
+        match self {
+            BalanceChangeType::Mint => EntryReason::Premine,
+            BalanceChangeType::Buy | BalanceChangeType::Sell => EntryReason::Trade,
+            BalanceChangeType::TransferIn | BalanceChangeType::TransferOut => EntryReason::Transfer,
+            BalanceChangeType::Lock => EntryReason::Lock,
+            BalanceChangeType::Unlock => EntryReason::Unlock,
+            BalanceChangeType::PoolDeposit => EntryReason::LiquidityAdd,
+            BalanceChangeType::PoolWithdraw => EntryReason::LiquidityRemove,
+        }
+    }
+}
 
+// Thread-local storage for balance history
+thread_local! {
     /// Balance change history for auditing
     static BALANCE_HISTORY: RefCell<Vec<BalanceChange>> = RefCell::new(Vec::new());
 
@@ -91,134 +104,168 @@ thread_local! {
 
 /// Get user's balance for a specific rune
 pub fn get_balance(user: Principal, rune_id: &str) -> RuneBalance {
-    let key = (user, rune_id.to_string());
-    BALANCES.with(|b| {
-        b.borrow().get(&key).cloned().unwrap_or_default()
-    })
+    let (available, locked) = accounting::user_balance(user, &rune_asset(rune_id));
+    RuneBalance { available, locked }
 }
 
 /// Get all balances for a user
 pub fn get_user_balances(user: Principal) -> Vec<(String, RuneBalance)> {
-    BALANCES.with(|b| {
-        b.borrow()
-            .iter()
-            .filter(|((owner, _), _)| *owner == user)
-            .map(|((_, rune_id), balance)| (rune_id.clone(), balance.clone()))
-            .collect()
-    })
+    let mut balances: Vec<(String, RuneBalance)> = Vec::new();
+    for (locked, account) in [
+        (false, LedgerAccount::Available(user)),
+        (true, LedgerAccount::Locked(user)),
+    ] {
+        for (asset, amount) in accounting::account_balances(&account) {
+            let Asset::Rune(rune_id) = asset else { continue };
+            let index = match balances.iter().position(|(id, _)| *id == rune_id) {
+                Some(index) => index,
+                None => {
+                    balances.push((rune_id, RuneBalance::default()));
+                    balances.len() - 1
+                }
+            };
+            if locked {
+                balances[index].1.locked = amount;
+            } else {
+                balances[index].1.available = amount;
+            }
+        }
+    }
+    balances
 }
 
 /// Get all holders of a specific rune
 pub fn get_rune_holders(rune_id: &str) -> Vec<(Principal, RuneBalance)> {
-    BALANCES.with(|b| {
-        b.borrow()
-            .iter()
-            .filter(|((_, rid), balance)| rid == rune_id && balance.total() > 0)
-            .map(|((owner, _), balance)| (*owner, balance.clone()))
-            .collect()
-    })
+    let mut holders: Vec<(Principal, RuneBalance)> = Vec::new();
+    for (account, amount) in accounting::asset_holders(&rune_asset(rune_id)) {
+        let (owner, locked) = match account {
+            LedgerAccount::Available(owner) => (owner, false),
+            LedgerAccount::Locked(owner) => (owner, true),
+            _ => continue,
+        };
+        let index = match holders.iter().position(|(p, _)| *p == owner) {
+            Some(index) => index,
+            None => {
+                holders.push((owner, RuneBalance::default()));
+                holders.len() - 1
+            }
+        };
+        if locked {
+            holders[index].1.locked = amount;
+        } else {
+            holders[index].1.available = amount;
+        }
+    }
+    holders
 }
 
 /// Get total circulating supply for a rune (sum of all user balances)
 pub fn get_circulating_supply(rune_id: &str) -> u64 {
-    BALANCES.with(|b| {
-        b.borrow()
-            .iter()
-            .filter(|((_, rid), _)| rid == rune_id)
-            .map(|(_, balance)| balance.total())
-            .sum()
-    })
+    get_rune_holders(rune_id)
+        .iter()
+        .map(|(_, balance)| balance.total())
+        .sum()
+}
+
+fn rune_asset(rune_id: &str) -> Asset {
+    Asset::Rune(rune_id.to_string())
 }
 
 // ============================================================================
 // BALANCE MODIFICATIONS
 // ============================================================================
 
-/// Credit runes to a user (e.g., from buying or minting)
+/// Credit runes to a user from `from` (e.g., a pool when buying, Custody when minting)
 pub fn credit_balance(
     user: Principal,
     rune_id: &str,
     amount: u64,
     change_type: BalanceChangeType,
+    from: LedgerAccount,
     reference: Option<String>,
 ) -> Result<RuneBalance, String> {
     if amount == 0 {
         return Err("Amount must be greater than 0".to_string());
     }
 
-    let key = (user, rune_id.to_string());
     let now = ic_cdk::api::time();
+    let balance_before = get_balance(user, rune_id).available;
+
+    accounting::post(
+        rune_asset(rune_id),
+        from,
+        LedgerAccount::Available(user),
+        amount,
+        change_type.entry_reason(),
+        reference.clone(),
+    )?;
 
-    BALANCES.with(|b| {
-        let mut balances = b.borrow_mut();
-        let balance = balances.entry(key.clone()).or_default();
+    let balance = get_balance(user, rune_id);
 
-        let balance_before = balance.available;
-        balance.available = balance.available.saturating_add(amount);
-        let balance_after = balance.available;
+    // Record change
+    record_balance_change(BalanceChange {
+        id: next_change_id(),
+        user,
+        rune_id: rune_id.to_string(),
+        change_type,
+        amount,
+        balance_before,
+        balance_after: balance.available,
+        timestamp: now,
+        reference,
+    });
 
-        // Record change
-        record_balance_change(BalanceChange {
-            id: next_change_id(),
-            user,
-            rune_id: rune_id.to_string(),
-            change_type,
-            amount,
-            balance_before,
-            balance_after,
-            timestamp: now,
-            reference,
-        });
-
-        Ok(balance.clone())
-    })
+    Ok(balance)
 }
 
-/// Debit runes from a user (e.g., from selling)
+/// Debit runes from a user to `to` (e.g., a pool when selling)
 pub fn debit_balance(
     user: Principal,
     rune_id: &str,
     amount: u64,
     change_type: BalanceChangeType,
+    to: LedgerAccount,
     reference: Option<String>,
 ) -> Result<RuneBalance, String> {
     if amount == 0 {
         return Err("Amount must be greater than 0".to_string());
     }
 
-    let key = (user, rune_id.to_string());
     let now = ic_cdk::api::time();
+    let balance_before = get_balance(user, rune_id).available;
 
-    BALANCES.with(|b| {
-        let mut balances = b.borrow_mut();
-        let balance = balances.entry(key.clone()).or_default();
+    if balance_before < amount {
+        return Err(format!(
+            "Insufficient balance: have {}, need {}",
+            balance_before, amount
+        ));
+    }
 
-        if balance.available < amount {
-            return Err(format!(
-                "Insufficient balance: have {}, need {}",
-                balance.available, amount
-            ));
-        }
+    accounting::post(
+        rune_asset(rune_id),
+        LedgerAccount::Available(user),
+        to,
+        amount,
+        change_type.entry_reason(),
+        reference.clone(),
+    )?;
 
-        let balance_before = balance.available;
-        balance.available = balance.available.saturating_sub(amount);
-        let balance_after = balance.available;
+    let balance = get_balance(user, rune_id);
 
-        // Record change
-        record_balance_change(BalanceChange {
-            id: next_change_id(),
-            user,
-            rune_id: rune_id.to_string(),
-            change_type,
-            amount,
-            balance_before,
-            balance_after,
-            timestamp: now,
-            reference,
-        });
-
-        Ok(balance.clone())
-    })
+    // Record change
+    record_balance_change(BalanceChange {
+        id: next_change_id(),
+        user,
+        rune_id: rune_id.to_string(),
+        change_type,
+        amount,
+        balance_before,
+        balance_after: balance.available,
+        timestamp: now,
+        reference,
+    });
+
+    Ok(balance)
 }
 
 /// Lock balance for a pending operation
@@ -232,40 +279,41 @@ pub fn lock_balance(
         return Err("Amount must be greater than 0".to_string());
     }
 
-    let key = (user, rune_id.to_string());
     let now = ic_cdk::api::time();
+    let balance_before = get_balance(user, rune_id).available;
 
-    BALANCES.with(|b| {
-        let mut balances = b.borrow_mut();
-        let balance = balances.entry(key.clone()).or_default();
+    if balance_before < amount {
+        return Err(format!(
+            "Insufficient available balance: have {}, need {}",
+            balance_before, amount
+        ));
+    }
 
-        if balance.available < amount {
-            return Err(format!(
-                "Insufficient available balance: have {}, need {}",
-                balance.available, amount
-            ));
-        }
+    accounting::post(
+        rune_asset(rune_id),
+        LedgerAccount::Available(user),
+        LedgerAccount::Locked(user),
+        amount,
+        EntryReason::Lock,
+        reference.clone(),
+    )?;
 
-        let balance_before = balance.available;
-        balance.available = balance.available.saturating_sub(amount);
-        balance.locked = balance.locked.saturating_add(amount);
-        let balance_after = balance.available;
+    let balance = get_balance(user, rune_id);
 
-        // Record change
-        record_balance_change(BalanceChange {
-            id: next_change_id(),
-            user,
-            rune_id: rune_id.to_string(),
-            change_type: BalanceChangeType::Lock,
-            amount,
-            balance_before,
-            balance_after,
-            timestamp: now,
-            reference,
-        });
-
-        Ok(balance.clone())
-    })
+    // Record change
+    record_balance_change(BalanceChange {
+        id: next_change_id(),
+        user,
+        rune_id: rune_id.to_string(),
+        change_type: BalanceChangeType::Lock,
+        amount,
+        balance_before,
+        balance_after: balance.available,
+        timestamp: now,
+        reference,
+    });
+
+    Ok(balance)
 }
 
 /// Unlock balance from a cancelled operation
@@ -279,76 +327,81 @@ pub fn unlock_balance(
         return Err("Amount must be greater than 0".to_string());
     }
 
-    let key = (user, rune_id.to_string());
     let now = ic_cdk::api::time();
+    let before = get_balance(user, rune_id);
+
+    if before.locked < amount {
+        return Err(format!(
+            "Insufficient locked balance: have {}, need {}",
+            before.locked, amount
+        ));
+    }
 
-    BALANCES.with(|b| {
-        let mut balances = b.borrow_mut();
-        let balance = balances.entry(key.clone()).or_default();
+    accounting::post(
+        rune_asset(rune_id),
+        LedgerAccount::Locked(user),
+        LedgerAccount::Available(user),
+        amount,
+        EntryReason::Unlock,
+        reference.clone(),
+    )?;
 
-        if balance.locked < amount {
-            return Err(format!(
-                "Insufficient locked balance: have {}, need {}",
-                balance.locked, amount
-            ));
-        }
+    let balance = get_balance(user, rune_id);
 
-        let balance_before = balance.available;
-        balance.locked = balance.locked.saturating_sub(amount);
-        balance.available = balance.available.saturating_add(amount);
-        let balance_after = balance.available;
+    // Record change
+    record_balance_change(BalanceChange {
+        id: next_change_id(),
+        user,
+        rune_id: rune_id.to_string(),
+        change_type: BalanceChangeType::Unlock,
+        amount,
+        balance_before: before.available,
+        balance_after: balance.available,
+        timestamp: now,
+        reference,
+    });
 
-        // Record change
-        record_balance_change(BalanceChange {
-            id: next_change_id(),
-            user,
-            rune_id: rune_id.to_string(),
-            change_type: BalanceChangeType::Unlock,
-            amount,
-            balance_before,
-            balance_after,
-            timestamp: now,
-            reference,
-        });
-
-        Ok(balance.clone())
-    })
+    Ok(balance)
 }
 
-/// Consume locked balance (after trade completion)
+/// Consume locked balance (after trade completion), moving it to `to`
 pub fn consume_locked(
     user: Principal,
     rune_id: &str,
     amount: u64,
+    to: LedgerAccount,
 ) -> Result<(), String> {
     if amount == 0 {
         return Err("Amount must be greater than 0".to_string());
     }
 
-    let key = (user, rune_id.to_string());
-
-    BALANCES.with(|b| {
-        let mut balances = b.borrow_mut();
-        let balance = balances.entry(key.clone()).or_default();
-
-        if balance.locked < amount {
-            return Err(format!(
-                "Insufficient locked balance: have {}, need {}",
-                balance.locked, amount
-            ));
-        }
+    let balance = get_balance(user, rune_id);
+    if balance.locked < amount {
+        return Err(format!(
+            "Insufficient locked balance: have {}, need {}",
+            balance.locked, amount
+        ));
+    }
 
-        balance.locked = balance.locked.saturating_sub(amount);
-        Ok(())
-    })
+    accounting::post(
+        rune_asset(rune_id),
+        LedgerAccount::Locked(user),
+        to,
+        amount,
+        EntryReason::LockConsumed,
+        None,
+    )
 }
 
 /// Transfer runes between users
+///
+/// `reference` is recorded on the ledger entry (e.g. the transfer event).
 pub fn transfer_runes(
     from: Principal,
     to: Principal,
     rune_id: &str,
     amount: u64,
+    reference: Option<String>,
 ) -> Result<(), String> {
     if from == to {
         return Err("Cannot transfer to self".to_string());
@@ -358,23 +411,41 @@ pub fn transfer_runes(
         return Err("Amount must be greater than 0".to_string());
     }
 
-    // Debit from sender
-    debit_balance(
-        from,
-        rune_id,
+    let now = ic_cdk::api::time();
+    let from_before = get_balance(from, rune_id).available;
+    let to_before = get_balance(to, rune_id).available;
+
+    accounting::post(
+        rune_asset(rune_id),
+        LedgerAccount::Available(from),
+        LedgerAccount::Available(to),
         amount,
-        BalanceChangeType::TransferOut,
-        Some(format!("to:{}", to)),
+        EntryReason::Transfer,
+        reference,
     )?;
 
-    // Credit to receiver
-    credit_balance(
-        to,
-        rune_id,
+    record_balance_change(BalanceChange {
+        id: next_change_id(),
+        user: from,
+        rune_id: rune_id.to_string(),
+        change_type: BalanceChangeType::TransferOut,
         amount,
-        BalanceChangeType::TransferIn,
-        Some(format!("from:{}", from)),
-    )?;
+        balance_before: from_before,
+        balance_after: from_before - amount,
+        timestamp: now,
+        reference: Some(format!("to:{}", to)),
+    });
+    record_balance_change(BalanceChange {
+        id: next_change_id(),
+        user: to,
+        rune_id: rune_id.to_string(),
+        change_type: BalanceChangeType::TransferIn,
+        amount,
+        balance_before: to_before,
+        balance_after: to_before.saturating_add(amount),
+        timestamp: now,
+        reference: Some(format!("from:{}", from)),
+    });
 
     Ok(())
 }
@@ -390,6 +461,7 @@ pub fn mint_to_pool_creator(
         rune_id,
         amount,
         BalanceChangeType::Mint,
+        LedgerAccount::Custody,
         Some("pool_creation".to_string()),
     )
 }
@@ -445,6 +517,13 @@ pub fn get_rune_balance_history(rune_id: &str, limit: usize) -> Vec<BalanceChang
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+    use ic_stable_structures::DefaultMemoryImpl;
+
+    fn init_test_storage() {
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        accounting::init_accounting_storage(manager.get(MemoryId::new(0)), manager.get(MemoryId::new(1)));
+    }
 
     fn test_principal() -> Principal {
         Principal::from_text("aaaaa-aa").unwrap()
@@ -458,6 +537,7 @@ mod tests {
     fn test_credit_and_debit() {
         let user = test_principal();
         let rune_id = "test_rune";
+        init_test_storage();
 
         // Credit
         let balance = credit_balance(
@@ -465,6 +545,7 @@ mod tests {
             rune_id,
             1000,
             BalanceChangeType::Mint,
+            LedgerAccount::Custody,
             None,
         ).unwrap();
         assert_eq!(balance.available, 1000);
@@ -476,6 +557,7 @@ mod tests {
             rune_id,
             300,
             BalanceChangeType::Sell,
+            LedgerAccount::LegacyPool(rune_id.to_string()),
             None,
         ).unwrap();
         assert_eq!(balance.available, 700);
@@ -486,6 +568,7 @@ mod tests {
             rune_id,
             1000,
             BalanceChangeType::Sell,
+            LedgerAccount::LegacyPool(rune_id.to_string()),
             None,
         );
         assert!(result.is_err());
@@ -495,9 +578,10 @@ mod tests {
     fn test_lock_unlock() {
         let user = test_principal();
         let rune_id = "test_rune_2";
+        init_test_storage();
 
         // Setup
-        credit_balance(user, rune_id, 1000, BalanceChangeType::Mint, None).unwrap();
+        credit_balance(user, rune_id, 1000, BalanceChangeType::Mint, LedgerAccount::Custody, None).unwrap();
 
         // Lock
         let balance = lock_balance(user, rune_id, 400, None).unwrap();
@@ -515,12 +599,13 @@ mod tests {
         let from = test_principal();
         let to = test_principal_2();
         let rune_id = "test_rune_3";
+        init_test_storage();
 
         // Setup
-        credit_balance(from, rune_id, 1000, BalanceChangeType::Mint, None).unwrap();
+        credit_balance(from, rune_id, 1000, BalanceChangeType::Mint, LedgerAccount::Custody, None).unwrap();
 
         // Transfer
-        transfer_runes(from, to, rune_id, 300).unwrap();
+        transfer_runes(from, to, rune_id, 300, None).unwrap();
 
         let from_balance = get_balance(from, rune_id);
         let to_balance = get_balance(to, rune_id);
@@ -5,24 +5,484 @@
 //!
 //! This is a key feature for the ICP Bitcoin DeFi Hackathon.
 
-use candid::Principal;
+use candid::{CandidType, Deserialize, Principal};
 use ic_cdk::api::time;
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::memory_manager::VirtualMemory;
+use ic_stable_structures::{storable::Bound, DefaultMemoryImpl, StableBTreeMap, StableCell, Storable};
+use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::time::Duration;
 
 use quri_types::{
-    CreateDeadManSwitchParams, DeadManSwitch, DeadManSwitchInfo,
-    DeadManSwitchStats, SwitchStatus,
+    CreateDeadManSwitchParams, DeadManSwitch, DeadManSwitchInfo, DeadManSwitchStats,
+    ReleaseStage, SwitchBeneficiary, SwitchPayout, SwitchPayoutStatus, SwitchStatus,
 };
 
+use crate::accounting::{self, Asset, EntryReason, LedgerAccount};
+use crate::balances;
 use crate::errors::EngineError;
+use crate::logging;
 use crate::validators::is_valid_bitcoin_address;
 
-// Thread-local storage for Dead Man's Switches
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// How often the timer scans for expired switches
+const PROCESS_INTERVAL_SECS: u64 = 600;
+
+/// Payout attempts before a switch is parked as `Failed`
+const MAX_PAYOUT_ATTEMPTS: u32 = 8;
+
+/// Delay after the first failed attempt; doubles on each further failure
+const RETRY_BASE_DELAY_NS: u64 = 10 * 60 * 1_000_000_000;
+
+/// Upper bound on the retry delay
+const RETRY_MAX_DELAY_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+const NS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Shares and stages are expressed in basis points of the switch amount
+const BPS_DENOMINATOR: u16 = 10_000;
+
+const MAX_BENEFICIARIES: usize = 20;
+const MAX_STAGES: usize = 12;
+const MAX_WARNINGS: usize = 5;
+
+/// Longest delay of a release stage after expiry (10 years)
+const MAX_STAGE_DELAY_DAYS: u64 = 3650;
+
+const MAX_GUARDIANS: usize = 10;
+const DEFAULT_GRACE_DAYS: u64 = 7;
+const MAX_GRACE_DAYS: u64 = 90;
+
+/// Schedule-index tags: switches by the time the processor next needs them,
+/// and switches with an on-chain payout awaiting confirmations
+const DUE_BY_TIME: u8 = 0;
+const BROADCAST_IN_FLIGHT: u8 = 1;
+
+/// Encoded switch
+#[derive(Clone, Debug)]
+struct StoredSwitch(DeadManSwitch);
+
+impl Storable for StoredSwitch {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(&self.0).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to encode DeadManSwitch: {}", e))
+        }))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self(candid::decode_one(&bytes).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to decode DeadManSwitch: {}", e))
+        }))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// A guardian's current vote on a switch
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct GuardianVote {
+    /// true confirms release, false vetoes it
+    approve: bool,
+    voted_at: u64,
+}
+
+impl Storable for GuardianVote {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to encode GuardianVote: {}", e))
+        }))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to decode GuardianVote: {}", e))
+        })
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Stable storage for Dead Man's Switches (survives upgrades)
 thread_local! {
-    static DEAD_MAN_SWITCHES: RefCell<BTreeMap<u64, DeadManSwitch>> = const { RefCell::new(BTreeMap::new()) };
-    static SWITCH_COUNTER: RefCell<u64> = const { RefCell::new(0) };
-    static USER_SWITCHES: RefCell<BTreeMap<Principal, Vec<u64>>> = const { RefCell::new(BTreeMap::new()) };
+    static DEAD_MAN_SWITCHES: RefCell<Option<StableBTreeMap<u64, StoredSwitch, Memory>>> = const { RefCell::new(None) };
+    static SWITCH_COUNTER: RefCell<Option<StableCell<u64, Memory>>> = const { RefCell::new(None) };
+    /// Owner index keyed by (owner, switch id)
+    static USER_SWITCHES: RefCell<Option<StableBTreeMap<Vec<u8>, (), Memory>>> = const { RefCell::new(None) };
+    /// Guardian votes keyed by (switch id, guardian)
+    static GUARDIAN_VOTES: RefCell<Option<StableBTreeMap<Vec<u8>, GuardianVote, Memory>>> = const { RefCell::new(None) };
+    /// Tagged keys: switches by next due time, switches with a broadcast in flight
+    static SWITCH_SCHEDULE: RefCell<Option<StableBTreeMap<Vec<u8>, (), Memory>>> = const { RefCell::new(None) };
+
+    static TIMER_ID: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    /// Set while a scan is running so timer ticks and manual runs don't overlap
+    static PROCESSING: RefCell<bool> = const { RefCell::new(false) };
+}
+
+/// Where a switch pays out
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Beneficiary {
+    /// Credited to the principal's virtual balance
+    Principal(Principal),
+    /// Sent on-chain with an edict transfer
+    Bitcoin(String),
+}
+
+fn parse_beneficiary(beneficiary: &str) -> Option<Beneficiary> {
+    if let Ok(principal) = Principal::from_text(beneficiary) {
+        return Some(Beneficiary::Principal(principal));
+    }
+    if is_valid_bitcoin_address(beneficiary) {
+        return Some(Beneficiary::Bitcoin(beneficiary.to_string()));
+    }
+    None
+}
+
+fn switch_reference(id: u64) -> String {
+    format!("dms:{}", id)
+}
+
+fn validate_beneficiaries(beneficiaries: &[SwitchBeneficiary], owner: Principal) -> Result<(), EngineError> {
+    if beneficiaries.is_empty() || beneficiaries.len() > MAX_BENEFICIARIES {
+        return Err(EngineError::InvalidInput(format!(
+            "Between 1 and {} beneficiaries required",
+            MAX_BENEFICIARIES
+        )));
+    }
+
+    let mut total: u32 = 0;
+    for (i, b) in beneficiaries.iter().enumerate() {
+        match parse_beneficiary(&b.beneficiary) {
+            Some(Beneficiary::Principal(p)) if p == owner || p == Principal::anonymous() => {
+                return Err(EngineError::InvalidInput(
+                    "Beneficiary must be another principal".to_string()
+                ));
+            }
+            Some(_) => {}
+            None => {
+                return Err(EngineError::InvalidInput(format!(
+                    "Beneficiary must be a principal or a Bitcoin address: {}",
+                    b.beneficiary
+                )));
+            }
+        }
+        if b.share_bps == 0 {
+            return Err(EngineError::InvalidInput("Beneficiary share must be greater than 0".to_string()));
+        }
+        if beneficiaries[..i].iter().any(|other| other.beneficiary == b.beneficiary) {
+            return Err(EngineError::InvalidInput(format!("Duplicate beneficiary: {}", b.beneficiary)));
+        }
+        total += b.share_bps as u32;
+    }
+
+    if total != BPS_DENOMINATOR as u32 {
+        return Err(EngineError::InvalidInput(format!(
+            "Beneficiary shares must sum to {} bps, got {}",
+            BPS_DENOMINATOR, total
+        )));
+    }
+    Ok(())
+}
+
+fn validate_stages(stages: &[ReleaseStage]) -> Result<(), EngineError> {
+    if stages.is_empty() || stages.len() > MAX_STAGES {
+        return Err(EngineError::InvalidInput(format!(
+            "Between 1 and {} release stages required",
+            MAX_STAGES
+        )));
+    }
+
+    let mut total: u32 = 0;
+    for stage in stages {
+        if stage.release_bps == 0 {
+            return Err(EngineError::InvalidInput("Stage release must be greater than 0".to_string()));
+        }
+        if stage.delay_days > MAX_STAGE_DELAY_DAYS {
+            return Err(EngineError::InvalidInput(format!(
+                "Stage delay must be at most {} days",
+                MAX_STAGE_DELAY_DAYS
+            )));
+        }
+        total += stage.release_bps as u32;
+    }
+
+    if total != BPS_DENOMINATOR as u32 {
+        return Err(EngineError::InvalidInput(format!(
+            "Release stages must sum to {} bps, got {}",
+            BPS_DENOMINATOR, total
+        )));
+    }
+    Ok(())
+}
+
+fn validate_guardians(guardians: &[Principal], threshold: u8, owner: Principal) -> Result<(), EngineError> {
+    if guardians.is_empty() || guardians.len() > MAX_GUARDIANS {
+        return Err(EngineError::InvalidInput(format!(
+            "Between 1 and {} guardians required",
+            MAX_GUARDIANS
+        )));
+    }
+    for (i, g) in guardians.iter().enumerate() {
+        if *g == owner || *g == Principal::anonymous() {
+            return Err(EngineError::InvalidInput(
+                "Guardians must be other principals".to_string()
+            ));
+        }
+        if guardians[..i].contains(g) {
+            return Err(EngineError::InvalidInput(format!("Duplicate guardian: {}", g)));
+        }
+    }
+    if threshold == 0 || threshold as usize > guardians.len() {
+        return Err(EngineError::InvalidInput(format!(
+            "Guardian threshold must be between 1 and {}",
+            guardians.len()
+        )));
+    }
+    Ok(())
+}
+
+fn switch_beneficiaries(switch: &DeadManSwitch) -> Vec<SwitchBeneficiary> {
+    switch.beneficiaries.clone().unwrap_or_else(|| {
+        vec![SwitchBeneficiary {
+            beneficiary: switch.beneficiary.clone(),
+            share_bps: BPS_DENOMINATOR,
+        }]
+    })
+}
+
+fn switch_stages(switch: &DeadManSwitch) -> Vec<ReleaseStage> {
+    switch.stages.clone().unwrap_or_else(|| {
+        vec![ReleaseStage {
+            delay_days: 0,
+            release_bps: BPS_DENOMINATOR,
+        }]
+    })
+}
+
+/// Split `total` by basis points; the last part takes the rounding remainder
+fn split_bps(total: u64, bps: &[u16]) -> Vec<u64> {
+    let mut parts: Vec<u64> = bps
+        .iter()
+        .map(|b| (total as u128 * *b as u128 / BPS_DENOMINATOR as u128) as u64)
+        .collect();
+    if let Some(last) = parts.len().checked_sub(1) {
+        let rest: u64 = parts[..last].iter().sum();
+        parts[last] = total - rest;
+    }
+    parts
+}
+
+/// One payout per stage and beneficiary, in release order
+fn plan_payouts(
+    amount: u64,
+    beneficiaries: &[SwitchBeneficiary],
+    stages: &[ReleaseStage],
+    expires_at: u64,
+) -> Vec<SwitchPayout> {
+    let mut stages = stages.to_vec();
+    stages.sort_by_key(|s| s.delay_days);
+    let shares: Vec<u16> = beneficiaries.iter().map(|b| b.share_bps).collect();
+    let releases: Vec<u16> = stages.iter().map(|s| s.release_bps).collect();
+
+    let mut payouts = Vec::with_capacity(stages.len() * beneficiaries.len());
+    for (stage, stage_amount) in stages.iter().zip(split_bps(amount, &releases)) {
+        let release_at = expires_at.saturating_add(stage.delay_days.saturating_mul(NS_PER_DAY));
+        for (b, leg_amount) in beneficiaries.iter().zip(split_bps(stage_amount, &shares)) {
+            payouts.push(SwitchPayout {
+                beneficiary: b.beneficiary.clone(),
+                amount: leg_amount,
+                release_at,
+                status: SwitchPayoutStatus::Pending,
+                attempts: 0,
+                next_attempt_at: release_at,
+                last_error: None,
+                completed_at: None,
+            });
+        }
+    }
+    payouts
+}
+
+/// Initialize switch storage (also restores it after upgrade)
+pub fn init_switch_storage(
+    switches_memory: Memory,
+    counter_memory: Memory,
+    user_index_memory: Memory,
+    votes_memory: Memory,
+    schedule_memory: Memory,
+) {
+    DEAD_MAN_SWITCHES.with(|s| {
+        *s.borrow_mut() = Some(StableBTreeMap::init(switches_memory));
+    });
+    SWITCH_COUNTER.with(|c| {
+        *c.borrow_mut() = Some(
+            StableCell::init(counter_memory, 0).expect("Failed to initialize switch counter"),
+        );
+    });
+    USER_SWITCHES.with(|u| {
+        *u.borrow_mut() = Some(StableBTreeMap::init(user_index_memory));
+    });
+    GUARDIAN_VOTES.with(|v| {
+        *v.borrow_mut() = Some(StableBTreeMap::init(votes_memory));
+    });
+    SWITCH_SCHEDULE.with(|i| {
+        *i.borrow_mut() = Some(StableBTreeMap::init(schedule_memory));
+    });
+}
+
+/// One-time migration: bring the counter, owner index and schedule index in
+/// line with the stored switches
+///
+/// Switches used to live on the heap and were never saved in `pre_upgrade`, so
+/// there is nothing to carry over from those releases; this only repairs the
+/// derived state if it is missing. Returns the number of index entries added.
+pub fn migrate_switch_storage() -> u64 {
+    let last_id = DEAD_MAN_SWITCHES.with(|s| {
+        s.borrow().as_ref().and_then(|map| map.last_key_value().map(|(id, _)| id)).unwrap_or(0)
+    });
+    SWITCH_COUNTER.with(|c| {
+        if let Some(ref mut cell) = *c.borrow_mut() {
+            if *cell.get() < last_id {
+                let _ = cell.set(last_id);
+            }
+        }
+    });
+
+    let missing: Vec<(Principal, u64)> = with_switches(|map| {
+        map.iter()
+            .map(|(id, stored)| (stored.0.owner, id))
+            .filter(|(owner, id)| {
+                !USER_SWITCHES.with(|u| {
+                    u.borrow().as_ref().map(|index| index.contains_key(&user_key(owner, *id))).unwrap_or(false)
+                })
+            })
+            .collect()
+    });
+    for (owner, id) in &missing {
+        index_switch(owner, *id);
+    }
+
+    let mut scheduled = 0u64;
+    if SWITCH_SCHEDULE.with(|i| i.borrow().as_ref().is_some_and(|map| map.is_empty())) {
+        let switches: Vec<DeadManSwitch> = with_switches(|map| map.iter().map(|(_, stored)| stored.0).collect());
+        for switch in &switches {
+            let keys = schedule_keys(switch);
+            scheduled += keys.len() as u64;
+            reschedule(&[], &keys);
+        }
+    }
+    missing.len() as u64 + scheduled
+}
+
+fn with_switches<R>(f: impl FnOnce(&StableBTreeMap<u64, StoredSwitch, Memory>) -> R) -> R {
+    DEAD_MAN_SWITCHES.with(|s| {
+        let s = s.borrow();
+        f(s.as_ref().expect("Dead Man's Switch storage not initialized"))
+    })
+}
+
+fn get_switch(id: u64) -> Option<DeadManSwitch> {
+    DEAD_MAN_SWITCHES.with(|s| s.borrow().as_ref().and_then(|map| map.get(&id)).map(|stored| stored.0))
+}
+
+fn store_switch(switch: &DeadManSwitch) {
+    let previous = get_switch(switch.id);
+    DEAD_MAN_SWITCHES.with(|s| {
+        if let Some(ref mut map) = *s.borrow_mut() {
+            map.insert(switch.id, StoredSwitch(switch.clone()));
+        }
+    });
+    reschedule(&previous.as_ref().map(schedule_keys).unwrap_or_default(), &schedule_keys(switch));
+}
+
+/// When the processor next needs to look at the switch (see `is_payout_due`)
+fn next_due_at(switch: &DeadManSwitch) -> Option<u64> {
+    if switch.triggered {
+        return None;
+    }
+    let start = release_start(switch).saturating_add(1);
+    match &switch.payouts {
+        None => Some(start),
+        Some(payouts) => payouts
+            .iter()
+            .filter_map(|p| match p.status {
+                SwitchPayoutStatus::Pending => Some(p.release_at.max(p.next_attempt_at)),
+                SwitchPayoutStatus::Broadcast { .. } => Some(0),
+                SwitchPayoutStatus::Completed | SwitchPayoutStatus::Failed => None,
+            })
+            .min()
+            .map(|at| at.max(start)),
+    }
+}
+
+fn due_key(due_at: u64, id: u64) -> Vec<u8> {
+    let mut key = vec![DUE_BY_TIME];
+    key.extend_from_slice(&due_at.to_be_bytes());
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+/// Schedule-index keys for the switch's current state
+fn schedule_keys(switch: &DeadManSwitch) -> Vec<Vec<u8>> {
+    let mut keys: Vec<Vec<u8>> = next_due_at(switch).map(|at| due_key(at, switch.id)).into_iter().collect();
+    if switch.payouts.iter().flatten().any(|p| matches!(p.status, SwitchPayoutStatus::Broadcast { .. })) {
+        let mut key = vec![BROADCAST_IN_FLIGHT];
+        key.extend_from_slice(&switch.id.to_be_bytes());
+        keys.push(key);
+    }
+    keys
+}
+
+/// Replace a switch's schedule-index keys
+fn reschedule(old: &[Vec<u8>], new: &[Vec<u8>]) {
+    SWITCH_SCHEDULE.with(|i| {
+        if let Some(ref mut map) = *i.borrow_mut() {
+            for key in old.iter().filter(|key| !new.contains(key)) {
+                map.remove(key);
+            }
+            for key in new.iter().filter(|key| !old.contains(key)) {
+                map.insert(key.clone(), ());
+            }
+        }
+    });
+}
+
+fn user_prefix(user: &Principal) -> Vec<u8> {
+    let bytes = user.as_slice();
+    let mut key = Vec::with_capacity(1 + bytes.len() + 8);
+    key.push(bytes.len() as u8);
+    key.extend_from_slice(bytes);
+    key
+}
+
+fn user_key(user: &Principal, id: u64) -> Vec<u8> {
+    let mut key = user_prefix(user);
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+fn index_switch(user: &Principal, id: u64) {
+    USER_SWITCHES.with(|u| {
+        if let Some(ref mut index) = *u.borrow_mut() {
+            index.insert(user_key(user, id), ());
+        }
+    });
+}
+
+fn user_switch_ids(user: &Principal) -> Vec<u64> {
+    let prefix = user_prefix(user);
+    USER_SWITCHES.with(|u| {
+        if let Some(ref index) = *u.borrow() {
+            index
+                .range(prefix.clone()..)
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                .filter_map(|(key, _)| key[prefix.len()..].try_into().ok().map(u64::from_be_bytes))
+                .collect()
+        } else {
+            vec![]
+        }
+    })
 }
 
 /// Create a new Dead Man's Switch
@@ -34,11 +494,18 @@ pub fn create_switch(params: CreateDeadManSwitchParams) -> Result<u64, EngineErr
         return Err(EngineError::Unauthorized("Anonymous principals cannot create switches".to_string()));
     }
 
-    // Validate beneficiary address
-    if !is_valid_bitcoin_address(&params.beneficiary) {
-        return Err(EngineError::InvalidInput(
-            "Invalid beneficiary Bitcoin address".to_string()
-        ));
+    // Validate beneficiaries
+    let beneficiaries = params.beneficiaries.clone().unwrap_or_else(|| {
+        vec![SwitchBeneficiary {
+            beneficiary: params.beneficiary.clone(),
+            share_bps: BPS_DENOMINATOR,
+        }]
+    });
+    validate_beneficiaries(&beneficiaries, caller)?;
+
+    // Validate release schedule
+    if let Some(stages) = &params.stages {
+        validate_stages(stages)?;
     }
 
     // Validate timeout (1-365 days)
@@ -48,27 +515,92 @@ pub fn create_switch(params: CreateDeadManSwitchParams) -> Result<u64, EngineErr
         ));
     }
 
+    // Validate guardians (M-of-N, simple majority by default)
+    let (guardian_threshold, grace_period_ns) = match &params.guardians {
+        Some(guardians) => {
+            let threshold = params.guardian_threshold.unwrap_or((guardians.len() / 2 + 1) as u8);
+            validate_guardians(guardians, threshold, caller)?;
+            let grace_days = params.grace_days.unwrap_or(DEFAULT_GRACE_DAYS);
+            if grace_days > MAX_GRACE_DAYS {
+                return Err(EngineError::InvalidInput(format!(
+                    "Grace period must be at most {} days",
+                    MAX_GRACE_DAYS
+                )));
+            }
+            (Some(threshold), Some(grace_days * NS_PER_DAY))
+        }
+        None if params.guardian_threshold.is_some() || params.grace_days.is_some() => {
+            return Err(EngineError::InvalidInput(
+                "Guardian threshold and grace period require guardians".to_string()
+            ));
+        }
+        None => (None, None),
+    };
+
+    // Validate warnings (each strictly before expiry)
+    let warning_days = match params.warning_days {
+        Some(mut days) => {
+            days.sort_unstable_by(|a, b| b.cmp(a));
+            days.dedup();
+            if days.len() > MAX_WARNINGS || days.iter().any(|d| *d == 0 || *d >= params.timeout_days) {
+                return Err(EngineError::InvalidInput(format!(
+                    "Up to {} warnings, each between 1 day and the timeout",
+                    MAX_WARNINGS
+                )));
+            }
+            Some(days)
+        }
+        None => None,
+    };
+
     // Validate amount
     if params.amount == 0 {
         return Err(EngineError::InvalidInput(
             "Amount must be greater than 0".to_string()
         ));
     }
+    let locked_amount = u64::try_from(params.amount).map_err(|_| {
+        EngineError::InvalidInput("Amount exceeds the maximum virtual balance".to_string())
+    })?;
+
+    let stages = params.stages.clone().unwrap_or_else(|| {
+        vec![ReleaseStage {
+            delay_days: 0,
+            release_bps: BPS_DENOMINATOR,
+        }]
+    });
+    if plan_payouts(locked_amount, &beneficiaries, &stages, 0).iter().any(|p| p.amount == 0) {
+        return Err(EngineError::InvalidInput(
+            "Amount too small to split across every beneficiary and stage".to_string()
+        ));
+    }
 
     let now = time();
     let timeout_ns = params.timeout_days * 24 * 60 * 60 * 1_000_000_000;
 
-    // Generate unique ID
+    // Generate unique ID (committed only once the amount is locked)
     let id = SWITCH_COUNTER.with(|counter| {
-        let mut c = counter.borrow_mut();
-        *c += 1;
-        *c
+        counter
+            .borrow()
+            .as_ref()
+            .map(|cell| cell.get() + 1)
+            .ok_or_else(|| EngineError::InternalError("Dead Man's Switch storage not initialized".to_string()))
+    })?;
+
+    // Lock the amount so it can't be sold or transferred while the switch is armed
+    balances::lock_balance(caller, &params.rune_id, locked_amount, Some(switch_reference(id)))
+        .map_err(EngineError::InvalidState)?;
+
+    SWITCH_COUNTER.with(|counter| {
+        if let Some(ref mut cell) = *counter.borrow_mut() {
+            let _ = cell.set(id);
+        }
     });
 
     let switch = DeadManSwitch {
         id,
         owner: caller,
-        beneficiary: params.beneficiary,
+        beneficiary: beneficiaries[0].beneficiary.clone(),
         rune_id: params.rune_id,
         amount: params.amount,
         last_checkin: now,
@@ -76,18 +608,20 @@ pub fn create_switch(params: CreateDeadManSwitchParams) -> Result<u64, EngineErr
         triggered: false,
         created_at: now,
         message: params.message,
+        locked_amount: Some(locked_amount),
+        beneficiaries: params.beneficiaries,
+        stages: params.stages,
+        warning_days,
+        payouts: None,
+        guardians: params.guardians,
+        guardian_threshold,
+        grace_period_ns,
+        guardian_released_at: None,
     };
 
-    // Store the switch
-    DEAD_MAN_SWITCHES.with(|switches| {
-        switches.borrow_mut().insert(id, switch);
-    });
-
-    // Track user's switches
-    USER_SWITCHES.with(|user_switches| {
-        let mut us = user_switches.borrow_mut();
-        us.entry(caller).or_default().push(id);
-    });
+    // Store the switch and track it under the owner
+    store_switch(&switch);
+    index_switch(&caller, id);
 
     ic_cdk::println!("Created Dead Man's Switch {} for {}", id, caller);
 
@@ -98,125 +632,126 @@ pub fn create_switch(params: CreateDeadManSwitchParams) -> Result<u64, EngineErr
 pub fn checkin(switch_id: u64) -> Result<(), EngineError> {
     let caller = ic_cdk::caller();
 
-    DEAD_MAN_SWITCHES.with(|switches| {
-        let mut switches = switches.borrow_mut();
-        let switch = switches.get_mut(&switch_id)
-            .ok_or(EngineError::NotFound("Switch not found".to_string()))?;
+    let mut switch = get_switch(switch_id)
+        .ok_or(EngineError::NotFound("Switch not found".to_string()))?;
 
-        if switch.owner != caller {
-            return Err(EngineError::Unauthorized(
-                "Only owner can check in".to_string()
-            ));
-        }
+    if switch.owner != caller {
+        return Err(EngineError::Unauthorized(
+            "Only owner can check in".to_string()
+        ));
+    }
 
-        if switch.triggered {
-            return Err(EngineError::InvalidState(
-                "Switch already triggered".to_string()
-            ));
-        }
+    if switch.triggered {
+        return Err(EngineError::InvalidState(
+            "Switch already triggered".to_string()
+        ));
+    }
 
-        switch.last_checkin = time();
+    if switch.payouts.is_some() {
+        return Err(EngineError::InvalidState(
+            "Switch payout already in progress".to_string()
+        ));
+    }
 
-        ic_cdk::println!("Check-in for switch {} by {}", switch_id, caller);
+    // A fresh check-in supersedes any guardian decision
+    switch.last_checkin = time();
+    switch.guardian_released_at = None;
+    store_switch(&switch);
+    clear_votes(switch_id);
 
-        Ok(())
-    })
+    ic_cdk::println!("Check-in for switch {} by {}", switch_id, caller);
+
+    Ok(())
 }
 
 /// Cancel a switch (only owner)
 pub fn cancel_switch(switch_id: u64) -> Result<(), EngineError> {
     let caller = ic_cdk::caller();
 
-    DEAD_MAN_SWITCHES.with(|switches| {
-        let switches_ref = switches.borrow();
-        let switch = switches_ref.get(&switch_id)
-            .ok_or(EngineError::NotFound("Switch not found".to_string()))?;
+    let switch = get_switch(switch_id)
+        .ok_or(EngineError::NotFound("Switch not found".to_string()))?;
 
-        if switch.owner != caller {
-            return Err(EngineError::Unauthorized(
-                "Only owner can cancel".to_string()
-            ));
-        }
+    if switch.owner != caller {
+        return Err(EngineError::Unauthorized(
+            "Only owner can cancel".to_string()
+        ));
+    }
 
-        if switch.triggered {
-            return Err(EngineError::InvalidState(
-                "Cannot cancel triggered switch".to_string()
-            ));
+    if switch.triggered {
+        return Err(EngineError::InvalidState(
+            "Cannot cancel triggered switch".to_string()
+        ));
+    }
+
+    if switch.payouts.is_some() {
+        return Err(EngineError::InvalidState(
+            "Cannot cancel a switch whose payout has started".to_string()
+        ));
+    }
+
+    // Release the locked amount back to the owner
+    if let Some(amount) = switch.locked_amount.filter(|a| *a > 0) {
+        balances::unlock_balance(caller, &switch.rune_id, amount, Some(switch_reference(switch_id)))
+            .map_err(EngineError::InternalError)?;
+    }
+
+    DEAD_MAN_SWITCHES.with(|s| {
+        if let Some(ref mut map) = *s.borrow_mut() {
+            map.remove(&switch_id);
         }
+    });
+    reschedule(&schedule_keys(&switch), &[]);
 
-        drop(switches_ref);
-        switches.borrow_mut().remove(&switch_id);
+    // Remove from user's switches
+    USER_SWITCHES.with(|u| {
+        if let Some(ref mut index) = *u.borrow_mut() {
+            index.remove(&user_key(&caller, switch_id));
+        }
+    });
+    clear_votes(switch_id);
 
-        // Remove from user's switches
-        USER_SWITCHES.with(|user_switches| {
-            if let Some(ids) = user_switches.borrow_mut().get_mut(&caller) {
-                ids.retain(|&id| id != switch_id);
-            }
-        });
+    ic_cdk::println!("Cancelled switch {} by {}", switch_id, caller);
 
-        ic_cdk::println!("Cancelled switch {} by {}", switch_id, caller);
+    Ok(())
+}
 
-        Ok(())
-    })
+fn switch_info(switch: DeadManSwitch, now: u64) -> DeadManSwitchInfo {
+    let status = calculate_status(&switch, now);
+    let time_remaining_ns = if switch.triggered {
+        0
+    } else {
+        let deadline = switch.last_checkin.saturating_add(switch.timeout_ns);
+        deadline.saturating_sub(now)
+    };
+    let elapsed_percentage = calculate_elapsed_percentage(&switch, now);
+    let warning_days = active_warning(&switch, now);
+    let (guardian_approvals, guardian_vetoes) = tally_votes(&switch);
+
+    DeadManSwitchInfo {
+        switch,
+        status,
+        time_remaining_ns,
+        elapsed_percentage,
+        warning_days,
+        guardian_approvals,
+        guardian_vetoes,
+    }
 }
 
 /// Get switch info with calculated status
 pub fn get_switch_info(switch_id: u64) -> Option<DeadManSwitchInfo> {
-    let now = time();
-
-    DEAD_MAN_SWITCHES.with(|switches| {
-        switches.borrow().get(&switch_id).map(|switch| {
-            let status = calculate_status(switch, now);
-            let time_remaining_ns = if switch.triggered {
-                0
-            } else {
-                let deadline = switch.last_checkin.saturating_add(switch.timeout_ns);
-                deadline.saturating_sub(now)
-            };
-            let elapsed_percentage = calculate_elapsed_percentage(switch, now);
-
-            DeadManSwitchInfo {
-                switch: switch.clone(),
-                status,
-                time_remaining_ns,
-                elapsed_percentage,
-            }
-        })
-    })
+    get_switch(switch_id).map(|switch| switch_info(switch, time()))
 }
 
 /// Get all switches for a user
 pub fn get_user_switches(user: Principal) -> Vec<DeadManSwitchInfo> {
     let now = time();
 
-    USER_SWITCHES.with(|user_switches| {
-        let ids = user_switches.borrow().get(&user).cloned().unwrap_or_default();
-
-        DEAD_MAN_SWITCHES.with(|switches| {
-            let switches = switches.borrow();
-            ids.iter()
-                .filter_map(|id| {
-                    switches.get(id).map(|switch| {
-                        let status = calculate_status(switch, now);
-                        let time_remaining_ns = if switch.triggered {
-                            0
-                        } else {
-                            let deadline = switch.last_checkin.saturating_add(switch.timeout_ns);
-                            deadline.saturating_sub(now)
-                        };
-                        let elapsed_percentage = calculate_elapsed_percentage(switch, now);
-
-                        DeadManSwitchInfo {
-                            switch: switch.clone(),
-                            status,
-                            time_remaining_ns,
-                            elapsed_percentage,
-                        }
-                    })
-                })
-                .collect()
-        })
-    })
+    user_switch_ids(&user)
+        .into_iter()
+        .filter_map(get_switch)
+        .map(|switch| switch_info(switch, now))
+        .collect()
 }
 
 /// Get caller's switches
@@ -228,14 +763,13 @@ pub fn get_my_switches() -> Vec<DeadManSwitchInfo> {
 pub fn get_stats() -> DeadManSwitchStats {
     let now = time();
 
-    DEAD_MAN_SWITCHES.with(|switches| {
-        let switches = switches.borrow();
-        let total = switches.len() as u64;
+    with_switches(|switches| {
+        let total = switches.len();
         let mut active = 0u64;
         let mut triggered = 0u64;
         let mut total_value = 0u128;
 
-        for switch in switches.values() {
+        for (_, StoredSwitch(switch)) in switches.iter() {
             total_value += switch.amount;
             if switch.triggered {
                 triggered += 1;
@@ -253,57 +787,525 @@ pub fn get_stats() -> DeadManSwitchStats {
     })
 }
 
-/// Process expired switches and trigger transfers
-/// This should be called periodically by a timer
+fn deadline(switch: &DeadManSwitch) -> u64 {
+    switch.last_checkin.saturating_add(switch.timeout_ns)
+}
+
+fn is_expired(switch: &DeadManSwitch, now: u64) -> bool {
+    !switch.triggered && now > deadline(switch)
+}
+
+/// Guardian veto window after expiry (zero without guardians)
+fn grace_period(switch: &DeadManSwitch) -> u64 {
+    if switch.guardians.is_some() {
+        switch.grace_period_ns.unwrap_or(0)
+    } else {
+        0
+    }
+}
+
+/// When the release starts: after the grace period, or earlier if the
+/// guardians confirmed it
+fn release_start(switch: &DeadManSwitch) -> u64 {
+    let after_grace = deadline(switch).saturating_add(grace_period(switch));
+    match switch.guardian_released_at {
+        Some(released_at) => released_at.min(after_grace),
+        None => after_grace,
+    }
+}
+
+fn is_leg_due(payout: &SwitchPayout, now: u64) -> bool {
+    match payout.status {
+        SwitchPayoutStatus::Pending => payout.release_at <= now && payout.next_attempt_at <= now,
+        SwitchPayoutStatus::Broadcast { .. } => true,
+        SwitchPayoutStatus::Completed | SwitchPayoutStatus::Failed => false,
+    }
+}
+
+/// Whether the processor should look at this switch now
+fn is_payout_due(switch: &DeadManSwitch, now: u64) -> bool {
+    if switch.triggered || now <= release_start(switch) {
+        return false;
+    }
+    match &switch.payouts {
+        None => true,
+        Some(payouts) => payouts.iter().any(|p| is_leg_due(p, now)),
+    }
+}
+
+fn due_switches(now: u64) -> Vec<DeadManSwitch> {
+    let ids: Vec<u64> = SWITCH_SCHEDULE.with(|i| {
+        i.borrow()
+            .as_ref()
+            .map(|map| {
+                map.range(due_key(0, 0)..=due_key(now, u64::MAX))
+                    .filter_map(|(key, _)| key[9..].try_into().ok().map(u64::from_be_bytes))
+                    .collect()
+            })
+            .unwrap_or_default()
+    });
+    ids.into_iter()
+        .filter_map(get_switch)
+        .filter(|s| is_payout_due(s, now))
+        .collect()
+}
+
+/// Whether an on-chain payout is still waiting for confirmations
+fn has_broadcast_in_flight() -> bool {
+    SWITCH_SCHEDULE.with(|i| {
+        i.borrow()
+            .as_ref()
+            .is_some_and(|map| map.range(vec![BROADCAST_IN_FLIGHT]..).next().is_some())
+    })
+}
+
+/// Delay before the next attempt after `attempts` failures
+fn retry_delay_ns(attempts: u32) -> u64 {
+    let shift = attempts.saturating_sub(1).min(16);
+    RETRY_BASE_DELAY_NS.saturating_mul(1 << shift).min(RETRY_MAX_DELAY_NS)
+}
+
+/// Tightest warning threshold already crossed, while the switch is still armed
+fn active_warning(switch: &DeadManSwitch, now: u64) -> Option<u64> {
+    if switch.triggered || is_expired(switch, now) {
+        return None;
+    }
+    let remaining = switch.last_checkin.saturating_add(switch.timeout_ns).saturating_sub(now);
+    switch
+        .warning_days
+        .as_ref()?
+        .iter()
+        .copied()
+        .filter(|days| remaining <= days.saturating_mul(NS_PER_DAY))
+        .min()
+}
+
+/// Holds the processing flag for one run; releasing it on drop means a trap
+/// after an await can't stop every later run
+struct ProcessingGuard;
+
+impl ProcessingGuard {
+    /// Take the flag, or None if a run is already in progress
+    fn acquire() -> Option<Self> {
+        if PROCESSING.with(|p| p.replace(true)) {
+            None
+        } else {
+            Some(ProcessingGuard)
+        }
+    }
+}
+
+impl Drop for ProcessingGuard {
+    fn drop(&mut self) {
+        PROCESSING.with(|p| *p.borrow_mut() = false);
+    }
+}
+
+/// Process expired switches and pay out their beneficiaries
+///
+/// Runs from the timer (see `start_switch_processor`); returns the IDs whose
+/// payouts all became final during this run.
 pub async fn process_expired_switches() -> Vec<u64> {
-    let now = time();
+    let Some(_guard) = ProcessingGuard::acquire() else {
+        return vec![];
+    };
+
+    // On-chain payouts spend the canister's UTXOs, so only one is in flight at a time
+    let mut btc_busy = has_broadcast_in_flight();
     let mut triggered_ids = Vec::new();
 
-    // Find all expired switches
-    let expired_switches: Vec<DeadManSwitch> = DEAD_MAN_SWITCHES.with(|switches| {
-        switches.borrow()
-            .values()
-            .filter(|s| !s.triggered && now > s.last_checkin.saturating_add(s.timeout_ns))
-            .cloned()
-            .collect()
-    });
+    for switch in due_switches(time()) {
+        if advance_payouts(switch.id, &mut btc_busy).await {
+            triggered_ids.push(switch.id);
+        }
+    }
+    triggered_ids
+}
+
+/// Move a switch's due payouts forward; returns true once all are final
+async fn advance_payouts(switch_id: u64, btc_busy: &mut bool) -> bool {
+    let Some(mut switch) = get_switch(switch_id) else {
+        return false;
+    };
+
+    // Plan the payouts on first sight; this also blocks check-in and cancel
+    if switch.payouts.is_none() {
+        let amount = switch
+            .locked_amount
+            .unwrap_or_else(|| u64::try_from(switch.amount).unwrap_or(u64::MAX));
+        switch.payouts = Some(plan_payouts(
+            amount,
+            &switch_beneficiaries(&switch),
+            &switch_stages(&switch),
+            release_start(&switch),
+        ));
+        store_switch(&switch);
+    }
+
+    let leg_count = switch.payouts.as_ref().map(|p| p.len()).unwrap_or(0);
+    for idx in 0..leg_count {
+        let now = time();
+        let Some(leg) = switch.payouts.as_mut().and_then(|p| p.get_mut(idx)) else {
+            break;
+        };
+        if !is_leg_due(leg, now) {
+            continue;
+        }
+
+        // Waiting on confirmations is not an attempt of its own
+        let waiting = matches!(leg.status, SwitchPayoutStatus::Broadcast { .. });
+        if !waiting {
+            if *btc_busy && matches!(parse_beneficiary(&leg.beneficiary), Some(Beneficiary::Bitcoin(_))) {
+                continue;
+            }
+            // Record the attempt up front so a trap can't cause a tight retry loop
+            leg.attempts += 1;
+            leg.next_attempt_at = now.saturating_add(retry_delay_ns(leg.attempts));
+            store_switch(&switch);
+        }
 
-    for switch in expired_switches {
-        // Execute the transfer
-        match execute_transfer(&switch).await {
-            Ok(_) => {
-                // Mark as triggered
-                DEAD_MAN_SWITCHES.with(|switches| {
-                    if let Some(s) = switches.borrow_mut().get_mut(&switch.id) {
-                        s.triggered = true;
+        let result = execute_transfer(&mut switch, idx).await;
+
+        // Nothing else touches a switch once its payouts are planned
+        let Some(leg) = switch.payouts.as_mut().and_then(|p| p.get_mut(idx)) else {
+            break;
+        };
+        match apply_transfer_result(leg, &result, time()) {
+            LegUpdate::Completed => logging::log_info(
+                "dead_man_switch",
+                format!(
+                    "Switch {} paid out {} {} to {}",
+                    switch.id, leg.amount, switch.rune_id, leg.beneficiary
+                ),
+                None,
+            ),
+            LegUpdate::Waiting => {
+                if let SwitchPayoutStatus::Broadcast { txid } = &leg.status {
+                    *btc_busy = true;
+                    if let Some(e) = &leg.last_error {
+                        logging::log_warn(
+                            "dead_man_switch",
+                            format!(
+                                "Switch {} payout to {} ({}) still in flight: {}",
+                                switch.id, leg.beneficiary, txid, e
+                            ),
+                            None,
+                        );
                     }
-                });
-                triggered_ids.push(switch.id);
-                ic_cdk::println!(
-                    "Triggered switch {}: {} {} to {}",
+                }
+            }
+            LegUpdate::Retrying => logging::log_warn(
+                "dead_man_switch",
+                format!(
+                    "Switch {} payout to {} attempt {} failed: {}",
+                    switch.id,
+                    leg.beneficiary,
+                    leg.attempts,
+                    leg.last_error.as_deref().unwrap_or_default()
+                ),
+                None,
+            ),
+            LegUpdate::Failed => logging::log_error(
+                "dead_man_switch",
+                format!(
+                    "Switch {} payout to {} failed after {} attempts: {}",
                     switch.id,
-                    switch.amount,
-                    switch.rune_id,
-                    switch.beneficiary
-                );
+                    leg.beneficiary,
+                    leg.attempts,
+                    leg.last_error.as_deref().unwrap_or_default()
+                ),
+                None,
+            ),
+        }
+        store_switch(&switch);
+    }
+
+    let all_final = switch
+        .payouts
+        .as_ref()
+        .map(|p| p.iter().all(|leg| leg.status == SwitchPayoutStatus::Completed))
+        .unwrap_or(false);
+    if all_final {
+        switch.triggered = true;
+        store_switch(&switch);
+    }
+    all_final
+}
+
+/// Outcome of one payout step for a leg
+#[derive(Debug, PartialEq, Eq)]
+enum LegUpdate {
+    Completed,
+    /// Broadcast and waiting on confirmations
+    Waiting,
+    Retrying,
+    Failed,
+}
+
+/// Apply the result of `execute_transfer` to a leg
+///
+/// A broadcast leg keeps its status and txid on error (e.g. the confirmation
+/// query failed): its transaction may still confirm, so building a new one
+/// could pay the beneficiary twice. Only an admin who knows the transaction
+/// was dropped can put it back in the queue (`reset_dropped_payout`).
+fn apply_transfer_result(
+    leg: &mut SwitchPayout,
+    result: &Result<bool, String>,
+    now: u64,
+) -> LegUpdate {
+    match result {
+        Ok(true) => {
+            leg.status = SwitchPayoutStatus::Completed;
+            leg.last_error = None;
+            leg.completed_at = Some(now);
+            LegUpdate::Completed
+        }
+        Ok(false) => {
+            leg.last_error = None;
+            LegUpdate::Waiting
+        }
+        Err(e) => {
+            leg.last_error = Some(e.clone());
+            if matches!(leg.status, SwitchPayoutStatus::Broadcast { .. }) {
+                LegUpdate::Waiting
+            } else if leg.attempts >= MAX_PAYOUT_ATTEMPTS {
+                leg.status = SwitchPayoutStatus::Failed;
+                LegUpdate::Failed
+            } else {
+                leg.status = SwitchPayoutStatus::Pending;
+                LegUpdate::Retrying
             }
-            Err(e) => {
-                ic_cdk::println!("Failed to trigger switch {}: {}", switch.id, e);
+        }
+    }
+}
+
+/// Re-queue a broadcast payout whose transaction was dropped or replaced (admin)
+///
+/// Only call this once `txid` can no longer confirm; the leg is rebuilt and
+/// broadcast again on the next run.
+pub fn reset_dropped_payout(switch_id: u64, txid: &str) -> Result<(), EngineError> {
+    let mut switch = get_switch(switch_id)
+        .ok_or(EngineError::NotFound("Switch not found".to_string()))?;
+
+    let now = time();
+    let leg = switch
+        .payouts
+        .iter_mut()
+        .flatten()
+        .find(|leg| matches!(&leg.status, SwitchPayoutStatus::Broadcast { txid: t } if t == txid))
+        .ok_or(EngineError::NotFound(format!("No payout in flight with txid {}", txid)))?;
+    leg.status = SwitchPayoutStatus::Pending;
+    leg.last_error = Some(format!("Transaction {} dropped", txid));
+    leg.next_attempt_at = now;
+
+    store_switch(&switch);
+    Ok(())
+}
+
+/// Put a switch's failed payouts back in the queue (admin)
+pub fn retry_payout(switch_id: u64) -> Result<(), EngineError> {
+    let mut switch = get_switch(switch_id)
+        .ok_or(EngineError::NotFound("Switch not found".to_string()))?;
+
+    let now = time();
+    let mut retried = 0;
+    for leg in switch.payouts.iter_mut().flatten() {
+        if leg.status == SwitchPayoutStatus::Failed {
+            leg.status = SwitchPayoutStatus::Pending;
+            leg.attempts = 0;
+            leg.next_attempt_at = now;
+            retried += 1;
+        }
+    }
+    if retried == 0 {
+        return Err(EngineError::InvalidState(
+            "Only failed payouts can be retried".to_string()
+        ));
+    }
+
+    store_switch(&switch);
+    Ok(())
+}
+
+// ============================================================================
+// Guardians
+// ============================================================================
+
+fn vote_key(switch_id: u64, guardian: &Principal) -> Vec<u8> {
+    let mut key = switch_id.to_be_bytes().to_vec();
+    key.extend_from_slice(guardian.as_slice());
+    key
+}
+
+fn switch_votes(switch_id: u64) -> Vec<(Principal, GuardianVote)> {
+    let prefix = switch_id.to_be_bytes();
+    GUARDIAN_VOTES.with(|v| {
+        if let Some(ref votes) = *v.borrow() {
+            votes
+                .range(prefix.to_vec()..)
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                .map(|(key, vote)| (Principal::from_slice(&key[prefix.len()..]), vote))
+                .collect()
+        } else {
+            vec![]
+        }
+    })
+}
+
+fn clear_votes(switch_id: u64) {
+    let keys: Vec<Vec<u8>> = switch_votes(switch_id)
+        .into_iter()
+        .map(|(guardian, _)| vote_key(switch_id, &guardian))
+        .collect();
+    GUARDIAN_VOTES.with(|v| {
+        if let Some(ref mut votes) = *v.borrow_mut() {
+            for key in keys {
+                votes.remove(&key);
             }
         }
+    });
+}
+
+/// (approvals, vetoes) from the switch's current guardians
+fn tally_votes(switch: &DeadManSwitch) -> (u32, u32) {
+    let Some(guardians) = &switch.guardians else {
+        return (0, 0);
+    };
+    switch_votes(switch.id)
+        .into_iter()
+        .filter(|(guardian, _)| guardians.contains(guardian))
+        .fold((0, 0), |(approvals, vetoes), (_, vote)| {
+            if vote.approve {
+                (approvals + 1, vetoes)
+            } else {
+                (approvals, vetoes + 1)
+            }
+        })
+}
+
+/// What a guardian vote changed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum VoteOutcome {
+    Recorded,
+    Released,
+    Vetoed,
+}
+
+/// Record a guardian's vote and apply the outcome once the threshold is met
+///
+/// Approvals can come at any time before the release starts and, at the
+/// threshold, release the switch early. Vetoes are only accepted during the
+/// grace period after expiry and, at the threshold, re-arm the switch as if
+/// the owner had checked in.
+fn apply_vote(switch_id: u64, guardian: Principal, approve: bool, now: u64) -> Result<VoteOutcome, EngineError> {
+    let mut switch = get_switch(switch_id)
+        .ok_or(EngineError::NotFound("Switch not found".to_string()))?;
+
+    if !switch.guardians.as_ref().is_some_and(|g| g.contains(&guardian)) {
+        return Err(EngineError::Unauthorized(
+            "Only guardians of this switch can vote".to_string()
+        ));
     }
 
-    triggered_ids
+    if switch.triggered || switch.payouts.is_some() {
+        return Err(EngineError::InvalidState(
+            "Switch release has already started".to_string()
+        ));
+    }
+
+    let deadline = deadline(&switch);
+    let in_grace = now > deadline && now <= deadline.saturating_add(grace_period(&switch));
+    if !approve && !in_grace {
+        return Err(EngineError::InvalidState(
+            "Vetoes are only accepted during the grace period after expiry".to_string()
+        ));
+    }
+
+    GUARDIAN_VOTES.with(|v| {
+        if let Some(ref mut votes) = *v.borrow_mut() {
+            votes.insert(vote_key(switch_id, &guardian), GuardianVote { approve, voted_at: now });
+        }
+    });
+
+    let threshold = switch.guardian_threshold.unwrap_or(1) as u32;
+    let (approvals, vetoes) = tally_votes(&switch);
+
+    if approve && approvals >= threshold && switch.guardian_released_at.is_none() {
+        switch.guardian_released_at = Some(now);
+        store_switch(&switch);
+        return Ok(VoteOutcome::Released);
+    }
+    if !approve && vetoes >= threshold {
+        switch.last_checkin = now;
+        switch.guardian_released_at = None;
+        store_switch(&switch);
+        clear_votes(switch_id);
+        return Ok(VoteOutcome::Vetoed);
+    }
+
+    Ok(VoteOutcome::Recorded)
+}
+
+/// Vote as a guardian: `approve` confirms release, otherwise vetoes it
+pub fn guardian_vote(switch_id: u64, approve: bool) -> Result<(), EngineError> {
+    match apply_vote(switch_id, ic_cdk::caller(), approve, time())? {
+        VoteOutcome::Released => logging::log_info(
+            "dead_man_switch",
+            format!("Guardians confirmed early release of switch {}", switch_id),
+            None,
+        ),
+        VoteOutcome::Vetoed => logging::log_info(
+            "dead_man_switch",
+            format!("Guardians vetoed release of switch {}; re-armed", switch_id),
+            None,
+        ),
+        VoteOutcome::Recorded => {}
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Beneficiary access
+// ============================================================================
+
+/// Owner of a switch, if it exists
+pub fn switch_owner(switch_id: u64) -> Option<Principal> {
+    get_switch(switch_id).map(|s| s.owner)
+}
+
+/// Rune a switch locks, if it exists
+pub fn switch_rune(switch_id: u64) -> Option<String> {
+    get_switch(switch_id).map(|s| s.rune_id)
+}
+
+/// Whether `principal` is one of the switch's principal beneficiaries
+pub fn is_beneficiary(switch_id: u64, principal: Principal) -> bool {
+    get_switch(switch_id)
+        .map(|switch| {
+            switch_beneficiaries(&switch)
+                .iter()
+                .any(|b| parse_beneficiary(&b.beneficiary) == Some(Beneficiary::Principal(principal)))
+        })
+        .unwrap_or(false)
+}
+
+/// Whether the switch has fired and `principal` is one of its beneficiaries
+///
+/// A switch has fired once its payouts are planned, so beneficiaries get
+/// access at the start of the release rather than after the last stage.
+pub fn is_released_to(switch_id: u64, principal: Principal) -> bool {
+    let fired = get_switch(switch_id)
+        .map(|s| s.triggered || s.payouts.is_some())
+        .unwrap_or(false);
+    fired && is_beneficiary(switch_id, principal)
 }
 
 /// Check if there are any expired switches
 pub fn has_expired_switches() -> bool {
     let now = time();
 
-    DEAD_MAN_SWITCHES.with(|switches| {
-        switches.borrow()
-            .values()
-            .any(|s| !s.triggered && now > s.last_checkin.saturating_add(s.timeout_ns))
+    with_switches(|switches| {
+        switches.iter().any(|(_, StoredSwitch(s))| is_expired(&s, now))
     })
 }
 
@@ -311,7 +1313,7 @@ pub fn has_expired_switches() -> bool {
 fn calculate_status(switch: &DeadManSwitch, now: u64) -> SwitchStatus {
     if switch.triggered {
         SwitchStatus::Triggered
-    } else if now > switch.last_checkin.saturating_add(switch.timeout_ns) {
+    } else if now > deadline(switch) || switch.guardian_released_at.is_some() {
         SwitchStatus::Expired
     } else {
         SwitchStatus::Active
@@ -329,100 +1331,172 @@ fn calculate_elapsed_percentage(switch: &DeadManSwitch, now: u64) -> u8 {
     std::cmp::min(percentage as u8, 100)
 }
 
-/// Execute the transfer when switch is triggered
+/// Execute one planned payout of an expired switch
 ///
-/// This integrates with the bitcoin-integration canister to:
-/// 1. Build a Runestone transaction with transfer Edicts
-/// 2. Sign using threshold Schnorr (tECDSA)
-/// 3. Broadcast to Bitcoin network
-/// 4. Return the Bitcoin transaction ID
-async fn execute_transfer(switch: &DeadManSwitch) -> Result<String, String> {
-    ic_cdk::println!(
-        "⚙️  Executing Dead Man's Switch #{}\n\
-         - Rune: {}\n\
-         - Amount: {}\n\
-         - Beneficiary: {}\n\
-         - Message: {:?}",
-        switch.id,
-        switch.rune_id,
-        switch.amount,
-        switch.beneficiary,
-        switch.message
-    );
-
-    // Get bitcoin-integration canister ID from config
-    let bitcoin_integration_id = crate::get_bitcoin_integration_id()
-        .map_err(|e| format!("Bitcoin integration not configured: {}", e))?;
-
-    // Build the Runestone transfer transaction
-    //
-    // A Runestone transfer uses OP_RETURN with Edicts:
-    // ```
-    // Edict {
-    //     rune_id: RuneId,      // Which Rune to transfer
-    //     amount: u128,         // How many units
-    //     output: u32,          // Which output receives it (1 = beneficiary)
-    // }
-    // ```
-    //
-    // The transaction structure:
-    // ```
-    // Input 0:  [Canister's UTXO with Runes] -> Signed with threshold Schnorr
-    // Output 0: [OP_RETURN with Runestone]   -> Contains transfer Edict
-    // Output 1: [Beneficiary address]        -> Receives the Runes
-    // Output 2: [Change back to canister]    -> Remaining Runes + BTC
-    // ```
-
-    // IMPLEMENTATION NOTE:
-    // For the hackathon demo, we demonstrate the integration pattern but don't
-    // implement the full Bitcoin settlement due to complexity. The full implementation
-    // would require:
-    //
-    // 1. Query UTXO set for the owner's address (via bitcoin-integration)
-    // 2. Build Runestone with transfer Edict
-    // 3. Create Bitcoin transaction with OP_RETURN output
-    // 4. Sign with threshold Schnorr via management canister
-    // 5. Broadcast via Hiro API
-    //
-    // This is demonstrated in the etching flow (etching_flow.rs) and can be adapted
-    // for transfers.
-
-    ic_cdk::println!(
-        "📞 Calling bitcoin-integration canister: {}",
-        bitcoin_integration_id
-    );
-
-    // Log the planned transfer for monitoring and debugging
-    ic_cdk::println!(
-        "🔗 Dead Man's Switch transfer planned:\n\
-         - From: Canister-controlled address\n\
-         - To: {}\n\
-         - Rune: {}\n\
-         - Amount: {}\n\
-         - Estimated fees: ~1000 sats",
-        switch.beneficiary,
-        switch.rune_id,
-        switch.amount
-    );
-
-    // For hackathon: Return a deterministic transaction ID
-    // In production: This would be the actual Bitcoin txid from broadcast
-    let mock_txid = format!(
-        "{}{}",
-        "dms", // Dead Man's Switch prefix
-        hex::encode(switch.id.to_le_bytes())
-    );
-
-    ic_cdk::println!(
-        "✅ Dead Man's Switch #{} executed successfully\n\
-         - Mock TX ID: {}\n\
-         - Status: Logged for settlement\n\
-         - Next: Manual verification required",
-        switch.id,
-        mock_txid
-    );
-
-    Ok(mock_txid)
+/// Principal beneficiaries are paid from the owner's locked virtual balance in
+/// one step. Bitcoin beneficiaries get an edict transfer built and signed by
+/// bitcoin-integration; the locked balance is only released to custody once
+/// that transaction has the configured number of confirmations.
+///
+/// Returns `Ok(true)` when the payout is final, `Ok(false)` while waiting.
+async fn execute_transfer(switch: &mut DeadManSwitch, idx: usize) -> Result<bool, String> {
+    let leg = switch
+        .payouts
+        .as_ref()
+        .and_then(|p| p.get(idx))
+        .cloned()
+        .ok_or_else(|| format!("Switch {} has no payout {}", switch.id, idx))?;
+    let beneficiary = parse_beneficiary(&leg.beneficiary)
+        .ok_or_else(|| format!("Invalid beneficiary: {}", leg.beneficiary))?;
+
+    // Switches from before payouts existed never locked their amount
+    if switch.locked_amount.is_none() {
+        let amount = u64::try_from(switch.amount)
+            .map_err(|_| "Amount exceeds the maximum virtual balance".to_string())?;
+        balances::lock_balance(switch.owner, &switch.rune_id, amount, Some(switch_reference(switch.id)))?;
+        switch.locked_amount = Some(amount);
+        store_switch(switch);
+    }
+
+    // The payout must still be covered by the owner's locked balance
+    let locked = balances::get_balance(switch.owner, &switch.rune_id).locked;
+    if locked < leg.amount {
+        return Err(format!(
+            "Owner's locked balance {} does not cover payout of {}",
+            locked, leg.amount
+        ));
+    }
+    let asset = Asset::Rune(switch.rune_id.clone());
+
+    match beneficiary {
+        Beneficiary::Principal(beneficiary) => {
+            accounting::post(
+                asset,
+                LedgerAccount::Locked(switch.owner),
+                LedgerAccount::Available(beneficiary),
+                leg.amount,
+                EntryReason::Transfer,
+                Some(switch_reference(switch.id)),
+            )?;
+            Ok(true)
+        }
+        Beneficiary::Bitcoin(address) => {
+            let bitcoin_integration_id = crate::get_bitcoin_integration_id()
+                .map_err(|e| format!("Bitcoin integration not configured: {}", e))?;
+            let etching_config = crate::config::get_etching_config();
+
+            if let SwitchPayoutStatus::Broadcast { txid } = &leg.status {
+                let (confirmations,): (Result<u32, String>,) =
+                    ic_cdk::call(bitcoin_integration_id, "get_confirmations", (txid.clone(),))
+                        .await
+                        .map_err(|(code, msg)| format!("get_confirmations failed: {:?} - {}", code, msg))?;
+                if confirmations? < etching_config.required_confirmations {
+                    return Ok(false);
+                }
+
+                // The runes left the canister's UTXOs; retire the virtual side
+                accounting::post(
+                    asset,
+                    LedgerAccount::Locked(switch.owner),
+                    LedgerAccount::Custody,
+                    leg.amount,
+                    EntryReason::Withdrawal,
+                    Some(switch_reference(switch.id)),
+                )?;
+                return Ok(true);
+            }
+
+            let onchain_id = crate::state::get_virtual_rune(&switch.rune_id)
+                .and_then(|rune| rune.onchain_id)
+                .ok_or_else(|| format!("Rune {} has no on-chain ID yet", switch.rune_id))?;
+
+            let (signed,): (Result<Vec<u8>, String>,) = ic_cdk::call(
+                bitcoin_integration_id,
+                "build_and_sign_rune_transfer_tx",
+                (onchain_id, leg.amount as u128, address, etching_config.fee_rate),
+            )
+            .await
+            .map_err(|(code, msg)| format!("build_and_sign_rune_transfer_tx failed: {:?} - {}", code, msg))?;
+
+            let (broadcast,): (Result<String, String>,) = ic_cdk::call(
+                bitcoin_integration_id,
+                "broadcast_and_track",
+                (signed?, etching_config.required_confirmations),
+            )
+            .await
+            .map_err(|(code, msg)| format!("broadcast_and_track failed: {:?} - {}", code, msg))?;
+            let txid = broadcast?;
+
+            if let Some(leg) = switch.payouts.as_mut().and_then(|p| p.get_mut(idx)) {
+                leg.status = SwitchPayoutStatus::Broadcast { txid };
+            }
+            Ok(false)
+        }
+    }
+}
+
+// ============================================================================
+// Timer
+// ============================================================================
+
+/// Start the periodic payout processor
+pub fn start_switch_processor() {
+    stop_switch_processor();
+
+    let timer_id = ic_cdk_timers::set_timer_interval(Duration::from_secs(PROCESS_INTERVAL_SECS), || {
+        ic_cdk::spawn(async {
+            let triggered = process_expired_switches().await;
+            if !triggered.is_empty() {
+                ic_cdk::println!("Triggered {} Dead Man's Switches", triggered.len());
+            }
+        });
+    });
+
+    TIMER_ID.with(|t| *t.borrow_mut() = Some(timer_id));
+}
+
+/// Stop the payout processor
+pub fn stop_switch_processor() {
+    TIMER_ID.with(|t| {
+        if let Some(timer_id) = t.borrow_mut().take() {
+            ic_cdk_timers::clear_timer(timer_id);
+        }
+    });
+}
+
+/// Store an armed switch paying `beneficiary` (tests in other modules)
+#[cfg(test)]
+pub(crate) fn store_test_switch(id: u64, owner: Principal, beneficiary: Principal) {
+    store_switch(&DeadManSwitch {
+        id,
+        owner,
+        beneficiary: beneficiary.to_text(),
+        rune_id: "TEST".to_string(),
+        amount: 1000,
+        last_checkin: 0,
+        timeout_ns: 100,
+        triggered: false,
+        created_at: 0,
+        message: None,
+        locked_amount: Some(1000),
+        beneficiaries: None,
+        stages: None,
+        warning_days: None,
+        payouts: None,
+        guardians: None,
+        guardian_threshold: None,
+        grace_period_ns: None,
+        guardian_released_at: None,
+    });
+}
+
+/// Mark a stored switch as fired (tests in other modules)
+#[cfg(test)]
+pub(crate) fn fire_test_switch(id: u64) {
+    if let Some(mut switch) = get_switch(id) {
+        switch.payouts = Some(vec![]);
+        store_switch(&switch);
+    }
 }
 
 #[cfg(test)]
@@ -442,6 +1516,15 @@ mod tests {
             triggered: false,
             created_at: 0,
             message: None,
+            locked_amount: None,
+            beneficiaries: None,
+            stages: None,
+            warning_days: None,
+            payouts: None,
+            guardians: None,
+            guardian_threshold: None,
+            grace_period_ns: None,
+            guardian_released_at: None,
         };
 
         // 0% elapsed
@@ -470,6 +1553,15 @@ mod tests {
             triggered: false,
             created_at: 0,
             message: None,
+            locked_amount: None,
+            beneficiaries: None,
+            stages: None,
+            warning_days: None,
+            payouts: None,
+            guardians: None,
+            guardian_threshold: None,
+            grace_period_ns: None,
+            guardian_released_at: None,
         };
 
         // Active
@@ -482,4 +1574,334 @@ mod tests {
         switch.triggered = true;
         assert_eq!(calculate_status(&switch, 0), SwitchStatus::Triggered);
     }
+
+    #[test]
+    fn test_retry_delay_backoff() {
+        assert_eq!(retry_delay_ns(1), RETRY_BASE_DELAY_NS);
+        assert_eq!(retry_delay_ns(2), RETRY_BASE_DELAY_NS * 2);
+        assert_eq!(retry_delay_ns(4), RETRY_BASE_DELAY_NS * 8);
+        // Capped at a day
+        assert_eq!(retry_delay_ns(9), RETRY_MAX_DELAY_NS);
+        assert_eq!(retry_delay_ns(u32::MAX), RETRY_MAX_DELAY_NS);
+    }
+
+    #[test]
+    fn test_payout_due_and_beneficiary() {
+        let owner = Principal::from_slice(&[1; 29]);
+        let mut switch = DeadManSwitch {
+            id: 1,
+            owner,
+            beneficiary: Principal::from_slice(&[2; 29]).to_text(),
+            rune_id: "TEST".to_string(),
+            amount: 1000,
+            last_checkin: 0,
+            timeout_ns: 100,
+            triggered: false,
+            created_at: 0,
+            message: None,
+            locked_amount: Some(1000),
+            beneficiaries: None,
+            stages: None,
+            warning_days: None,
+            payouts: None,
+            guardians: None,
+            guardian_threshold: None,
+            grace_period_ns: None,
+            guardian_released_at: None,
+        };
+
+        assert_eq!(
+            parse_beneficiary(&switch.beneficiary),
+            Some(Beneficiary::Principal(Principal::from_slice(&[2; 29])))
+        );
+        assert_eq!(parse_beneficiary("not an address"), None);
+
+        // Not expired yet
+        assert!(!is_payout_due(&switch, 50));
+        assert!(is_payout_due(&switch, 200));
+
+        // Backing off
+        let mut leg = plan_payouts(1000, &switch_beneficiaries(&switch), &switch_stages(&switch), 100).remove(0);
+        leg.attempts = 1;
+        leg.next_attempt_at = 500;
+        switch.payouts = Some(vec![leg]);
+        assert!(!is_payout_due(&switch, 200));
+        assert!(is_payout_due(&switch, 500));
+
+        // Waiting on confirmations is always re-checked
+        switch.payouts.as_mut().unwrap()[0].status = SwitchPayoutStatus::Broadcast { txid: "ab".to_string() };
+        assert!(is_payout_due(&switch, 200));
+
+        switch.payouts.as_mut().unwrap()[0].status = SwitchPayoutStatus::Failed;
+        assert!(!is_payout_due(&switch, 1_000));
+
+        switch.triggered = true;
+        switch.payouts.as_mut().unwrap()[0].status = SwitchPayoutStatus::Completed;
+        assert!(!is_payout_due(&switch, 1_000));
+    }
+
+    #[test]
+    fn test_plan_staged_split() {
+        let beneficiaries = vec![
+            SwitchBeneficiary { beneficiary: "a".to_string(), share_bps: 3_333 },
+            SwitchBeneficiary { beneficiary: "b".to_string(), share_bps: 6_667 },
+        ];
+        let stages = vec![
+            ReleaseStage { delay_days: 90, release_bps: 7_500 },
+            ReleaseStage { delay_days: 0, release_bps: 2_500 },
+        ];
+
+        let payouts = plan_payouts(1_001, &beneficiaries, &stages, 5);
+        assert_eq!(payouts.len(), 4);
+
+        // Released in order: 25% at expiry, the rest 90 days later
+        assert_eq!(payouts[0].release_at, 5);
+        assert_eq!(payouts[2].release_at, 5 + 90 * NS_PER_DAY);
+        let at_expiry: u64 = payouts[..2].iter().map(|p| p.amount).sum();
+        assert_eq!(at_expiry, 250);
+
+        // Rounding remainders never lose units
+        assert_eq!(payouts.iter().map(|p| p.amount).sum::<u64>(), 1_001);
+        assert_eq!(split_bps(10, &[3_333, 3_333, 3_334]), vec![3, 3, 4]);
+
+        assert!(validate_stages(&stages).is_ok());
+        assert!(validate_stages(&stages[..1]).is_err());
+    }
+
+    #[test]
+    fn test_active_warning() {
+        let mut switch = DeadManSwitch {
+            id: 1,
+            owner: Principal::anonymous(),
+            beneficiary: "bc1q...".to_string(),
+            rune_id: "TEST".to_string(),
+            amount: 1000,
+            last_checkin: 0,
+            timeout_ns: 30 * NS_PER_DAY,
+            triggered: false,
+            created_at: 0,
+            message: None,
+            locked_amount: Some(1000),
+            beneficiaries: None,
+            stages: None,
+            warning_days: Some(vec![7, 1]),
+            payouts: None,
+            guardians: None,
+            guardian_threshold: None,
+            grace_period_ns: None,
+            guardian_released_at: None,
+        };
+
+        assert_eq!(active_warning(&switch, 10 * NS_PER_DAY), None);
+        assert_eq!(active_warning(&switch, 25 * NS_PER_DAY), Some(7));
+        assert_eq!(active_warning(&switch, 29 * NS_PER_DAY + 1), Some(1));
+        // No warning once expired
+        assert_eq!(active_warning(&switch, 31 * NS_PER_DAY), None);
+
+        switch.warning_days = None;
+        assert_eq!(active_warning(&switch, 29 * NS_PER_DAY + 1), None);
+    }
+
+    #[test]
+    fn test_guardian_votes() {
+        use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        init_switch_storage(
+            manager.get(MemoryId::new(0)),
+            manager.get(MemoryId::new(1)),
+            manager.get(MemoryId::new(2)),
+            manager.get(MemoryId::new(3)),
+            manager.get(MemoryId::new(4)),
+        );
+
+        let guardians: Vec<Principal> = (1..=3).map(|i| Principal::from_slice(&[i; 29])).collect();
+        let outsider = Principal::from_slice(&[9; 29]);
+        for id in [1, 2] {
+            store_switch(&DeadManSwitch {
+                id,
+                owner: Principal::from_slice(&[7; 29]),
+                beneficiary: "bc1q...".to_string(),
+                rune_id: "TEST".to_string(),
+                amount: 1000,
+                last_checkin: 0,
+                timeout_ns: 100,
+                triggered: false,
+                created_at: 0,
+                message: None,
+                locked_amount: Some(1000),
+                beneficiaries: None,
+                stages: None,
+                warning_days: None,
+                payouts: None,
+                guardians: Some(guardians.clone()),
+                guardian_threshold: Some(2),
+                grace_period_ns: Some(100),
+                guardian_released_at: None,
+            });
+        }
+
+        // Grace period holds the release back
+        assert_eq!(release_start(&get_switch(1).unwrap()), 200);
+
+        assert!(matches!(apply_vote(1, outsider, true, 10), Err(EngineError::Unauthorized(_))));
+        assert!(matches!(apply_vote(1, guardians[0], false, 10), Err(EngineError::InvalidState(_))));
+
+        // 2-of-3 approvals release early
+        assert_eq!(apply_vote(1, guardians[0], true, 10).unwrap(), VoteOutcome::Recorded);
+        assert_eq!(apply_vote(1, guardians[1], true, 20).unwrap(), VoteOutcome::Released);
+        let switch = get_switch(1).unwrap();
+        assert_eq!(release_start(&switch), 20);
+        assert_eq!(tally_votes(&switch), (2, 0));
+        assert!(is_payout_due(&switch, 21));
+
+        // 2-of-3 vetoes in the grace period re-arm the switch
+        assert!(!is_payout_due(&get_switch(2).unwrap(), 150));
+        assert_eq!(apply_vote(2, guardians[0], false, 150).unwrap(), VoteOutcome::Recorded);
+        assert_eq!(apply_vote(2, guardians[2], false, 160).unwrap(), VoteOutcome::Vetoed);
+        let switch = get_switch(2).unwrap();
+        assert_eq!(switch.last_checkin, 160);
+        assert_eq!(tally_votes(&switch), (0, 0));
+        assert!(!is_payout_due(&switch, 250));
+
+        // Vetoes after the grace period are too late
+        assert!(apply_vote(2, guardians[0], false, 400).is_err());
+    }
+
+    #[test]
+    fn test_migration_restores_counter_and_index() {
+        use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        init_switch_storage(
+            manager.get(MemoryId::new(0)),
+            manager.get(MemoryId::new(1)),
+            manager.get(MemoryId::new(2)),
+            manager.get(MemoryId::new(3)),
+            manager.get(MemoryId::new(4)),
+        );
+
+        let alice = Principal::from_slice(&[1; 29]);
+        let bob = Principal::from_slice(&[2; 29]);
+        for (id, owner) in [(1, alice), (2, bob), (5, alice)] {
+            store_switch(&DeadManSwitch {
+                id,
+                owner,
+                beneficiary: "bc1q...".to_string(),
+                rune_id: "TEST".to_string(),
+                amount: 1000,
+                last_checkin: 0,
+                timeout_ns: 100_000_000_000,
+                triggered: false,
+                created_at: 0,
+                message: None,
+                locked_amount: None,
+                beneficiaries: None,
+            stages: None,
+            warning_days: None,
+            payouts: None,
+            guardians: None,
+            guardian_threshold: None,
+            grace_period_ns: None,
+            guardian_released_at: None,
+            });
+        }
+        index_switch(&bob, 2);
+
+        assert_eq!(migrate_switch_storage(), 2);
+        assert_eq!(user_switch_ids(&alice), vec![1, 5]);
+        assert_eq!(user_switch_ids(&bob), vec![2]);
+        assert_eq!(SWITCH_COUNTER.with(|c| *c.borrow().as_ref().unwrap().get()), 5);
+
+        // Idempotent
+        assert_eq!(migrate_switch_storage(), 0);
+    }
+
+    #[test]
+    fn test_schedule_index_tracks_due_switches() {
+        use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        init_switch_storage(
+            manager.get(MemoryId::new(0)),
+            manager.get(MemoryId::new(1)),
+            manager.get(MemoryId::new(2)),
+            manager.get(MemoryId::new(3)),
+            manager.get(MemoryId::new(4)),
+        );
+        let ids = |now: u64| due_switches(now).iter().map(|s| s.id).collect::<Vec<_>>();
+
+        // Both expire at 100
+        for id in [1, 2] {
+            store_test_switch(id, Principal::from_slice(&[1; 29]), Principal::from_slice(&[2; 29]));
+        }
+        assert_eq!(ids(100), Vec::<u64>::new());
+        assert_eq!(ids(101), vec![1, 2]);
+        assert!(!has_broadcast_in_flight());
+
+        // A backed-off leg moves its switch later; a broadcast one stays due
+        let mut first = get_switch(1).unwrap();
+        let mut leg = plan_payouts(1000, &switch_beneficiaries(&first), &switch_stages(&first), 100).remove(0);
+        leg.next_attempt_at = 500;
+        first.payouts = Some(vec![leg.clone()]);
+        store_switch(&first);
+        let mut second = get_switch(2).unwrap();
+        leg.status = SwitchPayoutStatus::Broadcast { txid: "ab".to_string() };
+        second.payouts = Some(vec![leg]);
+        store_switch(&second);
+        assert_eq!(ids(200), vec![2]);
+        assert_eq!(ids(500), vec![2, 1]);
+        assert!(has_broadcast_in_flight());
+
+        // Final payouts leave the schedule
+        second.payouts.as_mut().unwrap()[0].status = SwitchPayoutStatus::Completed;
+        second.triggered = true;
+        store_switch(&second);
+        assert_eq!(ids(u64::MAX), vec![1]);
+        assert!(!has_broadcast_in_flight());
+
+        // An empty index is rebuilt by the migration (plus the two owner-index
+        // entries the test helper never wrote)
+        SWITCH_SCHEDULE.with(|i| *i.borrow_mut() = Some(StableBTreeMap::new(manager.get(MemoryId::new(5)))));
+        assert_eq!(migrate_switch_storage(), 3);
+        assert_eq!(ids(500), vec![1]);
+
+        // The run flag is released when the guard drops
+        let guard = ProcessingGuard::acquire().unwrap();
+        assert!(ProcessingGuard::acquire().is_none());
+        drop(guard);
+        assert!(ProcessingGuard::acquire().is_some());
+    }
+
+    #[test]
+    fn test_broadcast_leg_survives_confirmation_error() {
+        let mut leg = SwitchPayout {
+            beneficiary: "bc1qexample".to_string(),
+            amount: 100,
+            release_at: 0,
+            status: SwitchPayoutStatus::Broadcast { txid: "abc".to_string() },
+            attempts: MAX_PAYOUT_ATTEMPTS,
+            next_attempt_at: 0,
+            last_error: None,
+            completed_at: None,
+        };
+
+        // A failed confirmation query keeps the txid and never fails the leg
+        let err = Err("get_confirmations failed".to_string());
+        let update = apply_transfer_result(&mut leg, &err, 1);
+        assert_eq!(update, LegUpdate::Waiting);
+        assert_eq!(leg.status, SwitchPayoutStatus::Broadcast { txid: "abc".to_string() });
+        assert_eq!(leg.attempts, MAX_PAYOUT_ATTEMPTS);
+        assert!(leg.last_error.is_some());
+        assert!(is_leg_due(&leg, 1));
+
+        assert_eq!(apply_transfer_result(&mut leg, &Ok(true), 2), LegUpdate::Completed);
+        assert_eq!(leg.completed_at, Some(2));
+
+        // A pending leg at the cap does fail
+        leg.status = SwitchPayoutStatus::Pending;
+        let err = Err("build failed".to_string());
+        assert_eq!(apply_transfer_result(&mut leg, &err, 3), LegUpdate::Failed);
+        assert_eq!(leg.status, SwitchPayoutStatus::Failed);
+    }
 }
@@ -1,18 +1,38 @@
 //! Encrypted Metadata Module (vetKeys)
 //!
 //! Provides encrypted storage for Rune metadata using ICP's vetKeys system.
-//! Metadata can be time-locked for reveals.
+//! A rune can carry several named, versioned items. Each item can be
+//! time-locked for reveals, shared with listed readers or gated to holders of
+//! the rune, or attached to a Dead Man's Switch so its beneficiaries can
+//! decrypt it once the switch fires.
+//!
+//! Items live in stable memory. Payloads larger than one message are uploaded
+//! in chunks (`begin_upload` → `put_chunk` … → `commit_upload`), checked
+//! against their SHA-256 and read back with `get_chunk`. Stored bytes count
+//! against per-user and per-rune quotas.
 //!
 //! Key feature for ICP Bitcoin DeFi Hackathon.
 
 use candid::{CandidType, Deserialize, Principal};
 use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::VirtualMemory;
+use ic_stable_structures::{storable::Bound, DefaultMemoryImpl, StableBTreeMap, StableCell, Storable};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::BTreeMap;
 
-use quri_types::{EncryptedRuneMetadata, StoreEncryptedMetadataParams};
+use quri_types::{
+    BeginUploadParams, EncryptedRuneMetadata, MetadataBlob, StoreEncryptedMetadataParams, UploadInfo,
+};
 
+use crate::accounting::{self, Asset};
+use crate::dead_man_switch;
 use crate::errors::EngineError;
+use crate::state;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+type MetadataMap = StableBTreeMap<Vec<u8>, StoredMetadata, Memory>;
+type ChunkMap = StableBTreeMap<Vec<u8>, Vec<u8>, Memory>;
 
 // vetKD API types (not yet in stable ic-cdk)
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -46,86 +66,618 @@ pub struct VetKDKeyId {
     pub name: String,
 }
 
+/// Named as in the management canister interface
 #[derive(CandidType, Deserialize, Clone, Debug)]
+#[allow(non_camel_case_types)]
 pub enum VetKDCurve {
     #[serde(rename = "bls12_381_g2")]
     Bls12_381_G2,
 }
 
-// Thread-local storage for encrypted metadata
-thread_local! {
-    static ENCRYPTED_METADATA: RefCell<BTreeMap<String, EncryptedRuneMetadata>> = const { RefCell::new(BTreeMap::new()) };
-}
+/// Item used by callers that don't name one
+pub const DEFAULT_ITEM_ID: &str = "default";
+
+const MAX_ITEM_ID_LEN: usize = 64;
+const MAX_READERS: usize = 50;
+
+/// Versions kept per item; the oldest are dropped beyond this
+const MAX_VERSIONS: usize = 16;
+
+/// Size of every upload chunk except the last
+pub const CHUNK_SIZE: u64 = 1024 * 1024;
+
+const MAX_UPLOAD_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Bytes a user may store across all runes (including uploads in progress)
+const USER_QUOTA_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Bytes stored for a single rune (or a single switch's notes) across all users
+const RUNE_QUOTA_BYTES: u64 = 128 * 1024 * 1024;
+
+const MAX_OPEN_UPLOADS: u64 = 4;
+
+/// Expired uploads discarded per call
+const MAX_UPLOAD_CLEANUP: usize = 50;
+
+/// Uploads not committed within a day are discarded
+const UPLOAD_TTL_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
 
 // vetKD key configuration
 const VETKD_KEY_NAME: &str = "quri_metadata_key";
 
-/// Store encrypted metadata for a Rune
-pub fn store_metadata(params: StoreEncryptedMetadataParams) -> Result<(), EngineError> {
-    let caller = ic_cdk::caller();
+/// Encoded metadata version
+#[derive(Clone, Debug)]
+struct StoredMetadata(EncryptedRuneMetadata);
+
+impl Storable for StoredMetadata {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(&self.0).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to encode EncryptedRuneMetadata: {}", e))
+        }))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self(candid::decode_one(&bytes).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to decode EncryptedRuneMetadata: {}", e))
+        }))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// A chunked upload in progress; becomes an item version on commit
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct UploadSession {
+    owner: Principal,
+    rune_id: String,
+    item_id: String,
+    size: u64,
+    chunk_count: u32,
+    sha256: Vec<u8>,
+    nonce: Vec<u8>,
+    reveal_time: Option<u64>,
+    switch_id: Option<u64>,
+    readers: Vec<Principal>,
+    min_holding: Option<u64>,
+    received: u32,
+    expires_at: u64,
+}
+
+impl Storable for UploadSession {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to encode UploadSession: {}", e))
+        }))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to decode UploadSession: {}", e))
+        })
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Stable storage for encrypted metadata (survives upgrades)
+thread_local! {
+    /// Item versions keyed by (rune, item, version)
+    static METADATA: RefCell<Option<MetadataMap>> = const { RefCell::new(None) };
+    static UPLOADS: RefCell<Option<StableBTreeMap<u64, UploadSession, Memory>>> = const { RefCell::new(None) };
+    /// Chunks keyed by (upload/blob id, index); a committed upload keeps its id as blob id
+    static CHUNKS: RefCell<Option<ChunkMap>> = const { RefCell::new(None) };
+    static UPLOAD_COUNTER: RefCell<Option<StableCell<u64, Memory>>> = const { RefCell::new(None) };
+    /// Stored bytes per user (tag 0), per rune (tag 1) and per switch (tag 2)
+    static USAGE: RefCell<Option<StableBTreeMap<Vec<u8>, u64, Memory>>> = const { RefCell::new(None) };
+}
+
+/// Initialize metadata storage (also restores it after upgrade)
+pub fn init_metadata_storage(
+    metadata_memory: Memory,
+    uploads_memory: Memory,
+    chunks_memory: Memory,
+    counter_memory: Memory,
+    usage_memory: Memory,
+) {
+    METADATA.with(|m| *m.borrow_mut() = Some(StableBTreeMap::init(metadata_memory)));
+    UPLOADS.with(|u| *u.borrow_mut() = Some(StableBTreeMap::init(uploads_memory)));
+    CHUNKS.with(|c| *c.borrow_mut() = Some(StableBTreeMap::init(chunks_memory)));
+    UPLOAD_COUNTER.with(|c| {
+        *c.borrow_mut() = Some(
+            StableCell::init(counter_memory, 0).expect("Failed to initialize upload counter"),
+        );
+    });
+    USAGE.with(|u| *u.borrow_mut() = Some(StableBTreeMap::init(usage_memory)));
+}
+
+// ============================================================================
+// Keys
+// ============================================================================
+
+/// Namespace an item lives in
+///
+/// Switch notes get their own namespace, quota and vetKD derivation so a
+/// switch owner can never claim item IDs, storage or keys of the rune itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Scope<'a> {
+    Rune(&'a str),
+    Switch(u64),
+}
+
+impl<'a> Scope<'a> {
+    fn of(meta: &'a EncryptedRuneMetadata) -> Self {
+        Self::new(&meta.rune_id, meta.switch_id)
+    }
+
+    fn new(rune_id: &'a str, switch_id: Option<u64>) -> Self {
+        match switch_id {
+            Some(switch_id) => Scope::Switch(switch_id),
+            None => Scope::Rune(rune_id),
+        }
+    }
+}
+
+/// Stands in for a rune ID length in switch keys; no rune ID is that long
+const SWITCH_SCOPE_MARKER: u32 = u32::MAX;
+
+fn push_str(key: &mut Vec<u8>, s: &str) {
+    key.extend_from_slice(&(s.len() as u32).to_be_bytes());
+    key.extend_from_slice(s.as_bytes());
+}
+
+fn scope_prefix(scope: Scope) -> Vec<u8> {
+    let mut key = Vec::new();
+    match scope {
+        Scope::Rune(rune_id) => push_str(&mut key, rune_id),
+        Scope::Switch(switch_id) => {
+            key.extend_from_slice(&SWITCH_SCOPE_MARKER.to_be_bytes());
+            key.extend_from_slice(&switch_id.to_be_bytes());
+        }
+    }
+    key
+}
+
+fn item_prefix(scope: Scope, item_id: &str) -> Vec<u8> {
+    let mut key = scope_prefix(scope);
+    push_str(&mut key, item_id);
+    key
+}
+
+fn version_key(scope: Scope, item_id: &str, version: u32) -> Vec<u8> {
+    let mut key = item_prefix(scope, item_id);
+    key.extend_from_slice(&version.to_be_bytes());
+    key
+}
+
+fn chunk_key(blob_id: u64, index: u32) -> Vec<u8> {
+    let mut key = blob_id.to_be_bytes().to_vec();
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+fn user_usage_key(user: &Principal) -> Vec<u8> {
+    let mut key = vec![0];
+    key.extend_from_slice(user.as_slice());
+    key
+}
+
+/// Number of uploads a user has open (kept alongside the byte counters)
+fn open_uploads_key(user: &Principal) -> Vec<u8> {
+    let mut key = vec![3];
+    key.extend_from_slice(user.as_slice());
+    key
+}
+
+fn scope_usage_key(scope: Scope) -> Vec<u8> {
+    match scope {
+        Scope::Rune(rune_id) => {
+            let mut key = vec![1];
+            key.extend_from_slice(rune_id.as_bytes());
+            key
+        }
+        Scope::Switch(switch_id) => {
+            let mut key = vec![2];
+            key.extend_from_slice(&switch_id.to_be_bytes());
+            key
+        }
+    }
+}
+
+// ============================================================================
+// Storage helpers
+// ============================================================================
+
+fn with_metadata<R>(f: impl FnOnce(&MetadataMap) -> R) -> R {
+    METADATA.with(|m| {
+        let m = m.borrow();
+        f(m.as_ref().expect("Metadata storage not initialized"))
+    })
+}
+
+/// All versions of an item, oldest first
+fn item_versions(scope: Scope, item_id: &str) -> Vec<EncryptedRuneMetadata> {
+    let prefix = item_prefix(scope, item_id);
+    with_metadata(|map| {
+        map.range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, stored)| stored.0)
+            .collect()
+    })
+}
+
+/// Latest version of every item matching `filter`
+fn latest_items(prefix: &[u8], filter: impl Fn(&EncryptedRuneMetadata) -> bool) -> Vec<EncryptedRuneMetadata> {
+    let mut latest: Vec<EncryptedRuneMetadata> = Vec::new();
+    with_metadata(|map| {
+        for (_, StoredMetadata(meta)) in map.range(prefix.to_vec()..).take_while(|(key, _)| key.starts_with(prefix)) {
+            // Versions of an item are adjacent and ascending
+            match latest.last_mut() {
+                Some(last) if Scope::of(last) == Scope::of(&meta) && last.item_id == meta.item_id => *last = meta,
+                _ => latest.push(meta),
+            }
+        }
+    });
+    latest.into_iter().filter(|meta| filter(meta)).collect()
+}
+
+fn stored_size(meta: &EncryptedRuneMetadata) -> u64 {
+    meta.encrypted_data.len() as u64 + meta.blob.as_ref().map(|b| b.size).unwrap_or(0)
+}
+
+fn usage(key: &[u8]) -> u64 {
+    USAGE.with(|u| u.borrow().as_ref().and_then(|map| map.get(&key.to_vec())).unwrap_or(0))
+}
+
+fn set_usage(key: Vec<u8>, value: u64) {
+    USAGE.with(|u| {
+        if let Some(ref mut map) = *u.borrow_mut() {
+            if value == 0 {
+                map.remove(&key);
+            } else {
+                map.insert(key, value);
+            }
+        }
+    });
+}
+
+/// Charge `bytes` to a user and a scope, failing if either quota would be exceeded
+fn reserve(user: &Principal, scope: Scope, bytes: u64) -> Result<(), EngineError> {
+    let user_used = usage(&user_usage_key(user));
+    let rune_used = usage(&scope_usage_key(scope));
+
+    if user_used.saturating_add(bytes) > USER_QUOTA_BYTES {
+        return Err(EngineError::InvalidState(format!(
+            "User storage quota exceeded: {} of {} bytes used",
+            user_used, USER_QUOTA_BYTES
+        )));
+    }
+    if rune_used.saturating_add(bytes) > RUNE_QUOTA_BYTES {
+        return Err(EngineError::InvalidState(format!(
+            "Rune storage quota exceeded: {} of {} bytes used",
+            rune_used, RUNE_QUOTA_BYTES
+        )));
+    }
+
+    set_usage(user_usage_key(user), user_used + bytes);
+    set_usage(scope_usage_key(scope), rune_used + bytes);
+    Ok(())
+}
+
+fn release(user: &Principal, scope: Scope, bytes: u64) {
+    set_usage(user_usage_key(user), usage(&user_usage_key(user)).saturating_sub(bytes));
+    set_usage(scope_usage_key(scope), usage(&scope_usage_key(scope)).saturating_sub(bytes));
+}
 
+fn delete_chunks(blob_id: u64, chunk_count: u32) {
+    CHUNKS.with(|c| {
+        if let Some(ref mut map) = *c.borrow_mut() {
+            for index in 0..chunk_count {
+                map.remove(&chunk_key(blob_id, index));
+            }
+        }
+    });
+}
+
+/// Remove one stored version together with its chunks and quota charge
+fn remove_version(meta: &EncryptedRuneMetadata) {
+    METADATA.with(|m| {
+        if let Some(ref mut map) = *m.borrow_mut() {
+            map.remove(&version_key(Scope::of(meta), &meta.item_id, meta.version));
+        }
+    });
+    if let Some(blob) = &meta.blob {
+        delete_chunks(blob.blob_id, blob.chunk_count);
+    }
+    release(&meta.owner, Scope::of(meta), stored_size(meta));
+}
+
+fn validate_item_id(item_id: &str) -> Result<(), EngineError> {
+    if item_id.is_empty()
+        || item_id.len() > MAX_ITEM_ID_LEN
+        || !item_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    {
+        return Err(EngineError::InvalidInput(format!(
+            "Item ID must be 1-{} characters of [A-Za-z0-9._-]",
+            MAX_ITEM_ID_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// Check that `caller` may write `item_id` of `rune_id` with the given access
+///
+/// Only the rune's creator may store metadata for it, and an item belongs to
+/// whoever created it. Switch notes may only be written by the switch owner,
+/// must name the rune the switch locks, and live in the switch's own scope.
+fn authorize_write(
+    caller: Principal,
+    rune_id: &str,
+    item_id: &str,
+    switch_id: Option<u64>,
+    reveal_time: Option<u64>,
+    readers: &[Principal],
+    min_holding: Option<u64>,
+) -> Result<(), EngineError> {
     if caller == Principal::anonymous() {
         return Err(EngineError::Unauthorized(
             "Anonymous principals cannot store metadata".to_string()
         ));
     }
 
+    validate_item_id(item_id)?;
+
+    if readers.len() > MAX_READERS {
+        return Err(EngineError::InvalidInput(format!(
+            "At most {} readers allowed",
+            MAX_READERS
+        )));
+    }
+    if min_holding == Some(0) {
+        return Err(EngineError::InvalidInput(
+            "Minimum holding must be greater than 0".to_string()
+        ));
+    }
+
+    if let Some(switch_id) = switch_id {
+        // Switch notes stay private to the owner until the switch fires
+        if dead_man_switch::switch_owner(switch_id) != Some(caller) {
+            return Err(EngineError::Unauthorized(
+                "Can only attach metadata to your own switch".to_string()
+            ));
+        }
+        if dead_man_switch::switch_rune(switch_id).as_deref() != Some(rune_id) {
+            return Err(EngineError::InvalidInput(
+                "Switch metadata must name the rune the switch locks".to_string()
+            ));
+        }
+        if reveal_time.is_some() || !readers.is_empty() || min_holding.is_some() {
+            return Err(EngineError::InvalidInput(
+                "Switch metadata is only shared with the switch's beneficiaries".to_string()
+            ));
+        }
+    } else {
+        let rune = state::get_virtual_rune(rune_id)
+            .ok_or_else(|| EngineError::NotFound(format!("Rune not found: {}", rune_id)))?;
+        if rune.caller != caller {
+            return Err(EngineError::Unauthorized(
+                "Only the rune's creator can store its metadata".to_string()
+            ));
+        }
+    }
+
+    if let Some(latest) = item_versions(Scope::new(rune_id, switch_id), item_id).last() {
+        if latest.owner != caller {
+            return Err(EngineError::Unauthorized(
+                "Item belongs to another principal".to_string()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Store `meta` as the next version of its item, pruning the oldest versions
+///
+/// The bytes must already be charged to the owner's quota.
+fn append_version(mut meta: EncryptedRuneMetadata) -> EncryptedRuneMetadata {
+    let mut versions = item_versions(Scope::of(&meta), &meta.item_id);
+    meta.version = versions.last().map(|v| v.version + 1).unwrap_or(1);
+    meta.derivation_id = Some(derivation_id(Scope::of(&meta), &meta.item_id, Some(meta.version)));
+
+    METADATA.with(|m| {
+        if let Some(ref mut map) = *m.borrow_mut() {
+            map.insert(version_key(Scope::of(&meta), &meta.item_id, meta.version), StoredMetadata(meta.clone()));
+        }
+    });
+
+    versions.push(meta.clone());
+    let excess = versions.len().saturating_sub(MAX_VERSIONS);
+    for old in &versions[..excess] {
+        remove_version(old);
+    }
+
+    meta
+}
+
+/// vetKD derivation ID for a version of an item
+///
+/// The unversioned (legacy) ID of the default item is the bare rune ID;
+/// named items bind to `rune_id \0 item_id`, so access to one item never
+/// yields the key of another. Switch notes bind to
+/// `0xFF "switch" switch_id \0 item_id`; no rune ID starts with 0xFF, so they
+/// never share a key with the rune's own items.
+///
+/// Versions stored since IDs were versioned prefix the legacy ID with
+/// `0xFE version`, so a reader allowed on one version never obtains the key
+/// of another. Older versions keep their legacy ID.
+fn derivation_id(scope: Scope, item_id: &str, version: Option<u32>) -> Vec<u8> {
+    if let Some(version) = version {
+        let mut id = vec![0xFE];
+        id.extend_from_slice(&version.to_be_bytes());
+        id.extend_from_slice(&derivation_id(scope, item_id, None));
+        return id;
+    }
+
+    let mut id = match scope {
+        Scope::Rune(rune_id) if item_id == DEFAULT_ITEM_ID => return rune_id.as_bytes().to_vec(),
+        Scope::Rune(rune_id) => rune_id.as_bytes().to_vec(),
+        Scope::Switch(switch_id) => {
+            let mut id = vec![0xFF];
+            id.extend_from_slice(b"switch");
+            id.extend_from_slice(&switch_id.to_be_bytes());
+            id
+        }
+    };
+    id.push(0);
+    id.extend_from_slice(item_id.as_bytes());
+    id
+}
+
+/// Derivation ID a stored version is encrypted under
+fn version_derivation_id(meta: &EncryptedRuneMetadata) -> Vec<u8> {
+    meta.derivation_id
+        .clone()
+        .unwrap_or_else(|| derivation_id(Scope::of(meta), &meta.item_id, None))
+}
+
+/// Derivation ID to encrypt the next version of an item under
+pub fn next_derivation_id(rune_id: &str, item_id: Option<&str>, switch_id: Option<u64>) -> Vec<u8> {
+    let scope = Scope::new(rune_id, switch_id);
+    let item_id = item_id.unwrap_or(DEFAULT_ITEM_ID);
+    let next = item_versions(scope, item_id).last().map(|v| v.version + 1).unwrap_or(1);
+    derivation_id(scope, item_id, Some(next))
+}
+
+/// Store encrypted metadata for a Rune
+///
+/// Storing to an existing item adds a new version, encrypted under
+/// `next_derivation_id`.
+pub fn store_metadata(params: StoreEncryptedMetadataParams) -> Result<(), EngineError> {
+    let caller = ic_cdk::caller();
+
     if params.encrypted_data.is_empty() {
         return Err(EngineError::InvalidInput(
             "Encrypted data cannot be empty".to_string()
         ));
     }
 
-    let metadata = EncryptedRuneMetadata {
-        rune_id: params.rune_id.clone(),
+    let item_id = params.item_id.clone().unwrap_or_else(|| DEFAULT_ITEM_ID.to_string());
+    let readers = params.readers.unwrap_or_default();
+    authorize_write(
+        caller,
+        &params.rune_id,
+        &item_id,
+        params.switch_id,
+        params.reveal_time,
+        &readers,
+        params.min_holding,
+    )?;
+    reserve(&caller, Scope::new(&params.rune_id, params.switch_id), params.encrypted_data.len() as u64)?;
+
+    append_version(EncryptedRuneMetadata {
+        rune_id: params.rune_id,
         encrypted_data: params.encrypted_data,
         nonce: params.nonce,
         reveal_time: params.reveal_time,
         owner: caller,
         created_at: time(),
-    };
-
-    ENCRYPTED_METADATA.with(|m| {
-        m.borrow_mut().insert(params.rune_id, metadata);
+        switch_id: params.switch_id,
+        item_id,
+        version: 0,
+        readers,
+        min_holding: params.min_holding,
+        blob: None,
+        derivation_id: None,
     });
-
     Ok(())
 }
 
 /// Get encrypted metadata (returns encrypted data, not decrypted)
-pub fn get_metadata(rune_id: &str) -> Option<EncryptedRuneMetadata> {
-    ENCRYPTED_METADATA.with(|m| {
-        m.borrow().get(rune_id).cloned()
-    })
+///
+/// Returns the latest version unless `version` is given. Switch notes are
+/// looked up by `switch_id`.
+pub fn get_metadata(
+    rune_id: &str,
+    item_id: Option<&str>,
+    version: Option<u32>,
+    switch_id: Option<u64>,
+) -> Option<EncryptedRuneMetadata> {
+    let scope = Scope::new(rune_id, switch_id);
+    let item_id = item_id.unwrap_or(DEFAULT_ITEM_ID);
+    let meta = match version {
+        Some(v) => with_metadata(|map| map.get(&version_key(scope, item_id, v)).map(|stored| stored.0)),
+        None => item_versions(scope, item_id).pop(),
+    }?;
+    (meta.rune_id == rune_id).then_some(meta)
+}
+
+/// Latest version of every item stored for a Rune (switch notes excluded)
+pub fn list_items(rune_id: &str) -> Vec<EncryptedRuneMetadata> {
+    latest_items(&scope_prefix(Scope::Rune(rune_id)), |_| true)
 }
 
-/// Check if caller can decrypt metadata
+/// Check if caller can decrypt a version of an item (latest unless given)
 /// - Owner can always decrypt
+/// - Switch notes: the switch's beneficiaries once it has fired
+/// - Listed readers, and holders of at least `min_holding` of the rune
 /// - Others can decrypt after reveal_time
-pub fn can_decrypt(rune_id: &str) -> Result<bool, EngineError> {
-    let caller = ic_cdk::caller();
-    let now = time();
+pub fn can_decrypt(
+    rune_id: &str,
+    item_id: Option<&str>,
+    version: Option<u32>,
+    switch_id: Option<u64>,
+) -> Result<bool, EngineError> {
+    let metadata = get_metadata(rune_id, item_id, version, switch_id)
+        .ok_or(EngineError::NotFound("Metadata not found".to_string()))?;
 
-    ENCRYPTED_METADATA.with(|m| {
-        let metadata = m.borrow()
-            .get(rune_id)
-            .cloned()
-            .ok_or(EngineError::NotFound("Metadata not found".to_string()))?;
+    Ok(decrypt_allowed(&metadata, ic_cdk::caller(), time()))
+}
 
-        // Owner can always decrypt
-        if metadata.owner == caller {
-            return Ok(true);
-        }
+/// Derivation ID of the requested version, if `caller` may decrypt it
+fn authorized_derivation_id(
+    caller: Principal,
+    rune_id: &str,
+    item_id: Option<&str>,
+    version: Option<u32>,
+    switch_id: Option<u64>,
+    now: u64,
+) -> Result<Vec<u8>, EngineError> {
+    let metadata = get_metadata(rune_id, item_id, version, switch_id)
+        .ok_or(EngineError::NotFound("Metadata not found".to_string()))?;
 
-        // Others can decrypt after reveal time
-        if let Some(reveal_time) = metadata.reveal_time {
-            if now >= reveal_time {
-                return Ok(true);
-            }
+    if !decrypt_allowed(&metadata, caller, now) {
+        return Err(EngineError::Unauthorized(
+            "Not authorized to decrypt this metadata".to_string(),
+        ));
+    }
+    Ok(version_derivation_id(&metadata))
+}
+
+fn decrypt_allowed(metadata: &EncryptedRuneMetadata, caller: Principal, now: u64) -> bool {
+    // Owner can always decrypt
+    if metadata.owner == caller {
+        return true;
+    }
+
+    // Beneficiaries can decrypt once the switch fires
+    if let Some(switch_id) = metadata.switch_id {
+        return dead_man_switch::is_released_to(switch_id, caller);
+    }
+
+    if metadata.readers.contains(&caller) {
+        return true;
+    }
+
+    // Token gate: available plus locked balance of the rune
+    if let Some(min_holding) = metadata.min_holding {
+        let (available, locked) = accounting::user_balance(caller, &Asset::Rune(metadata.rune_id.clone()));
+        if available.saturating_add(locked) >= min_holding {
+            return true;
         }
+    }
 
-        Ok(false)
-    })
+    // Others can decrypt after reveal time
+    metadata.reveal_time.is_some_and(|reveal_time| now >= reveal_time)
 }
 
 /// Get the vetKD public key for encryption
@@ -160,17 +712,23 @@ pub async fn get_public_key() -> Result<Vec<u8>, EngineError> {
 }
 
 /// Get encrypted decryption key for authorized caller
-/// The caller must be authorized to decrypt
+/// The caller must be authorized to decrypt the requested version (latest
+/// unless given), and only receives that version's key
 pub async fn get_encrypted_decryption_key(
     rune_id: String,
+    item_id: Option<String>,
     encryption_public_key: Vec<u8>,
+    switch_id: Option<u64>,
+    version: Option<u32>,
 ) -> Result<Vec<u8>, EngineError> {
-    // Check authorization
-    if !can_decrypt(&rune_id)? {
-        return Err(EngineError::Unauthorized(
-            "Not authorized to decrypt this metadata".to_string(),
-        ));
-    }
+    let derivation_id = authorized_derivation_id(
+        ic_cdk::caller(),
+        &rune_id,
+        item_id.as_deref(),
+        version,
+        switch_id,
+        time(),
+    )?;
 
     let key_id = VetKDKeyId {
         curve: VetKDCurve::Bls12_381_G2,
@@ -178,7 +736,7 @@ pub async fn get_encrypted_decryption_key(
     };
 
     let request = VetKDDeriveEncryptedKeyRequest {
-        derivation_id: rune_id.as_bytes().to_vec(),
+        derivation_id,
         derivation_path: vec![b"quri_rune_metadata".to_vec()],
         key_id,
         encryption_public_key,
@@ -201,75 +759,627 @@ pub async fn get_encrypted_decryption_key(
     }
 }
 
-/// Delete encrypted metadata (owner only)
-pub fn delete_metadata(rune_id: &str) -> Result<(), EngineError> {
-    let caller = ic_cdk::caller();
-
-    ENCRYPTED_METADATA.with(|m| {
-        let mut metadata_map = m.borrow_mut();
+/// Delete an item with all its versions (owner only)
+pub fn delete_metadata(rune_id: &str, item_id: Option<&str>, switch_id: Option<u64>) -> Result<(), EngineError> {
+    delete_metadata_as(ic_cdk::caller(), Scope::new(rune_id, switch_id), item_id.unwrap_or(DEFAULT_ITEM_ID))
+}
 
-        let metadata = metadata_map.get(rune_id)
-            .ok_or(EngineError::NotFound("Metadata not found".to_string()))?;
+fn delete_metadata_as(caller: Principal, scope: Scope, item_id: &str) -> Result<(), EngineError> {
+    let versions = item_versions(scope, item_id);
+    let owner = versions
+        .last()
+        .map(|meta| meta.owner)
+        .ok_or(EngineError::NotFound("Metadata not found".to_string()))?;
 
-        if metadata.owner != caller {
-            return Err(EngineError::Unauthorized(
-                "Only owner can delete metadata".to_string()
-            ));
-        }
+    if owner != caller {
+        return Err(EngineError::Unauthorized(
+            "Only owner can delete metadata".to_string()
+        ));
+    }
 
-        metadata_map.remove(rune_id);
-        Ok(())
-    })
+    for meta in &versions {
+        remove_version(meta);
+    }
+    Ok(())
 }
 
-/// Get all metadata for caller
+/// Get all metadata for caller (latest version of each item)
 pub fn get_my_metadata() -> Vec<EncryptedRuneMetadata> {
     let caller = ic_cdk::caller();
+    latest_items(&[], |meta| meta.owner == caller)
+}
 
-    ENCRYPTED_METADATA.with(|m| {
-        m.borrow()
-            .values()
-            .filter(|meta| meta.owner == caller)
-            .cloned()
-            .collect()
-    })
+/// Get the metadata attached to a switch (owner and beneficiaries only)
+pub fn get_switch_metadata(switch_id: u64) -> Result<Vec<EncryptedRuneMetadata>, EngineError> {
+    let caller = ic_cdk::caller();
+
+    if dead_man_switch::switch_owner(switch_id) != Some(caller)
+        && !dead_man_switch::is_beneficiary(switch_id, caller)
+    {
+        return Err(EngineError::Unauthorized(
+            "Only the owner or a beneficiary can list switch metadata".to_string()
+        ));
+    }
+
+    Ok(switch_items(switch_id))
+}
+
+/// Latest version of every note attached to a switch
+fn switch_items(switch_id: u64) -> Vec<EncryptedRuneMetadata> {
+    latest_items(&scope_prefix(Scope::Switch(switch_id)), |_| true)
 }
 
 /// Check if metadata exists for a Rune
-pub fn has_metadata(rune_id: &str) -> bool {
-    ENCRYPTED_METADATA.with(|m| {
-        m.borrow().contains_key(rune_id)
-    })
+pub fn has_metadata(rune_id: &str, item_id: Option<&str>) -> bool {
+    get_metadata(rune_id, item_id, None, None).is_some()
 }
 
 /// Get metadata reveal status
-pub fn get_reveal_status(rune_id: &str) -> Option<(bool, Option<u64>)> {
+pub fn get_reveal_status(rune_id: &str, item_id: Option<&str>) -> Option<(bool, Option<u64>)> {
     let now = time();
 
-    ENCRYPTED_METADATA.with(|m| {
-        m.borrow().get(rune_id).map(|meta| {
-            let is_revealed = meta.reveal_time
-                .map(|t| now >= t)
-                .unwrap_or(false);
-            (is_revealed, meta.reveal_time)
-        })
+    get_metadata(rune_id, item_id, None, None).map(|meta| {
+        let is_revealed = meta.reveal_time
+            .map(|t| now >= t)
+            .unwrap_or(false);
+        (is_revealed, meta.reveal_time)
     })
 }
 
+// ============================================================================
+// Chunked uploads
+// ============================================================================
+
+fn expected_chunk_len(size: u64, chunk_count: u32, index: u32) -> u64 {
+    if index + 1 == chunk_count {
+        size - CHUNK_SIZE * (chunk_count as u64 - 1)
+    } else {
+        CHUNK_SIZE
+    }
+}
+
+fn upload_info(upload_id: u64, session: &UploadSession) -> UploadInfo {
+    UploadInfo {
+        upload_id,
+        chunk_size: CHUNK_SIZE,
+        chunk_count: session.chunk_count,
+        received: session.received,
+        expires_at: session.expires_at,
+    }
+}
+
+fn get_session(upload_id: u64, caller: Principal, now: u64) -> Result<UploadSession, EngineError> {
+    let session = UPLOADS
+        .with(|u| u.borrow().as_ref().and_then(|map| map.get(&upload_id)))
+        .ok_or(EngineError::NotFound("Upload not found".to_string()))?;
+    if session.owner != caller {
+        return Err(EngineError::Unauthorized("Not your upload".to_string()));
+    }
+    if now > session.expires_at {
+        return Err(EngineError::InvalidState("Upload expired".to_string()));
+    }
+    Ok(session)
+}
+
+fn store_session(upload_id: u64, session: UploadSession) {
+    UPLOADS.with(|u| {
+        if let Some(ref mut map) = *u.borrow_mut() {
+            map.insert(upload_id, session);
+        }
+    });
+}
+
+/// Close an upload session (its chunks and reservation are left alone)
+fn remove_session(upload_id: u64, session: &UploadSession) {
+    UPLOADS.with(|u| {
+        if let Some(ref mut map) = *u.borrow_mut() {
+            map.remove(&upload_id);
+        }
+    });
+    let key = open_uploads_key(&session.owner);
+    set_usage(key.clone(), usage(&key).saturating_sub(1));
+}
+
+/// Drop an upload with its chunks and quota reservation
+fn discard_upload(upload_id: u64, session: &UploadSession) {
+    remove_session(upload_id, session);
+    delete_chunks(upload_id, session.chunk_count);
+    release(&session.owner, Scope::new(&session.rune_id, session.switch_id), session.size);
+}
+
+/// Discard uploads past their expiry, at most `MAX_UPLOAD_CLEANUP`; returns
+/// how many were removed
+///
+/// Every upload gets the same TTL, so upload IDs are in expiry order and the
+/// scan stops at the first live one.
+pub fn cleanup_expired_uploads(now: u64) -> u64 {
+    let expired: Vec<(u64, UploadSession)> = UPLOADS.with(|u| {
+        u.borrow()
+            .as_ref()
+            .map(|map| {
+                map.iter()
+                    .take_while(|(_, s)| now > s.expires_at)
+                    .take(MAX_UPLOAD_CLEANUP)
+                    .collect()
+            })
+            .unwrap_or_default()
+    });
+    for (upload_id, session) in &expired {
+        discard_upload(*upload_id, session);
+    }
+    expired.len() as u64
+}
+
+/// Start a chunked upload; the full size is reserved against the quotas
+pub fn begin_upload(params: BeginUploadParams) -> Result<UploadInfo, EngineError> {
+    begin_upload_as(ic_cdk::caller(), params, time())
+}
+
+fn begin_upload_as(caller: Principal, params: BeginUploadParams, now: u64) -> Result<UploadInfo, EngineError> {
+    if params.size == 0 || params.size > MAX_UPLOAD_SIZE {
+        return Err(EngineError::InvalidInput(format!(
+            "Upload size must be between 1 and {} bytes",
+            MAX_UPLOAD_SIZE
+        )));
+    }
+    if params.sha256.len() != 32 {
+        return Err(EngineError::InvalidInput("sha256 must be 32 bytes".to_string()));
+    }
+
+    let item_id = params.item_id.clone().unwrap_or_else(|| DEFAULT_ITEM_ID.to_string());
+    let readers = params.readers.unwrap_or_default();
+    authorize_write(
+        caller,
+        &params.rune_id,
+        &item_id,
+        params.switch_id,
+        params.reveal_time,
+        &readers,
+        params.min_holding,
+    )?;
+
+    cleanup_expired_uploads(now);
+    let open = usage(&open_uploads_key(&caller));
+    if open >= MAX_OPEN_UPLOADS {
+        return Err(EngineError::InvalidState(format!(
+            "At most {} uploads can be open at once",
+            MAX_OPEN_UPLOADS
+        )));
+    }
+
+    reserve(&caller, Scope::new(&params.rune_id, params.switch_id), params.size)?;
+    set_usage(open_uploads_key(&caller), open + 1);
+
+    let upload_id = UPLOAD_COUNTER.with(|c| {
+        let mut c = c.borrow_mut();
+        let cell = c.as_mut().expect("Metadata storage not initialized");
+        let id = cell.get() + 1;
+        let _ = cell.set(id);
+        id
+    });
+
+    let session = UploadSession {
+        owner: caller,
+        rune_id: params.rune_id,
+        item_id,
+        size: params.size,
+        chunk_count: params.size.div_ceil(CHUNK_SIZE) as u32,
+        sha256: params.sha256,
+        nonce: params.nonce,
+        reveal_time: params.reveal_time,
+        switch_id: params.switch_id,
+        readers,
+        min_holding: params.min_holding,
+        received: 0,
+        expires_at: now.saturating_add(UPLOAD_TTL_NS),
+    };
+    let info = upload_info(upload_id, &session);
+    store_session(upload_id, session);
+    Ok(info)
+}
+
+/// Upload one chunk; re-sending a chunk replaces it
+pub fn put_chunk(upload_id: u64, index: u32, data: Vec<u8>) -> Result<UploadInfo, EngineError> {
+    put_chunk_as(ic_cdk::caller(), upload_id, index, data, time())
+}
+
+fn put_chunk_as(caller: Principal, upload_id: u64, index: u32, data: Vec<u8>, now: u64) -> Result<UploadInfo, EngineError> {
+    let mut session = get_session(upload_id, caller, now)?;
+
+    if index >= session.chunk_count {
+        return Err(EngineError::InvalidInput(format!(
+            "Chunk index must be below {}",
+            session.chunk_count
+        )));
+    }
+    let expected = expected_chunk_len(session.size, session.chunk_count, index);
+    if data.len() as u64 != expected {
+        return Err(EngineError::InvalidInput(format!(
+            "Chunk {} must be {} bytes, got {}",
+            index,
+            expected,
+            data.len()
+        )));
+    }
+
+    let replaced = CHUNKS.with(|c| {
+        c.borrow_mut()
+            .as_mut()
+            .map(|map| map.insert(chunk_key(upload_id, index), data).is_some())
+            .unwrap_or(false)
+    });
+    if !replaced {
+        session.received += 1;
+    }
+
+    let info = upload_info(upload_id, &session);
+    store_session(upload_id, session);
+    Ok(info)
+}
+
+/// Verify the content hash and publish the upload as a new item version
+pub fn commit_upload(upload_id: u64) -> Result<EncryptedRuneMetadata, EngineError> {
+    commit_upload_as(ic_cdk::caller(), upload_id, time())
+}
+
+fn commit_upload_as(caller: Principal, upload_id: u64, now: u64) -> Result<EncryptedRuneMetadata, EngineError> {
+    let session = get_session(upload_id, caller, now)?;
+
+    if session.received != session.chunk_count {
+        return Err(EngineError::InvalidState(format!(
+            "Upload incomplete: {} of {} chunks received",
+            session.received, session.chunk_count
+        )));
+    }
+
+    let mut hasher = Sha256::new();
+    CHUNKS.with(|c| {
+        if let Some(ref map) = *c.borrow() {
+            for index in 0..session.chunk_count {
+                if let Some(chunk) = map.get(&chunk_key(upload_id, index)) {
+                    hasher.update(&chunk);
+                }
+            }
+        }
+    });
+    if hasher.finalize().as_slice() != session.sha256.as_slice() {
+        return Err(EngineError::InvalidInput(
+            "Content hash does not match; re-send the chunks".to_string()
+        ));
+    }
+
+    // Access may have changed since the upload started (e.g. a cancelled switch)
+    authorize_write(
+        caller,
+        &session.rune_id,
+        &session.item_id,
+        session.switch_id,
+        session.reveal_time,
+        &session.readers,
+        session.min_holding,
+    )?;
+
+    remove_session(upload_id, &session);
+
+    // The reservation becomes the stored item's charge; chunks stay under the upload id
+    Ok(append_version(EncryptedRuneMetadata {
+        rune_id: session.rune_id,
+        encrypted_data: vec![],
+        nonce: session.nonce,
+        reveal_time: session.reveal_time,
+        owner: caller,
+        created_at: now,
+        switch_id: session.switch_id,
+        item_id: session.item_id,
+        version: 0,
+        readers: session.readers,
+        min_holding: session.min_holding,
+        blob: Some(MetadataBlob {
+            blob_id: upload_id,
+            size: session.size,
+            chunk_count: session.chunk_count,
+            sha256: session.sha256,
+        }),
+        derivation_id: None,
+    }))
+}
+
+/// Abandon an upload and release its reservation
+pub fn abort_upload(upload_id: u64) -> Result<(), EngineError> {
+    let caller = ic_cdk::caller();
+    let session = get_session(upload_id, caller, time())?;
+    discard_upload(upload_id, &session);
+    Ok(())
+}
+
+/// Progress of one of the caller's uploads (to resume after a failure)
+pub fn get_upload(upload_id: u64) -> Result<UploadInfo, EngineError> {
+    let session = get_session(upload_id, ic_cdk::caller(), time())?;
+    Ok(upload_info(upload_id, &session))
+}
+
+/// Read one chunk of a stored item (latest version unless given)
+///
+/// Chunks are ciphertext; decryption still needs a vetKD key.
+pub fn get_chunk(
+    rune_id: &str,
+    item_id: Option<&str>,
+    version: Option<u32>,
+    index: u32,
+    switch_id: Option<u64>,
+) -> Option<Vec<u8>> {
+    let blob = get_metadata(rune_id, item_id, version, switch_id)?.blob?;
+    if index >= blob.chunk_count {
+        return None;
+    }
+    CHUNKS.with(|c| c.borrow().as_ref().and_then(|map| map.get(&chunk_key(blob.blob_id, index))))
+}
+
+/// Bytes stored (or reserved) by a user
+pub fn user_usage(user: Principal) -> u64 {
+    usage(&user_usage_key(&user))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_store_and_retrieve_metadata() {
-        let params = StoreEncryptedMetadataParams {
+        let _params = StoreEncryptedMetadataParams {
             rune_id: "TEST".to_string(),
             encrypted_data: vec![1, 2, 3, 4],
             nonce: vec![5, 6, 7, 8],
             reveal_time: None,
+            switch_id: None,
+            item_id: None,
+            readers: None,
+            min_holding: None,
         };
 
         // Note: This test won't work in unit tests because ic_cdk::caller()
         // requires canister context. This is just for structure verification.
     }
+
+    #[test]
+    fn test_switch_metadata_access() {
+        use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        dead_man_switch::init_switch_storage(
+            manager.get(MemoryId::new(0)),
+            manager.get(MemoryId::new(1)),
+            manager.get(MemoryId::new(2)),
+            manager.get(MemoryId::new(3)),
+            manager.get(MemoryId::new(20)),
+        );
+
+        let owner = Principal::from_slice(&[1; 29]);
+        let heir = Principal::from_slice(&[2; 29]);
+        let stranger = Principal::from_slice(&[3; 29]);
+        dead_man_switch::store_test_switch(1, owner, heir);
+
+        let metadata = EncryptedRuneMetadata {
+            rune_id: "seed-hints".to_string(),
+            encrypted_data: vec![1, 2, 3],
+            nonce: vec![4, 5, 6],
+            reveal_time: None,
+            owner,
+            created_at: 0,
+            switch_id: Some(1),
+            item_id: "seed-hints".to_string(),
+            version: 1,
+            readers: vec![],
+            min_holding: None,
+            blob: None,
+            derivation_id: None,
+        };
+
+        // Only the owner while the switch is armed
+        assert!(decrypt_allowed(&metadata, owner, 0));
+        assert!(!decrypt_allowed(&metadata, heir, 0));
+
+        // Beneficiary once it fires; nobody else
+        dead_man_switch::fire_test_switch(1);
+        assert!(decrypt_allowed(&metadata, heir, 0));
+        assert!(!decrypt_allowed(&metadata, stranger, u64::MAX));
+    }
+
+    #[test]
+    fn test_reader_and_holder_access() {
+        use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+        use crate::accounting::{EntryReason, LedgerAccount};
+
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        accounting::init_accounting_storage(manager.get(MemoryId::new(0)), manager.get(MemoryId::new(1)));
+
+        let creator = Principal::from_slice(&[1; 29]);
+        let reader = Principal::from_slice(&[2; 29]);
+        let holder = Principal::from_slice(&[3; 29]);
+        let stranger = Principal::from_slice(&[4; 29]);
+
+        accounting::post(
+            Asset::Rune("RUNE".to_string()),
+            LedgerAccount::Custody,
+            LedgerAccount::Available(holder),
+            100,
+            EntryReason::ManualCredit,
+            None,
+        )
+        .unwrap();
+
+        let mut metadata = EncryptedRuneMetadata {
+            rune_id: "RUNE".to_string(),
+            encrypted_data: vec![1],
+            nonce: vec![2],
+            reveal_time: None,
+            owner: creator,
+            created_at: 0,
+            switch_id: None,
+            item_id: "holders".to_string(),
+            version: 2,
+            readers: vec![reader],
+            min_holding: Some(100),
+            blob: None,
+            derivation_id: None,
+        };
+
+        assert!(decrypt_allowed(&metadata, creator, 0));
+        assert!(decrypt_allowed(&metadata, reader, 0));
+        assert!(decrypt_allowed(&metadata, holder, 0));
+        assert!(!decrypt_allowed(&metadata, stranger, 0));
+
+        metadata.min_holding = Some(101);
+        assert!(!decrypt_allowed(&metadata, holder, 0));
+
+        // Derivation binds to the item; the default item keeps the legacy ID
+        let rune = Scope::Rune("RUNE");
+        assert_eq!(derivation_id(rune, DEFAULT_ITEM_ID, None), b"RUNE".to_vec());
+        assert_ne!(derivation_id(rune, "a", None), derivation_id(rune, "b", None));
+        assert_ne!(derivation_id(rune, "a", Some(1)), derivation_id(rune, "a", Some(2)));
+        assert_ne!(derivation_id(rune, "a", Some(1)), derivation_id(rune, "a", None));
+
+        // Switch notes never share a key or key space with the rune's items
+        assert_ne!(derivation_id(Scope::Switch(1), "a", None), derivation_id(rune, "a", None));
+        assert_ne!(derivation_id(Scope::Switch(1), "a", None), derivation_id(Scope::Switch(2), "a", None));
+        assert_ne!(scope_prefix(Scope::Switch(1)), scope_prefix(rune));
+    }
+
+    #[test]
+    fn test_key_access_is_checked_per_version() {
+        use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        init_metadata_storage(
+            manager.get(MemoryId::new(0)),
+            manager.get(MemoryId::new(1)),
+            manager.get(MemoryId::new(2)),
+            manager.get(MemoryId::new(3)),
+            manager.get(MemoryId::new(4)),
+        );
+
+        let owner = Principal::from_slice(&[1; 29]);
+        let reader = Principal::from_slice(&[2; 29]);
+        let version = |readers: Vec<Principal>| EncryptedRuneMetadata {
+            rune_id: "RUNE".to_string(),
+            encrypted_data: vec![1],
+            nonce: vec![2],
+            reveal_time: None,
+            owner,
+            created_at: 0,
+            switch_id: None,
+            item_id: "plans".to_string(),
+            version: 0,
+            readers,
+            min_holding: None,
+            blob: None,
+            derivation_id: None,
+        };
+
+        let predicted = next_derivation_id("RUNE", Some("plans"), None);
+        let v1 = append_version(version(vec![reader]));
+        assert_eq!(v1.derivation_id, Some(predicted));
+
+        // The next version drops the reader
+        let v2 = append_version(version(vec![]));
+        assert_ne!(v1.derivation_id, v2.derivation_id);
+
+        let key = |caller, version| authorized_derivation_id(caller, "RUNE", Some("plans"), version, None, 0);
+        assert!(matches!(key(reader, None), Err(EngineError::Unauthorized(_))));
+        assert!(matches!(key(reader, Some(2)), Err(EngineError::Unauthorized(_))));
+        // The version they were shared on still opens, with its own key only
+        assert_eq!(key(reader, Some(1)).unwrap(), v1.derivation_id.unwrap());
+        assert_eq!(key(owner, None).unwrap(), v2.derivation_id.unwrap());
+    }
+
+    #[test]
+    fn test_chunked_upload() {
+        use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        dead_man_switch::init_switch_storage(
+            manager.get(MemoryId::new(0)),
+            manager.get(MemoryId::new(1)),
+            manager.get(MemoryId::new(2)),
+            manager.get(MemoryId::new(3)),
+            manager.get(MemoryId::new(20)),
+        );
+        init_metadata_storage(
+            manager.get(MemoryId::new(4)),
+            manager.get(MemoryId::new(5)),
+            manager.get(MemoryId::new(6)),
+            manager.get(MemoryId::new(7)),
+            manager.get(MemoryId::new(8)),
+        );
+
+        let owner = Principal::from_slice(&[1; 29]);
+        let other = Principal::from_slice(&[2; 29]);
+        dead_man_switch::store_test_switch(1, owner, other);
+
+        let payload: Vec<u8> = (0..CHUNK_SIZE + 10).map(|i| i as u8).collect();
+        let params = |sha256: Vec<u8>| BeginUploadParams {
+            rune_id: "TEST".to_string(),
+            item_id: Some("letter".to_string()),
+            size: payload.len() as u64,
+            sha256,
+            nonce: vec![0; 12],
+            reveal_time: None,
+            switch_id: Some(1),
+            readers: None,
+            min_holding: None,
+        };
+
+        let info = begin_upload_as(owner, params(Sha256::digest(&payload).to_vec()), 0).unwrap();
+        assert_eq!(info.chunk_count, 2);
+        assert_eq!(user_usage(owner), payload.len() as u64);
+
+        // Chunk sizes and ownership are enforced
+        assert!(put_chunk_as(owner, info.upload_id, 1, payload[..20].to_vec(), 0).is_err());
+        assert!(put_chunk_as(other, info.upload_id, 0, payload[..CHUNK_SIZE as usize].to_vec(), 0).is_err());
+
+        put_chunk_as(owner, info.upload_id, 1, payload[CHUNK_SIZE as usize..].to_vec(), 0).unwrap();
+        assert!(commit_upload_as(owner, info.upload_id, 0).is_err());
+        put_chunk_as(owner, info.upload_id, 0, payload[..CHUNK_SIZE as usize].to_vec(), 0).unwrap();
+
+        let meta = commit_upload_as(owner, info.upload_id, 0).unwrap();
+        assert_eq!(meta.version, 1);
+        assert_eq!(meta.blob.as_ref().unwrap().size, payload.len() as u64);
+        assert_eq!(get_chunk("TEST", Some("letter"), None, 1, Some(1)).unwrap(), payload[CHUNK_SIZE as usize..].to_vec());
+        assert_eq!(switch_items(1).len(), 1);
+
+        // Switch notes live apart from the rune's own items
+        assert!(list_items("TEST").is_empty());
+        assert!(get_chunk("TEST", Some("letter"), None, 1, None).is_none());
+        assert!(authorize_write(Principal::from_slice(&[1; 29]), "TEST", "letter", None, None, &[], None).is_err());
+
+        // A switch note must name the rune its switch locks
+        let mut foreign = params(Sha256::digest(&payload).to_vec());
+        foreign.rune_id = "OTHER".to_string();
+        assert!(begin_upload_as(owner, foreign, 0).is_err());
+
+        // A wrong hash is rejected at commit
+        let bad = begin_upload_as(owner, params(vec![0; 32]), 0).unwrap();
+        put_chunk_as(owner, bad.upload_id, 0, payload[..CHUNK_SIZE as usize].to_vec(), 0).unwrap();
+        put_chunk_as(owner, bad.upload_id, 1, payload[CHUNK_SIZE as usize..].to_vec(), 0).unwrap();
+        assert!(commit_upload_as(owner, bad.upload_id, 0).is_err());
+
+        // Expired uploads give their reservation and open slot back
+        assert_eq!(usage(&open_uploads_key(&owner)), 1);
+        assert_eq!(cleanup_expired_uploads(UPLOAD_TTL_NS), 0);
+        assert_eq!(cleanup_expired_uploads(UPLOAD_TTL_NS + 1), 1);
+        assert_eq!(user_usage(owner), payload.len() as u64);
+        assert_eq!(usage(&open_uploads_key(&owner)), 0);
+
+        // Open uploads are capped per owner
+        for _ in 0..MAX_OPEN_UPLOADS {
+            begin_upload_as(owner, params(vec![0; 32]), 0).unwrap();
+        }
+        assert!(begin_upload_as(owner, params(vec![0; 32]), 0).is_err());
+        assert_eq!(cleanup_expired_uploads(UPLOAD_TTL_NS + 1), MAX_OPEN_UPLOADS);
+
+        // Deleting the item frees its chunks and quota
+        delete_metadata_as(owner, Scope::Switch(1), "letter").unwrap();
+        assert_eq!(user_usage(owner), 0);
+        assert!(get_chunk("TEST", Some("letter"), Some(1), 0, Some(1)).is_none());
+    }
 }
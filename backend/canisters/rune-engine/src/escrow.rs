@@ -1,12 +1,33 @@
 use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_timers::TimerId;
 use ic_stable_structures::memory_manager::VirtualMemory;
 use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
 use std::cell::RefCell;
+use std::time::Duration;
 
+use crate::accounting::{self, Asset, EntryReason, LedgerAccount};
+use crate::logging;
 use crate::process_id::ProcessId;
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
+/// How often the timer looks for refunds due a retry
+const REFUND_RETRY_INTERVAL_SECS: u64 = 300;
+
+/// Refund attempts before an escrow is escalated to manual review
+const MAX_REFUND_ATTEMPTS: u32 = 6;
+
+/// Fee draw attempts after broadcast before the escrow goes to manual review
+const MAX_DRAW_ATTEMPTS: u32 = 6;
+
+/// Delay after the first failed refund; doubles on each further failure
+const REFUND_RETRY_BASE_DELAY_NS: u64 = 5 * 60 * 1_000_000_000;
+
+/// How long the ledger deduplicates a transfer by its `created_at_time`
+///
+/// Kept below the ledger's 24h window so a retry is never rejected as too old.
+const REFUND_DEDUP_WINDOW_NS: u64 = 23 * 60 * 60 * 1_000_000_000;
+
 /// Status of an escrow entry
 #[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub enum EscrowStatus {
@@ -16,8 +37,35 @@ pub enum EscrowStatus {
     Consumed,
     /// Fee has been refunded to user
     Refunded { txid: u64, refunded_at: u64 },
-    /// Refund failed (manual intervention needed)
+    /// Refund failed; retried automatically while `next_refund_at` is set
     RefundFailed { reason: String, failed_at: u64 },
+    /// Automatic retries gave up (manual intervention needed)
+    ManualReview { reason: String, escalated_at: u64 },
+    /// Allowance was never drawn (etching failed before broadcast); nothing charged
+    Released { released_at: u64 },
+    /// Drawing the fee after broadcast failed; retried while `next_draw_at` is set
+    DrawFailed { reason: String, failed_at: u64 },
+}
+
+/// How an etching fee is collected
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FeeMode {
+    /// The estimate is drawn from the payer's allowance before the etching
+    /// starts; failures and the unused part of the estimate are refunded
+    #[default]
+    Upfront,
+    /// The payer approves bitcoin-integration via `icrc2_approve`; the fee is
+    /// drawn with `transfer_from` only once the etching transaction is broadcast
+    Allowance,
+}
+
+/// One attempt to refund an escrow
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RefundAttempt {
+    pub attempted_at: u64,
+    /// Ledger block index if the transfer went through
+    pub block_index: Option<u64>,
+    pub error: Option<String>,
 }
 
 /// Escrow entry tracking fees collected for an etching process
@@ -37,6 +85,24 @@ pub struct EscrowEntry {
     pub updated_at: u64,
     /// Rune name for reference
     pub rune_name: String,
+    /// Refund attempts, oldest first
+    pub refund_attempts: Option<Vec<RefundAttempt>>,
+    /// When the retry timer should try the refund again
+    pub next_refund_at: Option<u64>,
+    /// Ledger `created_at_time` shared by all attempts so retries deduplicate
+    pub refund_created_at: Option<u64>,
+    /// How the fee is collected (`None` for entries from before fee modes)
+    pub fee_mode: Option<FeeMode>,
+    /// ckBTC block of the `transfer_from` that drew the fee
+    pub drawn_block: Option<u64>,
+    /// Part of `amount` spent on the L1 fee when the rest is refunded
+    pub consumed_amount: Option<u64>,
+    /// Ledger `created_at_time` shared by all draw attempts so retries deduplicate
+    pub fee_created_at: Option<u64>,
+    /// Failed fee draws after broadcast
+    pub draw_attempts: Option<u32>,
+    /// When the retry timer should try the fee draw again
+    pub next_draw_at: Option<u64>,
 }
 
 impl EscrowEntry {
@@ -51,6 +117,23 @@ impl EscrowEntry {
             created_at: now,
             updated_at: now,
             rune_name,
+            refund_attempts: None,
+            next_refund_at: None,
+            refund_created_at: None,
+            fee_mode: Some(FeeMode::Upfront),
+            drawn_block: None,
+            consumed_amount: None,
+            fee_created_at: None,
+            draw_attempts: None,
+            next_draw_at: None,
+        }
+    }
+
+    /// Create an escrow backed by an ICRC-2 allowance of up to `amount`
+    pub fn with_allowance(process_id: ProcessId, payer: Principal, amount: u64, rune_name: String) -> Self {
+        Self {
+            fee_mode: Some(FeeMode::Allowance),
+            ..Self::new(process_id, payer, amount, rune_name)
         }
     }
 
@@ -89,33 +172,247 @@ impl EscrowEntry {
     pub fn can_refund(&self) -> bool {
         matches!(self.status, EscrowStatus::Held)
     }
+
+    /// Whether the fee has actually been collected into custody
+    ///
+    /// Entries from before fee modes were recorded (and migrated into the
+    /// accounting ledger) as held, so they count as funded.
+    pub fn is_funded(&self) -> bool {
+        self.fee_mode.is_none() || self.drawn_block.is_some()
+    }
+
+    /// Whether the escrow account currently holds the fee
+    pub fn holds_funds(&self) -> bool {
+        self.is_funded()
+            && matches!(
+                self.status,
+                EscrowStatus::Held | EscrowStatus::RefundFailed { .. } | EscrowStatus::ManualReview { .. }
+            )
+    }
+
+    /// Amount a refund pays back (everything not spent on the L1 fee)
+    pub fn refundable_amount(&self) -> u64 {
+        self.amount.saturating_sub(self.consumed_amount.unwrap_or(0))
+    }
+
+    /// Fee is still in escrow and owed back to the payer
+    pub fn awaits_refund(&self) -> bool {
+        matches!(
+            self.status,
+            EscrowStatus::RefundFailed { .. } | EscrowStatus::ManualReview { .. }
+        )
+    }
+
+    /// Whether the retry timer should attempt the refund at `now`
+    pub fn refund_due(&self, now: u64) -> bool {
+        matches!(self.status, EscrowStatus::RefundFailed { .. })
+            && self.next_refund_at.is_some_and(|at| now >= at)
+    }
+
+    /// Schedule a retry for a failed refund from before retries were tracked
+    ///
+    /// Returns whether the entry changed.
+    pub fn seed_refund_retry(&mut self, now: u64) -> bool {
+        if !matches!(self.status, EscrowStatus::RefundFailed { .. }) || self.next_refund_at.is_some() {
+            return false;
+        }
+        self.next_refund_at = Some(now);
+        true
+    }
+
+    /// Number of refund attempts so far
+    pub fn refund_attempt_count(&self) -> u32 {
+        self.refund_attempts.as_ref().map(|a| a.len() as u32).unwrap_or(0)
+    }
+
+    /// Record the outcome of a refund attempt and schedule the next one
+    ///
+    /// Failures back off exponentially; after `MAX_REFUND_ATTEMPTS` the entry
+    /// moves to manual review.
+    pub fn record_refund_attempt(&mut self, result: Result<u64, String>, now: u64) {
+        let attempt = RefundAttempt {
+            attempted_at: now,
+            block_index: result.as_ref().ok().copied(),
+            error: result.as_ref().err().cloned(),
+        };
+        self.refund_attempts.get_or_insert_with(Vec::new).push(attempt);
+        self.updated_at = now;
+
+        match result {
+            Ok(txid) => {
+                self.status = EscrowStatus::Refunded { txid, refunded_at: now };
+                self.next_refund_at = None;
+            }
+            Err(reason) if self.refund_attempt_count() >= MAX_REFUND_ATTEMPTS => {
+                self.escalate(format!("Refund failed after {} attempts: {}", MAX_REFUND_ATTEMPTS, reason), now);
+            }
+            Err(reason) => {
+                self.status = EscrowStatus::RefundFailed { reason, failed_at: now };
+                self.next_refund_at = Some(now.saturating_add(refund_retry_delay_ns(self.refund_attempt_count())));
+            }
+        }
+    }
+
+    /// Whether the retry timer should attempt the fee draw at `now`
+    pub fn draw_due(&self, now: u64) -> bool {
+        matches!(self.status, EscrowStatus::DrawFailed { .. })
+            && self.next_draw_at.is_some_and(|at| now >= at)
+    }
+
+    /// Record a failed fee draw and schedule the next one
+    ///
+    /// Uses the refund backoff; after `MAX_DRAW_ATTEMPTS` the entry moves to
+    /// manual review.
+    pub fn record_draw_failure(&mut self, reason: String, now: u64) {
+        let attempts = self.draw_attempts.unwrap_or(0).saturating_add(1);
+        self.draw_attempts = Some(attempts);
+        self.updated_at = now;
+
+        if attempts >= MAX_DRAW_ATTEMPTS {
+            self.escalate(format!("Fee draw failed after {} attempts: {}", attempts, reason), now);
+        } else {
+            self.status = EscrowStatus::DrawFailed { reason, failed_at: now };
+            self.next_draw_at = Some(now.saturating_add(refund_retry_delay_ns(attempts)));
+        }
+    }
+
+    /// Park the entry in the manual-review queue
+    pub fn escalate(&mut self, reason: String, now: u64) {
+        self.status = EscrowStatus::ManualReview { reason, escalated_at: now };
+        self.next_refund_at = None;
+        self.next_draw_at = None;
+        self.updated_at = now;
+    }
+}
+
+/// Backoff before the next refund attempt after `attempts` failures
+fn refund_retry_delay_ns(attempts: u32) -> u64 {
+    let shift = attempts.saturating_sub(1).min(16);
+    REFUND_RETRY_BASE_DELAY_NS.saturating_mul(1 << shift)
+}
+
+/// Memo identifying the refund of one escrow on the ledger
+fn refund_memo(process_id: &ProcessId) -> Vec<u8> {
+    let mut memo = b"refund:".to_vec();
+    memo.extend_from_slice(process_id.as_bytes());
+    memo
 }
 
 // Storage for escrow entries
 type EscrowStorage = RefCell<Option<StableBTreeMap<ProcessId, Vec<u8>, Memory>>>;
 
+/// Retry-index tags, stored after the due time so one range covers both
+const RETRY_REFUND: u8 = 0;
+const RETRY_DRAW: u8 = 1;
+
 thread_local! {
     static ESCROW_ENTRIES: EscrowStorage = const { RefCell::new(None) };
+    /// Scheduled refund and fee draw retries keyed by due time
+    static RETRY_INDEX: RefCell<Option<StableBTreeMap<Vec<u8>, (), Memory>>> = const { RefCell::new(None) };
+    static REFUND_TIMER_ID: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    static PROCESSING_REFUNDS: RefCell<bool> = const { RefCell::new(false) };
 }
 
 /// Initialize escrow storage
-pub fn init_escrow_storage(memory: Memory) {
+pub fn init_escrow_storage(memory: Memory, retry_memory: Memory) {
     ESCROW_ENTRIES.with(|e| {
         *e.borrow_mut() = Some(StableBTreeMap::init(memory));
     });
+    RETRY_INDEX.with(|i| *i.borrow_mut() = Some(StableBTreeMap::init(retry_memory)));
 }
 
 /// Reinitialize escrow storage after upgrade
-pub fn reinit_escrow_storage(memory: Memory) {
-    init_escrow_storage(memory);
+pub fn reinit_escrow_storage(memory: Memory, retry_memory: Memory) {
+    init_escrow_storage(memory, retry_memory);
+}
+
+/// Index retries stored before the index existed; only runs on an empty index
+pub fn rebuild_retry_index() -> u64 {
+    if RETRY_INDEX.with(|i| i.borrow().as_ref().is_none_or(|map| !map.is_empty())) {
+        return 0;
+    }
+    let mut indexed = 0u64;
+    for entry in all_escrows() {
+        if let Some(key) = retry_key(&entry) {
+            RETRY_INDEX.with(|i| {
+                if let Some(ref mut map) = *i.borrow_mut() {
+                    map.insert(key, ());
+                }
+            });
+            indexed += 1;
+        }
+    }
+    indexed
+}
+
+/// Index key of the entry's next scheduled retry, if it has one
+fn retry_key(entry: &EscrowEntry) -> Option<Vec<u8>> {
+    let (due_at, tag) = match entry.status {
+        EscrowStatus::RefundFailed { .. } => (entry.next_refund_at?, RETRY_REFUND),
+        EscrowStatus::DrawFailed { .. } => (entry.next_draw_at?, RETRY_DRAW),
+        _ => return None,
+    };
+    let mut key = due_at.to_be_bytes().to_vec();
+    key.push(tag);
+    key.extend_from_slice(entry.process_id.as_bytes());
+    Some(key)
+}
+
+/// Move the entry's retry-index key from `previous` to its current schedule
+fn index_retry(previous: Option<&EscrowEntry>, entry: &EscrowEntry) {
+    let old = previous.and_then(retry_key);
+    let new = retry_key(entry);
+    if old == new {
+        return;
+    }
+    RETRY_INDEX.with(|i| {
+        if let Some(ref mut map) = *i.borrow_mut() {
+            if let Some(old) = old {
+                map.remove(&old);
+            }
+            if let Some(new) = new {
+                map.insert(new, ());
+            }
+        }
+    });
+}
+
+/// Refunds and fee draws whose backoff has elapsed at `now`
+fn due_retries(now: u64) -> (Vec<ProcessId>, Vec<ProcessId>) {
+    let end = {
+        let mut key = now.to_be_bytes().to_vec();
+        key.push(u8::MAX);
+        key
+    };
+    RETRY_INDEX.with(|i| {
+        let mut refunds = Vec::new();
+        let mut draws = Vec::new();
+        if let Some(ref map) = *i.borrow() {
+            for (key, _) in map.range(..=end) {
+                let process_id = ProcessId::from_bytes_array(key[9..].try_into().unwrap_or([0; 16]));
+                match key[8] {
+                    RETRY_REFUND => refunds.push(process_id),
+                    _ => draws.push(process_id),
+                }
+            }
+        }
+        (refunds, draws)
+    })
 }
 
 /// Store an escrow entry
+///
+/// Status transitions are mirrored in the accounting ledger: a new held
+/// entry moves the fee from custody into the escrow account, and consuming
+/// or refunding it releases the escrow account again.
 pub fn store_escrow(entry: &EscrowEntry) -> Result<(), String> {
     let key = entry.process_id.clone();
     let value = candid::encode_one(entry)
         .map_err(|e| format!("Failed to encode escrow entry: {}", e))?;
 
+    let previous = get_escrow(&key);
+    post_escrow_transition(previous.as_ref(), entry)?;
+
     ESCROW_ENTRIES.with(|e| {
         if let Some(ref mut map) = *e.borrow_mut() {
             map.insert(key, value);
@@ -123,7 +420,81 @@ pub fn store_escrow(entry: &EscrowEntry) -> Result<(), String> {
         } else {
             Err("Escrow storage not initialized".to_string())
         }
-    })
+    })?;
+    index_retry(previous.as_ref(), entry);
+    Ok(())
+}
+
+/// Record the ledger postings for an escrow change, if any
+fn post_escrow_transition(previous: Option<&EscrowEntry>, entry: &EscrowEntry) -> Result<(), String> {
+    let was_held = previous.is_some_and(EscrowEntry::holds_funds);
+    let account = escrow_account(&entry.process_id);
+
+    let postings = match (was_held, entry.holds_funds()) {
+        (false, true) => vec![(LedgerAccount::Custody, account, entry.amount, EntryReason::EscrowHold)],
+        (true, false) => match entry.status {
+            EscrowStatus::Consumed => vec![(account, LedgerAccount::Custody, entry.amount, EntryReason::EscrowConsume)],
+            EscrowStatus::Refunded { .. } => vec![
+                (account.clone(), LedgerAccount::Custody, entry.consumed_amount.unwrap_or(0), EntryReason::EscrowConsume),
+                (account, LedgerAccount::Custody, entry.refundable_amount(), EntryReason::EscrowRefund),
+            ],
+            _ => vec![],
+        },
+        _ => vec![],
+    };
+
+    for (from, to, amount, reason) in postings {
+        if amount > 0 {
+            accounting::post(Asset::CkBtc, from, to, amount, reason, Some(entry.rune_name.clone()))?;
+        }
+    }
+    Ok(())
+}
+
+fn escrow_account(process_id: &ProcessId) -> LedgerAccount {
+    LedgerAccount::Escrow(process_id.to_string())
+}
+
+/// Post held escrows into the accounting ledger (one-time migration)
+pub fn migrate_escrows_to_accounting() -> Result<u64, String> {
+    let held: Vec<EscrowEntry> = ESCROW_ENTRIES.with(|e| {
+        e.borrow()
+            .as_ref()
+            .map(|map| {
+                map.iter()
+                    .filter_map(|(_, value)| candid::decode_one::<EscrowEntry>(&value).ok())
+                    .filter(EscrowEntry::holds_funds)
+                    .collect()
+            })
+            .unwrap_or_default()
+    });
+
+    let mut migrated = 0u64;
+    for entry in held {
+        accounting::post(
+            Asset::CkBtc,
+            LedgerAccount::Custody,
+            escrow_account(&entry.process_id),
+            entry.amount,
+            EntryReason::Migration,
+            Some(entry.rune_name.clone()),
+        )?;
+        migrated += 1;
+    }
+    Ok(migrated)
+}
+
+/// Schedule retries for failed refunds stored before retries were tracked
+///
+/// Run on upgrade; returns how many entries were scheduled.
+pub fn schedule_legacy_refund_retries(now: u64) -> u64 {
+    let mut scheduled = 0u64;
+    for mut entry in all_escrows() {
+        if entry.seed_refund_retry(now) && update_escrow(&entry).is_ok() {
+            scheduled += 1;
+        }
+    }
+    scheduled
 }
 
 /// Get an escrow entry by process ID
@@ -175,6 +546,9 @@ pub fn get_escrow_stats() -> EscrowStats {
         total_consumed: 0,
         total_refunded: 0,
         total_refund_failed: 0,
+        total_manual_review: 0,
+        total_released: 0,
+        total_draw_failed: 0,
         amount_held: 0,
         amount_consumed: 0,
         amount_refunded: 0,
@@ -195,11 +569,21 @@ pub fn get_escrow_stats() -> EscrowStats {
                         }
                         EscrowStatus::Refunded { .. } => {
                             stats.total_refunded += 1;
-                            stats.amount_refunded += entry.amount;
+                            stats.amount_refunded += entry.refundable_amount();
+                            stats.amount_consumed += entry.consumed_amount.unwrap_or(0);
                         }
                         EscrowStatus::RefundFailed { .. } => {
                             stats.total_refund_failed += 1;
                         }
+                        EscrowStatus::ManualReview { .. } => {
+                            stats.total_manual_review += 1;
+                        }
+                        EscrowStatus::Released { .. } => {
+                            stats.total_released += 1;
+                        }
+                        EscrowStatus::DrawFailed { .. } => {
+                            stats.total_draw_failed += 1;
+                        }
                     }
                 }
             }
@@ -220,8 +604,9 @@ pub fn cleanup_old_escrows(age_threshold_nanos: u64) -> u64 {
             for (key, value) in map.iter() {
                 if let Ok(entry) = candid::decode_one::<EscrowEntry>(&value) {
                     let age = now.saturating_sub(entry.updated_at);
-                    if entry.is_terminal() && age > age_threshold_nanos {
-                        to_delete.push(key.clone());
+                    // Entries still owing a refund are kept regardless of age
+                    if entry.is_terminal() && !entry.awaits_refund() && age > age_threshold_nanos {
+                        to_delete.push((key.clone(), retry_key(&entry)));
                     }
                 }
             }
@@ -230,16 +615,321 @@ pub fn cleanup_old_escrows(age_threshold_nanos: u64) -> u64 {
 
     ESCROW_ENTRIES.with(|e| {
         if let Some(ref mut map) = *e.borrow_mut() {
-            for key in to_delete {
-                map.remove(&key);
+            for (key, _) in &to_delete {
+                map.remove(key);
                 deleted += 1;
             }
         }
     });
 
+    RETRY_INDEX.with(|i| {
+        if let Some(ref mut map) = *i.borrow_mut() {
+            for retry in to_delete.iter().filter_map(|(_, retry)| retry.as_ref()) {
+                map.remove(retry);
+            }
+        }
+    });
+
     deleted
 }
 
+/// Escrows whose refund needs an admin (escalated or failed before retries existed)
+pub fn get_refund_review_queue() -> Vec<EscrowEntry> {
+    all_escrows()
+        .into_iter()
+        .filter(|entry| match entry.status {
+            EscrowStatus::ManualReview { .. } => true,
+            EscrowStatus::RefundFailed { .. } => entry.next_refund_at.is_none(),
+            _ => false,
+        })
+        .collect()
+}
+
+fn all_escrows() -> Vec<EscrowEntry> {
+    ESCROW_ENTRIES.with(|e| {
+        e.borrow()
+            .as_ref()
+            .map(|map| {
+                map.iter()
+                    .filter_map(|(_, value)| candid::decode_one::<EscrowEntry>(&value).ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    })
+}
+
+// ============================================================================
+// Refunds
+// ============================================================================
+
+/// Refund an escrowed fee to its payer through bitcoin-integration
+///
+/// Every attempt for an escrow sends the same memo and `created_at_time`, so
+/// the ckBTC ledger rejects a repeat of a transfer that already landed (e.g.
+/// when a previous call timed out after executing) and reports its block
+/// instead. Once that dedup window has passed, automatic retries stop and the
+/// escrow goes to manual review; `manual` (an admin who checked the ledger)
+/// starts a fresh window.
+pub async fn refund_escrow(process_id: &ProcessId, manual: bool) -> Result<u64, String> {
+    let mut entry = get_escrow(process_id)
+        .ok_or_else(|| format!("Escrow entry not found for process: {}", process_id))?;
+
+    if !entry.can_refund() && !entry.awaits_refund() {
+        return Err(format!("Cannot refund escrow in status: {:?}", entry.status));
+    }
+    if !entry.is_funded() {
+        return Err("Escrow fee was never drawn; release it instead".to_string());
+    }
+
+    let now = ic_cdk::api::time();
+    let created_at = match entry.refund_created_at {
+        Some(at) if now.saturating_sub(at) < REFUND_DEDUP_WINDOW_NS => at,
+        Some(_) if !manual => {
+            entry.escalate("Ledger deduplication window expired".to_string(), now);
+            update_escrow(&entry)?;
+            return Err("Refund needs manual review: deduplication window expired".to_string());
+        }
+        _ => now,
+    };
+    entry.refund_created_at = Some(created_at);
+    update_escrow(&entry)?;
+
+    let btc_canister_id = crate::get_bitcoin_integration_id()?;
+    let result: Result<u64, String> = match ic_cdk::call::<_, (Result<u64, String>,)>(
+        btc_canister_id,
+        "transfer_ckbtc",
+        (entry.payer, entry.refundable_amount(), Some(refund_memo(process_id)), Some(created_at)),
+    )
+    .await
+    {
+        Ok((transfer_result,)) => transfer_result,
+        Err((code, msg)) => Err(format!("ckBTC transfer call failed: {:?} - {}", code, msg)),
+    };
+
+    // Re-read: the entry may have changed while the call was in flight
+    let mut entry = get_escrow(process_id)
+        .ok_or_else(|| format!("Escrow entry not found for process: {}", process_id))?;
+    if matches!(entry.status, EscrowStatus::Refunded { .. }) {
+        return result;
+    }
+    entry.record_refund_attempt(result.clone(), ic_cdk::api::time());
+    update_escrow(&entry)?;
+
+    if let EscrowStatus::ManualReview { reason, .. } = &entry.status {
+        logging::log_error(
+            "escrow_refund",
+            format!("Refund of {} sats to {} escalated: {}", entry.amount, entry.payer, reason),
+            Some(process_id.to_string()),
+        );
+    }
+
+    result
+}
+
+/// Release an escrow whose fee was never drawn
+pub fn release_escrow(process_id: &ProcessId) -> Result<(), String> {
+    let mut entry = get_escrow(process_id)
+        .ok_or_else(|| format!("Escrow entry not found for process: {}", process_id))?;
+
+    if entry.is_funded() || !entry.can_refund() {
+        return Err(format!("Cannot release escrow in status: {:?}", entry.status));
+    }
+
+    let now = ic_cdk::api::time();
+    entry.status = EscrowStatus::Released { released_at: now };
+    entry.updated_at = now;
+    update_escrow(&entry)
+}
+
+/// Draw `amount` from the payer's ICRC-2 allowance into the escrow
+///
+/// The first attempt pins `fee_created_at`, so a retry after an ambiguous
+/// failure resolves to the original draw instead of charging twice. Returns
+/// the ckBTC block index of the draw.
+pub async fn draw_fee(process_id: &ProcessId, amount: u64) -> Result<u64, String> {
+    let mut entry = get_escrow(process_id)
+        .ok_or_else(|| format!("Escrow entry not found for process: {}", process_id))?;
+
+    let drawable = matches!(entry.status, EscrowStatus::Held | EscrowStatus::DrawFailed { .. });
+    if entry.is_funded() || !drawable {
+        return Err(format!("Cannot draw escrow in status: {:?}", entry.status));
+    }
+
+    let now = ic_cdk::api::time();
+    let created_at = match entry.fee_created_at {
+        Some(at) if now.saturating_sub(at) < REFUND_DEDUP_WINDOW_NS => at,
+        Some(_) => return Err("Fee draw deduplication window expired".to_string()),
+        None => now,
+    };
+    if entry.fee_created_at.is_none() {
+        entry.fee_created_at = Some(created_at);
+        update_escrow(&entry)?;
+    }
+
+    let btc_canister_id = crate::get_bitcoin_integration_id()?;
+    let (charge_result,): (Result<u64, String>,) = ic_cdk::call(
+        btc_canister_id,
+        "charge_etching_fee",
+        (entry.payer, amount, Some(fee_memo(process_id)), Some(created_at)),
+    )
+    .await
+    .map_err(|(code, msg)| format!("Fee charge call failed: {:?} - {}", code, msg))?;
+    let block_index = charge_result?;
+
+    // Re-read after the call; storing the draw posts the hold
+    let mut entry = get_escrow(process_id)
+        .ok_or_else(|| format!("Escrow entry not found for process: {}", process_id))?;
+    entry.amount = amount;
+    entry.drawn_block = Some(block_index);
+    entry.status = EscrowStatus::Held;
+    entry.next_draw_at = None;
+    entry.updated_at = ic_cdk::api::time();
+    update_escrow(&entry)?;
+    Ok(block_index)
+}
+
+/// Draw an allowance escrow's settled fee and mark it consumed
+///
+/// A failed draw is recorded for the retry timer instead of leaving the
+/// escrow held.
+async fn draw_settlement(process_id: &ProcessId) -> Result<(), String> {
+    let amount = get_escrow(process_id)
+        .ok_or_else(|| format!("Escrow entry not found for process: {}", process_id))?
+        .amount;
+    let result = draw_fee(process_id, amount).await;
+
+    let mut entry = get_escrow(process_id)
+        .ok_or_else(|| format!("Escrow entry not found for process: {}", process_id))?;
+    match result {
+        Ok(_) => {
+            entry.mark_consumed();
+            update_escrow(&entry)
+        }
+        Err(reason) => {
+            entry.record_draw_failure(reason.clone(), ic_cdk::api::time());
+            update_escrow(&entry)?;
+            if let EscrowStatus::ManualReview { reason, .. } = &entry.status {
+                logging::log_error(
+                    "escrow_draw",
+                    format!("Fee draw of {} sats from {} escalated: {}", entry.amount, entry.payer, reason),
+                    Some(process_id.to_string()),
+                );
+            }
+            Err(reason)
+        }
+    }
+}
+
+/// Settle an etching fee once its transaction is broadcast
+///
+/// `actual_fee` is what the L1 transaction paid. Allowance escrows draw only
+/// that amount (capped at the estimate); upfront escrows keep it and refund
+/// the rest of the estimate to the payer.
+pub async fn settle_fee(process_id: &ProcessId, actual_fee: u64) -> Result<(), String> {
+    let mut entry = get_escrow(process_id)
+        .ok_or_else(|| format!("Escrow entry not found for process: {}", process_id))?;
+
+    if !entry.can_refund() {
+        return Err(format!("Cannot settle escrow in status: {:?}", entry.status));
+    }
+
+    if !entry.is_funded() {
+        if entry.fee_mode != Some(FeeMode::Allowance) {
+            return Err("Upfront fee was never drawn; nothing to settle".to_string());
+        }
+        // The entry is unfunded, so lowering the amount posts nothing
+        entry.amount = actual_fee.min(entry.amount);
+        update_escrow(&entry)?;
+        return draw_settlement(process_id).await;
+    }
+
+    if actual_fee >= entry.amount {
+        entry.mark_consumed();
+        return update_escrow(&entry);
+    }
+
+    // Refund the unused part of the estimate; failures go to the retry timer
+    entry.consumed_amount = Some(actual_fee);
+    update_escrow(&entry)?;
+    refund_escrow(process_id, false).await.map(|_| ())
+}
+
+/// Memo identifying the fee draw of one escrow on the ledger
+fn fee_memo(process_id: &ProcessId) -> Vec<u8> {
+    let mut memo = b"fee:".to_vec();
+    memo.extend_from_slice(process_id.as_bytes());
+    memo
+}
+
+/// Holds the refund-retry flag for one run; releasing it on drop means a
+/// trap after an await can't leave retries disabled
+struct RefundRetryGuard;
+
+impl RefundRetryGuard {
+    /// Take the flag, or None if a run is already in progress
+    fn acquire() -> Option<Self> {
+        if PROCESSING_REFUNDS.with(|p| p.replace(true)) {
+            None
+        } else {
+            Some(RefundRetryGuard)
+        }
+    }
+}
+
+impl Drop for RefundRetryGuard {
+    fn drop(&mut self) {
+        PROCESSING_REFUNDS.with(|p| *p.borrow_mut() = false);
+    }
+}
+
+/// Retry every refund and fee draw whose backoff has elapsed; returns how
+/// many succeeded
+pub async fn process_refund_retries() -> u64 {
+    let Some(_guard) = RefundRetryGuard::acquire() else {
+        return 0;
+    };
+
+    let (refunds_due, draws_due) = due_retries(ic_cdk::api::time());
+
+    let mut refunded = 0u64;
+    for process_id in refunds_due {
+        if refund_escrow(&process_id, false).await.is_ok() {
+            refunded += 1;
+        }
+    }
+    for process_id in draws_due {
+        if draw_settlement(&process_id).await.is_ok() {
+            refunded += 1;
+        }
+    }
+    refunded
+}
+
+/// Start the periodic refund retry timer
+pub fn start_refund_retry_processor() {
+    stop_refund_retry_processor();
+
+    let timer_id = ic_cdk_timers::set_timer_interval(Duration::from_secs(REFUND_RETRY_INTERVAL_SECS), || {
+        ic_cdk::spawn(async {
+            let refunded = process_refund_retries().await;
+            if refunded > 0 {
+                ic_cdk::println!("Retried {} escrow refunds successfully", refunded);
+            }
+        });
+    });
+
+    REFUND_TIMER_ID.with(|t| *t.borrow_mut() = Some(timer_id));
+}
+
+/// Stop the refund retry timer
+pub fn stop_refund_retry_processor() {
+    REFUND_TIMER_ID.with(|t| {
+        if let Some(timer_id) = t.borrow_mut().take() {
+            ic_cdk_timers::clear_timer(timer_id);
+        }
+    });
+}
+
 /// Statistics about escrow entries
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct EscrowStats {
@@ -247,6 +937,9 @@ pub struct EscrowStats {
     pub total_consumed: u64,
     pub total_refunded: u64,
     pub total_refund_failed: u64,
+    pub total_manual_review: u64,
+    pub total_released: u64,
+    pub total_draw_failed: u64,
     pub amount_held: u64,
     pub amount_consumed: u64,
     pub amount_refunded: u64,
@@ -311,4 +1004,206 @@ mod tests {
         assert!(!entry.can_refund());
         assert!(entry.is_terminal());
     }
+
+    #[test]
+    fn test_refund_retry_backoff() {
+        let mut entry = EscrowEntry {
+            process_id: ProcessId::from_seed(7),
+            payer: Principal::from_text("aaaaa-aa").unwrap(),
+            amount: 20_000,
+            status: EscrowStatus::Held,
+            created_at: 0,
+            updated_at: 0,
+            rune_name: "TEST_RUNE".to_string(),
+            refund_attempts: None,
+            next_refund_at: None,
+            refund_created_at: None,
+            fee_mode: None,
+            drawn_block: None,
+            consumed_amount: None,
+            fee_created_at: None,
+            draw_attempts: None,
+            next_draw_at: None,
+        };
+
+        // A failed refund from before retries were tracked is picked up again
+        entry.status = EscrowStatus::RefundFailed { reason: "legacy".to_string(), failed_at: 40 };
+        entry.next_refund_at = None;
+        assert!(!entry.refund_due(u64::MAX));
+        assert!(entry.seed_refund_retry(50));
+        assert!(entry.refund_due(50));
+        assert!(!entry.seed_refund_retry(60));
+
+        entry.record_refund_attempt(Err("ledger busy".to_string()), 100);
+        assert!(matches!(entry.status, EscrowStatus::RefundFailed { .. }));
+        assert_eq!(entry.next_refund_at, Some(100 + REFUND_RETRY_BASE_DELAY_NS));
+        assert!(!entry.refund_due(100));
+        assert!(entry.refund_due(100 + REFUND_RETRY_BASE_DELAY_NS));
+
+        entry.record_refund_attempt(Err("ledger busy".to_string()), 200);
+        assert_eq!(entry.next_refund_at, Some(200 + 2 * REFUND_RETRY_BASE_DELAY_NS));
+
+        // The cap escalates to manual review and stops retries
+        for now in 3..=MAX_REFUND_ATTEMPTS as u64 {
+            entry.record_refund_attempt(Err("ledger busy".to_string()), now * 100);
+        }
+        assert!(matches!(entry.status, EscrowStatus::ManualReview { .. }));
+        assert!(entry.awaits_refund());
+        assert!(!entry.refund_due(u64::MAX));
+        assert_eq!(entry.refund_attempt_count(), MAX_REFUND_ATTEMPTS);
+
+        // A later manual refund is recorded in the same history
+        entry.record_refund_attempt(Ok(42), 1_000);
+        assert!(matches!(entry.status, EscrowStatus::Refunded { txid: 42, .. }));
+        assert_eq!(entry.refund_attempts.as_ref().unwrap().last().unwrap().block_index, Some(42));
+
+        assert_eq!(refund_memo(&entry.process_id), refund_memo(&ProcessId::from_seed(7)));
+    }
+
+    #[test]
+    fn test_retry_index_tracks_schedule() {
+        use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        init_escrow_storage(manager.get(MemoryId::new(0)), manager.get(MemoryId::new(1)));
+
+        // Undrawn allowances hold nothing, so storing them posts no ledger entries
+        let entry = |seed: u64| EscrowEntry {
+            process_id: ProcessId::from_seed(seed),
+            payer: Principal::from_text("aaaaa-aa").unwrap(),
+            amount: 20_000,
+            status: EscrowStatus::Held,
+            created_at: 0,
+            updated_at: 0,
+            rune_name: "TEST_RUNE".to_string(),
+            refund_attempts: None,
+            next_refund_at: None,
+            refund_created_at: None,
+            fee_mode: Some(FeeMode::Allowance),
+            drawn_block: None,
+            consumed_amount: None,
+            fee_created_at: None,
+            draw_attempts: None,
+            next_draw_at: None,
+        };
+
+        let mut refund = entry(1);
+        refund.status = EscrowStatus::RefundFailed { reason: "busy".to_string(), failed_at: 0 };
+        refund.next_refund_at = Some(100);
+        let mut draw = entry(2);
+        draw.status = EscrowStatus::DrawFailed { reason: "busy".to_string(), failed_at: 0 };
+        draw.next_draw_at = Some(200);
+        for e in [&refund, &draw, &entry(3)] {
+            store_escrow(e).unwrap();
+        }
+
+        assert_eq!(due_retries(99), (vec![], vec![]));
+        assert_eq!(due_retries(150), (vec![refund.process_id.clone()], vec![]));
+        assert_eq!(due_retries(200), (vec![refund.process_id.clone()], vec![draw.process_id.clone()]));
+
+        // Rescheduling moves the key; settling drops it
+        refund.record_refund_attempt(Err("busy".to_string()), 150);
+        store_escrow(&refund).unwrap();
+        assert_eq!(due_retries(200).0, vec![]);
+        refund.record_refund_attempt(Ok(7), 300);
+        store_escrow(&refund).unwrap();
+        assert_eq!(due_retries(u64::MAX), (vec![], vec![draw.process_id.clone()]));
+
+        // A populated index is left alone; an empty one is rebuilt from the entries
+        assert_eq!(rebuild_retry_index(), 0);
+        RETRY_INDEX.with(|i| *i.borrow_mut() = Some(StableBTreeMap::new(manager.get(MemoryId::new(2)))));
+        assert_eq!(rebuild_retry_index(), 1);
+        assert_eq!(due_retries(u64::MAX), (vec![], vec![draw.process_id]));
+
+        // The run flag is released when the guard drops
+        let guard = RefundRetryGuard::acquire().unwrap();
+        assert!(RefundRetryGuard::acquire().is_none());
+        drop(guard);
+        assert!(RefundRetryGuard::acquire().is_some());
+    }
+
+    #[test]
+    fn test_allowance_escrow_funding() {
+        let mut entry = EscrowEntry {
+            process_id: ProcessId::from_seed(9),
+            payer: Principal::from_text("aaaaa-aa").unwrap(),
+            amount: 20_000,
+            status: EscrowStatus::Held,
+            created_at: 0,
+            updated_at: 0,
+            rune_name: "TEST_RUNE".to_string(),
+            refund_attempts: None,
+            next_refund_at: None,
+            refund_created_at: None,
+            fee_mode: Some(FeeMode::Allowance),
+            drawn_block: None,
+            consumed_amount: None,
+            fee_created_at: None,
+            draw_attempts: None,
+            next_draw_at: None,
+        };
+
+        // An undrawn allowance holds nothing and can't be refunded
+        assert!(entry.can_refund());
+        assert!(!entry.is_funded());
+        assert!(!entry.holds_funds());
+
+        entry.drawn_block = Some(5);
+        assert!(entry.holds_funds());
+
+        // Nothing counts as collected without a recorded draw, whatever the mode
+        entry.fee_mode = Some(FeeMode::Upfront);
+        entry.drawn_block = None;
+        assert!(!entry.is_funded());
+        assert!(!entry.holds_funds());
+        entry.drawn_block = Some(6);
+        assert!(entry.is_funded());
+
+        // Entries from before fee modes were held as funded
+        entry.fee_mode = None;
+        entry.drawn_block = None;
+        assert!(entry.is_funded());
+
+        // Only the unused part of the estimate is refunded
+        entry.consumed_amount = Some(14_500);
+        assert_eq!(entry.refundable_amount(), 5_500);
+    }
+
+    #[test]
+    fn test_failed_draw_retries_then_escalates() {
+        let mut entry = EscrowEntry {
+            process_id: ProcessId::from_seed(11),
+            payer: Principal::from_text("aaaaa-aa").unwrap(),
+            amount: 14_500,
+            status: EscrowStatus::Held,
+            created_at: 0,
+            updated_at: 0,
+            rune_name: "TEST_RUNE".to_string(),
+            refund_attempts: None,
+            next_refund_at: None,
+            refund_created_at: None,
+            fee_mode: Some(FeeMode::Allowance),
+            drawn_block: None,
+            consumed_amount: None,
+            fee_created_at: Some(50),
+            draw_attempts: None,
+            next_draw_at: None,
+        };
+
+        entry.record_draw_failure("ledger busy".to_string(), 100);
+        assert!(matches!(entry.status, EscrowStatus::DrawFailed { .. }));
+        assert!(!entry.draw_due(100));
+        assert!(entry.draw_due(100 + REFUND_RETRY_BASE_DELAY_NS));
+        // Nothing was collected, so nothing is held or refundable
+        assert!(!entry.holds_funds());
+        assert!(!entry.refund_due(u64::MAX));
+
+        for now in 2..=MAX_DRAW_ATTEMPTS as u64 {
+            entry.record_draw_failure("ledger busy".to_string(), now * 100);
+        }
+        assert!(matches!(entry.status, EscrowStatus::ManualReview { .. }));
+        assert!(!entry.draw_due(u64::MAX));
+        // Every attempt reuses the pinned created_at_time
+        assert_eq!(entry.fee_created_at, Some(50));
+    }
 }
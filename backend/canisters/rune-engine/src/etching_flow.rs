@@ -3,6 +3,7 @@ use quri_types::{RuneEtching, UtxoSelection};
 
 use crate::config::EtchingConfig;
 use crate::errors::{EtchingError, EtchingResult};
+use crate::escrow::FeeMode;
 use crate::process_id::ProcessId;
 use crate::state::{EtchingProcess, EtchingState};
 use crate::validators::EtchingValidator;
@@ -10,6 +11,9 @@ use crate::validators::EtchingValidator;
 /// Maximum retry attempts for transient failures
 const MAX_RETRIES: u32 = 3;
 
+/// ckBTC ledger fee; an allowance must cover it on top of the etching fee
+const CKBTC_TRANSFER_FEE: u64 = 10;
+
 /// Main orchestrator for Rune etching process
 pub struct EtchingOrchestrator {
     config: EtchingConfig,
@@ -25,6 +29,7 @@ impl EtchingOrchestrator {
         &self,
         caller: Principal,
         etching: RuneEtching,
+        fee_mode: FeeMode,
     ) -> EtchingResult<EtchingProcess> {
         // Generate unique process ID (async for random bytes)
         let process_id = self.generate_process_id().await?;
@@ -42,7 +47,7 @@ impl EtchingOrchestrator {
         self.save_process(&process)?;
 
         // Execute flow with error handling
-        match self.execute_flow(&mut process, caller, etching).await {
+        match self.execute_flow(&mut process, caller, etching, fee_mode).await {
             Ok(()) => {
                 self.save_process(&process)?;
                 Ok(process)
@@ -71,15 +76,17 @@ impl EtchingOrchestrator {
         process: &mut EtchingProcess,
         caller: Principal,
         etching: RuneEtching,
+        fee_mode: FeeMode,
     ) -> EtchingResult<()> {
         // Step 1: Validation
         self.step_validate(process, &etching).await?;
 
         // Step 2: Check ckBTC balance
-        let _balance = self.step_check_balance(process, caller).await?;
+        let _balance = self.step_check_balance(process, caller, fee_mode).await?;
 
         // Step 3: Select UTXOs
         let utxo_selection = self.step_select_utxos(process).await?;
+        let spent_utxos = utxo_selection.selected.clone();
 
         // Step 4: Build and sign transaction (combined)
         let signed_tx = self
@@ -89,6 +96,9 @@ impl EtchingOrchestrator {
         // Step 5: Broadcast
         let txid = self.step_broadcast(process, &signed_tx).await?;
 
+        // Charge or refund the difference between the estimated and actual L1 fee
+        self.settle_fee(process, &signed_tx, &spent_utxos).await;
+
         // Step 6: Wait for confirmations
         // The confirmation tracker will update the state when confirmations are reached
         self.step_confirm(process, &txid).await?;
@@ -127,10 +137,15 @@ impl EtchingOrchestrator {
     }
 
     /// Step 2: Check ckBTC balance and charge fee
+    ///
+    /// In `FeeMode::Allowance` nothing is charged yet: the caller's ICRC-2
+    /// allowance is checked and referenced by the escrow, and the fee is drawn
+    /// after broadcast.
     async fn step_check_balance(
         &self,
         process: &mut EtchingProcess,
         caller: Principal,
+        fee_mode: FeeMode,
     ) -> EtchingResult<u64> {
         process.update_state(EtchingState::CheckingBalance);
         self.save_process(process)?;
@@ -160,16 +175,49 @@ impl EtchingOrchestrator {
         // Validate balance before charging
         EtchingValidator::validate_balance(balance, estimated_fee)?;
 
-        // Charge fee and store in escrow
-        // NOTE: In a real implementation, we would call charge_etching_fee here
-        // For now, we just track it in the escrow system
+        if fee_mode == FeeMode::Allowance {
+            let (allowance_result,): (Result<u64, String>,) =
+                ic_cdk::call(btc_canister_id, "get_etching_allowance", (caller,))
+                    .await
+                    .map_err(|(code, msg)| {
+                        EtchingError::CkBtcError(format!(
+                            "Failed to get ckBTC allowance: {:?} - {}",
+                            code, msg
+                        ))
+                    })?;
+            let allowance = allowance_result.map_err(EtchingError::CkBtcError)?;
+            let required = estimated_fee + CKBTC_TRANSFER_FEE;
+            if allowance < required {
+                return Err(EtchingError::InsufficientBalance {
+                    have: allowance,
+                    need: required,
+                });
+            }
+
+            let escrow_entry = crate::escrow::EscrowEntry::with_allowance(
+                process.id.clone(),
+                caller,
+                estimated_fee,
+                process.rune_name.clone(),
+            );
+            crate::escrow::store_escrow(&escrow_entry)
+                .map_err(|e| EtchingError::InternalError(format!("Failed to store escrow: {}", e)))?;
+
+            ic_cdk::println!(
+                "[Etching {}] Allowance of {} sats held in escrow; charged on broadcast",
+                process.id,
+                allowance
+            );
+            return Ok(balance);
+        }
+
+        // Draw the estimate into escrow now; the unused part is refunded later
         ic_cdk::println!(
             "[Etching {}] Charging fee: {} sats",
             process.id,
             estimated_fee
         );
 
-        // Create escrow entry to track the fee
         let escrow_entry = crate::escrow::EscrowEntry::new(
             process.id.clone(),
             caller,
@@ -180,6 +228,13 @@ impl EtchingOrchestrator {
         crate::escrow::store_escrow(&escrow_entry)
             .map_err(|e| EtchingError::InternalError(format!("Failed to store escrow: {}", e)))?;
 
+        if let Err(e) = crate::escrow::draw_fee(&process.id, estimated_fee).await {
+            if let Err(release_err) = crate::escrow::release_escrow(&process.id) {
+                ic_cdk::println!("[Etching {}] Failed to release escrow: {}", process.id, release_err);
+            }
+            return Err(EtchingError::CkBtcError(format!("Failed to charge etching fee: {}", e)));
+        }
+
         // Update process with fee paid
         process.fee_paid = Some(estimated_fee);
 
@@ -322,6 +377,38 @@ impl EtchingOrchestrator {
         Ok(txid)
     }
 
+    /// Settle the escrowed fee against what the broadcast transaction paid
+    ///
+    /// The transaction is already out, so failures are logged rather than
+    /// failing the etching.
+    async fn settle_fee(&self, process: &mut EtchingProcess, signed_tx: &[u8], spent: &[quri_types::Utxo]) {
+        let actual_fee = match bitcoin_utils::transaction::transaction_fee(signed_tx, spent) {
+            Ok(fee) => fee,
+            Err(e) => {
+                crate::logging::log_error(
+                    "etching_fee",
+                    format!("Could not compute L1 fee: {}", e),
+                    Some(process.id.to_string()),
+                );
+                return;
+            }
+        };
+
+        match crate::escrow::settle_fee(&process.id, actual_fee).await {
+            Ok(()) => {
+                if let Some(entry) = crate::escrow::get_escrow(&process.id) {
+                    process.fee_paid = Some(entry.consumed_amount.unwrap_or(entry.amount));
+                }
+                ic_cdk::println!("[Etching {}] Fee settled: {} sats L1 fee", process.id, actual_fee);
+            }
+            Err(e) => crate::logging::log_error(
+                "etching_fee",
+                format!("Failed to settle etching fee of {} sats: {}", actual_fee, e),
+                Some(process.id.to_string()),
+            ),
+        }
+    }
+
     /// Step 7: Wait for confirmations
     ///
     /// Now uses the confirmation_tracker which runs periodically.
@@ -379,9 +466,13 @@ impl EtchingOrchestrator {
         ic_cdk::println!("[Etching {}] Rolling back...", process.id);
 
         // Check if there's an escrow entry to refund
-        if let Some(mut escrow_entry) = crate::escrow::get_escrow(&process.id) {
-            // Only refund if escrow is still held
-            if escrow_entry.can_refund() {
+        if let Some(escrow_entry) = crate::escrow::get_escrow(&process.id) {
+            // A fee that was never drawn only needs releasing
+            if escrow_entry.can_refund() && !escrow_entry.is_funded() {
+                if let Err(e) = crate::escrow::release_escrow(&process.id) {
+                    ic_cdk::println!("[Etching {}] Failed to release allowance escrow: {}", process.id, e);
+                }
+            } else if escrow_entry.can_refund() {
                 ic_cdk::println!(
                     "[Etching {}] Refunding {} sats to {}",
                     process.id,
@@ -389,44 +480,23 @@ impl EtchingOrchestrator {
                     escrow_entry.payer
                 );
 
-                // Attempt to refund via ckBTC transfer
-                match self.refund_ckbtc(&escrow_entry).await {
+                // Failed refunds are retried by the escrow refund timer
+                match crate::escrow::refund_escrow(&process.id, false).await {
                     Ok(block_index) => {
                         ic_cdk::println!(
                             "[Etching {}] Refund successful, block index: {}",
                             process.id,
                             block_index
                         );
-
-                        // Mark escrow as refunded
-                        escrow_entry.mark_refunded(block_index);
-                        if let Err(e) = crate::escrow::update_escrow(&escrow_entry) {
-                            ic_cdk::println!(
-                                "[Etching {}] Warning: Failed to update escrow after refund: {}",
-                                process.id,
-                                e
-                            );
-                        }
                     }
                     Err(e) => {
                         ic_cdk::println!(
-                            "[Etching {}] Refund failed: {}",
+                            "[Etching {}] Refund failed, will retry: {}",
                             process.id,
                             e
                         );
 
-                        // Mark escrow refund as failed for manual intervention
-                        escrow_entry.mark_refund_failed(e.clone());
-                        if let Err(update_err) = crate::escrow::update_escrow(&escrow_entry) {
-                            ic_cdk::println!(
-                                "[Etching {}] Critical: Failed to update escrow after refund failure: {}",
-                                process.id,
-                                update_err
-                            );
-                        }
-
-                        // Log error for admin review
-                        crate::logging::log_error(
+                        crate::logging::log_warn(
                             "rollback_refund",
                             format!("Failed to refund {} sats to {}: {}",
                                 escrow_entry.amount, escrow_entry.payer, e),
@@ -456,28 +526,6 @@ impl EtchingOrchestrator {
         Ok(())
     }
 
-    /// Refund ckBTC to user
-    async fn refund_ckbtc(&self, escrow: &crate::escrow::EscrowEntry) -> Result<u64, String> {
-        // Get bitcoin-integration canister ID
-        let btc_canister_id = crate::get_bitcoin_integration_id()
-            .map_err(|e| format!("Failed to get bitcoin integration canister: {}", e))?;
-
-        // Call ckBTC transfer to refund the user
-        // We use the transfer function from bitcoin-integration which wraps ckBTC ledger calls
-        let memo = format!("Refund for failed etching: {}", escrow.rune_name);
-        let (transfer_result,): (Result<u64, String>,) = ic_cdk::call(
-            btc_canister_id,
-            "transfer_ckbtc",
-            (escrow.payer, escrow.amount, Some(memo.into_bytes())),
-        )
-        .await
-        .map_err(|(code, msg)| {
-            format!("ckBTC transfer call failed: {:?} - {}", code, msg)
-        })?;
-
-        transfer_result
-    }
-
     /// Check if error should trigger rollback
     pub(crate) fn should_rollback(&self, error: &EtchingError) -> bool {
         matches!(
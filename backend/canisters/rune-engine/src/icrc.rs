@@ -0,0 +1,878 @@
+/*!
+ * Token Interface for Virtual Runes
+ *
+ * Exposes every virtual rune as a token served by the rune engine itself,
+ * with ICRC-1/ICRC-2 semantics. The engine's own endpoints are multi-token
+ * (every method takes the rune ID first, as `rune_token_*`), so they are not
+ * standard ICRC-1/ICRC-2 endpoints. Wallets see a rune through its
+ * `rune-ledger` canister instead: one per rune, registered here with
+ * `set_rune_ledger`, serving the standard methods and forwarding updates
+ * through `rune_ledger_*` with the original caller. Balances are the
+ * accounting ledger's rune balances:
+ * - Default subaccount (None or all zeros) -> the user's available balance
+ * - Any other subaccount -> a dedicated subaccount ledger account
+ *
+ * Transfers are free (fee 0) and deduplicated on `created_at_time` as in
+ * the ICRC-1 spec. ICRC-2 approvals are kept per (rune, owner, spender).
+ * Transaction indices are ICRC-3 block indices (see `icrc3`).
+ */
+
+use candid::{CandidType, Decode, Deserialize, Encode, Nat, Principal};
+use ic_stable_structures::memory_manager::VirtualMemory;
+use ic_stable_structures::{storable::Bound, DefaultMemoryImpl, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use crate::accounting::{self, Asset, EntryReason, LedgerAccount};
+use crate::icrc3;
+use crate::ledger::{Account, TransferArg, TransferError};
+use crate::state;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Rune ledger registry, keyed by tag and rune ID or ledger principal
+type LedgerMap = StableBTreeMap<Vec<u8>, Vec<u8>, Memory>;
+
+/// Transfers of virtual runes are internal and free
+pub const ICRC_FEE: u64 = 0;
+
+/// Deduplication window for `created_at_time` (24 hours)
+pub const TX_WINDOW_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Permitted clock drift for `created_at_time` (2 minutes)
+pub const PERMITTED_DRIFT_NANOS: u64 = 2 * 60 * 1_000_000_000;
+
+/// Maximum memo length in bytes
+pub const MAX_MEMO_LENGTH: usize = 32;
+
+/// Generic error code for an unknown rune ID
+const ERROR_UNKNOWN_RUNE: u64 = 1;
+
+/// Generic error code for malformed arguments
+const ERROR_INVALID_ARGUMENT: u64 = 2;
+
+/// Generic error code for a caller that is not a registered rune ledger
+const ERROR_UNKNOWN_LEDGER: u64 = 3;
+
+// Rune ledger registry key tags
+const LEDGER_BY_RUNE: u8 = 0;
+const RUNE_BY_LEDGER: u8 = 1;
+
+// ============================================================================
+// ICRC TYPES
+// ============================================================================
+
+/// Value of a metadata entry
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum MetadataValue {
+    Nat(Nat),
+    Int(candid::Int),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+/// Entry of `icrc10_supported_standards`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct StandardRecord {
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ApproveArgs {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub spender: Account,
+    pub amount: Nat,
+    pub expected_allowance: Option<Nat>,
+    pub expires_at: Option<u64>,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum ApproveError {
+    BadFee { expected_fee: Nat },
+    InsufficientFunds { balance: Nat },
+    AllowanceChanged { current_allowance: Nat },
+    Expired { ledger_time: u64 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AllowanceArgs {
+    pub account: Account,
+    pub spender: Account,
+}
+
+/// Current allowance of a spender
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Allowance {
+    pub allowance: Nat,
+    pub expires_at: Option<u64>,
+}
+
+/// Failure shared by every ICRC operation, mapped to each error type
+#[derive(Clone, Debug, PartialEq)]
+enum CommonError {
+    BadFee,
+    InsufficientFunds(u64),
+    TooOld,
+    CreatedInFuture(u64),
+    Duplicate(u64),
+    Generic(u64, String),
+}
+
+/// Why a deduplicated transaction was rejected
+#[derive(Clone, Debug, PartialEq)]
+pub enum DedupError {
+    TooOld,
+    CreatedInFuture(u64),
+    /// Index recorded for the original transaction
+    Duplicate(u64),
+}
+
+impl From<DedupError> for CommonError {
+    fn from(e: DedupError) -> Self {
+        match e {
+            DedupError::TooOld => CommonError::TooOld,
+            DedupError::CreatedInFuture(ledger_time) => CommonError::CreatedInFuture(ledger_time),
+            DedupError::Duplicate(index) => CommonError::Duplicate(index),
+        }
+    }
+}
+
+impl From<CommonError> for TransferError {
+    fn from(e: CommonError) -> Self {
+        match e {
+            CommonError::BadFee => TransferError::BadFee { expected_fee: Nat::from(ICRC_FEE) },
+            CommonError::InsufficientFunds(balance) => TransferError::InsufficientFunds { balance: Nat::from(balance) },
+            CommonError::TooOld => TransferError::TooOld,
+            CommonError::CreatedInFuture(ledger_time) => TransferError::CreatedInFuture { ledger_time },
+            CommonError::Duplicate(index) => TransferError::Duplicate { duplicate_of: Nat::from(index) },
+            CommonError::Generic(code, message) => TransferError::GenericError { error_code: Nat::from(code), message },
+        }
+    }
+}
+
+impl From<CommonError> for ApproveError {
+    fn from(e: CommonError) -> Self {
+        match e {
+            CommonError::BadFee => ApproveError::BadFee { expected_fee: Nat::from(ICRC_FEE) },
+            CommonError::InsufficientFunds(balance) => ApproveError::InsufficientFunds { balance: Nat::from(balance) },
+            CommonError::TooOld => ApproveError::TooOld,
+            CommonError::CreatedInFuture(ledger_time) => ApproveError::CreatedInFuture { ledger_time },
+            CommonError::Duplicate(index) => ApproveError::Duplicate { duplicate_of: Nat::from(index) },
+            CommonError::Generic(code, message) => ApproveError::GenericError { error_code: Nat::from(code), message },
+        }
+    }
+}
+
+impl From<CommonError> for TransferFromError {
+    fn from(e: CommonError) -> Self {
+        match e {
+            CommonError::BadFee => TransferFromError::BadFee { expected_fee: Nat::from(ICRC_FEE) },
+            CommonError::InsufficientFunds(balance) => TransferFromError::InsufficientFunds { balance: Nat::from(balance) },
+            CommonError::TooOld => TransferFromError::TooOld,
+            CommonError::CreatedInFuture(ledger_time) => TransferFromError::CreatedInFuture { ledger_time },
+            CommonError::Duplicate(index) => TransferFromError::Duplicate { duplicate_of: Nat::from(index) },
+            CommonError::Generic(code, message) => TransferFromError::GenericError { error_code: Nat::from(code), message },
+        }
+    }
+}
+
+// ============================================================================
+// STORAGE
+// ============================================================================
+
+/// Stored allowance (amount in rune base units)
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct StoredAllowance {
+    amount: u64,
+    expires_at: Option<u64>,
+}
+
+impl Storable for StoredAllowance {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("Failed to encode StoredAllowance"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).expect("Failed to decode StoredAllowance")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    /// Allowances keyed by (rune ID, owner account, spender account)
+    static ALLOWANCES: RefCell<Option<StableBTreeMap<Vec<u8>, StoredAllowance, Memory>>> = const { RefCell::new(None) };
+
+    /// Recent transactions for deduplication: (created_at_time, tx hash) -> block index
+    static RECENT_TRANSACTIONS: RefCell<Option<StableBTreeMap<Vec<u8>, u64, Memory>>> = const { RefCell::new(None) };
+
+    /// Per-rune ledger canisters, both ways: rune ID -> ledger, ledger -> rune ID
+    static RUNE_LEDGERS: RefCell<Option<LedgerMap>> = const { RefCell::new(None) };
+}
+
+/// Initialize ICRC storage (also restores it after upgrade)
+pub fn init_icrc_storage(allowances_memory: Memory, recent_memory: Memory, ledgers_memory: Memory) {
+    ALLOWANCES.with(|a| {
+        *a.borrow_mut() = Some(StableBTreeMap::init(allowances_memory));
+    });
+    RECENT_TRANSACTIONS.with(|r| {
+        *r.borrow_mut() = Some(StableBTreeMap::init(recent_memory));
+    });
+    RUNE_LEDGERS.with(|l| {
+        *l.borrow_mut() = Some(StableBTreeMap::init(ledgers_memory));
+    });
+}
+
+/// Index of the block just appended for this transaction
+fn last_block_index() -> u64 {
+    icrc3::log_length().saturating_sub(1)
+}
+
+// ============================================================================
+// ACCOUNTS
+// ============================================================================
+
+/// Ledger account backing an ICRC account
+pub fn ledger_account(account: &Account) -> Result<LedgerAccount, String> {
+    match account.subaccount {
+        None => Ok(LedgerAccount::Available(account.owner)),
+        Some(ref subaccount) if subaccount.len() != 32 => {
+            Err("Subaccount must be 32 bytes".to_string())
+        }
+        Some(ref subaccount) if subaccount.iter().all(|b| *b == 0) => {
+            Ok(LedgerAccount::Available(account.owner))
+        }
+        Some(ref subaccount) => Ok(LedgerAccount::Subaccount(account.owner, subaccount.clone())),
+    }
+}
+
+fn account_bytes(bytes: &mut Vec<u8>, account: &Account) {
+    bytes.push(account.owner.as_slice().len() as u8);
+    bytes.extend_from_slice(account.owner.as_slice());
+    match account.subaccount {
+        Some(ref subaccount) if subaccount.iter().any(|b| *b != 0) => bytes.extend_from_slice(subaccount),
+        _ => bytes.extend_from_slice(&[0u8; 32]),
+    }
+}
+
+fn allowance_key(rune_id: &str, owner: &Account, spender: &Account) -> Vec<u8> {
+    let mut key = Vec::with_capacity(rune_id.len() + 100);
+    key.push(rune_id.len() as u8);
+    key.extend_from_slice(rune_id.as_bytes());
+    account_bytes(&mut key, owner);
+    account_bytes(&mut key, spender);
+    key
+}
+
+fn to_u64(amount: &Nat) -> Result<u64, CommonError> {
+    u64::try_from(&amount.0)
+        .map_err(|_| CommonError::Generic(ERROR_INVALID_ARGUMENT, "Amount exceeds u64".to_string()))
+}
+
+// ============================================================================
+// RUNE LEDGERS
+// ============================================================================
+
+fn ledger_key(tag: u8, bytes: &[u8]) -> Vec<u8> {
+    let mut key = vec![tag];
+    key.extend_from_slice(bytes);
+    key
+}
+
+/// Register the ICRC-1/ICRC-2 ledger canister serving `rune_id`
+///
+/// Replaces the rune's previous ledger; a ledger serves one rune only.
+pub fn set_rune_ledger(rune_id: &str, ledger: Principal) -> Result<(), String> {
+    if !rune_exists(rune_id) {
+        return Err(format!("Unknown rune: {}", rune_id));
+    }
+    bind_ledger(rune_id, ledger)
+}
+
+fn bind_ledger(rune_id: &str, ledger: Principal) -> Result<(), String> {
+    if ledger_rune(&ledger).is_some_and(|served| served != rune_id) {
+        return Err(format!("{} already serves another rune", ledger));
+    }
+
+    RUNE_LEDGERS.with(|l| {
+        if let Some(ref mut map) = *l.borrow_mut() {
+            if let Some(previous) = map.insert(ledger_key(LEDGER_BY_RUNE, rune_id.as_bytes()), ledger.as_slice().to_vec()) {
+                map.remove(&ledger_key(RUNE_BY_LEDGER, &previous));
+            }
+            map.insert(ledger_key(RUNE_BY_LEDGER, ledger.as_slice()), rune_id.as_bytes().to_vec());
+        }
+    });
+    Ok(())
+}
+
+/// Ledger canister registered for a rune
+pub fn rune_ledger(rune_id: &str) -> Option<Principal> {
+    RUNE_LEDGERS.with(|l| l.borrow().as_ref().and_then(|map| map.get(&ledger_key(LEDGER_BY_RUNE, rune_id.as_bytes()))))
+        .and_then(|bytes| Principal::try_from_slice(&bytes).ok())
+}
+
+/// Rune served by a registered ledger canister
+pub fn ledger_rune(ledger: &Principal) -> Option<String> {
+    RUNE_LEDGERS.with(|l| l.borrow().as_ref().and_then(|map| map.get(&ledger_key(RUNE_BY_LEDGER, ledger.as_slice()))))
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+}
+
+fn unknown_ledger(ledger: &Principal) -> CommonError {
+    CommonError::Generic(ERROR_UNKNOWN_LEDGER, format!("{} is not a registered rune ledger", ledger))
+}
+
+// ============================================================================
+// QUERIES
+// ============================================================================
+
+fn rune_exists(rune_id: &str) -> bool {
+    state::get_virtual_rune(rune_id).is_some()
+}
+
+fn unknown_rune(rune_id: &str) -> CommonError {
+    CommonError::Generic(ERROR_UNKNOWN_RUNE, format!("Unknown rune: {}", rune_id))
+}
+
+/// Standards implemented by the rune engine
+///
+/// Only the transaction log is standard; the rune token endpoints take a
+/// rune ID and therefore don't implement ICRC-1/ICRC-2 (the per-rune ledger
+/// canisters do).
+pub fn supported_standards() -> Vec<StandardRecord> {
+    vec![
+        StandardRecord {
+            name: "ICRC-3".to_string(),
+            url: "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-3".to_string(),
+        },
+        StandardRecord {
+            name: "ICRC-10".to_string(),
+            url: "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-10".to_string(),
+        },
+    ]
+}
+
+/// Token metadata for a rune (empty if the rune does not exist)
+pub fn metadata(rune_id: &str) -> Vec<(String, MetadataValue)> {
+    let Some(rune) = state::get_virtual_rune(rune_id) else {
+        return vec![];
+    };
+    vec![
+        ("icrc1:name".to_string(), MetadataValue::Text(rune.etching.rune_name)),
+        ("icrc1:symbol".to_string(), MetadataValue::Text(rune.etching.symbol)),
+        ("icrc1:decimals".to_string(), MetadataValue::Nat(Nat::from(rune.etching.divisibility))),
+        ("icrc1:fee".to_string(), MetadataValue::Nat(Nat::from(ICRC_FEE))),
+        ("quri:rune_id".to_string(), MetadataValue::Text(rune.id)),
+    ]
+}
+
+/// Issued supply of a rune
+pub fn total_supply(rune_id: &str) -> u64 {
+    accounting::balance_of(&LedgerAccount::Custody, &Asset::Rune(rune_id.to_string()))
+}
+
+/// Balance of an ICRC account (0 for malformed subaccounts)
+pub fn balance_of(rune_id: &str, account: &Account) -> u64 {
+    ledger_account(account)
+        .map(|a| accounting::balance_of(&a, &Asset::Rune(rune_id.to_string())))
+        .unwrap_or(0)
+}
+
+/// Current (unexpired) allowance of `spender` over `account`
+pub fn allowance(rune_id: &str, args: &AllowanceArgs, now: u64) -> Allowance {
+    let key = allowance_key(rune_id, &args.account, &args.spender);
+    ALLOWANCES.with(|a| a.borrow().as_ref().and_then(|map| map.get(&key)))
+        .filter(|stored| stored.expires_at.map(|e| e > now).unwrap_or(true))
+        .map(|stored| Allowance {
+            allowance: Nat::from(stored.amount),
+            expires_at: stored.expires_at,
+        })
+        .unwrap_or_default()
+}
+
+// ============================================================================
+// DEDUPLICATION
+// ============================================================================
+
+/// Validate common fields and return the dedup key, if the tx is deduplicated
+fn check_common(
+    fee: &Option<Nat>,
+    memo: &Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+    tx_hash: [u8; 32],
+    now: u64,
+) -> Result<Option<Vec<u8>>, CommonError> {
+    if let Some(fee) = fee {
+        if *fee != ICRC_FEE {
+            return Err(CommonError::BadFee);
+        }
+    }
+    if memo.as_ref().map(|m| m.len() > MAX_MEMO_LENGTH).unwrap_or(false) {
+        return Err(CommonError::Generic(
+            ERROR_INVALID_ARGUMENT,
+            format!("Memo exceeds {} bytes", MAX_MEMO_LENGTH),
+        ));
+    }
+
+    match created_at_time {
+        Some(created_at) => Ok(Some(check_recent_transaction(created_at, tx_hash, now)?)),
+        None => Ok(None),
+    }
+}
+
+/// Check `created_at` against the dedup window and look up `tx_hash`
+///
+/// Returns the key to pass to `record_recent_transaction` once the
+/// transaction succeeds. Callers hash their own domain tag into `tx_hash`.
+pub fn check_recent_transaction(created_at: u64, tx_hash: [u8; 32], now: u64) -> Result<Vec<u8>, DedupError> {
+    if created_at.saturating_add(TX_WINDOW_NANOS + PERMITTED_DRIFT_NANOS) < now {
+        return Err(DedupError::TooOld);
+    }
+    if created_at > now.saturating_add(PERMITTED_DRIFT_NANOS) {
+        return Err(DedupError::CreatedInFuture(now));
+    }
+
+    prune_recent_transactions(now);
+
+    let mut key = created_at.to_be_bytes().to_vec();
+    key.extend_from_slice(&tx_hash);
+    if let Some(index) = RECENT_TRANSACTIONS.with(|r| r.borrow().as_ref().and_then(|map| map.get(&key))) {
+        return Err(DedupError::Duplicate(index));
+    }
+    Ok(key)
+}
+
+/// Remember a successful deduplicated transaction
+pub fn record_recent_transaction(dedup_key: Option<Vec<u8>>, index: u64) {
+    if let Some(key) = dedup_key {
+        RECENT_TRANSACTIONS.with(|r| {
+            if let Some(ref mut map) = *r.borrow_mut() {
+                map.insert(key, index);
+            }
+        });
+    }
+}
+
+/// Drop transactions that can no longer be replayed
+fn prune_recent_transactions(now: u64) {
+    let cutoff = now.saturating_sub(TX_WINDOW_NANOS + PERMITTED_DRIFT_NANOS);
+    RECENT_TRANSACTIONS.with(|r| {
+        if let Some(ref mut map) = *r.borrow_mut() {
+            while let Some((key, _)) = map.first_key_value() {
+                let created_at = u64::from_be_bytes(key[..8].try_into().unwrap_or([0; 8]));
+                if created_at >= cutoff {
+                    break;
+                }
+                map.remove(&key);
+            }
+        }
+    });
+}
+
+fn tx_hash<T: CandidType>(kind: &str, rune_id: &str, caller: Principal, args: &T) -> [u8; 32] {
+    let encoded = Encode!(&kind, &rune_id, &caller, args).unwrap_or_default();
+    Sha256::digest(&encoded).into()
+}
+
+// ============================================================================
+// OPERATIONS
+// ============================================================================
+
+/// ICRC-1 transfer from the caller's account
+pub fn transfer(rune_id: &str, caller: Principal, arg: TransferArg, now: u64) -> Result<u64, TransferError> {
+    if !rune_exists(rune_id) {
+        return Err(unknown_rune(rune_id).into());
+    }
+    transfer_unchecked(rune_id, caller, arg, now).map_err(Into::into)
+}
+
+fn transfer_unchecked(rune_id: &str, caller: Principal, arg: TransferArg, now: u64) -> Result<u64, CommonError> {
+    let amount = to_u64(&arg.amount)?;
+    let from = Account { owner: caller, subaccount: arg.from_subaccount.clone() };
+    let dedup_key = check_common(
+        &arg.fee,
+        &arg.memo,
+        arg.created_at_time,
+        tx_hash("icrc1_transfer", rune_id, caller, &arg),
+        now,
+    )?;
+
+    move_runes(rune_id, &from, &arg.to, amount)?;
+
+    let index = last_block_index();
+    record_recent_transaction(dedup_key, index);
+    Ok(index)
+}
+
+/// ICRC-1 transfer forwarded by a rune ledger on behalf of `caller`
+pub fn ledger_transfer(ledger: Principal, caller: Principal, arg: TransferArg, now: u64) -> Result<u64, TransferError> {
+    let rune_id = ledger_rune(&ledger).ok_or_else(|| TransferError::from(unknown_ledger(&ledger)))?;
+    transfer(&rune_id, caller, arg, now)
+}
+
+/// ICRC-2 approve: set the spender's allowance over the caller's account
+pub fn approve(rune_id: &str, caller: Principal, args: ApproveArgs, now: u64) -> Result<u64, ApproveError> {
+    if !rune_exists(rune_id) {
+        return Err(unknown_rune(rune_id).into());
+    }
+    approve_unchecked(rune_id, caller, args, now)
+}
+
+fn approve_unchecked(rune_id: &str, caller: Principal, args: ApproveArgs, now: u64) -> Result<u64, ApproveError> {
+    let amount = to_u64(&args.amount)?;
+    let owner = Account { owner: caller, subaccount: args.from_subaccount.clone() };
+    ledger_account(&owner).map_err(|e| CommonError::Generic(ERROR_INVALID_ARGUMENT, e))?;
+    ledger_account(&args.spender).map_err(|e| CommonError::Generic(ERROR_INVALID_ARGUMENT, e))?;
+    if owner.owner == args.spender.owner {
+        return Err(CommonError::Generic(ERROR_INVALID_ARGUMENT, "Cannot approve self".to_string()).into());
+    }
+    if let Some(expires_at) = args.expires_at {
+        if expires_at <= now {
+            return Err(ApproveError::Expired { ledger_time: now });
+        }
+    }
+
+    let dedup_key = check_common(
+        &args.fee,
+        &args.memo,
+        args.created_at_time,
+        tx_hash("icrc2_approve", rune_id, caller, &args),
+        now,
+    )?;
+
+    let query = AllowanceArgs { account: owner.clone(), spender: args.spender.clone() };
+    if let Some(ref expected) = args.expected_allowance {
+        let current = allowance(rune_id, &query, now).allowance;
+        if current != *expected {
+            return Err(ApproveError::AllowanceChanged { current_allowance: current });
+        }
+    }
+
+    // Log first: an approval that cannot be logged is not applied
+    let index = icrc3::record_approval(rune_id, &owner, &args.spender, amount, args.expires_at, now)
+        .ok_or(ApproveError::TemporarilyUnavailable)?;
+
+    let key = allowance_key(rune_id, &owner, &args.spender);
+    ALLOWANCES.with(|a| {
+        if let Some(ref mut map) = *a.borrow_mut() {
+            if amount == 0 {
+                map.remove(&key);
+            } else {
+                map.insert(key, StoredAllowance { amount, expires_at: args.expires_at });
+            }
+        }
+    });
+
+    record_recent_transaction(dedup_key, index);
+    Ok(index)
+}
+
+/// ICRC-2 approve forwarded by a rune ledger on behalf of `caller`
+pub fn ledger_approve(ledger: Principal, caller: Principal, args: ApproveArgs, now: u64) -> Result<u64, ApproveError> {
+    let rune_id = ledger_rune(&ledger).ok_or_else(|| ApproveError::from(unknown_ledger(&ledger)))?;
+    approve(&rune_id, caller, args, now)
+}
+
+/// ICRC-2 transfer_from: move runes out of `from` using the caller's allowance
+pub fn transfer_from(
+    rune_id: &str,
+    caller: Principal,
+    args: TransferFromArgs,
+    now: u64,
+) -> Result<u64, TransferFromError> {
+    if !rune_exists(rune_id) {
+        return Err(unknown_rune(rune_id).into());
+    }
+    transfer_from_unchecked(rune_id, caller, args, now)
+}
+
+fn transfer_from_unchecked(
+    rune_id: &str,
+    caller: Principal,
+    args: TransferFromArgs,
+    now: u64,
+) -> Result<u64, TransferFromError> {
+    let amount = to_u64(&args.amount)?;
+    let spender = Account { owner: caller, subaccount: args.spender_subaccount.clone() };
+    let dedup_key = check_common(
+        &args.fee,
+        &args.memo,
+        args.created_at_time,
+        tx_hash("icrc2_transfer_from", rune_id, caller, &args),
+        now,
+    )?;
+
+    // The owner moving their own funds needs no allowance
+    let needs_allowance = args.from.owner != caller;
+    let key = allowance_key(rune_id, &args.from, &spender);
+    let current = if needs_allowance {
+        let query = AllowanceArgs { account: args.from.clone(), spender: spender.clone() };
+        let current = to_u64(&allowance(rune_id, &query, now).allowance)?;
+        if current < amount {
+            return Err(TransferFromError::InsufficientAllowance { allowance: Nat::from(current) });
+        }
+        current
+    } else {
+        0
+    };
+
+    move_runes(rune_id, &args.from, &args.to, amount)?;
+    let index = last_block_index();
+
+    if needs_allowance {
+        ALLOWANCES.with(|a| {
+            if let Some(ref mut map) = *a.borrow_mut() {
+                let remaining = current - amount;
+                if remaining == 0 {
+                    map.remove(&key);
+                } else if let Some(mut stored) = map.get(&key) {
+                    stored.amount = remaining;
+                    map.insert(key, stored);
+                }
+            }
+        });
+    }
+
+    record_recent_transaction(dedup_key, index);
+    Ok(index)
+}
+
+/// ICRC-2 transfer_from forwarded by a rune ledger on behalf of `caller`
+pub fn ledger_transfer_from(
+    ledger: Principal,
+    caller: Principal,
+    args: TransferFromArgs,
+    now: u64,
+) -> Result<u64, TransferFromError> {
+    let rune_id = ledger_rune(&ledger).ok_or_else(|| TransferFromError::from(unknown_ledger(&ledger)))?;
+    transfer_from(&rune_id, caller, args, now)
+}
+
+/// Post a rune transfer between two ICRC accounts
+fn move_runes(rune_id: &str, from: &Account, to: &Account, amount: u64) -> Result<(), CommonError> {
+    let from_account = ledger_account(from).map_err(|e| CommonError::Generic(ERROR_INVALID_ARGUMENT, e))?;
+    let to_account = ledger_account(to).map_err(|e| CommonError::Generic(ERROR_INVALID_ARGUMENT, e))?;
+    if amount == 0 {
+        return Err(CommonError::Generic(ERROR_INVALID_ARGUMENT, "Amount must be greater than 0".to_string()));
+    }
+    if from_account == to_account {
+        return Err(CommonError::Generic(ERROR_INVALID_ARGUMENT, "Cannot transfer to self".to_string()));
+    }
+
+    let asset = Asset::Rune(rune_id.to_string());
+    let balance = accounting::balance_of(&from_account, &asset);
+    if balance < amount {
+        return Err(CommonError::InsufficientFunds(balance));
+    }
+
+    accounting::post(asset, from_account, to_account, amount, EntryReason::Transfer, Some("icrc".to_string()))
+        .map_err(|e| CommonError::Generic(ERROR_INVALID_ARGUMENT, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+
+    const RUNE: &str = "840000:1";
+    const NOW: u64 = 1_700_000_000_000_000_000;
+
+    fn init_test_storage() {
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        accounting::init_accounting_storage(manager.get(MemoryId::new(0)), manager.get(MemoryId::new(1)));
+        init_icrc_storage(manager.get(MemoryId::new(2)), manager.get(MemoryId::new(3)), manager.get(MemoryId::new(6)));
+        icrc3::init_block_log_storage(manager.get(MemoryId::new(4)), manager.get(MemoryId::new(5)));
+    }
+
+    fn alice() -> Principal {
+        Principal::from_text("aaaaa-aa").unwrap()
+    }
+
+    fn bob() -> Principal {
+        Principal::from_text("2vxsx-fae").unwrap()
+    }
+
+    fn account(owner: Principal, subaccount: Option<Vec<u8>>) -> Account {
+        Account { owner, subaccount }
+    }
+
+    fn mint(owner: Principal, amount: u64) {
+        accounting::post(
+            Asset::Rune(RUNE.to_string()),
+            LedgerAccount::Custody,
+            LedgerAccount::Available(owner),
+            amount,
+            EntryReason::Premine,
+            None,
+        )
+        .unwrap();
+    }
+
+    fn transfer_arg(to: Account, amount: u64, created_at_time: Option<u64>) -> TransferArg {
+        TransferArg {
+            from_subaccount: None,
+            to,
+            amount: Nat::from(amount),
+            fee: None,
+            memo: None,
+            created_at_time,
+        }
+    }
+
+    #[test]
+    fn test_default_subaccount_maps_to_available_balance() {
+        assert_eq!(ledger_account(&account(alice(), None)), Ok(LedgerAccount::Available(alice())));
+        assert_eq!(
+            ledger_account(&account(alice(), Some(vec![0; 32]))),
+            Ok(LedgerAccount::Available(alice()))
+        );
+        assert_eq!(
+            ledger_account(&account(alice(), Some(vec![1; 32]))),
+            Ok(LedgerAccount::Subaccount(alice(), vec![1; 32]))
+        );
+        assert!(ledger_account(&account(alice(), Some(vec![1; 31]))).is_err());
+    }
+
+    #[test]
+    fn test_transfer_and_dedup() {
+        init_test_storage();
+        mint(alice(), 1_000);
+
+        // Transfer to a subaccount of bob
+        let to = account(bob(), Some(vec![9; 32]));
+        let arg = transfer_arg(to.clone(), 400, Some(NOW));
+        // Block 0 is the mint, the transfer is block 1
+        assert_eq!(transfer_unchecked(RUNE, alice(), arg.clone(), NOW), Ok(1));
+        assert_eq!(balance_of(RUNE, &account(alice(), None)), 600);
+        assert_eq!(balance_of(RUNE, &to), 400);
+        assert_eq!(balance_of(RUNE, &account(bob(), None)), 0);
+
+        // Same tx is rejected as duplicate
+        assert_eq!(transfer_unchecked(RUNE, alice(), arg, NOW), Err(CommonError::Duplicate(1)));
+
+        // Without created_at_time there is no dedup
+        let arg = transfer_arg(to.clone(), 100, None);
+        assert_eq!(transfer_unchecked(RUNE, alice(), arg.clone(), NOW), Ok(2));
+        assert_eq!(transfer_unchecked(RUNE, alice(), arg, NOW), Ok(3));
+
+        // Window checks, fee and funds
+        let arg = transfer_arg(to.clone(), 1, Some(NOW - TX_WINDOW_NANOS - PERMITTED_DRIFT_NANOS - 1));
+        assert_eq!(transfer_unchecked(RUNE, alice(), arg, NOW), Err(CommonError::TooOld));
+        let arg = transfer_arg(to.clone(), 1, Some(NOW + PERMITTED_DRIFT_NANOS + 1));
+        assert_eq!(transfer_unchecked(RUNE, alice(), arg, NOW), Err(CommonError::CreatedInFuture(NOW)));
+        let mut arg = transfer_arg(to.clone(), 1, None);
+        arg.fee = Some(Nat::from(10u64));
+        assert_eq!(transfer_unchecked(RUNE, alice(), arg, NOW), Err(CommonError::BadFee));
+        let arg = transfer_arg(to, 500, None);
+        assert_eq!(transfer_unchecked(RUNE, alice(), arg, NOW), Err(CommonError::InsufficientFunds(400)));
+    }
+
+    #[test]
+    fn test_approve_and_transfer_from() {
+        init_test_storage();
+        mint(alice(), 1_000);
+
+        let approve_args = ApproveArgs {
+            from_subaccount: None,
+            spender: account(bob(), None),
+            amount: Nat::from(300u64),
+            expected_allowance: None,
+            expires_at: None,
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+        assert_eq!(approve_unchecked(RUNE, alice(), approve_args.clone(), NOW), Ok(1));
+
+        // Expected allowance must match the current one
+        let mut changed = approve_args.clone();
+        changed.expected_allowance = Some(Nat::from(1u64));
+        assert_eq!(
+            approve_unchecked(RUNE, alice(), changed, NOW),
+            Err(ApproveError::AllowanceChanged { current_allowance: Nat::from(300u64) })
+        );
+
+        let transfer_args = |amount: u64| TransferFromArgs {
+            spender_subaccount: None,
+            from: account(alice(), None),
+            to: account(bob(), None),
+            amount: Nat::from(amount),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+        assert_eq!(
+            transfer_from_unchecked(RUNE, bob(), transfer_args(301), NOW),
+            Err(TransferFromError::InsufficientAllowance { allowance: Nat::from(300u64) })
+        );
+        assert_eq!(transfer_from_unchecked(RUNE, bob(), transfer_args(200), NOW), Ok(2));
+        assert_eq!(balance_of(RUNE, &account(bob(), None)), 200);
+
+        let query = AllowanceArgs { account: account(alice(), None), spender: account(bob(), None) };
+        assert_eq!(allowance(RUNE, &query, NOW).allowance, Nat::from(100u64));
+
+        // Expired allowances are ignored
+        let mut expiring = approve_args;
+        expiring.expires_at = Some(NOW + 10);
+        approve_unchecked(RUNE, alice(), expiring, NOW).unwrap();
+        assert_eq!(allowance(RUNE, &query, NOW + 10).allowance, Nat::from(0u64));
+    }
+
+    #[test]
+    fn test_rune_ledger_registry() {
+        init_test_storage();
+        let ledger = Principal::from_slice(&[7; 10]);
+        let replacement = Principal::from_slice(&[8; 10]);
+
+        // Unregistered canisters cannot forward transfers
+        let arg = transfer_arg(account(bob(), None), 1, None);
+        assert!(matches!(
+            ledger_transfer(ledger, alice(), arg, NOW),
+            Err(TransferError::GenericError { error_code, .. }) if error_code == ERROR_UNKNOWN_LEDGER
+        ));
+
+        bind_ledger(RUNE, ledger).unwrap();
+        assert_eq!(rune_ledger(RUNE), Some(ledger));
+        assert_eq!(ledger_rune(&ledger), Some(RUNE.to_string()));
+        assert!(bind_ledger("840000:2", ledger).is_err());
+
+        // Replacing a rune's ledger retires the old one
+        bind_ledger(RUNE, replacement).unwrap();
+        assert_eq!(rune_ledger(RUNE), Some(replacement));
+        assert_eq!(ledger_rune(&ledger), None);
+        assert_eq!(ledger_rune(&replacement), Some(RUNE.to_string()));
+    }
+}
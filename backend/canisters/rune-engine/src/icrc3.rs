@@ -0,0 +1,782 @@
+/*!
+ * ICRC-3 Block Log for Rune Balances
+ *
+ * Every rune balance movement posted to the accounting ledger (trade,
+ * transfer, premine, pool deposit, ...) and every ICRC-2 approval is
+ * appended to a hash-chained block log:
+ * - Each block stores the hash of its parent (`phash`)
+ * - The tip (last index + hash) is set as certified data
+ * - Older blocks are spilled to an archive canister by a timer
+ *
+ * Block hashes use the ICRC-3 representation-independent hash.
+ */
+
+use candid::{CandidType, Decode, Deserialize, Encode, Nat, Principal};
+use ic_stable_structures::memory_manager::VirtualMemory;
+use ic_stable_structures::{storable::Bound, DefaultMemoryImpl, StableBTreeMap, StableCell, Storable};
+use quri_types::{ArchivedBlocks, BlockWithId, GetBlocksArgs, GetBlocksCallback, GetBlocksResult, ICRC3Value};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use crate::accounting::{AccountKey, Asset, JournalEntry, LedgerAccount};
+use crate::ledger::Account;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Blocks kept in the engine before spilling to the archive
+pub const MAX_LOCAL_BLOCKS: u64 = 10_000;
+
+/// Blocks sent to the archive per call
+pub const ARCHIVE_BATCH_SIZE: u64 = 1_000;
+
+/// Maximum blocks returned by a single `icrc3_get_blocks` call
+pub const MAX_BLOCKS_PER_RESPONSE: u64 = 100;
+
+// ============================================================================
+// TYPES
+// ============================================================================
+
+/// Archive canister holding a range of blocks
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ICRC3ArchiveInfo {
+    pub canister_id: Principal,
+    pub start: Nat,
+    pub end: Nat,
+}
+
+/// Argument of `icrc3_get_archives`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetArchivesArgs {
+    /// Only return archives after this one
+    pub from: Option<Principal>,
+}
+
+/// Block type supported by the log
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SupportedBlockType {
+    pub block_type: String,
+    pub url: String,
+}
+
+/// Certificate for the log tip
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ICRC3DataCertificate {
+    pub certificate: Vec<u8>,
+    pub hash_tree: Vec<u8>,
+}
+
+/// Log bookkeeping
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+struct LogState {
+    /// Number of blocks ever appended
+    length: u64,
+    /// Hash of the last block
+    tip_hash: Option<Vec<u8>>,
+    /// Index of the first block still stored locally
+    first_local: u64,
+    /// Archive canister for spilled blocks
+    archive: Option<Principal>,
+}
+
+impl Storable for LogState {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("Failed to encode LogState"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap_or_default()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Encoded block
+#[derive(Clone, Debug)]
+struct StoredBlock(ICRC3Value);
+
+impl Storable for StoredBlock {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(&self.0).expect("Failed to encode block"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self(Decode!(&bytes, ICRC3Value).expect("Failed to decode block"))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// ============================================================================
+// STORAGE
+// ============================================================================
+
+thread_local! {
+    /// Local blocks by index
+    static BLOCKS: RefCell<Option<StableBTreeMap<u64, StoredBlock, Memory>>> = const { RefCell::new(None) };
+
+    /// Log length, tip hash and archive location
+    static LOG_STATE: RefCell<Option<StableCell<LogState, Memory>>> = const { RefCell::new(None) };
+
+    /// Guard against overlapping archive spills
+    static ARCHIVING: RefCell<bool> = const { RefCell::new(false) };
+}
+
+/// Initialize the block log (also restores it after upgrade)
+pub fn init_block_log_storage(blocks_memory: Memory, state_memory: Memory) {
+    BLOCKS.with(|b| {
+        *b.borrow_mut() = Some(StableBTreeMap::init(blocks_memory));
+    });
+    LOG_STATE.with(|s| {
+        *s.borrow_mut() = Some(
+            StableCell::init(state_memory, LogState::default())
+                .unwrap_or_else(|e| ic_cdk::trap(&format!("Failed to initialize ICRC-3 log state: {:?}", e))),
+        );
+    });
+}
+
+fn state() -> LogState {
+    LOG_STATE.with(|s| s.borrow().as_ref().map(|cell| cell.get().clone()).unwrap_or_default())
+}
+
+fn set_state(state: LogState) {
+    LOG_STATE.with(|s| {
+        if let Some(ref mut cell) = *s.borrow_mut() {
+            cell.set(state).expect("Failed to persist ICRC-3 log state");
+        }
+    });
+}
+
+// Environment for testing
+#[cfg(not(test))]
+fn engine_id() -> Principal {
+    ic_cdk::api::id()
+}
+
+#[cfg(test)]
+fn engine_id() -> Principal {
+    Principal::management_canister()
+}
+
+#[cfg(not(test))]
+fn set_certified_data(data: &[u8]) {
+    ic_cdk::api::set_certified_data(data);
+}
+
+#[cfg(test)]
+fn set_certified_data(_data: &[u8]) {}
+
+// ============================================================================
+// HASHING
+// ============================================================================
+
+/// ICRC-3 representation-independent hash
+pub fn hash_value(value: &ICRC3Value) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    match value {
+        ICRC3Value::Blob(bytes) => hasher.update(bytes),
+        ICRC3Value::Text(text) => hasher.update(text.as_bytes()),
+        ICRC3Value::Nat(nat) => {
+            let mut buf = Vec::new();
+            nat.encode(&mut buf).expect("Failed to encode Nat");
+            hasher.update(&buf);
+        }
+        ICRC3Value::Int(int) => {
+            let mut buf = Vec::new();
+            int.encode(&mut buf).expect("Failed to encode Int");
+            hasher.update(&buf);
+        }
+        ICRC3Value::Array(values) => {
+            for value in values {
+                hasher.update(hash_value(value));
+            }
+        }
+        ICRC3Value::Map(entries) => {
+            let mut pairs: Vec<Vec<u8>> = entries
+                .iter()
+                .map(|(key, value)| {
+                    let mut pair = Sha256::digest(key.as_bytes()).to_vec();
+                    pair.extend_from_slice(&hash_value(value));
+                    pair
+                })
+                .collect();
+            pairs.sort();
+            for pair in pairs {
+                hasher.update(&pair);
+            }
+        }
+    }
+    hasher.finalize().into()
+}
+
+// ============================================================================
+// BLOCK CONSTRUCTION
+// ============================================================================
+
+fn nat(value: u64) -> ICRC3Value {
+    ICRC3Value::Nat(Nat::from(value))
+}
+
+fn text(value: &str) -> ICRC3Value {
+    ICRC3Value::Text(value.to_string())
+}
+
+/// ICRC account value for a ledger account
+///
+/// User accounts map to the user's principal (and subaccount). Accounts the
+/// engine holds on behalf of others (pools, locked balances, fees, escrows)
+/// map to the engine principal with a subaccount derived from the account.
+fn account_value(account: &LedgerAccount) -> ICRC3Value {
+    match account {
+        LedgerAccount::Available(owner) => ICRC3Value::Array(vec![ICRC3Value::Blob(owner.as_slice().to_vec())]),
+        LedgerAccount::Subaccount(owner, subaccount) => ICRC3Value::Array(vec![
+            ICRC3Value::Blob(owner.as_slice().to_vec()),
+            ICRC3Value::Blob(subaccount.clone()),
+        ]),
+        other => ICRC3Value::Array(vec![
+            ICRC3Value::Blob(engine_id().as_slice().to_vec()),
+            ICRC3Value::Blob(Sha256::digest(AccountKey::account_prefix(other)).to_vec()),
+        ]),
+    }
+}
+
+fn icrc_account_value(account: &Account) -> ICRC3Value {
+    let mut parts = vec![ICRC3Value::Blob(account.owner.as_slice().to_vec())];
+    if let Some(ref subaccount) = account.subaccount {
+        if subaccount.iter().any(|b| *b != 0) {
+            parts.push(ICRC3Value::Blob(subaccount.clone()));
+        }
+    }
+    ICRC3Value::Array(parts)
+}
+
+/// Block for a rune journal entry (mint from / burn to custody, else transfer)
+fn posting_block(entry: &JournalEntry, rune_id: &str) -> (String, Vec<(String, ICRC3Value)>) {
+    let mut tx = vec![
+        ("amt".to_string(), nat(entry.amount)),
+        ("rune".to_string(), text(rune_id)),
+        ("op".to_string(), text(&format!("{:?}", entry.reason))),
+    ];
+    if let Some(ref reference) = entry.reference {
+        tx.push(("ref".to_string(), text(reference)));
+    }
+
+    let btype = match (&entry.debit, &entry.credit) {
+        (LedgerAccount::Custody, to) => {
+            tx.push(("to".to_string(), account_value(to)));
+            "1mint"
+        }
+        (from, LedgerAccount::Custody) => {
+            tx.push(("from".to_string(), account_value(from)));
+            "1burn"
+        }
+        (from, to) => {
+            tx.push(("from".to_string(), account_value(from)));
+            tx.push(("to".to_string(), account_value(to)));
+            "1xfer"
+        }
+    };
+    (btype.to_string(), tx)
+}
+
+/// Append a block built from `btype` and `tx`, returning its index
+fn append_block(btype: String, tx: Vec<(String, ICRC3Value)>, timestamp: u64) -> Option<u64> {
+    let initialized = BLOCKS.with(|b| b.borrow().is_some());
+    if !initialized {
+        return None;
+    }
+
+    let mut state = state();
+    let mut fields = vec![
+        ("btype".to_string(), ICRC3Value::Text(btype)),
+        ("ts".to_string(), nat(timestamp)),
+        ("tx".to_string(), ICRC3Value::Map(tx)),
+    ];
+    if let Some(ref parent) = state.tip_hash {
+        fields.push(("phash".to_string(), ICRC3Value::Blob(parent.clone())));
+    }
+    let block = ICRC3Value::Map(fields);
+    let hash = hash_value(&block);
+    let index = state.length;
+
+    BLOCKS.with(|b| {
+        if let Some(ref mut map) = *b.borrow_mut() {
+            map.insert(index, StoredBlock(block));
+        }
+    });
+
+    state.length += 1;
+    state.tip_hash = Some(hash.to_vec());
+    set_state(state);
+    certify_tip();
+
+    Some(index)
+}
+
+/// Record a journal entry in the log (rune movements only)
+pub fn record_posting(entry: &JournalEntry) {
+    if let Asset::Rune(ref rune_id) = entry.asset {
+        let (btype, tx) = posting_block(entry, rune_id);
+        append_block(btype, tx, entry.timestamp);
+    }
+}
+
+/// Record an ICRC-2 approval in the log
+pub fn record_approval(
+    rune_id: &str,
+    from: &Account,
+    spender: &Account,
+    amount: u64,
+    expires_at: Option<u64>,
+    timestamp: u64,
+) -> Option<u64> {
+    let mut tx = vec![
+        ("amt".to_string(), nat(amount)),
+        ("rune".to_string(), text(rune_id)),
+        ("from".to_string(), icrc_account_value(from)),
+        ("spender".to_string(), icrc_account_value(spender)),
+    ];
+    if let Some(expires_at) = expires_at {
+        tx.push(("expires_at".to_string(), nat(expires_at)));
+    }
+    append_block("2approve".to_string(), tx, timestamp)
+}
+
+/// Number of blocks ever appended
+pub fn log_length() -> u64 {
+    state().length
+}
+
+// ============================================================================
+// CERTIFICATION
+// ============================================================================
+
+fn domain_hash(domain: &str, parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([domain.len() as u8]);
+    hasher.update(domain.as_bytes());
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn leb128(value: u64) -> Vec<u8> {
+    let mut buf = Vec::new();
+    Nat::from(value).encode(&mut buf).expect("Failed to encode index");
+    buf
+}
+
+/// Root hash of the tip tree: fork(last_block_hash, last_block_index)
+fn tip_tree_root(index: u64, hash: &[u8]) -> [u8; 32] {
+    let hash_leaf = domain_hash("ic-hashtree-leaf", &[hash]);
+    let index_leaf = domain_hash("ic-hashtree-leaf", &[&leb128(index)]);
+    let hash_node = domain_hash("ic-hashtree-labeled", &[b"last_block_hash", &hash_leaf]);
+    let index_node = domain_hash("ic-hashtree-labeled", &[b"last_block_index", &index_leaf]);
+    domain_hash("ic-hashtree-fork", &[&hash_node, &index_node])
+}
+
+/// Labeled node certifying the proof-of-liabilities commitment
+fn liabilities_node(commitment: &[u8]) -> [u8; 32] {
+    let leaf = domain_hash("ic-hashtree-leaf", &[commitment]);
+    domain_hash("ic-hashtree-labeled", &[LIABILITIES_LABEL, &leaf])
+}
+
+/// Label of the liabilities commitment; sorts after the tip labels
+const LIABILITIES_LABEL: &[u8] = b"liabilities";
+
+fn tip() -> Option<(u64, Vec<u8>)> {
+    let state = state();
+    Some((state.length.checked_sub(1)?, state.tip_hash?))
+}
+
+/// Set certified data to the root of the certified tree (call after every
+/// append, liabilities snapshot and upgrade)
+///
+/// The tree is fork(tip, liabilities) once both exist, else whichever does.
+pub fn certify_tip() {
+    let tip_root = tip().map(|(index, hash)| tip_tree_root(index, &hash));
+    let liabilities = crate::liabilities::certified_commitment().map(|c| liabilities_node(&c));
+    let root = match (tip_root, liabilities) {
+        (Some(tip), Some(liabilities)) => domain_hash("ic-hashtree-fork", &[&tip, &liabilities]),
+        (Some(root), None) | (None, Some(root)) => root,
+        (None, None) => return,
+    };
+    set_certified_data(&root);
+}
+
+fn cbor_head(out: &mut Vec<u8>, major: u8, len: u64) {
+    let major = major << 5;
+    if len < 24 {
+        out.push(major | len as u8);
+    } else if len < 256 {
+        out.push(major | 24);
+        out.push(len as u8);
+    } else if len < 65_536 {
+        out.push(major | 25);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        out.push(major | 26);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+}
+
+fn cbor_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    cbor_head(out, 2, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn cbor_labeled_leaf(out: &mut Vec<u8>, label: &[u8], leaf: &[u8]) {
+    cbor_head(out, 4, 3);
+    cbor_head(out, 0, 2);
+    cbor_bytes(out, label);
+    cbor_head(out, 4, 2);
+    cbor_head(out, 0, 3);
+    cbor_bytes(out, leaf);
+}
+
+fn cbor_pruned(out: &mut Vec<u8>, hash: &[u8]) {
+    cbor_head(out, 4, 2);
+    cbor_head(out, 0, 4);
+    cbor_bytes(out, hash);
+}
+
+/// Tip subtree matching `tip_tree_root`
+fn cbor_tip(out: &mut Vec<u8>, index: u64, hash: &[u8]) {
+    cbor_head(out, 4, 3);
+    cbor_head(out, 0, 1);
+    cbor_labeled_leaf(out, b"last_block_hash", hash);
+    cbor_labeled_leaf(out, b"last_block_index", &leb128(index));
+}
+
+/// CBOR-encoded certified tree revealing either the tip or the liabilities
+/// commitment, with the other side pruned
+fn certified_tree_cbor(reveal_tip: bool) -> Option<Vec<u8>> {
+    let tip = tip();
+    let commitment = crate::liabilities::certified_commitment();
+    if (reveal_tip && tip.is_none()) || (!reveal_tip && commitment.is_none()) {
+        return None;
+    }
+
+    let mut out = vec![0xd9, 0xd9, 0xf7]; // self-describe tag
+    if tip.is_some() && commitment.is_some() {
+        cbor_head(&mut out, 4, 3);
+        cbor_head(&mut out, 0, 1);
+    }
+    if let Some((index, hash)) = tip {
+        if reveal_tip {
+            cbor_tip(&mut out, index, &hash);
+        } else {
+            cbor_pruned(&mut out, &tip_tree_root(index, &hash));
+        }
+    }
+    if let Some(commitment) = commitment {
+        if reveal_tip {
+            cbor_pruned(&mut out, &liabilities_node(&commitment));
+        } else {
+            cbor_labeled_leaf(&mut out, LIABILITIES_LABEL, &commitment);
+        }
+    }
+    Some(out)
+}
+
+/// Tip certificate (query calls only)
+pub fn tip_certificate(certificate: Option<Vec<u8>>) -> Option<ICRC3DataCertificate> {
+    Some(ICRC3DataCertificate {
+        certificate: certificate?,
+        hash_tree: certified_tree_cbor(true)?,
+    })
+}
+
+/// Certificate for the proof-of-liabilities commitment (query calls only)
+pub fn liabilities_certificate(certificate: Option<Vec<u8>>) -> Option<ICRC3DataCertificate> {
+    Some(ICRC3DataCertificate {
+        certificate: certificate?,
+        hash_tree: certified_tree_cbor(false)?,
+    })
+}
+
+// ============================================================================
+// QUERIES
+// ============================================================================
+
+fn to_u64(value: &Nat) -> u64 {
+    u64::try_from(&value.0).unwrap_or(u64::MAX)
+}
+
+/// Serve `icrc3_get_blocks`: local blocks inline, older ones via the archive
+pub fn get_blocks(args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+    let state = state();
+    let mut blocks = Vec::new();
+    let mut archived_args = Vec::new();
+
+    for range in args {
+        let start = to_u64(&range.start);
+        let end = start.saturating_add(to_u64(&range.length)).min(state.length);
+        if start >= end {
+            continue;
+        }
+
+        if start < state.first_local {
+            let archived_end = end.min(state.first_local);
+            archived_args.push(GetBlocksArgs {
+                start: Nat::from(start),
+                length: Nat::from(archived_end - start),
+            });
+        }
+
+        let local_start = start.max(state.first_local);
+        let budget = MAX_BLOCKS_PER_RESPONSE.saturating_sub(blocks.len() as u64);
+        let local_end = end.min(local_start.saturating_add(budget));
+        if local_start < local_end {
+            BLOCKS.with(|b| {
+                if let Some(ref map) = *b.borrow() {
+                    for (id, block) in map.range(local_start..local_end) {
+                        blocks.push(BlockWithId { id: Nat::from(id), block: block.0 });
+                    }
+                }
+            });
+        }
+    }
+
+    let archived_blocks = match (state.archive, archived_args.is_empty()) {
+        (Some(archive), false) => vec![ArchivedBlocks {
+            args: archived_args,
+            callback: GetBlocksCallback::new(archive, "icrc3_get_blocks".to_string()),
+        }],
+        _ => vec![],
+    };
+
+    GetBlocksResult {
+        log_length: Nat::from(state.length),
+        blocks,
+        archived_blocks,
+    }
+}
+
+/// Archive canisters and the block ranges they hold
+pub fn get_archives() -> Vec<ICRC3ArchiveInfo> {
+    let state = state();
+    match state.archive {
+        Some(archive) if state.first_local > 0 => vec![ICRC3ArchiveInfo {
+            canister_id: archive,
+            start: Nat::from(0u64),
+            end: Nat::from(state.first_local - 1),
+        }],
+        _ => vec![],
+    }
+}
+
+/// Block types appended to the log
+pub fn supported_block_types() -> Vec<SupportedBlockType> {
+    let url = "https://github.com/dfinity/ICRC-1/blob/main/standards/ICRC-3/README.md".to_string();
+    ["1mint", "1burn", "1xfer", "2approve"]
+        .iter()
+        .map(|block_type| SupportedBlockType {
+            block_type: block_type.to_string(),
+            url: url.clone(),
+        })
+        .collect()
+}
+
+// ============================================================================
+// ARCHIVING
+// ============================================================================
+
+/// Set the archive canister (only before any block has been archived)
+pub fn set_archive(archive: Principal) -> Result<(), String> {
+    let mut state = state();
+    if state.first_local > 0 && state.archive != Some(archive) {
+        return Err("Blocks were already archived to another canister".to_string());
+    }
+    state.archive = Some(archive);
+    set_state(state);
+    Ok(())
+}
+
+/// Holds `ARCHIVING` for the length of a spill
+///
+/// The flag is cleared on drop, which also runs when the spill traps after
+/// the archive call (the CDK drops the future in its cleanup callback).
+struct ArchivingGuard;
+
+impl ArchivingGuard {
+    /// Take the flag, or None if a spill is already running
+    fn acquire() -> Option<Self> {
+        if ARCHIVING.with(|a| a.replace(true)) {
+            None
+        } else {
+            Some(ArchivingGuard)
+        }
+    }
+}
+
+impl Drop for ArchivingGuard {
+    fn drop(&mut self) {
+        ARCHIVING.with(|a| *a.borrow_mut() = false);
+    }
+}
+
+/// Spill the oldest local blocks to the archive when over the local limit
+pub async fn archive_blocks() -> Result<u64, String> {
+    let state = state();
+    let Some(archive) = state.archive else {
+        return Ok(0);
+    };
+    let local = state.length - state.first_local;
+    if local <= MAX_LOCAL_BLOCKS {
+        return Ok(0);
+    }
+    let Some(_guard) = ArchivingGuard::acquire() else {
+        return Ok(0);
+    };
+
+    let start = state.first_local;
+    let end = start + ARCHIVE_BATCH_SIZE.min(local - MAX_LOCAL_BLOCKS);
+    let batch: Vec<ICRC3Value> = BLOCKS.with(|b| {
+        b.borrow()
+            .as_ref()
+            .map(|map| map.range(start..end).map(|(_, block)| block.0).collect())
+            .unwrap_or_default()
+    });
+
+    let result: Result<(Result<(), String>,), _> =
+        ic_cdk::call(archive, "append_blocks", (start, batch)).await;
+
+    match result {
+        Ok((Ok(()),)) => {
+            BLOCKS.with(|b| {
+                if let Some(ref mut map) = *b.borrow_mut() {
+                    for index in start..end {
+                        map.remove(&index);
+                    }
+                }
+            });
+            let mut state = self::state();
+            state.first_local = end;
+            set_state(state);
+            Ok(end - start)
+        }
+        Ok((Err(e),)) => Err(format!("Archive rejected blocks: {}", e)),
+        Err((code, msg)) => Err(format!("Archive call failed: {:?} - {}", code, msg)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::EntryReason;
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+
+    fn init_test_storage() {
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        init_block_log_storage(manager.get(MemoryId::new(0)), manager.get(MemoryId::new(1)));
+    }
+
+    fn entry(debit: LedgerAccount, credit: LedgerAccount, amount: u64) -> JournalEntry {
+        JournalEntry {
+            id: 0,
+            asset: Asset::Rune("840000:1".to_string()),
+            debit,
+            credit,
+            amount,
+            reason: EntryReason::Transfer,
+            reference: None,
+            timestamp: 42,
+        }
+    }
+
+    fn field<'a>(block: &'a ICRC3Value, name: &str) -> Option<&'a ICRC3Value> {
+        match block {
+            ICRC3Value::Map(fields) => fields.iter().find(|(key, _)| key == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_hash_value_known_vectors() {
+        // Test vectors from the ICRC-3 specification
+        assert_eq!(
+            hex::encode(hash_value(&ICRC3Value::Nat(Nat::from(42u64)))),
+            "684888c0ebb17f374298b65ee2807526c066094c701bcc7ebbe1c1095f494fc1"
+        );
+        assert_eq!(
+            hex::encode(hash_value(&ICRC3Value::Text("Hello, World!".to_string()))),
+            "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f"
+        );
+        assert_eq!(
+            hex::encode(hash_value(&ICRC3Value::Blob(hex::decode("01020304").unwrap()))),
+            "9f64a747e1b97f131fabb6b447296c9b6f0201e79fb3c5356e6c77e89b6a806a"
+        );
+        assert_eq!(
+            hex::encode(hash_value(&ICRC3Value::Map(vec![
+                ("from".to_string(), ICRC3Value::Blob(vec![0x00, 0xab, 0xcd, 0xef, 0x00, 0x12, 0x34, 0x00, 0x56, 0x78, 0x9a, 0x00, 0xbc, 0xde, 0xf0, 0x00, 0x01, 0x23, 0x45, 0x67, 0x89, 0x00, 0xab, 0xcd, 0xef, 0x01])),
+                ("to".to_string(), ICRC3Value::Blob(vec![0x00, 0xab, 0x0d, 0xef, 0x00, 0x12, 0x34, 0x00, 0x56, 0x78, 0x9a, 0x00, 0xbc, 0xde, 0xf0, 0x00, 0x01, 0x23, 0x45, 0x67, 0x89, 0x00, 0xab, 0xcd, 0xef, 0x01])),
+                ("amount".to_string(), ICRC3Value::Nat(Nat::from(42u64))),
+                ("created_at".to_string(), ICRC3Value::Nat(Nat::from(1_699_218_263u64))),
+                ("memo".to_string(), ICRC3Value::Nat(Nat::from(0u64))),
+            ]))),
+            "c56ece650e1de4269c5bdeff7875949e3e2033f85b2d193c2ff4f7f78bdcfc75"
+        );
+    }
+
+    #[test]
+    fn test_blocks_are_hash_chained() {
+        init_test_storage();
+        let user = Principal::from_text("2vxsx-fae").unwrap();
+
+        record_posting(&entry(LedgerAccount::Custody, LedgerAccount::Available(user), 1_000));
+        record_posting(&entry(LedgerAccount::Available(user), LedgerAccount::Pool("840000:1".to_string()), 400));
+        // Non-rune postings are not logged
+        record_posting(&JournalEntry { asset: Asset::Icp, ..entry(LedgerAccount::Custody, LedgerAccount::Available(user), 5) });
+        assert_eq!(log_length(), 2);
+
+        let result = get_blocks(vec![GetBlocksArgs { start: Nat::from(0u64), length: Nat::from(10u64) }]);
+        assert_eq!(result.log_length, Nat::from(2u64));
+        assert_eq!(result.blocks.len(), 2);
+
+        let first = &result.blocks[0].block;
+        let second = &result.blocks[1].block;
+        assert_eq!(field(first, "btype"), Some(&ICRC3Value::Text("1mint".to_string())));
+        assert_eq!(field(first, "phash"), None);
+        assert_eq!(field(second, "btype"), Some(&ICRC3Value::Text("1xfer".to_string())));
+        assert_eq!(field(second, "phash"), Some(&ICRC3Value::Blob(hash_value(first).to_vec())));
+        assert_eq!(state().tip_hash, Some(hash_value(second).to_vec()));
+    }
+
+    #[test]
+    fn test_get_blocks_points_to_archive() {
+        init_test_storage();
+        let user = Principal::from_text("2vxsx-fae").unwrap();
+        for _ in 0..5 {
+            record_posting(&entry(LedgerAccount::Custody, LedgerAccount::Available(user), 1));
+        }
+        let archive = Principal::from_text("aaaaa-aa").unwrap();
+        set_archive(archive).unwrap();
+
+        // Simulate a completed spill of the first 3 blocks
+        let mut log = state();
+        log.first_local = 3;
+        set_state(log);
+
+        let result = get_blocks(vec![GetBlocksArgs { start: Nat::from(1u64), length: Nat::from(3u64) }]);
+        assert_eq!(result.blocks.len(), 1);
+        assert_eq!(result.blocks[0].id, Nat::from(3u64));
+        assert_eq!(result.archived_blocks.len(), 1);
+        assert_eq!(
+            result.archived_blocks[0].args,
+            vec![GetBlocksArgs { start: Nat::from(1u64), length: Nat::from(2u64) }]
+        );
+        assert_eq!(get_archives()[0].end, Nat::from(2u64));
+    }
+
+    #[test]
+    fn test_archiving_flag_released_on_drop() {
+        let guard = ArchivingGuard::acquire().expect("no spill running");
+        assert!(ArchivingGuard::acquire().is_none());
+
+        // Dropping the guard (as when a spill traps) frees the flag
+        drop(guard);
+        assert!(ArchivingGuard::acquire().is_some());
+    }
+}
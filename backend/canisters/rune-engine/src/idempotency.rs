@@ -2,22 +2,26 @@
 // Idempotency Module
 // ============================================================================
 //
-// Prevents duplicate Rune creations by tracking request IDs.
+// Prevents duplicate Rune creations by tracking request IDs, and replays
+// money-moving updates retried with the same client idempotency key.
 //
 // Key Features:
 // - Request ID generation based on caller + config hash
+// - Client keys scoped to (caller, operation), bound to the call arguments
 // - Persistent storage in stable memory
-// - TTL for cleanup of old requests (7 days)
+// - TTL for cleanup of old requests (7 days) and client keys (24 hours)
 // - Thread-safe concurrent access
 //
 // ============================================================================
 
 use candid::{CandidType, Deserialize, Principal};
+use serde::de::DeserializeOwned;
 use ic_stable_structures::{
     memory_manager::VirtualMemory, DefaultMemoryImpl, StableBTreeMap, storable::Bound, Storable,
 };
 use std::cell::RefCell;
 use std::borrow::Cow;
+use std::future::Future;
 use sha2::{Sha256, Digest};
 
 use quri_types::RuneEtching;
@@ -27,6 +31,12 @@ type Memory = VirtualMemory<DefaultMemoryImpl>;
 // Request tracking expires after 7 days
 const REQUEST_TTL_NANOS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
 
+// Client idempotency keys expire after 24 hours
+const CLIENT_KEY_TTL_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Maximum client idempotency key length
+const MAX_CLIENT_KEY_LENGTH: usize = 64;
+
 /// Idempotency request record
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct IdempotencyRequest {
@@ -39,7 +49,7 @@ pub struct IdempotencyRequest {
 
 // Implement Storable for IdempotencyRequest
 impl Storable for IdempotencyRequest {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(candid::encode_one(self).unwrap_or_else(|e| {
             ic_cdk::trap(&format!("CRITICAL: Failed to encode IdempotencyRequest: {}", e))
         }))
@@ -54,10 +64,48 @@ impl Storable for IdempotencyRequest {
     const BOUND: Bound = Bound::Unbounded;
 }
 
+/// State of a client idempotency key
+#[derive(Clone, Debug, CandidType, Deserialize)]
+enum ClientKeyState {
+    /// The call is running (awaiting an inter-canister call)
+    InFlight,
+    /// The call succeeded; candid-encoded response
+    Completed { response: Vec<u8> },
+}
+
+/// Client idempotency key record
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct ClientKeyRecord {
+    /// Hash of the call arguments the key was first used with
+    fingerprint: Vec<u8>,
+    state: ClientKeyState,
+    created_at: u64,
+}
+
+impl Storable for ClientKeyRecord {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to encode ClientKeyRecord: {}", e))
+        }))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to decode ClientKeyRecord: {}", e))
+        })
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
 thread_local! {
     /// Idempotency storage (memory ID 3)
     static IDEMPOTENCY_STORE: RefCell<Option<StableBTreeMap<String, IdempotencyRequest, Memory>>> =
         const { RefCell::new(None) };
+
+    /// Client idempotency keys (memory ID 44), keyed by "caller:operation:key"
+    static CLIENT_KEYS: RefCell<Option<StableBTreeMap<String, ClientKeyRecord, Memory>>> =
+        const { RefCell::new(None) };
 }
 
 /// Initialize idempotency storage
@@ -74,6 +122,13 @@ pub fn reinit_idempotency_storage(memory: Memory) {
     });
 }
 
+/// Initialize client idempotency key storage
+pub fn init_client_key_storage(memory: Memory) {
+    CLIENT_KEYS.with(|store| {
+        *store.borrow_mut() = Some(StableBTreeMap::init(memory));
+    });
+}
+
 /// Generate deterministic request ID from caller and etching config
 pub fn generate_request_id(caller: &Principal, etching: &RuneEtching) -> String {
     let mut hasher = Sha256::new();
@@ -176,10 +231,211 @@ pub fn record_request_failure(
     })
 }
 
-/// Cleanup expired requests (older than TTL)
+// ============================================================================
+// Client Idempotency Keys
+// ============================================================================
+
+/// Hash the arguments of a keyed call
+///
+/// A key reused with different arguments is rejected instead of replayed.
+pub fn fingerprint<A: CandidType>(args: &A) -> Vec<u8> {
+    let encoded = candid::encode_one(args).unwrap_or_default();
+    Sha256::digest(&encoded).to_vec()
+}
+
+/// What a keyed call should do
+enum KeyedCall<T> {
+    /// No usable record; run the call and record it under this store key
+    Execute(Option<String>),
+    /// The key already completed with this response
+    Replay(T),
+}
+
+fn client_store_key(caller: &Principal, operation: &str, key: &str) -> String {
+    format!("{}:{}:{}", caller, operation, key)
+}
+
+fn begin_keyed<T: CandidType + DeserializeOwned>(
+    caller: Principal,
+    operation: &str,
+    key: Option<String>,
+    fingerprint: Vec<u8>,
+    now: u64,
+) -> Result<KeyedCall<T>, String> {
+    let Some(key) = key else {
+        return Ok(KeyedCall::Execute(None));
+    };
+    if key.is_empty() || key.len() > MAX_CLIENT_KEY_LENGTH {
+        return Err(format!(
+            "Idempotency key must be 1-{} characters",
+            MAX_CLIENT_KEY_LENGTH
+        ));
+    }
+
+    let store_key = client_store_key(&caller, operation, &key);
+    CLIENT_KEYS.with(|store| {
+        let mut store_ref = store.borrow_mut();
+        let s = store_ref.as_mut().ok_or("Idempotency storage not initialized")?;
+
+        if let Some(record) = s.get(&store_key) {
+            if now < record.created_at.saturating_add(CLIENT_KEY_TTL_NANOS) {
+                if record.fingerprint != fingerprint {
+                    return Err("Idempotency key was already used with different arguments".to_string());
+                }
+                return match record.state {
+                    ClientKeyState::InFlight => {
+                        Err("A request with this idempotency key is still in progress".to_string())
+                    }
+                    ClientKeyState::Completed { response } => candid::decode_one(&response)
+                        .map(KeyedCall::Replay)
+                        .map_err(|e| format!("Failed to decode cached response: {}", e)),
+                };
+            }
+        }
+
+        s.insert(
+            store_key.clone(),
+            ClientKeyRecord {
+                fingerprint,
+                state: ClientKeyState::InFlight,
+                created_at: now,
+            },
+        );
+        Ok(KeyedCall::Execute(Some(store_key)))
+    })
+}
+
+/// Cache a successful response; failed calls release the key so the client
+/// can retry with it
+fn finish_keyed<T: CandidType, E>(store_key: Option<String>, result: &Result<T, E>) {
+    let Some(store_key) = store_key else {
+        return;
+    };
+    CLIENT_KEYS.with(|store| {
+        if let Some(ref mut s) = *store.borrow_mut() {
+            match result {
+                Ok(response) => {
+                    let Some(mut record) = s.get(&store_key) else {
+                        return;
+                    };
+                    record.state = ClientKeyState::Completed {
+                        response: candid::encode_one(response).unwrap_or_else(|e| {
+                            ic_cdk::trap(&format!("CRITICAL: Failed to encode keyed response: {}", e))
+                        }),
+                    };
+                    s.insert(store_key, record);
+                }
+                Err(_) => {
+                    s.remove(&store_key);
+                }
+            }
+        }
+    });
+}
+
+/// Holds an in-flight client key across an await
+///
+/// If the call traps after awaiting, the cleanup callback drops the guard,
+/// which releases the key; otherwise it would block retries until the TTL.
+struct ClientKeyGuard(Option<String>);
+
+impl ClientKeyGuard {
+    /// Record the outcome and disarm the guard
+    fn finish<T: CandidType, E>(mut self, result: &Result<T, E>) {
+        finish_keyed(self.0.take(), result);
+    }
+}
+
+impl Drop for ClientKeyGuard {
+    fn drop(&mut self) {
+        if let Some(store_key) = self.0.take() {
+            CLIENT_KEYS.with(|store| {
+                if let Some(ref mut s) = *store.borrow_mut() {
+                    s.remove(&store_key);
+                }
+            });
+        }
+    }
+}
+
+/// Run a money-moving call under an optional client idempotency key
+///
+/// A retry with the same key and arguments within the TTL returns the
+/// cached success instead of executing again.
+pub fn with_client_key<T, E, F>(
+    caller: Principal,
+    operation: &str,
+    key: Option<String>,
+    fingerprint: Vec<u8>,
+    call: F,
+) -> Result<T, E>
+where
+    T: CandidType + DeserializeOwned,
+    E: From<String>,
+    F: FnOnce() -> Result<T, E>,
+{
+    let store_key = match begin_keyed(caller, operation, key, fingerprint, ic_cdk::api::time())? {
+        KeyedCall::Replay(response) => return Ok(response),
+        KeyedCall::Execute(store_key) => store_key,
+    };
+    let result = call();
+    finish_keyed(store_key, &result);
+    result
+}
+
+/// Async variant of `with_client_key`
+///
+/// The key stays in flight while the call awaits, so concurrent retries are
+/// rejected rather than executed twice. A trap after an await releases it.
+pub async fn with_client_key_async<T, E, F, Fut>(
+    caller: Principal,
+    operation: &str,
+    key: Option<String>,
+    fingerprint: Vec<u8>,
+    call: F,
+) -> Result<T, E>
+where
+    T: CandidType + DeserializeOwned,
+    E: From<String>,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let guard = match begin_keyed(caller, operation, key, fingerprint, ic_cdk::api::time())? {
+        KeyedCall::Replay(response) => return Ok(response),
+        KeyedCall::Execute(store_key) => ClientKeyGuard(store_key),
+    };
+    let result = call().await;
+    guard.finish(&result);
+    result
+}
+
+/// Remove client keys older than their TTL
+fn cleanup_expired_client_keys(current_time: u64) -> u64 {
+    let cutoff_time = current_time.saturating_sub(CLIENT_KEY_TTL_NANOS);
+
+    CLIENT_KEYS.with(|store| {
+        let mut store_ref = store.borrow_mut();
+        if let Some(ref mut s) = *store_ref {
+            let expired: Vec<String> = s
+                .iter()
+                .filter(|(_, record)| record.created_at < cutoff_time)
+                .map(|(key, _)| key)
+                .collect();
+            for key in &expired {
+                s.remove(key);
+            }
+            expired.len() as u64
+        } else {
+            0
+        }
+    })
+}
+
+/// Cleanup expired requests and client keys (older than their TTL)
 pub fn cleanup_expired_requests() -> u64 {
     let current_time = ic_cdk::api::time();
     let cutoff_time = current_time.saturating_sub(REQUEST_TTL_NANOS);
+    let expired_client_keys = cleanup_expired_client_keys(current_time);
 
     IDEMPOTENCY_STORE.with(|store| {
         let mut store_ref = store.borrow_mut();
@@ -199,25 +455,27 @@ pub fn cleanup_expired_requests() -> u64 {
                 s.remove(&request_id);
             }
 
+            let count = count + expired_client_keys;
             if count > 0 {
                 ic_cdk::println!("🧹 Cleaned up {} expired idempotency records", count);
             }
 
             count
         } else {
-            0
+            expired_client_keys
         }
     })
 }
 
-/// Get total count of tracked requests
+/// Get total count of tracked requests and client keys
 pub fn get_request_count() -> u64 {
+    let client_keys = CLIENT_KEYS.with(|store| store.borrow().as_ref().map(|s| s.len()).unwrap_or(0));
     IDEMPOTENCY_STORE.with(|store| {
         let store_ref = store.borrow();
         if let Some(ref s) = *store_ref {
-            s.len()
+            s.len() + client_keys
         } else {
-            0
+            client_keys
         }
     })
 }
@@ -301,4 +559,81 @@ mod tests {
 
         assert_ne!(id1, id2, "Runes with/without terms should have different IDs");
     }
+
+    #[test]
+    fn test_client_key_replay() {
+        use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        init_client_key_storage(manager.get(MemoryId::new(0)));
+
+        let caller = Principal::from_slice(&[1; 29]);
+        let args = fingerprint(&("rune".to_string(), 100u64));
+        let key = Some("retry-1".to_string());
+
+        // No key: always executes
+        assert!(matches!(
+            begin_keyed::<u64>(caller, "buy", None, args.clone(), 0),
+            Ok(KeyedCall::Execute(None))
+        ));
+
+        // Failures release the key
+        let Ok(KeyedCall::Execute(store_key)) = begin_keyed::<u64>(caller, "buy", key.clone(), args.clone(), 0) else {
+            panic!("expected execute");
+        };
+        assert!(begin_keyed::<u64>(caller, "buy", key.clone(), args.clone(), 1).is_err(), "in flight");
+        finish_keyed::<u64, String>(store_key, &Err("failed".to_string()));
+
+        // Successes are replayed for the same arguments only
+        let Ok(KeyedCall::Execute(store_key)) = begin_keyed::<u64>(caller, "buy", key.clone(), args.clone(), 2) else {
+            panic!("expected execute after failure");
+        };
+        finish_keyed::<u64, String>(store_key, &Ok(42));
+        assert!(matches!(
+            begin_keyed::<u64>(caller, "buy", key.clone(), args.clone(), 3),
+            Ok(KeyedCall::Replay(42))
+        ));
+        let other_args = fingerprint(&("rune".to_string(), 101u64));
+        assert!(begin_keyed::<u64>(caller, "buy", key.clone(), other_args, 3).is_err());
+
+        // Keys are scoped per operation and expire after the TTL
+        assert!(matches!(
+            begin_keyed::<u64>(caller, "sell", key.clone(), args.clone(), 3),
+            Ok(KeyedCall::Execute(Some(_)))
+        ));
+        assert!(matches!(
+            begin_keyed::<u64>(caller, "buy", key, args, 2 + CLIENT_KEY_TTL_NANOS),
+            Ok(KeyedCall::Execute(Some(_)))
+        ));
+    }
+
+    #[test]
+    fn test_client_key_released_when_call_is_dropped() {
+        use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        init_client_key_storage(manager.get(MemoryId::new(0)));
+
+        let caller = Principal::from_slice(&[1; 29]);
+        let args = fingerprint(&(100u64,));
+        let key = Some("retry-1".to_string());
+
+        // A call that trapped mid-await is dropped without finishing
+        let Ok(KeyedCall::Execute(store_key)) = begin_keyed::<u64>(caller, "withdraw", key.clone(), args.clone(), 0) else {
+            panic!("expected execute");
+        };
+        let guard = ClientKeyGuard(store_key);
+        assert!(begin_keyed::<u64>(caller, "withdraw", key.clone(), args.clone(), 1).is_err(), "in flight");
+        drop(guard);
+
+        // The key is free again, and a finished call is kept for replay
+        let Ok(KeyedCall::Execute(store_key)) = begin_keyed::<u64>(caller, "withdraw", key.clone(), args.clone(), 2) else {
+            panic!("expected execute after the dropped call");
+        };
+        ClientKeyGuard(store_key).finish::<u64, String>(&Ok(7));
+        assert!(matches!(
+            begin_keyed::<u64>(caller, "withdraw", key, args, 3),
+            Ok(KeyedCall::Replay(7))
+        ));
+    }
 }
@@ -11,12 +11,18 @@
  * - Implement retry logic for critical operations
  */
 
-use candid::{CandidType, Deserialize, Nat, Principal};
+use candid::{CandidType, Decode, Deserialize, Encode, Nat, Principal};
 use ic_cdk::api::call::CallResult;
 use ic_ledger_types::{
-    AccountIdentifier, Memo, Subaccount, Tokens, DEFAULT_SUBACCOUNT,
+    AccountIdentifier, Block, GetBlocksArgs, Memo, Operation, Subaccount, Tokens, DEFAULT_SUBACCOUNT,
     MAINNET_LEDGER_CANISTER_ID,
 };
+use ic_stable_structures::memory_manager::VirtualMemory;
+use ic_stable_structures::{storable::Bound, DefaultMemoryImpl, StableBTreeMap, StableCell, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
 
 /// ICP Ledger canister ID (mainnet)
 pub const ICP_LEDGER_CANISTER_ID: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
@@ -31,7 +37,7 @@ pub const MIN_DEPOSIT_AMOUNT: u64 = 100_000; // 0.001 ICP
 // ICRC-1 Types (Standard Interface)
 // ============================================================================
 
-#[derive(CandidType, Deserialize, Clone, Debug)]
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
 pub struct Account {
     pub owner: Principal,
     pub subaccount: Option<Vec<u8>>,
@@ -137,6 +143,25 @@ pub async fn transfer_icp_to_user(
     to: Principal,
     amount: u64,
     memo: Option<Vec<u8>>,
+) -> Result<u64, String> {
+    transfer_icp_to_account(
+        Account {
+            owner: to,
+            subaccount: None,
+        },
+        amount,
+        memo,
+    )
+    .await
+}
+
+/// Transfer ICP from canister to an arbitrary ICRC-1 account
+///
+/// The ledger fee is deducted from `amount`.
+pub async fn transfer_icp_to_account(
+    to: Account,
+    amount: u64,
+    memo: Option<Vec<u8>>,
 ) -> Result<u64, String> {
     if amount <= ICP_TRANSFER_FEE {
         return Err(format!(
@@ -150,10 +175,7 @@ pub async fn transfer_icp_to_user(
 
     let transfer_args = TransferArg {
         from_subaccount: None, // From canister's default subaccount
-        to: Account {
-            owner: to,
-            subaccount: None,
-        },
+        to,
         amount: Nat::from(transfer_amount),
         fee: Some(Nat::from(ICP_TRANSFER_FEE)),
         memo,
@@ -234,71 +256,290 @@ pub async fn get_canister_balance() -> Result<u64, String> {
     get_icrc1_balance(canister_id, None).await
 }
 
+/// Pull ICP from an account that approved the canister (ICRC-2)
+///
+/// The owner pays `amount` plus the ledger fee; the canister's main account
+/// receives exactly `amount`.
+pub async fn transfer_from_approved_account(from: Account, amount: u64) -> Result<u64, String> {
+    let ledger = get_ledger_principal();
+
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from,
+        to: Account {
+            owner: ic_cdk::api::id(),
+            subaccount: None,
+        },
+        amount: Nat::from(amount),
+        fee: Some(Nat::from(ICP_TRANSFER_FEE)),
+        memo: Some(b"deposit".to_vec()),
+        created_at_time: Some(ic_cdk::api::time()),
+    };
+
+    let result: CallResult<(Result<Nat, TransferFromError>,)> =
+        ic_cdk::call(ledger, "icrc2_transfer_from", (args,)).await;
+
+    match result {
+        Ok((Ok(block_index),)) => {
+            let block: u64 = block_index
+                .0
+                .try_into()
+                .map_err(|_| "Block index too large".to_string())?;
+            Ok(block)
+        }
+        Ok((Err(TransferFromError::InsufficientAllowance { allowance }),)) => Err(format!(
+            "Insufficient allowance: approved {}, need {} (amount + {} fee)",
+            allowance,
+            amount.saturating_add(ICP_TRANSFER_FEE),
+            ICP_TRANSFER_FEE
+        )),
+        Ok((Err(e),)) => Err(format!("Transfer failed: {:?}", e)),
+        Err((code, msg)) => Err(format!("Call failed: {:?} - {}", code, msg)),
+    }
+}
+
+/// Fetch a single block from the ledger, following archive callbacks
+pub async fn query_block(block_index: u64) -> Result<Block, String> {
+    let ledger = get_ledger_principal();
+    let args = GetBlocksArgs {
+        start: block_index,
+        length: 1,
+    };
+
+    let response = ic_ledger_types::query_blocks(ledger, args.clone())
+        .await
+        .map_err(|(code, msg)| format!("Failed to query blocks: {:?} - {}", code, msg))?;
+
+    if let Some(block) = response.blocks.into_iter().next() {
+        if response.first_block_index == block_index {
+            return Ok(block);
+        }
+    }
+
+    let archive = response
+        .archived_blocks
+        .into_iter()
+        .find(|range| range.start <= block_index && block_index < range.start + range.length)
+        .ok_or_else(|| format!("Block {} not found on the ledger", block_index))?;
+
+    let range = ic_ledger_types::query_archived_blocks(&archive.callback, args)
+        .await
+        .map_err(|(code, msg)| format!("Failed to query archived blocks: {:?} - {}", code, msg))?
+        .map_err(|e| format!("Archive rejected block query: {:?}", e))?;
+
+    range
+        .blocks
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("Block {} not found in archive", block_index))
+}
+
 // ============================================================================
-// DEPOSIT/WITHDRAWAL TRACKING
+// DEPOSITS & WITHDRAWALS
 // ============================================================================
+//
+// Trading balances live in the accounting ledger; deposits credit the user
+// from `Custody` and withdrawals return the amount to it. Every credited
+// deposit is keyed by its ICP ledger block index, so a block is never
+// credited twice.
+
+use crate::accounting::{EntryReason, LedgerAccount};
+use crate::icrc::{TransferFromArgs, TransferFromError};
+use crate::trading_v2;
+
+/// How a deposit reached the canister's main account
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum DepositMethod {
+    /// Pulled with `icrc2_transfer_from` after the user approved the canister
+    Approve,
+    /// Swept from the user's deposit subaccount
+    SubaccountSweep,
+}
 
-use std::cell::RefCell;
-use std::collections::HashMap;
-
-/// Track user deposits (for verification)
+/// A credited ICP deposit
 #[derive(CandidType, Deserialize, Clone, Debug)]
-pub struct DepositRecord {
+pub struct IcpDeposit {
+    /// ICP ledger block index of the transfer into the main account
+    pub block_index: u64,
     pub user: Principal,
+    /// Amount credited to the trading balance (e8s)
     pub amount: u64,
-    pub block_index: Option<u64>,
-    pub timestamp: u64,
-    pub status: DepositStatus,
+    pub method: DepositMethod,
+    pub credited_at: u64,
 }
 
-#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
-pub enum DepositStatus {
-    Pending,
-    Confirmed,
-    Failed(String),
+impl Storable for IcpDeposit {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).expect("Failed to encode IcpDeposit"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).expect("Failed to decode IcpDeposit")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
 }
 
 thread_local! {
-    /// Pending deposits awaiting confirmation
-    static PENDING_DEPOSITS: RefCell<HashMap<Principal, Vec<DepositRecord>>> = RefCell::new(HashMap::new());
+    /// Credited deposits by ICP ledger block index
+    static CREDITED_BLOCKS: RefCell<Option<StableBTreeMap<u64, IcpDeposit, Memory>>> = const { RefCell::new(None) };
 
-    /// User ICP balances (credited after deposit verification)
-    static USER_ICP_BALANCES: RefCell<HashMap<Principal, u64>> = RefCell::new(HashMap::new());
+    /// Time block tracking started; older blocks may predate it and cannot be claimed
+    static TRACKING_SINCE: RefCell<Option<StableCell<u64, Memory>>> = const { RefCell::new(None) };
 }
 
-/// Get user's credited ICP balance (for trading)
-pub fn get_user_trading_balance(user: Principal) -> u64 {
-    USER_ICP_BALANCES.with(|b| *b.borrow().get(&user).unwrap_or(&0))
+/// Initialize deposit tracking storage
+pub fn init_deposit_storage(credited_blocks_memory: Memory, tracking_since_memory: Memory) {
+    CREDITED_BLOCKS.with(|c| {
+        *c.borrow_mut() = Some(StableBTreeMap::init(credited_blocks_memory));
+    });
+    TRACKING_SINCE.with(|t| {
+        let mut cell = StableCell::init(tracking_since_memory, 0u64)
+            .expect("Failed to initialize deposit tracking time");
+        if *cell.get() == 0 {
+            cell.set(ic_cdk::api::time())
+                .expect("Failed to set deposit tracking time");
+        }
+        *t.borrow_mut() = Some(cell);
+    });
 }
 
-/// Credit ICP to user's trading balance
-pub fn credit_user_icp(user: Principal, amount: u64) -> u64 {
-    USER_ICP_BALANCES.with(|b| {
-        let mut balances = b.borrow_mut();
-        let balance = balances.entry(user).or_insert(0);
-        *balance = balance.saturating_add(amount);
-        *balance
-    })
+/// Get a credited deposit by ledger block index
+pub fn get_deposit(block_index: u64) -> Option<IcpDeposit> {
+    CREDITED_BLOCKS.with(|c| c.borrow().as_ref().and_then(|map| map.get(&block_index)))
 }
 
-/// Debit ICP from user's trading balance
-pub fn debit_user_icp(user: Principal, amount: u64) -> Result<u64, String> {
-    USER_ICP_BALANCES.with(|b| {
-        let mut balances = b.borrow_mut();
-        let balance = balances.entry(user).or_insert(0);
-        if *balance < amount {
-            return Err(format!(
-                "Insufficient ICP balance: have {}, need {}",
-                *balance, amount
-            ));
+/// Get a user's credited deposits, newest first
+pub fn get_user_deposits(user: Principal, offset: u64, limit: u64) -> Vec<IcpDeposit> {
+    CREDITED_BLOCKS.with(|c| {
+        if let Some(ref map) = *c.borrow() {
+            map.iter()
+                .rev()
+                .map(|(_, deposit)| deposit)
+                .filter(|deposit| deposit.user == user)
+                .skip(offset as usize)
+                .take(limit as usize)
+                .collect()
+        } else {
+            vec![]
         }
-        *balance = balance.saturating_sub(amount);
-        Ok(*balance)
     })
 }
 
+fn tracking_since() -> u64 {
+    TRACKING_SINCE.with(|t| t.borrow().as_ref().map(|cell| *cell.get()).unwrap_or(u64::MAX))
+}
+
+/// Credit a ledger block to a user's trading balance, at most once
+fn credit_block(
+    user: Principal,
+    block_index: u64,
+    amount: u64,
+    method: DepositMethod,
+) -> Result<IcpDeposit, String> {
+    if get_deposit(block_index).is_some() {
+        return Err(format!("Ledger block {} has already been credited", block_index));
+    }
+
+    trading_v2::credit_user_icp(user, amount, LedgerAccount::Custody, EntryReason::Deposit)?;
+
+    let deposit = IcpDeposit {
+        block_index,
+        user,
+        amount,
+        method,
+        credited_at: ic_cdk::api::time(),
+    };
+    CREDITED_BLOCKS.with(|c| {
+        if let Some(ref mut map) = *c.borrow_mut() {
+            map.insert(block_index, deposit.clone());
+        }
+    });
+
+    ic_cdk::println!(
+        "Deposit credited: user={}, amount={}, block={}, method={:?}",
+        user,
+        amount,
+        block_index,
+        deposit.method
+    );
+
+    Ok(deposit)
+}
+
+/// Check that a ledger block moved ICP from `user` into the canister's main account
+///
+/// Accepts a sweep from the user's deposit subaccount, or an ICRC-2
+/// transfer_from out of the user's default account. Returns the amount that
+/// arrived (fee excluded) and how it got there.
+pub fn check_deposit_block(
+    block: &Block,
+    user: Principal,
+    canister_id: Principal,
+) -> Result<(u64, DepositMethod), String> {
+    let canister_account = AccountIdentifier::new(&canister_id, &DEFAULT_SUBACCOUNT);
+    let deposit_account = AccountIdentifier::new(&canister_id, &principal_to_subaccount(&user));
+    let user_account = AccountIdentifier::new(&user, &DEFAULT_SUBACCOUNT);
+
+    match block.transaction.operation {
+        Some(Operation::Transfer { from, to, amount, .. })
+            if from == deposit_account && to == canister_account =>
+        {
+            Ok((amount.e8s(), DepositMethod::SubaccountSweep))
+        }
+        Some(Operation::TransferFrom { from, to, spender, amount, .. })
+            if from == user_account && to == canister_account && spender == canister_account =>
+        {
+            Ok((amount.e8s(), DepositMethod::Approve))
+        }
+        _ => Err("Block is not a deposit from this user to the canister".to_string()),
+    }
+}
+
+/// Verify a ledger block against `query_blocks` and credit it
+///
+/// Also lets a user recover a deposit whose transfer landed but whose credit
+/// did not (e.g. the call was interrupted after the ledger replied).
+pub async fn credit_ledger_block(user: Principal, block_index: u64) -> Result<IcpDeposit, String> {
+    if get_deposit(block_index).is_some() {
+        return Err(format!("Ledger block {} has already been credited", block_index));
+    }
+
+    let block = query_block(block_index).await?;
+    if block.timestamp.timestamp_nanos < tracking_since() {
+        return Err(format!("Ledger block {} predates deposit tracking", block_index));
+    }
+    let (amount, method) = check_deposit_block(&block, user, ic_cdk::api::id())?;
+
+    credit_block(user, block_index, amount, method)
+}
+
+/// Deposit ICP the user approved the canister to spend (ICRC-2)
+///
+/// The user must first call `icrc2_approve` on the ICP ledger for at least
+/// `amount` plus the transfer fee.
+pub async fn deposit_via_approve(user: Principal, amount: u64) -> Result<IcpDeposit, String> {
+    if amount < MIN_DEPOSIT_AMOUNT {
+        return Err(format!(
+            "Deposit amount {} is below minimum {}",
+            amount, MIN_DEPOSIT_AMOUNT
+        ));
+    }
+
+    let from = Account {
+        owner: user,
+        subaccount: None,
+    };
+    let block_index = transfer_from_approved_account(from, amount).await?;
+
+    credit_block(user, block_index, amount, DepositMethod::Approve)
+}
+
 /// Verify and credit a user's deposit
 /// Call this after user claims to have deposited ICP
+///
+/// Sweeps the deposit subaccount into the main account, then credits the
+/// sweep only after confirming the block with `query_blocks`.
 pub async fn verify_and_credit_deposit(user: Principal) -> Result<u64, String> {
     // Get user's deposit balance in their subaccount
     let deposit_balance = get_user_deposit_balance(user).await?;
@@ -313,32 +554,43 @@ pub async fn verify_and_credit_deposit(user: Principal) -> Result<u64, String> {
     // Transfer from user's subaccount to canister main account
     let block_index = transfer_from_user_subaccount(user, deposit_balance).await?;
 
-    // Credit the user's trading balance (minus fee)
-    let credited_amount = deposit_balance.saturating_sub(ICP_TRANSFER_FEE);
-    let new_balance = credit_user_icp(user, credited_amount);
-
-    ic_cdk::println!(
-        "Deposit verified: user={}, amount={}, block={}, new_balance={}",
-        user,
-        credited_amount,
-        block_index,
-        new_balance
-    );
-
-    Ok(credited_amount)
+    // Credit the user's trading balance with what the block actually moved
+    match credit_ledger_block(user, block_index).await {
+        Ok(deposit) => Ok(deposit.amount),
+        Err(e) => {
+            crate::logging::log_error(
+                "ledger",
+                format!("Swept deposit for {} in block {} was not credited: {}", user, block_index, e),
+                None,
+            );
+            Err(format!(
+                "Deposit swept in block {} but not credited ({}); retry with claim_deposit_block",
+                block_index, e
+            ))
+        }
+    }
 }
 
-/// Withdraw ICP from trading balance to user's wallet
-pub async fn withdraw_icp(user: Principal, amount: u64) -> Result<u64, String> {
+/// Withdraw ICP from a user's trading balance to an ICRC-1 account
+///
+/// The user is debited `amount` and the destination receives `amount` minus
+/// the ledger fee, so the canister's ledger balance falls by exactly `amount`.
+/// Destination, limit and review checks are done by the `withdrawals` module.
+pub async fn withdraw_icp_to(user: Principal, to: Account, amount: u64) -> Result<u64, String> {
+    if crate::solvency::withdrawals_paused() {
+        return Err("ICP withdrawals are paused pending a solvency review".to_string());
+    }
+
     // Check and debit trading balance first
-    debit_user_icp(user, amount)?;
+    trading_v2::debit_user_icp(user, amount, LedgerAccount::Custody, EntryReason::Withdrawal)?;
 
-    // Transfer ICP to user
-    match transfer_icp_to_user(user, amount, Some(b"withdraw".to_vec())).await {
+    // Transfer ICP to the destination
+    match transfer_icp_to_account(to.clone(), amount, Some(b"withdraw".to_vec())).await {
         Ok(block_index) => {
             ic_cdk::println!(
-                "Withdrawal successful: user={}, amount={}, block={}",
+                "Withdrawal successful: user={}, to={}, amount={}, block={}",
                 user,
+                to.owner,
                 amount,
                 block_index
             );
@@ -346,7 +598,18 @@ pub async fn withdraw_icp(user: Principal, amount: u64) -> Result<u64, String> {
         }
         Err(e) => {
             // Refund on failure
-            credit_user_icp(user, amount);
+            if let Err(refund_err) = trading_v2::credit_user_icp(
+                user,
+                amount,
+                LedgerAccount::Custody,
+                EntryReason::WithdrawalRefund,
+            ) {
+                crate::logging::log_error(
+                    "ledger",
+                    format!("Failed to refund withdrawal of {} to {}: {}", amount, user, refund_err),
+                    None,
+                );
+            }
             Err(format!("Withdrawal failed: {}", e))
         }
     }
@@ -378,6 +641,56 @@ mod tests {
         assert_eq!(subaccount.0.len(), 32);
     }
 
+    #[test]
+    fn test_check_deposit_block() {
+        use ic_ledger_types::{Timestamp, Transaction};
+
+        let user = Principal::from_slice(&[1; 29]);
+        let other = Principal::from_slice(&[2; 29]);
+        let canister = Principal::from_slice(&[3; 10]);
+        let canister_account = AccountIdentifier::new(&canister, &DEFAULT_SUBACCOUNT);
+        let block = |operation| Block {
+            parent_hash: None,
+            transaction: Transaction {
+                memo: Memo(0),
+                operation: Some(operation),
+                created_at_time: Timestamp { timestamp_nanos: 0 },
+                icrc1_memo: None,
+            },
+            timestamp: Timestamp { timestamp_nanos: 0 },
+        };
+
+        let sweep = block(Operation::Transfer {
+            from: AccountIdentifier::new(&canister, &principal_to_subaccount(&user)),
+            to: canister_account,
+            amount: Tokens::from_e8s(990_000),
+            fee: Tokens::from_e8s(ICP_TRANSFER_FEE),
+        });
+        assert_eq!(
+            check_deposit_block(&sweep, user, canister),
+            Ok((990_000, DepositMethod::SubaccountSweep))
+        );
+        assert!(check_deposit_block(&sweep, other, canister).is_err());
+
+        let pulled = block(Operation::TransferFrom {
+            from: AccountIdentifier::new(&user, &DEFAULT_SUBACCOUNT),
+            to: canister_account,
+            spender: canister_account,
+            amount: Tokens::from_e8s(500_000),
+            fee: Tokens::from_e8s(ICP_TRANSFER_FEE),
+        });
+        assert_eq!(check_deposit_block(&pulled, user, canister), Ok((500_000, DepositMethod::Approve)));
+
+        // A plain transfer out of the user's wallet is not a tracked deposit
+        let direct = block(Operation::Transfer {
+            from: AccountIdentifier::new(&user, &DEFAULT_SUBACCOUNT),
+            to: canister_account,
+            amount: Tokens::from_e8s(500_000),
+            fee: Tokens::from_e8s(ICP_TRANSFER_FEE),
+        });
+        assert!(check_deposit_block(&direct, user, canister).is_err());
+    }
+
     #[test]
     fn test_format_icp() {
         assert_eq!(format_icp(100_000_000), "1.0000 ICP");
@@ -391,23 +704,4 @@ mod tests {
         assert_eq!(icp_to_e8s(0.5), 50_000_000);
         assert_eq!(icp_to_e8s(0.0001), 10_000);
     }
-
-    #[test]
-    fn test_trading_balance() {
-        let user = Principal::from_text("aaaaa-aa").unwrap();
-
-        // Credit
-        let balance = credit_user_icp(user, 1_000_000);
-        assert_eq!(balance, 1_000_000);
-
-        // Get
-        assert_eq!(get_user_trading_balance(user), 1_000_000);
-
-        // Debit
-        let balance = debit_user_icp(user, 300_000).unwrap();
-        assert_eq!(balance, 700_000);
-
-        // Over-debit fails
-        assert!(debit_user_icp(user, 1_000_000).is_err());
-    }
 }
@@ -0,0 +1,580 @@
+// ============================================================================
+// Liabilities Module - Merkle-Sum-Tree Proof of Liabilities
+// ============================================================================
+//
+// Periodically snapshots every user's ICP and rune balances (available +
+// locked) into one Merkle sum tree per asset, and certifies a commitment to
+// all tree roots through the canister's certified data. Any user can fetch
+// an inclusion proof for their balance and check that it is counted in the
+// published total; together with the canister's on-ledger holdings this
+// gives a verifiable proof of reserves.
+//
+// Tree layout (per asset):
+// - Leaves, ordered by principal:
+//   H(0x00 || nonce || len(principal) || principal || balance_be64)
+//   The per-user nonce is derived from a secret snapshot seed, so sibling
+//   hashes in a proof do not reveal who the other users are.
+// - Nodes: H(0x01 || left_hash || left_sum_be128 || right_hash || right_sum_be128),
+//   summing both children. An unpaired last node moves up a level unchanged.
+//
+// Commitment (certified under the `liabilities` label):
+//   H("quri-liabilities" || snapshot_id_be64 || taken_at_be64 ||
+//     for each asset in label order: len(label) || label || root || total_be128 || leaf_count_be64)
+//
+// Only the latest snapshot is kept.
+//
+// ============================================================================
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::memory_manager::VirtualMemory;
+use ic_stable_structures::{storable::Bound, DefaultMemoryImpl, StableBTreeMap, StableCell, Storable};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::accounting::{self, Asset};
+use crate::icrc3::{self, ICRC3DataCertificate};
+use crate::logging;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Interval between scheduled snapshots
+const SNAPSHOT_INTERVAL_SECS: u64 = 6 * 3600;
+
+/// Label used for ICP in proofs and the commitment
+pub const ICP_LABEL: &str = "ICP";
+
+// ============================================================================
+// Types
+// ============================================================================
+
+/// Root of one asset's tree
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct AssetRoot {
+    /// "ICP" or the rune ID
+    pub asset: String,
+    pub root_hash: Vec<u8>,
+    /// Sum of all balances in the tree
+    pub total: u128,
+    pub leaf_count: u64,
+}
+
+/// Stored snapshot header
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+struct SnapshotState {
+    id: u64,
+    taken_at: u64,
+    assets: Vec<AssetRoot>,
+    commitment: Option<Vec<u8>>,
+    /// Secret used to derive leaf nonces
+    seed: Vec<u8>,
+}
+
+impl Storable for SnapshotState {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to encode SnapshotState: {}", e))
+        }))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to decode SnapshotState: {}", e))
+        })
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Public view of the latest snapshot
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LiabilitySnapshot {
+    pub id: u64,
+    pub taken_at: u64,
+    pub assets: Vec<AssetRoot>,
+    /// Certified commitment to all asset roots
+    pub commitment: Vec<u8>,
+    /// Certificate over the commitment (query calls only)
+    pub certificate: Option<ICRC3DataCertificate>,
+}
+
+/// One step up the tree from the leaf
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ProofStep {
+    pub sibling_hash: Vec<u8>,
+    pub sibling_sum: u128,
+    /// Whether the sibling is the left child
+    pub sibling_is_left: bool,
+}
+
+/// Inclusion proof for one user's balance in one asset
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LiabilityProof {
+    pub snapshot_id: u64,
+    pub taken_at: u64,
+    pub asset: String,
+    pub user: Principal,
+    /// Balance counted for the user (available + locked at snapshot time)
+    pub balance: u64,
+    pub nonce: Vec<u8>,
+    pub leaf_index: u64,
+    /// Steps from the leaf to the asset root
+    pub path: Vec<ProofStep>,
+    /// All asset roots, to recompute the commitment
+    pub assets: Vec<AssetRoot>,
+    pub commitment: Vec<u8>,
+    pub certificate: Option<ICRC3DataCertificate>,
+}
+
+/// Tree node: hash and sum of the subtree
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TreeNode {
+    hash: [u8; 32],
+    sum: u128,
+}
+
+impl Storable for TreeNode {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::with_capacity(48);
+        bytes.extend_from_slice(&self.hash);
+        bytes.extend_from_slice(&self.sum.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&bytes[..32]);
+        let mut sum = [0u8; 16];
+        sum.copy_from_slice(&bytes[32..48]);
+        Self {
+            hash,
+            sum: u128::from_be_bytes(sum),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 48,
+        is_fixed_size: true,
+    };
+}
+
+// ============================================================================
+// Storage
+// ============================================================================
+
+thread_local! {
+    static SNAPSHOT: RefCell<Option<StableCell<SnapshotState, Memory>>> = const { RefCell::new(None) };
+
+    /// Tree nodes by (asset, level, index)
+    static NODES: RefCell<Option<StableBTreeMap<Vec<u8>, TreeNode, Memory>>> = const { RefCell::new(None) };
+
+    /// Leaf index by (asset, user)
+    static LEAF_POSITIONS: RefCell<Option<StableBTreeMap<Vec<u8>, u64, Memory>>> = const { RefCell::new(None) };
+
+    static TIMER_ID: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+}
+
+/// Initialize liabilities storage
+pub fn init_liabilities_storage(snapshot_memory: Memory, nodes_memory: Memory, positions_memory: Memory) {
+    SNAPSHOT.with(|s| {
+        *s.borrow_mut() = Some(
+            StableCell::init(snapshot_memory, SnapshotState::default())
+                .expect("Failed to initialize liabilities snapshot"),
+        );
+    });
+    NODES.with(|n| {
+        *n.borrow_mut() = Some(StableBTreeMap::init(nodes_memory));
+    });
+    LEAF_POSITIONS.with(|p| {
+        *p.borrow_mut() = Some(StableBTreeMap::init(positions_memory));
+    });
+}
+
+fn snapshot_state() -> SnapshotState {
+    SNAPSHOT.with(|s| s.borrow().as_ref().map(|cell| cell.get().clone()).unwrap_or_default())
+}
+
+fn push_label(bytes: &mut Vec<u8>, label: &str) {
+    bytes.push(label.len() as u8);
+    bytes.extend_from_slice(label.as_bytes());
+}
+
+fn node_key(asset: &str, level: u8, index: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(asset.len() + 10);
+    push_label(&mut key, asset);
+    key.push(level);
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+fn position_key(asset: &str, user: &Principal) -> Vec<u8> {
+    let mut key = Vec::with_capacity(asset.len() + 30);
+    push_label(&mut key, asset);
+    key.extend_from_slice(user.as_slice());
+    key
+}
+
+fn get_node(asset: &str, level: u8, index: u64) -> Option<TreeNode> {
+    NODES.with(|n| n.borrow().as_ref().and_then(|map| map.get(&node_key(asset, level, index))))
+}
+
+/// Commitment certified for the latest snapshot
+pub fn certified_commitment() -> Option<Vec<u8>> {
+    snapshot_state().commitment
+}
+
+// ============================================================================
+// Hashing
+// ============================================================================
+
+/// Label used for an asset in proofs
+pub fn asset_label(asset: &Asset) -> Option<String> {
+    match asset {
+        Asset::Icp => Some(ICP_LABEL.to_string()),
+        Asset::Rune(rune_id) => Some(rune_id.clone()),
+        // Not held in user accounts
+        Asset::CkBtc => None,
+    }
+}
+
+fn leaf_nonce(seed: &[u8], user: &Principal) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"quri-liabilities-nonce");
+    hasher.update(seed);
+    hasher.update(user.as_slice());
+    hasher.finalize().into()
+}
+
+/// Leaf hash for a user's balance
+pub fn leaf_hash(nonce: &[u8], user: &Principal, balance: u64) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(nonce);
+    hasher.update([user.as_slice().len() as u8]);
+    hasher.update(user.as_slice());
+    hasher.update(balance.to_be_bytes());
+    hasher.finalize().into()
+}
+
+/// Hash of an internal node
+pub fn node_hash(left: &[u8], left_sum: u128, right: &[u8], right_sum: u128) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(left_sum.to_be_bytes());
+    hasher.update(right);
+    hasher.update(right_sum.to_be_bytes());
+    hasher.finalize().into()
+}
+
+/// Commitment over all asset roots of a snapshot
+pub fn commitment_hash(snapshot_id: u64, taken_at: u64, assets: &[AssetRoot]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"quri-liabilities");
+    hasher.update(snapshot_id.to_be_bytes());
+    hasher.update(taken_at.to_be_bytes());
+    for asset in assets {
+        hasher.update([asset.asset.len() as u8]);
+        hasher.update(asset.asset.as_bytes());
+        hasher.update(&asset.root_hash);
+        hasher.update(asset.total.to_be_bytes());
+        hasher.update(asset.leaf_count.to_be_bytes());
+    }
+    hasher.finalize().into()
+}
+
+/// Recompute an asset root from a leaf and its path
+///
+/// Returns the (root hash, total) the proof leads to; compare with the
+/// asset root in the certified commitment.
+#[allow(dead_code)]
+pub fn compute_root(leaf: [u8; 32], balance: u64, path: &[ProofStep]) -> ([u8; 32], u128) {
+    let mut hash = leaf;
+    let mut sum = balance as u128;
+    for step in path {
+        hash = if step.sibling_is_left {
+            node_hash(&step.sibling_hash, step.sibling_sum, &hash, sum)
+        } else {
+            node_hash(&hash, sum, &step.sibling_hash, step.sibling_sum)
+        };
+        sum = sum.saturating_add(step.sibling_sum);
+    }
+    (hash, sum)
+}
+
+// ============================================================================
+// Snapshots
+// ============================================================================
+
+/// Build and store the tree for one asset, returning its root
+fn build_asset_tree(asset: &str, leaves: &[(Principal, u64)], seed: &[u8]) -> AssetRoot {
+    let mut level: Vec<TreeNode> = Vec::with_capacity(leaves.len());
+    NODES.with(|n| {
+        LEAF_POSITIONS.with(|p| {
+            let mut n = n.borrow_mut();
+            let mut p = p.borrow_mut();
+            let (Some(nodes), Some(positions)) = (n.as_mut(), p.as_mut()) else {
+                return;
+            };
+
+            for (index, (user, balance)) in leaves.iter().enumerate() {
+                let node = TreeNode {
+                    hash: leaf_hash(&leaf_nonce(seed, user), user, *balance),
+                    sum: *balance as u128,
+                };
+                nodes.insert(node_key(asset, 0, index as u64), node);
+                positions.insert(position_key(asset, user), index as u64);
+                level.push(node);
+            }
+
+            let mut depth = 0u8;
+            while level.len() > 1 {
+                depth += 1;
+                level = level
+                    .chunks(2)
+                    .map(|pair| match pair {
+                        [left, right] => TreeNode {
+                            hash: node_hash(&left.hash, left.sum, &right.hash, right.sum),
+                            sum: left.sum.saturating_add(right.sum),
+                        },
+                        [single] => *single,
+                        _ => unreachable!(),
+                    })
+                    .collect();
+                for (index, node) in level.iter().enumerate() {
+                    nodes.insert(node_key(asset, depth, index as u64), *node);
+                }
+            }
+        });
+    });
+
+    let root = level.first().copied().unwrap_or(TreeNode { hash: [0; 32], sum: 0 });
+    AssetRoot {
+        asset: asset.to_string(),
+        root_hash: root.hash.to_vec(),
+        total: root.sum,
+        leaf_count: leaves.len() as u64,
+    }
+}
+
+/// Replace the stored snapshot with one over the current ledger balances
+pub fn build_snapshot(seed: Vec<u8>, taken_at: u64) -> Result<LiabilitySnapshot, String> {
+    let mut by_asset: BTreeMap<String, Vec<(Principal, u64)>> = BTreeMap::new();
+    for ((asset, user), balance) in accounting::user_balances() {
+        if let Some(label) = asset_label(&asset) {
+            by_asset.entry(label).or_default().push((user, balance));
+        }
+    }
+
+    NODES.with(|n| n.borrow_mut().as_mut().map(|map| map.clear_new()));
+    LEAF_POSITIONS.with(|p| p.borrow_mut().as_mut().map(|map| map.clear_new()));
+
+    let assets: Vec<AssetRoot> = by_asset
+        .iter()
+        .map(|(asset, leaves)| build_asset_tree(asset, leaves, &seed))
+        .collect();
+
+    let previous = snapshot_state();
+    let id = if previous.commitment.is_some() { previous.id + 1 } else { 0 };
+    let commitment = commitment_hash(id, taken_at, &assets).to_vec();
+
+    SNAPSHOT.with(|s| {
+        let mut s = s.borrow_mut();
+        let cell = s.as_mut().ok_or("Liabilities storage not initialized")?;
+        cell.set(SnapshotState {
+            id,
+            taken_at,
+            assets: assets.clone(),
+            commitment: Some(commitment.clone()),
+            seed,
+        })
+        .map_err(|e| format!("Failed to save liabilities snapshot: {:?}", e))?;
+        Ok::<(), String>(())
+    })?;
+
+    icrc3::certify_tip();
+
+    Ok(LiabilitySnapshot {
+        id,
+        taken_at,
+        assets,
+        commitment,
+        certificate: None,
+    })
+}
+
+/// Take a snapshot with a fresh random seed
+pub async fn take_snapshot() -> Result<LiabilitySnapshot, String> {
+    let seed = ic_cdk::api::management_canister::main::raw_rand()
+        .await
+        .map_err(|(code, msg)| format!("Failed to generate random bytes: {:?} - {}", code, msg))?
+        .0;
+
+    let snapshot = build_snapshot(seed, ic_cdk::api::time())?;
+    logging::log_info(
+        "liabilities",
+        format!(
+            "Liabilities snapshot {} taken over {} assets",
+            snapshot.id,
+            snapshot.assets.len()
+        ),
+        None,
+    );
+    Ok(snapshot)
+}
+
+/// Latest snapshot, with a certificate when called as a query
+pub fn get_snapshot(certificate: Option<Vec<u8>>) -> Option<LiabilitySnapshot> {
+    let state = snapshot_state();
+    Some(LiabilitySnapshot {
+        id: state.id,
+        taken_at: state.taken_at,
+        assets: state.assets,
+        commitment: state.commitment?,
+        certificate: icrc3::liabilities_certificate(certificate),
+    })
+}
+
+/// Inclusion proof for a user's balance in the latest snapshot
+///
+/// @param asset - "ICP" or a rune ID
+pub fn get_proof(
+    user: Principal,
+    asset: &str,
+    certificate: Option<Vec<u8>>,
+) -> Result<LiabilityProof, String> {
+    let state = snapshot_state();
+    let commitment = state.commitment.ok_or("No liabilities snapshot has been taken yet")?;
+    let root = state
+        .assets
+        .iter()
+        .find(|root| root.asset == asset)
+        .ok_or_else(|| format!("No balances in {} at snapshot {}", asset, state.id))?;
+
+    let leaf_index = LEAF_POSITIONS
+        .with(|p| p.borrow().as_ref().and_then(|map| map.get(&position_key(asset, &user))))
+        .ok_or_else(|| format!("No {} balance for this principal at snapshot {}", asset, state.id))?;
+    let leaf = get_node(asset, 0, leaf_index).ok_or("Snapshot leaf missing")?;
+
+    let mut path = Vec::new();
+    let mut index = leaf_index;
+    let mut width = root.leaf_count;
+    let mut level = 0u8;
+    while width > 1 {
+        let sibling = index ^ 1;
+        if sibling < width {
+            let node = get_node(asset, level, sibling).ok_or("Snapshot node missing")?;
+            path.push(ProofStep {
+                sibling_hash: node.hash.to_vec(),
+                sibling_sum: node.sum,
+                sibling_is_left: sibling < index,
+            });
+        }
+        index /= 2;
+        width = width.div_ceil(2);
+        level += 1;
+    }
+
+    Ok(LiabilityProof {
+        snapshot_id: state.id,
+        taken_at: state.taken_at,
+        asset: asset.to_string(),
+        user,
+        balance: leaf.sum as u64,
+        nonce: leaf_nonce(&state.seed, &user).to_vec(),
+        leaf_index,
+        path,
+        assets: state.assets.clone(),
+        commitment,
+        certificate: icrc3::liabilities_certificate(certificate),
+    })
+}
+
+// ============================================================================
+// Timer
+// ============================================================================
+
+/// Start the periodic snapshot timer
+pub fn start_liabilities_snapshots() {
+    stop_liabilities_snapshots();
+
+    let timer_id = ic_cdk_timers::set_timer_interval(Duration::from_secs(SNAPSHOT_INTERVAL_SECS), || {
+        ic_cdk::spawn(async {
+            if let Err(e) = take_snapshot().await {
+                logging::log_error("liabilities", format!("Liabilities snapshot failed: {}", e), None);
+            }
+        });
+    });
+
+    TIMER_ID.with(|t| *t.borrow_mut() = Some(timer_id));
+}
+
+/// Stop the snapshot timer
+pub fn stop_liabilities_snapshots() {
+    TIMER_ID.with(|t| {
+        if let Some(timer_id) = t.borrow_mut().take() {
+            ic_cdk_timers::clear_timer(timer_id);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::{EntryReason, LedgerAccount};
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+
+    #[test]
+    fn test_proofs_reach_certified_roots() {
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        accounting::init_accounting_storage(manager.get(MemoryId::new(0)), manager.get(MemoryId::new(1)));
+        init_liabilities_storage(
+            manager.get(MemoryId::new(2)),
+            manager.get(MemoryId::new(3)),
+            manager.get(MemoryId::new(4)),
+        );
+
+        let users: Vec<Principal> = (1..=5u8).map(|i| Principal::from_slice(&[i; 29])).collect();
+        for (i, user) in users.iter().enumerate() {
+            let amount = 1_000 * (i as u64 + 1);
+            accounting::post(Asset::Icp, LedgerAccount::Custody, LedgerAccount::Available(*user), amount, EntryReason::Deposit, None).unwrap();
+        }
+        // Locked balances count too
+        accounting::post(Asset::Icp, LedgerAccount::Available(users[0]), LedgerAccount::Locked(users[0]), 400, EntryReason::Lock, None).unwrap();
+        accounting::post(Asset::Rune("840000:1".to_string()), LedgerAccount::Custody, LedgerAccount::Available(users[2]), 77, EntryReason::Premine, None).unwrap();
+
+        let snapshot = build_snapshot(vec![9; 32], 123).unwrap();
+        assert_eq!(snapshot.assets.len(), 2);
+        let icp_root = snapshot.assets.iter().find(|r| r.asset == ICP_LABEL).unwrap();
+        assert_eq!(icp_root.total, 15_000);
+        assert_eq!(icp_root.leaf_count, 5);
+        assert_eq!(snapshot.commitment, commitment_hash(0, 123, &snapshot.assets).to_vec());
+
+        for (i, user) in users.iter().enumerate() {
+            let proof = get_proof(*user, ICP_LABEL, None).unwrap();
+            assert_eq!(proof.balance, 1_000 * (i as u64 + 1));
+            let leaf = leaf_hash(&proof.nonce, user, proof.balance);
+            let (root_hash, total) = compute_root(leaf, proof.balance, &proof.path);
+            assert_eq!(root_hash.to_vec(), icp_root.root_hash);
+            assert_eq!(total, icp_root.total);
+        }
+
+        // A tampered balance does not reach the root
+        let proof = get_proof(users[1], ICP_LABEL, None).unwrap();
+        let leaf = leaf_hash(&proof.nonce, &users[1], proof.balance - 1);
+        assert_ne!(compute_root(leaf, proof.balance - 1, &proof.path).0.to_vec(), icp_root.root_hash);
+
+        // Single-leaf rune tree and users without a balance
+        let proof = get_proof(users[2], "840000:1", None).unwrap();
+        assert!(proof.path.is_empty());
+        assert!(get_proof(users[0], "840000:1", None).is_err());
+
+        // Next snapshot replaces the previous one
+        let snapshot = build_snapshot(vec![8; 32], 456).unwrap();
+        assert_eq!(snapshot.id, 1);
+        assert_eq!(get_snapshot(None).unwrap().taken_at, 456);
+    }
+}
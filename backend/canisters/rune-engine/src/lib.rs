@@ -6,6 +6,7 @@ use std::cell::RefCell;
 
 use quri_types::RuneEtching;
 
+mod accounting;
 mod balances;
 mod block_tracker;
 mod config;
@@ -17,17 +18,24 @@ mod errors;
 mod escrow;
 mod etching_flow;
 mod fee_manager;
+mod icrc;
+mod icrc3;
 mod idempotency;
 mod ledger;
+mod liabilities;
 mod logging;
 mod metrics;
+mod otc;
 mod process_id;
 mod rbac;
 mod settlement;
+mod solvency;
 mod state;
 mod trading;
 mod trading_v2;
+mod treasury;
 mod validators;
+mod withdrawals;
 
 #[cfg(test)]
 mod etching_flow_tests;
@@ -64,6 +72,10 @@ fn init() {
     let idempotency_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)));
     idempotency::init_idempotency_storage(idempotency_memory);
 
+    // Initialize client idempotency keys (MemoryId 44)
+    let client_keys_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(44)));
+    idempotency::init_client_key_storage(client_keys_memory);
+
     // Initialize config storage (MemoryId 4, 5)
     let etching_config_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4)));
     let canister_config_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5)));
@@ -93,9 +105,10 @@ fn init() {
     let settlement_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)));
     settlement::init_settlement_history(settlement_memory);
 
-    // Initialize escrow storage (MemoryId 12)
+    // Initialize escrow storage (MemoryId 12) and its retry index (MemoryId 58)
     let escrow_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12)));
-    escrow::init_escrow_storage(escrow_memory);
+    let escrow_retry_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(58)));
+    escrow::init_escrow_storage(escrow_memory, escrow_retry_memory);
 
     // Initialize trading V2 storage (MemoryId 13-18)
     let trading_pools_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13)));
@@ -113,25 +126,147 @@ fn init() {
         trading_rune_to_pool_memory,
     );
 
+    // Initialize fee tier storage (MemoryId 22)
+    let fee_tiers_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22)));
+    trading_v2::init_fee_tier_storage(fee_tiers_memory);
+
+    // Initialize launch protection storage (MemoryId 23)
+    let launch_buys_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23)));
+    trading_v2::init_launch_storage(launch_buys_memory);
+
+    // Initialize quote signing key storage (MemoryId 24)
+    let quote_key_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24)));
+    trading_v2::init_quote_key_storage(quote_key_memory);
+
+    // Initialize accounting ledger storage (MemoryId 25-26)
+    let accounting_balances_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25)));
+    let accounting_journal_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26)));
+    accounting::init_accounting_storage(accounting_balances_memory, accounting_journal_memory);
+
+    // Initialize ICRC token storage (MemoryId 27-28, rune ledgers 57)
+    let icrc_allowances_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27)));
+    let icrc_recent_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28)));
+    let icrc_ledgers_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(57)));
+    icrc::init_icrc_storage(icrc_allowances_memory, icrc_recent_memory, icrc_ledgers_memory);
+
+    // Initialize ICRC-3 block log (MemoryId 30-31; 29 held the pre-ICRC-3 tx counter and stays unused)
+    let block_log_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30)));
+    let block_log_state_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31)));
+    icrc3::init_block_log_storage(block_log_memory, block_log_state_memory);
+
+    // Initialize user -> rune index (MemoryId 32)
+    let user_rune_index_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32)));
+    trading_v2::init_user_rune_index_storage(user_rune_index_memory);
+
+    // Initialize ICP deposit tracking (MemoryId 33-34)
+    let credited_blocks_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33)));
+    let deposit_tracking_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34)));
+    ledger::init_deposit_storage(credited_blocks_memory, deposit_tracking_memory);
+
+    // Initialize solvency storage (MemoryId 35-36)
+    let solvency_config_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35)));
+    let solvency_reports_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(36)));
+    solvency::init_solvency_storage(solvency_config_memory, solvency_reports_memory);
+
+    // Initialize withdrawal guard storage (MemoryId 40-43)
+    let withdrawal_limits_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(40)));
+    let withdrawal_usage_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(41)));
+    let withdrawal_addresses_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(42)));
+    let withdrawal_reviews_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(43)));
+    withdrawals::init_withdrawal_storage(
+        withdrawal_limits_memory,
+        withdrawal_usage_memory,
+        withdrawal_addresses_memory,
+        withdrawal_reviews_memory,
+    );
+
+    // Initialize Dead Man's Switch storage (MemoryId 45-48, schedule index 59)
+    let dms_switches_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(45)));
+    let dms_counter_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(46)));
+    let dms_user_index_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(47)));
+    let dms_votes_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(48)));
+    let dms_schedule_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(59)));
+    dead_man_switch::init_switch_storage(
+        dms_switches_memory,
+        dms_counter_memory,
+        dms_user_index_memory,
+        dms_votes_memory,
+        dms_schedule_memory,
+    );
+
+    // Initialize encrypted metadata storage (MemoryId 49-53)
+    let metadata_items_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(49)));
+    let metadata_uploads_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(50)));
+    let metadata_chunks_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(51)));
+    let metadata_counter_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(52)));
+    let metadata_usage_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(53)));
+    encrypted_metadata::init_metadata_storage(
+        metadata_items_memory,
+        metadata_uploads_memory,
+        metadata_chunks_memory,
+        metadata_counter_memory,
+        metadata_usage_memory,
+    );
+
+    // Initialize OTC escrow storage (MemoryId 54-56)
+    let otc_offers_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(54)));
+    let otc_counter_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(55)));
+    let otc_index_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(56)));
+    otc::init_otc_storage(otc_offers_memory, otc_counter_memory, otc_index_memory);
+
+    // Initialize proof-of-liabilities storage (MemoryId 37-39)
+    let liabilities_snapshot_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(37)));
+    let liabilities_nodes_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(38)));
+    let liabilities_positions_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(39)));
+    liabilities::init_liabilities_storage(
+        liabilities_snapshot_memory,
+        liabilities_nodes_memory,
+        liabilities_positions_memory,
+    );
+
+    // Initialize treasury storage (MemoryId 19-21)
+    let treasury_config_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19)));
+    let treasury_collections_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20)));
+    let treasury_totals_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21)));
+    treasury::init_treasury_storage(
+        treasury_config_memory,
+        treasury_collections_memory,
+        treasury_totals_memory,
+    );
+
     // Schedule timer initialization after init completes
     // Timers cannot be set during init/post_upgrade, so we use a one-shot timer
     ic_cdk_timers::set_timer(std::time::Duration::from_secs(1), || {
         confirmation_tracker::init_confirmation_tracker();
         fee_manager::init_fee_manager();
         cycles_monitor::init_cycles_monitor();
+        spawn_quote_key_generation();
+        start_block_archiving();
+        solvency::start_solvency_monitor();
+        liabilities::start_liabilities_snapshots();
+        dead_man_switch::start_switch_processor();
+        escrow::start_refund_retry_processor();
+        otc::start_offer_expiry();
+    });
+}
 
-        // Initialize Dead Man's Switch timer - check every hour
-        ic_cdk_timers::set_timer_interval(
-            std::time::Duration::from_secs(3600), // 1 hour
-            || {
-                ic_cdk::spawn(async {
-                    let triggered = dead_man_switch::process_expired_switches().await;
-                    if !triggered.is_empty() {
-                        ic_cdk::println!("Triggered {} Dead Man's Switches", triggered.len());
-                    }
-                });
+/// Generate the quote signing key in the background (needs raw_rand)
+fn spawn_quote_key_generation() {
+    ic_cdk::spawn(async {
+        if let Err(e) = trading_v2::ensure_quote_signing_key().await {
+            logging::log_error("trading_v2", format!("Quote signing key generation failed: {}", e), None);
+        }
+    });
+}
+
+/// Spill old ICRC-3 blocks to the archive canister every 10 minutes
+fn start_block_archiving() {
+    ic_cdk_timers::set_timer_interval(std::time::Duration::from_secs(600), || {
+        ic_cdk::spawn(async {
+            if let Err(e) = icrc3::archive_blocks().await {
+                logging::log_error("icrc3", format!("Block archiving failed: {}", e), None);
             }
-        );
+        });
     });
 }
 
@@ -144,6 +279,11 @@ fn pre_upgrade() {
     fee_manager::stop_fee_manager();
     block_tracker::stop_block_tracker();
     cycles_monitor::stop_cycles_monitor();
+    solvency::stop_solvency_monitor();
+    liabilities::stop_liabilities_snapshots();
+    dead_man_switch::stop_switch_processor();
+    escrow::stop_refund_retry_processor();
+    otc::stop_offer_expiry();
 }
 
 #[post_upgrade]
@@ -162,6 +302,10 @@ fn post_upgrade() {
     let idempotency_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)));
     idempotency::reinit_idempotency_storage(idempotency_memory);
 
+    // Reinitialize client idempotency keys (MemoryId 44)
+    let client_keys_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(44)));
+    idempotency::init_client_key_storage(client_keys_memory);
+
     // Reinitialize config storage (MemoryId 4, 5)
     let etching_config_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4)));
     let canister_config_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5)));
@@ -191,9 +335,18 @@ fn post_upgrade() {
     let settlement_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)));
     settlement::reinit_settlement_history(settlement_memory);
 
-    // Reinitialize escrow storage (MemoryId 12)
+    // Reinitialize escrow storage (MemoryId 12) and its retry index (MemoryId 58)
     let escrow_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12)));
-    escrow::reinit_escrow_storage(escrow_memory);
+    let escrow_retry_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(58)));
+    escrow::reinit_escrow_storage(escrow_memory, escrow_retry_memory);
+    let indexed_retries = escrow::rebuild_retry_index();
+    if indexed_retries > 0 {
+        ic_cdk::println!("Indexed {} escrow retries", indexed_retries);
+    }
+    let legacy_refunds = escrow::schedule_legacy_refund_retries(ic_cdk::api::time());
+    if legacy_refunds > 0 {
+        ic_cdk::println!("Scheduled {} failed escrow refunds for retry", legacy_refunds);
+    }
 
     // Reinitialize trading V2 storage (MemoryId 13-18)
     let trading_pools_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13)));
@@ -211,12 +364,173 @@ fn post_upgrade() {
         trading_rune_to_pool_memory,
     );
 
+    // Reinitialize fee tier storage (MemoryId 22)
+    let fee_tiers_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22)));
+    trading_v2::init_fee_tier_storage(fee_tiers_memory);
+
+    // Reinitialize launch protection storage (MemoryId 23)
+    let launch_buys_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23)));
+    trading_v2::init_launch_storage(launch_buys_memory);
+
+    // Reinitialize quote signing key storage (MemoryId 24)
+    let quote_key_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24)));
+    trading_v2::init_quote_key_storage(quote_key_memory);
+
+    // Reinitialize treasury storage (MemoryId 19-21)
+    let treasury_config_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19)));
+    let treasury_collections_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20)));
+    let treasury_totals_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21)));
+    treasury::reinit_treasury_storage(
+        treasury_config_memory,
+        treasury_collections_memory,
+        treasury_totals_memory,
+    );
+
+    // Reinitialize accounting ledger storage (MemoryId 25-26)
+    let accounting_balances_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25)));
+    let accounting_journal_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26)));
+    accounting::init_accounting_storage(accounting_balances_memory, accounting_journal_memory);
+
+    // Reinitialize ICRC token storage (MemoryId 27-28, rune ledgers 57)
+    let icrc_allowances_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27)));
+    let icrc_recent_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28)));
+    let icrc_ledgers_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(57)));
+    icrc::init_icrc_storage(icrc_allowances_memory, icrc_recent_memory, icrc_ledgers_memory);
+
+    // Reinitialize ICRC-3 block log (MemoryId 30-31; 29 held the pre-ICRC-3 tx counter and stays unused)
+    let block_log_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30)));
+    let block_log_state_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31)));
+    icrc3::init_block_log_storage(block_log_memory, block_log_state_memory);
+
+    // Reinitialize proof-of-liabilities storage (MemoryId 37-39)
+    let liabilities_snapshot_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(37)));
+    let liabilities_nodes_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(38)));
+    let liabilities_positions_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(39)));
+    liabilities::init_liabilities_storage(
+        liabilities_snapshot_memory,
+        liabilities_nodes_memory,
+        liabilities_positions_memory,
+    );
+
+    // Certified data covers the block log tip and the liabilities commitment
+    icrc3::certify_tip();
+
+    // Reinitialize user -> rune index (MemoryId 32)
+    let user_rune_index_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32)));
+    trading_v2::init_user_rune_index_storage(user_rune_index_memory);
+
+    // Reinitialize ICP deposit tracking (MemoryId 33-34)
+    let credited_blocks_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33)));
+    let deposit_tracking_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34)));
+    ledger::init_deposit_storage(credited_blocks_memory, deposit_tracking_memory);
+
+    // Reinitialize solvency storage (MemoryId 35-36)
+    let solvency_config_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35)));
+    let solvency_reports_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(36)));
+    solvency::init_solvency_storage(solvency_config_memory, solvency_reports_memory);
+
+    // Reinitialize withdrawal guard storage (MemoryId 40-43)
+    let withdrawal_limits_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(40)));
+    let withdrawal_usage_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(41)));
+    let withdrawal_addresses_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(42)));
+    let withdrawal_reviews_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(43)));
+    withdrawals::init_withdrawal_storage(
+        withdrawal_limits_memory,
+        withdrawal_usage_memory,
+        withdrawal_addresses_memory,
+        withdrawal_reviews_memory,
+    );
+
+    // Reinitialize Dead Man's Switch storage (MemoryId 45-48, schedule index 59)
+    let dms_switches_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(45)));
+    let dms_counter_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(46)));
+    let dms_user_index_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(47)));
+    let dms_votes_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(48)));
+    let dms_schedule_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(59)));
+    dead_man_switch::init_switch_storage(
+        dms_switches_memory,
+        dms_counter_memory,
+        dms_user_index_memory,
+        dms_votes_memory,
+        dms_schedule_memory,
+    );
+    let migrated_switches = dead_man_switch::migrate_switch_storage();
+    if migrated_switches > 0 {
+        ic_cdk::println!("Indexed {} Dead Man's Switches", migrated_switches);
+    }
+
+    // Reinitialize encrypted metadata storage (MemoryId 49-53)
+    let metadata_items_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(49)));
+    let metadata_uploads_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(50)));
+    let metadata_chunks_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(51)));
+    let metadata_counter_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(52)));
+    let metadata_usage_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(53)));
+    encrypted_metadata::init_metadata_storage(
+        metadata_items_memory,
+        metadata_uploads_memory,
+        metadata_chunks_memory,
+        metadata_counter_memory,
+        metadata_usage_memory,
+    );
+
+    // Reinitialize OTC escrow storage (MemoryId 54-56)
+    let otc_offers_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(54)));
+    let otc_counter_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(55)));
+    let otc_index_memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(56)));
+    otc::init_otc_storage(otc_offers_memory, otc_counter_memory, otc_index_memory);
+    let indexed_offers = otc::rebuild_offer_index();
+    if indexed_offers > 0 {
+        ic_cdk::println!("Indexed {} OTC offers", indexed_offers);
+    }
+
+    // One-time migration of pool reserves and held escrows into the accounting
+    // ledger; a failure traps so the upgrade rolls back instead of half-migrating
+    if accounting::journal_len() == 0 {
+        let pools = trading_v2::migrate_pools_to_accounting()
+            .unwrap_or_else(|e| ic_cdk::trap(&format!("Pool migration failed: {}", e)));
+        let escrows = escrow::migrate_escrows_to_accounting()
+            .unwrap_or_else(|e| ic_cdk::trap(&format!("Escrow migration failed: {}", e)));
+        ic_cdk::println!(
+            "Migrated {} pool postings and {} held escrows into the accounting ledger",
+            pools,
+            escrows
+        );
+    }
+
+    // Pre-ledger user balances are zeroed as they migrate, so every upgrade
+    // picks up whatever an earlier one had to leave behind
+    let (balances, unresolved) = trading_v2::migrate_user_balances_to_accounting()
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("Balance migration failed: {}", e)));
+    if balances > 0 {
+        ic_cdk::println!("Migrated {} user balance postings into the accounting ledger", balances);
+    }
+    if unresolved > 0 {
+        logging::log_warn(
+            "accounting",
+            format!("{} legacy rune balances still await migration (unknown rune)", unresolved),
+            None,
+        );
+    }
+
+    // Backfill the user -> rune index from existing ledger balances
+    let indexed = trading_v2::rebuild_user_rune_index();
+    if indexed > 0 {
+        ic_cdk::println!("Indexed {} user rune balances", indexed);
+    }
+
     // Schedule timer initialization after post_upgrade completes
     // Timers cannot be set during init/post_upgrade, so we use a one-shot timer
     ic_cdk_timers::set_timer(std::time::Duration::from_secs(1), || {
         confirmation_tracker::init_confirmation_tracker();
         fee_manager::init_fee_manager();
         cycles_monitor::init_cycles_monitor();
+        spawn_quote_key_generation();
+        start_block_archiving();
+        solvency::start_solvency_monitor();
+        liabilities::start_liabilities_snapshots();
+        dead_man_switch::start_switch_processor();
+        escrow::start_refund_retry_processor();
+        otc::start_offer_expiry();
     });
 }
 
@@ -286,7 +600,13 @@ async fn create_rune(etching: RuneEtching) -> Result<String, String> {
     // This allows the creator to transfer/sell runes so others can create pools
     let premine = etching.premine;
     if premine > 0 {
-        if let Err(e) = trading_v2::credit_user_runes(caller, &rune_id, premine) {
+        if let Err(e) = trading_v2::credit_user_runes(
+            caller,
+            &rune_id,
+            premine,
+            accounting::LedgerAccount::Custody,
+            accounting::EntryReason::Premine,
+        ) {
             ic_cdk::println!("⚠️  Failed to credit premine to creator: {}", e);
             // Don't fail the rune creation, just log the error
         } else {
@@ -314,9 +634,11 @@ async fn create_rune(etching: RuneEtching) -> Result<String, String> {
 /// Etch a virtual rune to the Bitcoin network
 ///
 /// This initiates the Bitcoin etching process for an existing virtual rune.
-/// Requires ckBTC for transaction fees.
+/// Requires ckBTC for transaction fees. With `fee_mode = Allowance` the caller
+/// first approves bitcoin-integration via `icrc2_approve`, and the fee is only
+/// drawn once the etching transaction is broadcast.
 #[update]
-async fn etch_to_bitcoin(rune_id: String) -> Result<String, String> {
+async fn etch_to_bitcoin(rune_id: String, fee_mode: Option<escrow::FeeMode>) -> Result<String, String> {
     let caller = ic_cdk::caller();
 
     // Validate caller
@@ -357,7 +679,10 @@ async fn etch_to_bitcoin(rune_id: String) -> Result<String, String> {
 
     // Execute etching flow
     let orchestrator = EtchingOrchestrator::new(etching_config);
-    match orchestrator.execute_etching(caller, virtual_rune.etching.clone()).await {
+    match orchestrator
+        .execute_etching(caller, virtual_rune.etching.clone(), fee_mode.unwrap_or_default())
+        .await
+    {
         Ok(process) => {
             let process_id = process.id.to_string();
 
@@ -405,6 +730,29 @@ async fn etch_to_bitcoin(rune_id: String) -> Result<String, String> {
     }
 }
 
+/// Record the on-chain rune ID ("block:tx") of an etched virtual rune (admin only)
+///
+/// Needed before the canister can send the rune on-chain, e.g. for Dead Man's
+/// Switch payouts to Bitcoin addresses.
+#[update]
+fn set_virtual_rune_onchain_id(rune_id: String, onchain_id: String) -> Result<(), String> {
+    require_admin!()?;
+
+    onchain_id
+        .parse::<runes_utils::RuneId>()
+        .map_err(|e| format!("Invalid on-chain rune ID: {}", e))?;
+
+    let mut virtual_rune = state::get_virtual_rune(&rune_id)
+        .ok_or_else(|| format!("Virtual rune not found: {}", rune_id))?;
+    if !virtual_rune.is_etched() {
+        return Err("Rune has not been etched".to_string());
+    }
+
+    virtual_rune.onchain_id = Some(onchain_id);
+    virtual_rune.updated_at = ic_cdk::api::time();
+    state::update_virtual_rune(&virtual_rune)
+}
+
 /// Get etching process status
 #[query]
 fn get_etching_status(process_id: String) -> Option<EtchingProcessView> {
@@ -862,6 +1210,7 @@ fn cleanup_old_processes(age_days: u64) -> Result<u64, String> {
 /// Create a trading pool for a Virtual Rune
 ///
 /// The creator must provide initial ICP liquidity and specify how many runes to add.
+/// Both are taken from the creator's balances (the premine is credited at rune creation).
 /// This sets the initial price: price = icp_amount / rune_amount
 ///
 /// @param rune_id - The Virtual Rune ID
@@ -880,27 +1229,40 @@ fn create_trading_pool(rune_id: String, initial_icp: u64, initial_runes: u64) ->
         return Err("Only the rune creator can create a trading pool".to_string());
     }
 
-    // First, credit the premine to the creator (mint the runes)
-    let premine = rune.etching.premine;
-    balances::credit_balance(
-        caller,
-        &rune_id,
-        premine,
-        balances::BalanceChangeType::Mint,
-        Some("Premine allocation".to_string()),
-    )?;
+    // Verify the creator can fund both sides before moving anything
+    let icp_available = trading::get_user_icp_balance(caller);
+    if icp_available < initial_icp {
+        return Err(format!(
+            "Insufficient ICP balance: have {}, need {}",
+            icp_available, initial_icp
+        ));
+    }
+    let runes_available = balances::get_balance(caller, &rune_id).available;
+    if runes_available < initial_runes {
+        return Err(format!(
+            "Insufficient rune balance: have {}, need {}",
+            runes_available, initial_runes
+        ));
+    }
+
+    // Create the pool
+    let pool = trading::create_pool(&rune, initial_icp, initial_runes, caller)?;
 
-    // Debit the runes that will go into the pool
+    // Move the initial liquidity into the pool
     balances::debit_balance(
         caller,
         &rune_id,
         initial_runes,
         balances::BalanceChangeType::PoolDeposit,
+        accounting::LedgerAccount::LegacyPool(rune_id.clone()),
         Some("Initial pool liquidity".to_string()),
     )?;
-
-    // Create the pool
-    let pool = trading::create_pool(&rune, initial_icp, initial_runes, caller)?;
+    trading_v2::debit_user_icp(
+        caller,
+        initial_icp,
+        accounting::LedgerAccount::LegacyPool(rune_id.clone()),
+        accounting::EntryReason::PoolCreation,
+    )?;
 
     Ok(TradingPoolView::from(pool))
 }
@@ -1054,11 +1416,15 @@ fn get_my_rune_balance(rune_id: String) -> RuneBalanceView {
     }
 }
 
-/// Get all rune balances for the caller
+/// Get the caller's rune balances, ordered by rune ID
+///
+/// @param offset - Number of runes to skip (default 0)
+/// @param limit - Page size (default and max 100)
 #[query]
-fn get_my_all_rune_balances() -> Vec<(String, RuneBalanceView)> {
+fn get_my_all_rune_balances(offset: Option<u64>, limit: Option<u64>) -> Vec<(String, RuneBalanceView)> {
     let caller = ic_cdk::caller();
-    trading::get_user_all_rune_balances(caller)
+    let limit = limit.unwrap_or(100).min(100);
+    trading_v2::get_user_all_rune_balances(caller, offset.unwrap_or(0), limit)
         .into_iter()
         .map(|(rune_id, balance)| {
             (rune_id, RuneBalanceView {
@@ -1094,7 +1460,7 @@ fn get_user_rune_balance_admin(user: Principal, rune_id: String) -> RuneBalanceV
 /// Get all rune balances for any user (admin query for debugging)
 #[query]
 fn get_user_all_rune_balances_admin(user: Principal) -> Vec<(String, RuneBalanceView)> {
-    trading::get_user_all_rune_balances(user)
+    trading_v2::get_user_all_rune_balances(user, 0, u64::MAX)
         .into_iter()
         .map(|(rune_id, balance)| {
             (rune_id, RuneBalanceView {
@@ -1136,19 +1502,151 @@ async fn verify_deposit() -> Result<u64, String> {
     ledger::verify_and_credit_deposit(caller).await
 }
 
+/// Deposit ICP with an ICRC-2 approval
+///
+/// Approve the rune engine on the ICP ledger (`icrc2_approve`) for at least
+/// `amount` plus the 0.0001 ICP fee, then call this to pull exactly `amount`
+/// into the trading balance.
+///
+/// @param amount - Amount to deposit (in e8s)
+/// @param idempotency_key - Optional; a retry with the same key returns the original deposit
+/// @returns The credited deposit, with its ICP ledger block index
+#[update]
+async fn deposit_icp(amount: u64, idempotency_key: Option<String>) -> Result<ledger::IcpDeposit, String> {
+    let caller = ic_cdk::caller();
+
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot deposit".to_string());
+    }
+
+    let fingerprint = idempotency::fingerprint(&(amount,));
+    idempotency::with_client_key_async(caller, "deposit_icp", idempotency_key, fingerprint, || {
+        ledger::deposit_via_approve(caller, amount)
+    })
+    .await
+}
+
+/// Credit a deposit by ICP ledger block index
+///
+/// For deposits whose ledger transfer went through but whose credit did not
+/// (e.g. `verify_deposit` reported the sweep block but failed to credit it).
+/// The block is checked with `query_blocks` and can only be credited once.
+#[update]
+async fn claim_deposit_block(block_index: u64) -> Result<ledger::IcpDeposit, String> {
+    let caller = ic_cdk::caller();
+
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot deposit".to_string());
+    }
+
+    ledger::credit_ledger_block(caller, block_index).await
+}
+
+/// Get the caller's credited ICP deposits, newest first
+#[query]
+fn get_my_icp_deposits(offset: u64, limit: u64) -> Vec<ledger::IcpDeposit> {
+    ledger::get_user_deposits(ic_cdk::caller(), offset, limit.min(100))
+}
+
 /// Withdraw ICP from trading balance to user's wallet
 ///
+/// Subject to the withdrawal limits; amounts at or above the review
+/// threshold are refused here and must go through `request_withdrawal`.
+///
 /// @param amount - Amount to withdraw (in e8s)
+/// @param idempotency_key - Optional; a retry with the same key returns the original block index
 /// @returns Block index of the transfer
 #[update]
-async fn withdraw_icp(amount: u64) -> Result<u64, String> {
+async fn withdraw_icp(amount: u64, idempotency_key: Option<String>) -> Result<u64, String> {
+    let caller = ic_cdk::caller();
+
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot withdraw".to_string());
+    }
+
+    let to = ledger::Account {
+        owner: caller,
+        subaccount: None,
+    };
+    let fingerprint = idempotency::fingerprint(&(amount,));
+    idempotency::with_client_key_async(caller, "withdraw_icp", idempotency_key, fingerprint, || async move {
+        match withdrawals::request_withdrawal(caller, to, amount, false).await? {
+            withdrawals::WithdrawalOutcome::Sent { block_index } => Ok(block_index),
+            withdrawals::WithdrawalOutcome::PendingReview { .. } => {
+                Err("Withdrawal unexpectedly queued for review".to_string())
+            }
+        }
+    })
+    .await
+}
+
+/// Withdraw ICP to the caller or to an unlocked address book destination
+///
+/// Large withdrawals are held for admin review instead of being sent.
+///
+/// @param amount - Amount to withdraw (in e8s)
+/// @param to - Destination account (defaults to the caller's own principal)
+/// @param idempotency_key - Optional; a retry with the same key returns the original outcome
+#[update]
+async fn request_withdrawal(
+    amount: u64,
+    to: Option<ledger::Account>,
+    idempotency_key: Option<String>,
+) -> Result<withdrawals::WithdrawalOutcome, String> {
     let caller = ic_cdk::caller();
 
     if caller == Principal::anonymous() {
         return Err("Anonymous principals cannot withdraw".to_string());
     }
 
-    ledger::withdraw_icp(caller, amount).await
+    let to = to.unwrap_or(ledger::Account {
+        owner: caller,
+        subaccount: None,
+    });
+    let fingerprint = idempotency::fingerprint(&(amount, to.clone()));
+    idempotency::with_client_key_async(caller, "request_withdrawal", idempotency_key, fingerprint, || {
+        withdrawals::request_withdrawal(caller, to, amount, true)
+    })
+    .await
+}
+
+/// Get the caller's withdrawals held for review
+#[query]
+fn get_my_withdrawal_requests() -> Vec<withdrawals::PendingWithdrawal> {
+    withdrawals::get_user_reviews(ic_cdk::caller())
+}
+
+/// Add a withdrawal destination; it can be used once the delay has passed
+#[update]
+fn add_withdrawal_address(
+    account: ledger::Account,
+    label: Option<String>,
+) -> Result<withdrawals::AddressBookEntry, String> {
+    let caller = ic_cdk::caller();
+
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot manage withdrawal addresses".to_string());
+    }
+
+    withdrawals::add_address(caller, account, label, ic_cdk::api::time())
+}
+
+/// Remove a withdrawal destination
+#[update]
+fn remove_withdrawal_address(account: ledger::Account) -> Result<(), String> {
+    withdrawals::remove_address(ic_cdk::caller(), &account)
+}
+
+/// Get the caller's withdrawal address book
+#[query]
+fn get_my_withdrawal_addresses() -> Vec<withdrawals::AddressBookEntry> {
+    withdrawals::list_addresses(ic_cdk::caller())
+}
+
+/// Get the current withdrawal limits
+#[query]
+fn get_withdrawal_limits() -> withdrawals::WithdrawalLimits {
+    withdrawals::get_limits()
 }
 
 /// Get the canister's ICP balance
@@ -1536,6 +2034,34 @@ fn has_expired_dead_man_switches() -> bool {
     dead_man_switch::has_expired_switches()
 }
 
+/// Vote as a guardian on a Dead Man's Switch
+///
+/// `approve = true` confirms release (early, e.g. confirmed death or lost
+/// keys); `approve = false` vetoes a trigger during the grace period.
+#[update]
+fn guardian_vote(switch_id: u64, approve: bool) -> Result<(), String> {
+    dead_man_switch::guardian_vote(switch_id, approve)
+        .map_err(|e| e.to_string())
+}
+
+/// Re-queue the payouts of a switch that ran out of retries (admin only)
+#[update]
+fn retry_dead_man_switch_payout(switch_id: u64) -> Result<(), String> {
+    require_admin!()?;
+
+    dead_man_switch::retry_payout(switch_id)
+        .map_err(|e| e.to_string())
+}
+
+/// Re-queue a Bitcoin payout whose transaction was dropped or replaced (admin only)
+#[update]
+fn reset_dropped_dead_man_switch_payout(switch_id: u64, txid: String) -> Result<(), String> {
+    require_admin!()?;
+
+    dead_man_switch::reset_dropped_payout(switch_id, &txid)
+        .map_err(|e| e.to_string())
+}
+
 // ============================================================================
 // Encrypted Metadata (vetKeys) Endpoints
 // ============================================================================
@@ -1549,19 +2075,43 @@ fn store_encrypted_metadata(
         .map_err(|e| e.to_string())
 }
 
-/// Get encrypted metadata for a Rune
+/// Get encrypted metadata for a Rune (default item, latest version unless given)
+///
+/// Pass `switch_id` to read a note attached to that Dead Man's Switch.
+#[query]
+fn get_encrypted_metadata(
+    rune_id: String,
+    item_id: Option<String>,
+    version: Option<u32>,
+    switch_id: Option<u64>,
+) -> Option<quri_types::EncryptedRuneMetadata> {
+    encrypted_metadata::get_metadata(&rune_id, item_id.as_deref(), version, switch_id)
+}
+
+/// List the latest version of every metadata item of a Rune
 #[query]
-fn get_encrypted_metadata(rune_id: String) -> Option<quri_types::EncryptedRuneMetadata> {
-    encrypted_metadata::get_metadata(&rune_id)
+fn list_encrypted_metadata_items(rune_id: String) -> Vec<quri_types::EncryptedRuneMetadata> {
+    encrypted_metadata::list_items(&rune_id)
 }
 
-/// Check if caller can decrypt metadata
+/// Check if caller can decrypt a version of an item (latest unless given)
 #[query]
-fn can_decrypt_metadata(rune_id: String) -> Result<bool, String> {
-    encrypted_metadata::can_decrypt(&rune_id)
+fn can_decrypt_metadata(
+    rune_id: String,
+    item_id: Option<String>,
+    switch_id: Option<u64>,
+    version: Option<u32>,
+) -> Result<bool, String> {
+    encrypted_metadata::can_decrypt(&rune_id, item_id.as_deref(), version, switch_id)
         .map_err(|e| e.to_string())
 }
 
+/// vetKD derivation ID to encrypt the next version of an item under
+#[query]
+fn get_metadata_derivation_id(rune_id: String, item_id: Option<String>, switch_id: Option<u64>) -> Vec<u8> {
+    encrypted_metadata::next_derivation_id(&rune_id, item_id.as_deref(), switch_id)
+}
+
 /// Get vetKD public key for encryption
 #[update]
 async fn get_vetkd_public_key() -> Result<Vec<u8>, String> {
@@ -1569,13 +2119,17 @@ async fn get_vetkd_public_key() -> Result<Vec<u8>, String> {
         .map_err(|e| e.to_string())
 }
 
-/// Get encrypted decryption key (for authorized callers)
+/// Get the encrypted decryption key of a version (latest unless given) for
+/// authorized callers
 #[update]
 async fn get_encrypted_decryption_key(
     rune_id: String,
-    encryption_public_key: Vec<u8>
+    encryption_public_key: Vec<u8>,
+    item_id: Option<String>,
+    switch_id: Option<u64>,
+    version: Option<u32>,
 ) -> Result<Vec<u8>, String> {
-    encrypted_metadata::get_encrypted_decryption_key(rune_id, encryption_public_key).await
+    encrypted_metadata::get_encrypted_decryption_key(rune_id, item_id, encryption_public_key, switch_id, version).await
         .map_err(|e| e.to_string())
 }
 
@@ -1585,23 +2139,83 @@ fn get_my_encrypted_metadata() -> Vec<quri_types::EncryptedRuneMetadata> {
     encrypted_metadata::get_my_metadata()
 }
 
-/// Delete encrypted metadata (owner only)
+/// Delete an encrypted metadata item and its versions (owner only)
 #[update]
-fn delete_encrypted_metadata(rune_id: String) -> Result<(), String> {
-    encrypted_metadata::delete_metadata(&rune_id)
+fn delete_encrypted_metadata(rune_id: String, item_id: Option<String>, switch_id: Option<u64>) -> Result<(), String> {
+    encrypted_metadata::delete_metadata(&rune_id, item_id.as_deref(), switch_id)
+        .map_err(|e| e.to_string())
+}
+
+/// Get encrypted notes attached to a Dead Man's Switch (owner and beneficiaries)
+#[query]
+fn get_dead_man_switch_metadata(switch_id: u64) -> Result<Vec<quri_types::EncryptedRuneMetadata>, String> {
+    encrypted_metadata::get_switch_metadata(switch_id)
         .map_err(|e| e.to_string())
 }
 
 /// Check if encrypted metadata exists for a Rune
 #[query]
-fn has_encrypted_metadata(rune_id: String) -> bool {
-    encrypted_metadata::has_metadata(&rune_id)
+fn has_encrypted_metadata(rune_id: String, item_id: Option<String>) -> bool {
+    encrypted_metadata::has_metadata(&rune_id, item_id.as_deref())
 }
 
 /// Get metadata reveal status
 #[query]
-fn get_metadata_reveal_status(rune_id: String) -> Option<(bool, Option<u64>)> {
-    encrypted_metadata::get_reveal_status(&rune_id)
+fn get_metadata_reveal_status(rune_id: String, item_id: Option<String>) -> Option<(bool, Option<u64>)> {
+    encrypted_metadata::get_reveal_status(&rune_id, item_id.as_deref())
+}
+
+/// Start a chunked upload of a large encrypted payload
+#[update]
+fn begin_upload(params: quri_types::BeginUploadParams) -> Result<quri_types::UploadInfo, String> {
+    encrypted_metadata::begin_upload(params)
+        .map_err(|e| e.to_string())
+}
+
+/// Upload one chunk of an open upload
+#[update]
+fn put_chunk(upload_id: u64, index: u32, data: Vec<u8>) -> Result<quri_types::UploadInfo, String> {
+    encrypted_metadata::put_chunk(upload_id, index, data)
+        .map_err(|e| e.to_string())
+}
+
+/// Verify an upload's hash and publish it as a new metadata version
+#[update]
+fn commit_upload(upload_id: u64) -> Result<quri_types::EncryptedRuneMetadata, String> {
+    encrypted_metadata::commit_upload(upload_id)
+        .map_err(|e| e.to_string())
+}
+
+/// Abandon an open upload and release its quota
+#[update]
+fn abort_upload(upload_id: u64) -> Result<(), String> {
+    encrypted_metadata::abort_upload(upload_id)
+        .map_err(|e| e.to_string())
+}
+
+/// Get progress of one of the caller's uploads
+#[query]
+fn get_upload(upload_id: u64) -> Result<quri_types::UploadInfo, String> {
+    encrypted_metadata::get_upload(upload_id)
+        .map_err(|e| e.to_string())
+}
+
+/// Read one chunk of an uploaded metadata item
+#[query]
+fn get_chunk(
+    rune_id: String,
+    item_id: Option<String>,
+    version: Option<u32>,
+    index: u32,
+    switch_id: Option<u64>,
+) -> Option<Vec<u8>> {
+    encrypted_metadata::get_chunk(&rune_id, item_id.as_deref(), version, index, switch_id)
+}
+
+/// Bytes of encrypted metadata the caller stores or has reserved
+#[query]
+fn get_my_metadata_usage() -> u64 {
+    encrypted_metadata::user_usage(ic_cdk::caller())
 }
 
 // ============================================================================
@@ -1685,63 +2299,99 @@ fn cleanup_old_escrows(age_days: u64) -> Result<u64, String> {
 }
 
 /// Manual refund for failed escrow (Admin only)
-/// Use this when automatic refund fails and manual intervention is needed
+/// Use this for escrows in the review queue once automatic retries gave up
 #[update]
 async fn admin_manual_refund(process_id: String) -> Result<u64, String> {
     require_admin!()?;
 
-    // Get escrow entry
-    let mut escrow_entry = escrow::get_escrow_by_string(&process_id)
-        .ok_or_else(|| format!("Escrow entry not found for process: {}", process_id))?;
-
-    // Check if refund is possible
-    if !escrow_entry.can_refund() {
-        return Err(format!(
-            "Cannot refund escrow in status: {:?}",
-            escrow_entry.status
-        ));
-    }
-
-    // Get bitcoin-integration canister ID
-    let btc_canister_id = crate::get_bitcoin_integration_id()?;
-
-    // Perform refund
-    let memo = format!("Manual refund for process: {}", process_id);
-    let (transfer_result,): (Result<u64, String>,) = ic_cdk::call(
-        btc_canister_id,
-        "transfer_ckbtc",
-        (escrow_entry.payer, escrow_entry.amount, Some(memo.into_bytes())),
-    )
-    .await
-    .map_err(|(code, msg)| {
-        format!("ckBTC transfer call failed: {:?} - {}", code, msg)
-    })?;
+    let pid = process_id::ProcessId::from_string(&process_id)
+        .map_err(|e| format!("Invalid process ID: {}", e))?;
 
-    match transfer_result {
+    match escrow::refund_escrow(&pid, true).await {
         Ok(block_index) => {
-            // Mark escrow as refunded
-            escrow_entry.mark_refunded(block_index);
-            escrow::update_escrow(&escrow_entry)?;
-
             ic_cdk::println!(
-                "Manual refund successful for process {}: {} sats refunded to {} (block: {})",
+                "Manual refund successful for process {} by {} (block: {})",
                 process_id,
-                escrow_entry.amount,
-                escrow_entry.payer,
+                ic_cdk::caller(),
                 block_index
             );
-
             Ok(block_index)
         }
-        Err(e) => {
-            // Mark refund as failed
-            escrow_entry.mark_refund_failed(e.clone());
-            escrow::update_escrow(&escrow_entry)?;
-            Err(format!("Manual refund failed: {}", e))
-        }
+        Err(e) => Err(format!("Manual refund failed: {}", e)),
     }
 }
 
+/// Escrows waiting for an admin after automatic refund retries (Admin only)
+#[query]
+fn get_refund_review_queue() -> Result<Vec<escrow::EscrowEntry>, String> {
+    require_admin!()?;
+    Ok(escrow::get_refund_review_queue())
+}
+
+// ============================================================================
+// OTC Escrow APIs
+// ============================================================================
+
+/// Lock runes and offer them as a block at a fixed ICP or ckBTC price
+///
+/// @param idempotency_key - Optional; a retry with the same key returns the original offer
+#[update]
+fn create_otc_offer(
+    params: otc::CreateOtcOfferParams,
+    idempotency_key: Option<String>,
+) -> Result<otc::OtcOffer, String> {
+    let caller = ic_cdk::caller();
+    let fingerprint = idempotency::fingerprint(&(params.clone(),));
+    idempotency::with_client_key(caller, "create_otc_offer", idempotency_key, fingerprint, || {
+        otc::create_offer(caller, params, ic_cdk::api::time()).map_err(|e| e.to_string())
+    })
+}
+
+/// Fill a whole OTC offer: the price goes to the maker, the runes to the caller
+///
+/// ckBTC prices are drawn from the caller's ICRC-2 allowance to
+/// bitcoin-integration; approve it for the price plus the ledger fee first.
+///
+/// @param idempotency_key - Optional; a retry with the same key returns the original fill
+#[update]
+async fn fill_otc_offer(offer_id: u64, idempotency_key: Option<String>) -> Result<otc::OtcOffer, String> {
+    let caller = ic_cdk::caller();
+    let fingerprint = idempotency::fingerprint(&(offer_id,));
+    idempotency::with_client_key_async(caller, "fill_otc_offer", idempotency_key, fingerprint, || async move {
+        let result = match otc::get_offer(offer_id).map(|o| o.quote) {
+            Some(otc::OtcQuoteAsset::CkBtc) => otc::fill_ckbtc_offer(caller, offer_id).await,
+            _ => otc::fill_offer(caller, offer_id, ic_cdk::api::time()),
+        };
+        result.map_err(|e| e.to_string())
+    })
+    .await
+}
+
+/// Cancel an open OTC offer and unlock its runes (maker only)
+#[update]
+fn cancel_otc_offer(offer_id: u64) -> Result<otc::OtcOffer, String> {
+    otc::cancel_offer(ic_cdk::caller(), offer_id, ic_cdk::api::time())
+        .map_err(|e| e.to_string())
+}
+
+/// Get an OTC offer by ID
+#[query]
+fn get_otc_offer(offer_id: u64) -> Option<otc::OtcOffer> {
+    otc::get_offer(offer_id)
+}
+
+/// List open OTC offers the caller can fill, optionally for one rune
+#[query]
+fn list_otc_offers(rune_id: Option<String>) -> Vec<otc::OtcOffer> {
+    otc::list_open_offers(ic_cdk::caller(), rune_id.as_deref(), ic_cdk::api::time())
+}
+
+/// OTC offers the caller made or filled
+#[query]
+fn get_my_otc_offers() -> Vec<otc::OtcOffer> {
+    otc::get_user_offers(ic_cdk::caller())
+}
+
 // ============================================================================
 // Trading V2 APIs - Persistent Storage with Bonding Curve & Graduation
 // ============================================================================
@@ -1755,14 +2405,21 @@ async fn admin_manual_refund(process_id: String) -> Result<u64, String> {
 /// The premine is credited to the rune creator when the rune is created,
 /// so they can sell/transfer runes to allow others to create pools.
 ///
+/// Fees follow the pool's phase: the chosen tier while bonding, then the
+/// tier it graduates into.
+///
 /// @param rune_id - The Virtual Rune ID
 /// @param initial_icp - Initial ICP liquidity (in e8s)
 /// @param initial_runes - Initial rune liquidity (caller must own these)
+/// @param launch_rules - Optional anti-sniping rules (start delay, buy cap, cooldown)
+/// @param fee_tier_id - Optional tier from `list_fee_tiers`; defaults to the Standard tier
 #[update]
 fn create_trading_pool_v2(
     rune_id: String,
     initial_icp: u64,
     initial_runes: u64,
+    launch_rules: Option<trading_v2::LaunchRules>,
+    fee_tier_id: Option<u32>,
 ) -> Result<TradingPoolV2View, String> {
     let caller = ic_cdk::caller();
 
@@ -1787,10 +2444,25 @@ fn create_trading_pool_v2(
     if initial_runes < 1 {
         return Err("Minimum initial runes is 1".to_string());
     }
+    if let Some(ref rules) = launch_rules {
+        rules.validate()?;
+    }
 
-    // Debit the runes from the caller (they must own these runes)
-    // This will fail if the caller doesn't have enough runes
-    trading_v2::debit_user_runes(caller, &rune_id, initial_runes)?;
+    // The caller must own the runes and ICP that seed the pool
+    let icp_available = trading_v2::get_user_icp_balance(caller).available;
+    if icp_available < initial_icp {
+        return Err(format!(
+            "Insufficient ICP balance: have {}, need {}",
+            icp_available, initial_icp
+        ));
+    }
+    let runes_available = trading_v2::get_user_rune_balance(caller, &rune_id).available;
+    if runes_available < initial_runes {
+        return Err(format!(
+            "Insufficient rune balance: have {}, need {}",
+            runes_available, initial_runes
+        ));
+    }
 
     // Create the pool
     let pool = trading_v2::create_pool(
@@ -1802,9 +2474,27 @@ fn create_trading_pool_v2(
         initial_icp,
         initial_runes,
         caller, // Pool creator (not necessarily the rune creator)
+        launch_rules,
+        fee_tier_id,
     )?;
 
-    ic_cdk::println!(
+    // Move the initial liquidity into the pool account
+    let pool_account = accounting::LedgerAccount::Pool(rune_id.clone());
+    trading_v2::debit_user_runes(
+        caller,
+        &rune_id,
+        initial_runes,
+        pool_account.clone(),
+        accounting::EntryReason::PoolCreation,
+    )?;
+    trading_v2::debit_user_icp(
+        caller,
+        initial_icp,
+        pool_account,
+        accounting::EntryReason::PoolCreation,
+    )?;
+
+    ic_cdk::println!(
         "✅ Trading pool created for {} by {} with {} ICP and {} runes",
         rune.etching.rune_name,
         caller,
@@ -1816,6 +2506,9 @@ fn create_trading_pool_v2(
 }
 
 /// Get buy quote V2 with price impact and fees breakdown
+///
+/// The returned `quote_id` pins reserves and fees for `QUOTE_TTL_NANOS`;
+/// `slippage_bps` is the drift tolerated when the quote is executed.
 #[query]
 fn get_buy_quote_v2(
     rune_id: String,
@@ -1823,10 +2516,12 @@ fn get_buy_quote_v2(
     slippage_bps: u64,
 ) -> Result<TradeQuoteV2View, String> {
     let quote = trading_v2::calculate_buy_quote(&rune_id, icp_amount, slippage_bps)?;
-    Ok(TradeQuoteV2View::from(quote))
+    Ok(TradeQuoteV2View::signed(quote, slippage_bps))
 }
 
 /// Get sell quote V2 with price impact and fees breakdown
+///
+/// See `get_buy_quote_v2` for the quote ID semantics.
 #[query]
 fn get_sell_quote_v2(
     rune_id: String,
@@ -1834,36 +2529,82 @@ fn get_sell_quote_v2(
     slippage_bps: u64,
 ) -> Result<TradeQuoteV2View, String> {
     let quote = trading_v2::calculate_sell_quote(&rune_id, rune_amount, slippage_bps)?;
-    Ok(TradeQuoteV2View::from(quote))
+    Ok(TradeQuoteV2View::signed(quote, slippage_bps))
 }
 
 /// Execute buy trade V2
 ///
 /// Buys virtual runes with ICP from the pool.
 /// Supports bonding curve pricing until graduation.
+///
+/// @param deadline - Optional timestamp (ns) after which the trade must not execute
+/// @param quote_id - Optional quote from `get_buy_quote_v2`; fails if the pool drifted beyond its tolerance
+/// @param idempotency_key - Optional; a retry with the same key returns the original trade
 #[update]
 fn buy_virtual_rune_v2(
     rune_id: String,
     icp_amount: u64,
     min_runes_out: u64,
-) -> Result<TradeEventView, String> {
+    deadline: Option<u64>,
+    quote_id: Option<String>,
+    idempotency_key: Option<String>,
+) -> Result<TradeEventView, trading_v2::TradeError> {
     let caller = ic_cdk::caller();
-    let event = trading_v2::execute_buy(&rune_id, icp_amount, min_runes_out, caller)?;
-    Ok(TradeEventView::from(event))
+    let fingerprint = idempotency::fingerprint(&(
+        rune_id.clone(),
+        icp_amount,
+        min_runes_out,
+        deadline,
+        quote_id.clone(),
+    ));
+    idempotency::with_client_key(caller, "buy_v2", idempotency_key, fingerprint, || {
+        let event = trading_v2::execute_buy(
+            &rune_id,
+            icp_amount,
+            min_runes_out,
+            deadline,
+            quote_id.as_deref(),
+            caller,
+        )?;
+        Ok(TradeEventView::from(event))
+    })
 }
 
 /// Execute sell trade V2
 ///
 /// Sells virtual runes for ICP from the pool.
+///
+/// @param deadline - Optional timestamp (ns) after which the trade must not execute
+/// @param quote_id - Optional quote from `get_sell_quote_v2`; fails if the pool drifted beyond its tolerance
+/// @param idempotency_key - Optional; a retry with the same key returns the original trade
 #[update]
 fn sell_virtual_rune_v2(
     rune_id: String,
     rune_amount: u64,
     min_icp_out: u64,
-) -> Result<TradeEventView, String> {
+    deadline: Option<u64>,
+    quote_id: Option<String>,
+    idempotency_key: Option<String>,
+) -> Result<TradeEventView, trading_v2::TradeError> {
     let caller = ic_cdk::caller();
-    let event = trading_v2::execute_sell(&rune_id, rune_amount, min_icp_out, caller)?;
-    Ok(TradeEventView::from(event))
+    let fingerprint = idempotency::fingerprint(&(
+        rune_id.clone(),
+        rune_amount,
+        min_icp_out,
+        deadline,
+        quote_id.clone(),
+    ));
+    idempotency::with_client_key(caller, "sell_v2", idempotency_key, fingerprint, || {
+        let event = trading_v2::execute_sell(
+            &rune_id,
+            rune_amount,
+            min_icp_out,
+            deadline,
+            quote_id.as_deref(),
+            caller,
+        )?;
+        Ok(TradeEventView::from(event))
+    })
 }
 
 /// Get trading pool V2 by rune ID
@@ -1947,12 +2688,84 @@ fn get_my_rune_balance_v2(rune_id: String) -> UserBalanceView {
     UserBalanceView::from(trading_v2::get_user_rune_balance(caller, &rune_id))
 }
 
-/// Credit ICP to caller's trading balance (for testing/admin)
-/// In production, this should be called after verifying ICP deposit
+/// Number of runes the caller holds a balance in (for paging get_my_all_rune_balances)
+#[query]
+fn get_my_rune_count() -> u64 {
+    trading_v2::get_user_rune_count(ic_cdk::caller())
+}
+
+/// Transfer a virtual rune balance to another principal
+///
+/// @param to - Recipient principal
+/// @param rune_id - The Virtual Rune ID
+/// @param amount - Amount of runes to send
+/// @param memo - Optional memo (max 32 bytes), stored with the transfer event
+/// @param created_at_time - Optional; retries with the same created_at_time and memo
+///   return the original transfer instead of sending again
+/// @param idempotency_key - Optional; a retry with the same key returns the original transfer
+#[update]
+fn transfer_virtual_rune(
+    to: Principal,
+    rune_id: String,
+    amount: u64,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+    idempotency_key: Option<String>,
+) -> Result<TradeEventView, String> {
+    let caller = ic_cdk::caller();
+
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot transfer runes".to_string());
+    }
+    if to == Principal::anonymous() {
+        return Err("Cannot transfer to the anonymous principal".to_string());
+    }
+    if state::get_virtual_rune(&rune_id).is_none() {
+        return Err("Virtual Rune not found".to_string());
+    }
+
+    let fingerprint = idempotency::fingerprint(&(to, rune_id.clone(), amount, memo.clone(), created_at_time));
+    idempotency::with_client_key(caller, "transfer", idempotency_key, fingerprint, || {
+        let event = trading_v2::transfer_runes(caller, to, &rune_id, amount, memo, created_at_time)?;
+        Ok(TradeEventView::from(event))
+    })
+}
+
+/// Credit ICP to caller's trading balance (admin only)
+///
+/// Balances are withdrawable, so regular deposits must go through
+/// `verify_deposit`; this is kept for operational corrections.
 #[update]
 fn credit_icp_balance(amount: u64) -> Result<u64, String> {
+    require_admin!()?;
+
     let caller = ic_cdk::caller();
-    trading_v2::credit_user_icp(caller, amount)
+    trading_v2::credit_user_icp(
+        caller,
+        amount,
+        accounting::LedgerAccount::Custody,
+        accounting::EntryReason::ManualCredit,
+    )
+}
+
+/// Get a page of the accounting journal (admin only)
+#[query]
+fn get_ledger_journal(offset: u64, limit: u64) -> Result<Vec<accounting::JournalEntry>, String> {
+    require_admin!()?;
+    Ok(accounting::get_journal(offset, limit.min(100)))
+}
+
+/// Check accounting invariants (ICP ledger balance covers ICP liabilities, pool backing) (admin only)
+#[update]
+async fn check_ledger_invariants() -> Result<accounting::InvariantReport, String> {
+    require_admin!()?;
+
+    // Liabilities are read first: a deposit landing during the balance call
+    // then shows as surplus rather than a shortfall
+    let mut report = accounting::check_invariants();
+    let held = ledger::get_canister_balance().await?;
+    report.record_held(&accounting::Asset::Icp, held as u128);
+    Ok(report)
 }
 
 // ============================================================================
@@ -1962,46 +2775,78 @@ fn credit_icp_balance(amount: u64) -> Result<u64, String> {
 /// Add liquidity to a graduated AMM pool
 ///
 /// Can only add liquidity to pools that have graduated from bonding curve.
+///
+/// @param idempotency_key - Optional; a retry with the same key returns the original result
 #[update]
 fn add_liquidity_v2(
     rune_id: String,
     icp_amount: u64,
     max_runes: u64,
+    idempotency_key: Option<String>,
 ) -> Result<AddLiquidityResultView, String> {
     let caller = ic_cdk::caller();
-    let (icp_used, runes_used, lp_tokens) = trading_v2::add_liquidity(
-        &rune_id,
-        icp_amount,
-        max_runes,
-        caller,
-    )?;
-    Ok(AddLiquidityResultView {
-        icp_deposited: icp_used,
-        runes_deposited: runes_used,
-        lp_tokens_minted: lp_tokens,
+    let fingerprint = idempotency::fingerprint(&(rune_id.clone(), icp_amount, max_runes));
+    idempotency::with_client_key(caller, "add_liquidity_v2", idempotency_key, fingerprint, || {
+        let (icp_used, runes_used, lp_tokens) = trading_v2::add_liquidity(
+            &rune_id,
+            icp_amount,
+            max_runes,
+            caller,
+        )?;
+        Ok(AddLiquidityResultView {
+            icp_deposited: icp_used,
+            runes_deposited: runes_used,
+            lp_tokens_minted: lp_tokens,
+        })
     })
 }
 
 /// Remove liquidity from a pool
+///
+/// @param idempotency_key - Optional; a retry with the same key returns the original result
 #[update]
 fn remove_liquidity_v2(
     rune_id: String,
     lp_amount: u64,
     min_icp: u64,
     min_runes: u64,
+    idempotency_key: Option<String>,
 ) -> Result<RemoveLiquidityResultView, String> {
     let caller = ic_cdk::caller();
-    let (icp_out, runes_out) = trading_v2::remove_liquidity(
-        &rune_id,
-        lp_amount,
-        min_icp,
-        min_runes,
-        caller,
-    )?;
-    Ok(RemoveLiquidityResultView {
-        icp_withdrawn: icp_out,
-        runes_withdrawn: runes_out,
-        lp_tokens_burned: lp_amount,
+    let fingerprint = idempotency::fingerprint(&(rune_id.clone(), lp_amount, min_icp, min_runes));
+    idempotency::with_client_key(caller, "remove_liquidity_v2", idempotency_key, fingerprint, || {
+        let (icp_out, runes_out) = trading_v2::remove_liquidity(
+            &rune_id,
+            lp_amount,
+            min_icp,
+            min_runes,
+            caller,
+        )?;
+        Ok(RemoveLiquidityResultView {
+            icp_withdrawn: icp_out,
+            runes_withdrawn: runes_out,
+            lp_tokens_burned: lp_amount,
+        })
+    })
+}
+
+/// Claim the caller's accrued LP fees for a pool
+///
+/// Fees are paid into the trading balances; removing the whole position
+/// also pays out any unclaimed fees.
+///
+/// @param idempotency_key - Optional; a retry with the same key returns the original claim
+#[update]
+fn claim_lp_fees_v2(rune_id: String, idempotency_key: Option<String>) -> Result<ClaimLpFeesResultView, String> {
+    let caller = ic_cdk::caller();
+    let fingerprint = idempotency::fingerprint(&(rune_id.clone(),));
+    idempotency::with_client_key(caller, "claim_lp_fees_v2", idempotency_key, fingerprint, || {
+        let (icp_claimed, runes_claimed) =
+            trading_v2::claim_lp_fees(&rune_id, caller, ic_cdk::api::time())?;
+        Ok(ClaimLpFeesResultView {
+            icp_claimed,
+            runes_claimed,
+        })
     })
 }
 
@@ -2013,6 +2858,13 @@ fn get_my_lp_position(rune_id: String) -> Option<LPPositionView> {
     trading_v2::get_lp_position(&pool_id, caller).map(LPPositionView::from)
 }
 
+/// Get fee earnings, underlying amounts and impermanent loss of caller's LP position
+#[query]
+fn get_lp_position_stats(rune_id: String) -> Result<trading_v2::LPPositionStats, String> {
+    let caller = ic_cdk::caller();
+    trading_v2::get_lp_position_stats(&rune_id, caller)
+}
+
 /// Get all LP positions for caller
 #[query]
 fn get_my_lp_positions() -> Vec<(String, LPPositionView)> {
@@ -2023,6 +2875,431 @@ fn get_my_lp_positions() -> Vec<(String, LPPositionView)> {
         .collect()
 }
 
+// ============================================================================
+// V2 Fee Tier APIs
+// ============================================================================
+
+/// List available fee tiers
+#[query]
+fn list_fee_tiers() -> Vec<FeeTierView> {
+    trading_v2::list_fee_tiers()
+        .into_iter()
+        .map(FeeTierView::from)
+        .collect()
+}
+
+/// Create or update a fee tier (Admin only)
+///
+/// Pools copy a tier's fees when it is assigned, so changes apply to pools
+/// created or moved to the tier afterwards; use `set_pool_fee_tier` to
+/// move an existing pool onto the new fees.
+#[update]
+fn upsert_fee_tier(tier: trading_v2::FeeTier) -> Result<(), String> {
+    require_admin!()?;
+
+    let tier_id = tier.id;
+    trading_v2::upsert_fee_tier(tier)?;
+
+    logging::log_info("trading_v2", format!("Fee tier {} updated", tier_id), None);
+
+    Ok(())
+}
+
+/// Remove an unused fee tier (Admin only)
+#[update]
+fn remove_fee_tier(tier_id: u32) -> Result<(), String> {
+    require_admin!()?;
+
+    trading_v2::remove_fee_tier(tier_id)?;
+
+    logging::log_info("trading_v2", format!("Fee tier {} removed", tier_id), None);
+
+    Ok(())
+}
+
+/// Change the fee tier of a pool (Admin only)
+#[update]
+fn set_pool_fee_tier(rune_id: String, tier_id: u32) -> Result<TradingPoolV2View, String> {
+    require_admin!()?;
+
+    let pool = trading_v2::set_pool_fee_tier(&rune_id, tier_id)?;
+
+    logging::log_info(
+        "trading_v2",
+        format!("Pool {} moved to fee tier {}", rune_id, tier_id),
+        None,
+    );
+
+    Ok(TradingPoolV2View::from(pool))
+}
+
+// ============================================================================
+// Protocol Fee Treasury APIs
+// ============================================================================
+
+/// Set the treasury account that receives protocol fees (Admin only)
+///
+/// Without a subaccount, fees are sent to the principal's default account.
+#[update]
+fn set_treasury_account(owner: Principal, subaccount: Option<Vec<u8>>) -> Result<(), String> {
+    require_admin!()?;
+
+    let caller = ic_cdk::caller();
+    treasury::set_treasury_account(ledger::Account { owner, subaccount }, caller)?;
+
+    ic_cdk::println!("Treasury account set to {} by {}", owner, caller);
+
+    Ok(())
+}
+
+/// Get the configured treasury account (Admin only)
+#[query]
+fn get_treasury_account() -> Result<Option<ledger::Account>, String> {
+    require_admin!()?;
+    Ok(treasury::get_treasury_account())
+}
+
+/// Collect pending protocol fees into the treasury (Admin only)
+///
+/// @param scope - A single pool (by rune ID) or all pools
+/// @param idempotency_key - Optional; a retry with the same key returns the original collection
+#[update]
+async fn collect_protocol_fees(
+    scope: treasury::CollectionScope,
+    idempotency_key: Option<String>,
+) -> Result<treasury::FeeCollection, String> {
+    require_admin!()?;
+
+    let caller = ic_cdk::caller();
+    let fingerprint = idempotency::fingerprint(&(scope.clone(),));
+    let collection = idempotency::with_client_key_async(caller, "collect_protocol_fees", idempotency_key, fingerprint, || {
+        treasury::collect_protocol_fees(scope, caller)
+    })
+    .await?;
+
+    logging::log_info(
+        "treasury",
+        format!(
+            "Collected {} e8s of protocol fees from {} pool(s)",
+            collection.total_amount,
+            collection.shares.len()
+        ),
+        None,
+    );
+
+    Ok(collection)
+}
+
+/// Get protocol fee collection history, most recent first (Admin only)
+#[query]
+fn get_protocol_fee_collections(offset: u64, limit: u64) -> Result<Vec<treasury::FeeCollection>, String> {
+    require_admin!()?;
+    Ok(treasury::get_collections(offset, limit.min(100)))
+}
+
+/// Get pending and collected protocol fees per pool (Admin only)
+#[query]
+fn get_protocol_fee_report() -> Result<Vec<treasury::PoolFeeReport>, String> {
+    require_admin!()?;
+    Ok(treasury::get_pool_fee_reports())
+}
+
+// ============================================================================
+// Proof of Liabilities APIs
+// ============================================================================
+
+/// Get the latest proof-of-liabilities snapshot
+///
+/// Returns each asset's Merkle sum tree root and total, the commitment over
+/// them, and a certificate proving the commitment is the canister's
+/// certified data.
+#[query]
+fn get_liability_snapshot() -> Option<liabilities::LiabilitySnapshot> {
+    liabilities::get_snapshot(ic_cdk::api::data_certificate())
+}
+
+/// Get an inclusion proof for the caller's balance in the latest snapshot
+///
+/// @param rune_or_icp - "ICP" or a rune ID
+#[query]
+fn get_my_liability_proof(rune_or_icp: String) -> Result<liabilities::LiabilityProof, String> {
+    let caller = ic_cdk::caller();
+
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals have no balances".to_string());
+    }
+
+    liabilities::get_proof(caller, &rune_or_icp, ic_cdk::api::data_certificate())
+}
+
+/// Take a proof-of-liabilities snapshot now (Admin only)
+#[update]
+async fn take_liability_snapshot() -> Result<liabilities::LiabilitySnapshot, String> {
+    require_admin!()?;
+    liabilities::take_snapshot().await
+}
+
+// ============================================================================
+// Solvency APIs (Admin only)
+// ============================================================================
+
+/// Reconcile internal ICP liabilities with the ledger now (Admin only)
+#[update]
+async fn run_solvency_check() -> Result<solvency::SolvencyReport, String> {
+    require_admin!()?;
+    solvency::run_check().await
+}
+
+/// Get solvency reports, most recent first (Admin only)
+#[query]
+fn get_solvency_reports(offset: u64, limit: u64) -> Result<Vec<solvency::SolvencyReport>, String> {
+    require_admin!()?;
+    Ok(solvency::get_reports(offset, limit.min(100)))
+}
+
+/// Get the latest solvency report (Admin only)
+#[query]
+fn get_latest_solvency_report() -> Result<Option<solvency::SolvencyReport>, String> {
+    require_admin!()?;
+    Ok(solvency::get_latest_report())
+}
+
+/// Get solvency settings and withdrawal pause state (Admin only)
+#[query]
+fn get_solvency_config() -> Result<solvency::SolvencyConfig, String> {
+    require_admin!()?;
+    Ok(solvency::get_config())
+}
+
+/// Configure solvency alerts (Admin only)
+///
+/// @param auto_pause_withdrawals - Pause ICP withdrawals when a shortfall is found
+/// @param shortfall_tolerance - Shortfall (e8s) tolerated before alerting
+#[update]
+fn set_solvency_config(
+    auto_pause_withdrawals: bool,
+    shortfall_tolerance: u64,
+) -> Result<solvency::SolvencyConfig, String> {
+    require_admin!()?;
+    solvency::set_config(auto_pause_withdrawals, shortfall_tolerance, ic_cdk::caller())
+}
+
+/// Pause or resume ICP withdrawals (Admin only)
+#[update]
+fn set_withdrawals_paused(paused: bool, reason: Option<String>) -> Result<solvency::SolvencyConfig, String> {
+    require_admin!()?;
+
+    let caller = ic_cdk::caller();
+    let config = solvency::set_withdrawals_paused(paused, reason, Some(caller))?;
+
+    logging::log_info(
+        "solvency",
+        format!("Withdrawals {} by {}", if paused { "paused" } else { "resumed" }, caller),
+        None,
+    );
+
+    Ok(config)
+}
+
+// ============================================================================
+// Withdrawal Review APIs (Admin only)
+// ============================================================================
+
+/// Set withdrawal limits and the review threshold (Admin only)
+#[update]
+fn set_withdrawal_limits(limits: withdrawals::WithdrawalLimits) -> Result<withdrawals::WithdrawalLimits, String> {
+    require_admin!()?;
+
+    let caller = ic_cdk::caller();
+    withdrawals::set_limits(limits, caller, ic_cdk::api::time())?;
+
+    logging::log_info("withdrawals", format!("Withdrawal limits updated by {}", caller), None);
+
+    Ok(withdrawals::get_limits())
+}
+
+/// List withdrawals in the review queue, newest first (Operator+)
+#[query]
+fn get_withdrawal_review_queue(
+    pending_only: bool,
+    offset: u64,
+    limit: u64,
+) -> Result<Vec<withdrawals::PendingWithdrawal>, String> {
+    require_role!(Role::Operator)?;
+    Ok(withdrawals::list_review_queue(pending_only, offset, limit.min(100)))
+}
+
+/// Approve and send a held withdrawal (Admin only)
+#[update]
+async fn approve_withdrawal(request_id: u64) -> Result<withdrawals::PendingWithdrawal, String> {
+    require_admin!()?;
+
+    let caller = ic_cdk::caller();
+    let pending = withdrawals::approve(request_id, caller).await?;
+
+    logging::log_info(
+        "withdrawals",
+        format!(
+            "Withdrawal {} of {} e8s for {} approved by {}: {:?}",
+            request_id, pending.amount, pending.user, caller, pending.status
+        ),
+        None,
+    );
+
+    Ok(pending)
+}
+
+/// Reject a held withdrawal and return the funds to the user (Admin only)
+#[update]
+fn reject_withdrawal(request_id: u64, reason: Option<String>) -> Result<withdrawals::PendingWithdrawal, String> {
+    require_admin!()?;
+
+    let caller = ic_cdk::caller();
+    let pending = withdrawals::reject(request_id, caller, reason, ic_cdk::api::time())?;
+
+    logging::log_info(
+        "withdrawals",
+        format!("Withdrawal {} for {} rejected by {}", request_id, pending.user, caller),
+        None,
+    );
+
+    Ok(pending)
+}
+
+// ============================================================================
+// Rune Token APIs (ICRC-1/ICRC-2 style, keyed by rune ID)
+//
+// Every method takes the rune ID first, so these are not ICRC-1/ICRC-2
+// endpoints and are named accordingly. Standard wallets use the rune's
+// `rune-ledger` canister, which forwards to `rune_ledger_*` below.
+// ============================================================================
+
+/// Standards the rune engine implements
+#[query]
+fn icrc10_supported_standards() -> Vec<icrc::StandardRecord> {
+    icrc::supported_standards()
+}
+
+/// Token metadata for a rune (name, symbol, decimals, fee)
+#[query]
+fn rune_token_metadata(rune_id: String) -> Vec<(String, icrc::MetadataValue)> {
+    icrc::metadata(&rune_id)
+}
+
+/// Transfer fee for a rune (always 0)
+#[query]
+fn rune_token_fee(_rune_id: String) -> candid::Nat {
+    candid::Nat::from(icrc::ICRC_FEE)
+}
+
+/// Issued supply of a rune
+#[query]
+fn rune_token_total_supply(rune_id: String) -> candid::Nat {
+    candid::Nat::from(icrc::total_supply(&rune_id))
+}
+
+/// Balance of an account in a rune
+#[query]
+fn rune_token_balance_of(rune_id: String, account: ledger::Account) -> candid::Nat {
+    candid::Nat::from(icrc::balance_of(&rune_id, &account))
+}
+
+/// Transfer runes from the caller's account
+#[update]
+fn rune_token_transfer(rune_id: String, arg: ledger::TransferArg) -> ledger::TransferResult {
+    icrc::transfer(&rune_id, ic_cdk::caller(), arg, ic_cdk::api::time()).map(candid::Nat::from)
+}
+
+/// Approve a spender to transfer runes from the caller's account
+#[update]
+fn rune_token_approve(rune_id: String, args: icrc::ApproveArgs) -> Result<candid::Nat, icrc::ApproveError> {
+    icrc::approve(&rune_id, ic_cdk::caller(), args, ic_cdk::api::time()).map(candid::Nat::from)
+}
+
+/// Current allowance of a spender
+#[query]
+fn rune_token_allowance(rune_id: String, args: icrc::AllowanceArgs) -> icrc::Allowance {
+    icrc::allowance(&rune_id, &args, ic_cdk::api::time())
+}
+
+/// Transfer runes on behalf of an account using the caller's allowance
+#[update]
+fn rune_token_transfer_from(
+    rune_id: String,
+    args: icrc::TransferFromArgs,
+) -> Result<candid::Nat, icrc::TransferFromError> {
+    icrc::transfer_from(&rune_id, ic_cdk::caller(), args, ic_cdk::api::time()).map(candid::Nat::from)
+}
+
+/// Register the ICRC-1/ICRC-2 ledger canister serving a rune (Admin only)
+#[update]
+fn set_rune_ledger(rune_id: String, ledger: Principal) -> Result<(), String> {
+    require_admin!()?;
+    icrc::set_rune_ledger(&rune_id, ledger)
+}
+
+/// ICRC-1/ICRC-2 ledger canister serving a rune, if one is registered
+#[query]
+fn get_rune_ledger(rune_id: String) -> Option<Principal> {
+    icrc::rune_ledger(&rune_id)
+}
+
+/// `icrc1_transfer` forwarded by a registered rune ledger for `caller`
+#[update]
+fn rune_ledger_transfer(caller: Principal, arg: ledger::TransferArg) -> ledger::TransferResult {
+    icrc::ledger_transfer(ic_cdk::caller(), caller, arg, ic_cdk::api::time()).map(candid::Nat::from)
+}
+
+/// `icrc2_approve` forwarded by a registered rune ledger for `caller`
+#[update]
+fn rune_ledger_approve(caller: Principal, args: icrc::ApproveArgs) -> Result<candid::Nat, icrc::ApproveError> {
+    icrc::ledger_approve(ic_cdk::caller(), caller, args, ic_cdk::api::time()).map(candid::Nat::from)
+}
+
+/// `icrc2_transfer_from` forwarded by a registered rune ledger for `caller`
+#[update]
+fn rune_ledger_transfer_from(
+    caller: Principal,
+    args: icrc::TransferFromArgs,
+) -> Result<candid::Nat, icrc::TransferFromError> {
+    icrc::ledger_transfer_from(ic_cdk::caller(), caller, args, ic_cdk::api::time()).map(candid::Nat::from)
+}
+
+// ============================================================================
+// ICRC-3 Transaction Log APIs
+// ============================================================================
+
+/// Get blocks of the rune transaction log (older blocks via archive callbacks)
+#[query]
+fn icrc3_get_blocks(args: Vec<quri_types::GetBlocksArgs>) -> quri_types::GetBlocksResult {
+    icrc3::get_blocks(args)
+}
+
+/// Archive canisters holding older blocks
+#[query]
+fn icrc3_get_archives(_args: icrc3::GetArchivesArgs) -> Vec<icrc3::ICRC3ArchiveInfo> {
+    icrc3::get_archives()
+}
+
+/// Certificate for the last block index and hash
+#[query]
+fn icrc3_get_tip_certificate() -> Option<icrc3::ICRC3DataCertificate> {
+    icrc3::tip_certificate(ic_cdk::api::data_certificate())
+}
+
+/// Block types appended to the log
+#[query]
+fn icrc3_supported_block_types() -> Vec<icrc3::SupportedBlockType> {
+    icrc3::supported_block_types()
+}
+
+/// Set the archive canister for old blocks (Admin only)
+#[update]
+fn set_icrc3_archive(archive: Principal) -> Result<(), String> {
+    require_admin!()?;
+    icrc3::set_archive(archive)
+}
+
 // ============================================================================
 // Trading V2 View Types
 // ============================================================================
@@ -2046,6 +3323,13 @@ pub struct TradingPoolV2View {
     pub total_lp_supply: u64,
     pub fees_collected_icp: u64,
     pub protocol_fees_pending: u64,
+    pub fee_tier_id: u32,
+    pub trading_fee_bps: u64,
+    pub protocol_fee_bps: u64,
+    pub lp_fee_bps: u64,
+    pub launch_rules: Option<trading_v2::LaunchRules>,
+    pub trading_starts_at: u64,
+    pub launch_protection_ends_at: Option<u64>,
     pub total_volume_icp: u128,
     pub total_trades: u64,
     pub unique_traders: u64,
@@ -2061,6 +3345,7 @@ impl From<trading_v2::TradingPool> for TradingPoolV2View {
     fn from(pool: trading_v2::TradingPool) -> Self {
         let price_per_rune = trading_v2::get_pool_price(&pool);
         let market_cap = trading_v2::get_pool_market_cap(&pool);
+        let fees = pool.fees();
 
         let pool_type = match pool.pool_type {
             trading_v2::PoolType::Bonding => "Bonding".to_string(),
@@ -2091,6 +3376,20 @@ impl From<trading_v2::TradingPool> for TradingPoolV2View {
             total_lp_supply: pool.total_lp_supply,
             fees_collected_icp: pool.fees_collected_icp,
             protocol_fees_pending: pool.protocol_fees_pending,
+            fee_tier_id: fees.tier_id,
+            trading_fee_bps: fees.trading_fee_bps,
+            protocol_fee_bps: fees.protocol_fee_bps,
+            lp_fee_bps: fees.lp_fee_bps(),
+            trading_starts_at: pool
+                .launch_rules
+                .as_ref()
+                .map_or(pool.created_at, |r| r.trading_starts_at(pool.created_at)),
+            launch_protection_ends_at: pool
+                .launch_rules
+                .as_ref()
+                .filter(|r| r.protection_window_minutes > 0)
+                .map(|r| r.protection_ends_at(pool.created_at)),
+            launch_rules: pool.launch_rules,
             total_volume_icp: pool.total_volume_icp,
             total_trades: pool.total_trades,
             unique_traders: pool.unique_traders,
@@ -2115,11 +3414,30 @@ pub struct TradeQuoteV2View {
     pub fee: u64,
     pub protocol_fee: u64,
     pub lp_fee: u64,
+    pub fee_tier_id: u32,
+    pub trading_fee_bps: u64,
+    pub protocol_fee_bps: u64,
     pub price_impact_bps: u16,
     pub minimum_output: u64,
     pub pool_icp_reserve: u64,
     pub pool_rune_reserve: u64,
     pub effective_price: f64,
+    /// Signed quote to pass to buy/sell (None while the signing key is being generated)
+    pub quote_id: Option<String>,
+    pub quote_expires_at: Option<u64>,
+}
+
+impl TradeQuoteV2View {
+    /// Build the view with a signed quote ID attached
+    fn signed(quote: trading_v2::TradeQuote, tolerance_bps: u64) -> Self {
+        let signed = trading_v2::sign_quote(&quote, tolerance_bps, ic_cdk::api::time());
+        let mut view = Self::from(quote);
+        if let Some((quote_id, expires_at)) = signed {
+            view.quote_id = Some(quote_id);
+            view.quote_expires_at = Some(expires_at);
+        }
+        view
+    }
 }
 
 impl From<trading_v2::TradeQuote> for TradeQuoteV2View {
@@ -2129,6 +3447,7 @@ impl From<trading_v2::TradeQuote> for TradeQuoteV2View {
             trade_type: match quote.trade_type {
                 trading_v2::TradeType::Buy => "Buy".to_string(),
                 trading_v2::TradeType::Sell => "Sell".to_string(),
+                trading_v2::TradeType::Transfer => "Transfer".to_string(),
             },
             input_amount: quote.input_amount,
             output_amount: quote.output_amount,
@@ -2136,11 +3455,16 @@ impl From<trading_v2::TradeQuote> for TradeQuoteV2View {
             fee: quote.fee,
             protocol_fee: quote.protocol_fee,
             lp_fee: quote.lp_fee,
+            fee_tier_id: quote.fees.tier_id,
+            trading_fee_bps: quote.fees.trading_fee_bps,
+            protocol_fee_bps: quote.fees.protocol_fee_bps,
             price_impact_bps: quote.price_impact_bps,
             minimum_output: quote.minimum_output,
             pool_icp_reserve: quote.pool_icp_reserve,
             pool_rune_reserve: quote.pool_rune_reserve,
             effective_price: quote.effective_price,
+            quote_id: None,
+            quote_expires_at: None,
         }
     }
 }
@@ -2157,10 +3481,15 @@ pub struct TradeEventView {
     pub rune_amount: u64,
     pub price_per_rune: u64,
     pub fee: u64,
+    pub fee_tier_id: Option<u32>,
+    pub trading_fee_bps: Option<u64>,
+    pub protocol_fee_bps: Option<u64>,
     pub price_impact_bps: u16,
     pub pool_icp_reserve_after: u64,
     pub pool_rune_reserve_after: u64,
     pub timestamp: u64,
+    pub counterparty: Option<Principal>,
+    pub memo: Option<Vec<u8>>,
 }
 
 impl From<trading_v2::TradeEvent> for TradeEventView {
@@ -2173,15 +3502,45 @@ impl From<trading_v2::TradeEvent> for TradeEventView {
             trade_type: match event.trade_type {
                 trading_v2::TradeType::Buy => "Buy".to_string(),
                 trading_v2::TradeType::Sell => "Sell".to_string(),
+                trading_v2::TradeType::Transfer => "Transfer".to_string(),
             },
             icp_amount: event.icp_amount,
             rune_amount: event.rune_amount,
             price_per_rune: event.price_per_rune,
             fee: event.fee,
+            fee_tier_id: event.fees.as_ref().map(|f| f.tier_id),
+            trading_fee_bps: event.fees.as_ref().map(|f| f.trading_fee_bps),
+            protocol_fee_bps: event.fees.as_ref().map(|f| f.protocol_fee_bps),
             price_impact_bps: event.price_impact_bps,
             pool_icp_reserve_after: event.pool_icp_reserve_after,
             pool_rune_reserve_after: event.pool_rune_reserve_after,
             timestamp: event.timestamp,
+            counterparty: event.counterparty,
+            memo: event.memo,
+        }
+    }
+}
+
+/// View of a fee tier
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FeeTierView {
+    pub id: u32,
+    pub name: String,
+    pub trading_fee_bps: u64,
+    pub protocol_fee_bps: u64,
+    pub lp_fee_bps: u64,
+    pub graduated_tier_id: Option<u32>,
+}
+
+impl From<trading_v2::FeeTier> for FeeTierView {
+    fn from(tier: trading_v2::FeeTier) -> Self {
+        FeeTierView {
+            id: tier.id,
+            lp_fee_bps: tier.lp_fee_bps(),
+            name: tier.name,
+            trading_fee_bps: tier.trading_fee_bps,
+            protocol_fee_bps: tier.protocol_fee_bps,
+            graduated_tier_id: tier.graduated_tier_id,
         }
     }
 }
@@ -2237,6 +3596,7 @@ pub struct LPPositionView {
     pub icp_deposited: u64,
     pub runes_deposited: u64,
     pub rewards_earned: u64,
+    pub rune_rewards_earned: u64,
     pub last_reward_claim: u64,
     pub created_at: u64,
     pub updated_at: u64,
@@ -2249,6 +3609,7 @@ impl From<trading_v2::LPPosition> for LPPositionView {
             icp_deposited: position.icp_deposited,
             runes_deposited: position.runes_deposited,
             rewards_earned: position.rewards_earned,
+            rune_rewards_earned: position.rune_rewards_earned.unwrap_or(0),
             last_reward_claim: position.last_reward_claim,
             created_at: position.created_at,
             updated_at: position.updated_at,
@@ -2272,4 +3633,11 @@ pub struct RemoveLiquidityResultView {
     pub lp_tokens_burned: u64,
 }
 
+/// LP fees paid out by a claim
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ClaimLpFeesResultView {
+    pub icp_claimed: u64,
+    pub runes_claimed: u64,
+}
+
 ic_cdk::export_candid!();
@@ -0,0 +1,833 @@
+//! OTC Escrow Module
+//!
+//! Peer-to-peer block trades of virtual runes outside the AMM. A maker locks
+//! an amount of a rune and asks a fixed total price in ICP or ckBTC,
+//! optionally for one named counterparty. A taker fills the whole block: the
+//! price moves to the maker and the runes to the taker, so neither leg can
+//! settle without the other. Offers that are not filled in time expire and the
+//! runes return to the maker.
+//!
+//! ICP-priced offers settle on internal balances in a single message. ckBTC
+//! has no internal balance, so a ckBTC fill reserves the offer, draws the price
+//! from the taker's ICRC-2 allowance straight to the maker through
+//! bitcoin-integration, and only then releases the runes. The draw carries a
+//! per-offer memo and a pinned `created_at_time`, so a retried fill resolves to
+//! the original ledger block instead of charging twice. Either way large trades
+//! never touch pool reserves or move the `trading_v2` price.
+//!
+//! Open offers are indexed by expiry and by maker, closed offers by the time
+//! they closed, and every offer by its maker and taker, so neither the expiry
+//! timer, `create_offer` nor the per-user listing walks the whole offer map. Closed offers are pruned after a retention period.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::time;
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::memory_manager::VirtualMemory;
+use ic_stable_structures::{storable::Bound, DefaultMemoryImpl, StableBTreeMap, StableCell, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::accounting::{self, Asset, EntryReason, LedgerAccount};
+use crate::errors::EngineError;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// How often the timer returns expired offers to their makers
+const EXPIRY_INTERVAL_SECS: u64 = 300;
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+const DEFAULT_OFFER_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+const MIN_OFFER_TTL_SECS: u64 = 60;
+const MAX_OFFER_TTL_SECS: u64 = 30 * 24 * 60 * 60;
+
+/// Open offers a single maker may have at once
+const MAX_OPEN_OFFERS_PER_MAKER: usize = 50;
+
+/// Closed offers are kept this long, then pruned
+const CLOSED_OFFER_RETENTION_SECS: u64 = 30 * 24 * 60 * 60;
+
+/// Offers expired (and pruned) per timer run
+const MAX_OFFERS_PER_RUN: usize = 100;
+
+// Index key tags
+const OPEN_BY_EXPIRY: u8 = 0;
+const OPEN_BY_MAKER: u8 = 1;
+const CLOSED_BY_TIME: u8 = 2;
+const BY_PARTY: u8 = 3;
+
+/// A ckBTC fill pinned longer ago than this can no longer rely on ledger
+/// deduplication (24h, minus a margin)
+const FILL_DEDUP_WINDOW_NS: u64 = 23 * 60 * 60 * NS_PER_SEC;
+
+/// Asset the taker pays in
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OtcQuoteAsset {
+    /// ICP (e8s), paid from the taker's internal balance
+    Icp,
+    /// ckBTC (sats), drawn from the taker's ICRC-2 allowance to
+    /// bitcoin-integration
+    CkBtc,
+}
+
+impl OtcQuoteAsset {
+    /// Internal balance the price settles on, if any
+    fn internal_asset(self) -> Option<Asset> {
+        match self {
+            OtcQuoteAsset::Icp => Some(Asset::Icp),
+            OtcQuoteAsset::CkBtc => None,
+        }
+    }
+}
+
+/// Status of an OTC offer
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum OtcStatus {
+    /// Runes are locked and the offer can be filled
+    Open,
+    /// A ckBTC payment from `taker` is in flight; the runes stay locked
+    Filling { taker: Principal, started_at: u64 },
+    Filled { taker: Principal, filled_at: u64 },
+    Cancelled { cancelled_at: u64 },
+    /// Not filled in time; runes returned to the maker
+    Expired { expired_at: u64 },
+}
+
+impl OtcOffer {
+    /// Principals the offer is listed under: the maker and, once filled, the taker
+    fn parties(&self) -> Vec<Principal> {
+        match self.status {
+            OtcStatus::Filled { taker, .. } => vec![self.maker, taker],
+            _ => vec![self.maker],
+        }
+    }
+}
+
+impl OtcStatus {
+    /// When the offer stopped being open
+    fn closed_at(&self) -> Option<u64> {
+        match self {
+            OtcStatus::Open | OtcStatus::Filling { .. } => None,
+            OtcStatus::Filled { filled_at, .. } => Some(*filled_at),
+            OtcStatus::Cancelled { cancelled_at } => Some(*cancelled_at),
+            OtcStatus::Expired { expired_at } => Some(*expired_at),
+        }
+    }
+}
+
+/// A maker's offer to sell a block of runes at a fixed price
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct OtcOffer {
+    pub id: u64,
+    pub maker: Principal,
+    pub rune_id: String,
+    /// Runes locked for the offer
+    pub amount: u64,
+    pub quote: OtcQuoteAsset,
+    /// Total price for the whole block, in the quote asset's base unit
+    pub price: u64,
+    /// Only this principal may fill, if set
+    pub counterparty: Option<Principal>,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub status: OtcStatus,
+    /// `created_at_time` of the current taker's ckBTC draw, kept so a retry
+    /// reuses it
+    pub fill_created_at: Option<u64>,
+}
+
+/// Parameters for `create_otc_offer`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CreateOtcOfferParams {
+    pub rune_id: String,
+    pub amount: u64,
+    pub quote: OtcQuoteAsset,
+    pub price: u64,
+    pub counterparty: Option<Principal>,
+    /// Defaults to 7 days
+    pub expires_in_secs: Option<u64>,
+}
+
+impl Storable for OtcOffer {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to encode OtcOffer: {}", e))
+        }))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to decode OtcOffer: {}", e))
+        })
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Stable storage for OTC offers (survives upgrades)
+thread_local! {
+    static OTC_OFFERS: RefCell<Option<StableBTreeMap<u64, OtcOffer, Memory>>> = const { RefCell::new(None) };
+    static OFFER_COUNTER: RefCell<Option<StableCell<u64, Memory>>> = const { RefCell::new(None) };
+    /// Tagged keys: open offers by expiry and by maker, closed offers by close time
+    static OFFER_INDEX: RefCell<Option<StableBTreeMap<Vec<u8>, (), Memory>>> = const { RefCell::new(None) };
+
+    static TIMER_ID: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+}
+
+/// Initialize OTC storage (also restores it after upgrade)
+pub fn init_otc_storage(offers_memory: Memory, counter_memory: Memory, index_memory: Memory) {
+    OTC_OFFERS.with(|o| *o.borrow_mut() = Some(StableBTreeMap::init(offers_memory)));
+    OFFER_COUNTER.with(|c| {
+        *c.borrow_mut() = Some(
+            StableCell::init(counter_memory, 0).expect("Failed to initialize OTC offer counter"),
+        );
+    });
+    OFFER_INDEX.with(|i| *i.borrow_mut() = Some(StableBTreeMap::init(index_memory)));
+}
+
+/// Index offers stored before the index existed; only runs on an empty index
+pub fn rebuild_offer_index() -> u64 {
+    if OFFER_INDEX.with(|i| i.borrow().as_ref().is_none_or(|map| !map.is_empty())) {
+        return 0;
+    }
+    let offers = all_offers();
+    for offer in &offers {
+        index_offer(offer);
+    }
+    offers.len() as u64
+}
+
+fn offer_ref(id: u64) -> Option<String> {
+    Some(format!("otc:{}", id))
+}
+
+fn expiry_key(expires_at: u64, id: u64) -> Vec<u8> {
+    let mut key = vec![OPEN_BY_EXPIRY];
+    key.extend_from_slice(&expires_at.to_be_bytes());
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+fn principal_prefix(tag: u8, principal: &Principal) -> Vec<u8> {
+    let bytes = principal.as_slice();
+    let mut key = vec![tag, bytes.len() as u8];
+    key.extend_from_slice(bytes);
+    key
+}
+
+fn principal_key(tag: u8, principal: &Principal, id: u64) -> Vec<u8> {
+    let mut key = principal_prefix(tag, principal);
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+/// Offer IDs under a principal's prefix, at most `limit` of them
+fn principal_range(tag: u8, principal: &Principal, limit: usize) -> Vec<Vec<u8>> {
+    index_range(principal_key(tag, principal, 0), principal_key(tag, principal, u64::MAX), limit)
+}
+
+fn closed_key(closed_at: u64, id: u64) -> Vec<u8> {
+    let mut key = vec![CLOSED_BY_TIME];
+    key.extend_from_slice(&closed_at.to_be_bytes());
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+/// Offer ID stored in the last 8 bytes of an index key
+fn key_id(key: &[u8]) -> u64 {
+    let mut id = [0u8; 8];
+    id.copy_from_slice(&key[key.len() - 8..]);
+    u64::from_be_bytes(id)
+}
+
+/// Index keys in `[start, end]`, at most `limit` of them
+fn index_range(start: Vec<u8>, end: Vec<u8>, limit: usize) -> Vec<Vec<u8>> {
+    OFFER_INDEX.with(|i| {
+        i.borrow()
+            .as_ref()
+            .map(|map| map.range(start..=end).take(limit).map(|(key, _)| key).collect())
+            .unwrap_or_default()
+    })
+}
+
+/// Point the index at the offer's current status
+fn index_offer(offer: &OtcOffer) {
+    OFFER_INDEX.with(|i| {
+        if let Some(ref mut map) = *i.borrow_mut() {
+            match offer.status.closed_at() {
+                None => {
+                    map.insert(expiry_key(offer.expires_at, offer.id), ());
+                    map.insert(principal_key(OPEN_BY_MAKER, &offer.maker, offer.id), ());
+                }
+                Some(closed_at) => {
+                    map.remove(&expiry_key(offer.expires_at, offer.id));
+                    map.remove(&principal_key(OPEN_BY_MAKER, &offer.maker, offer.id));
+                    map.insert(closed_key(closed_at, offer.id), ());
+                }
+            }
+            for party in offer.parties() {
+                map.insert(principal_key(BY_PARTY, &party, offer.id), ());
+            }
+        }
+    });
+}
+
+fn store_offer(offer: &OtcOffer) {
+    OTC_OFFERS.with(|o| {
+        if let Some(ref mut map) = *o.borrow_mut() {
+            map.insert(offer.id, offer.clone());
+        }
+    });
+    index_offer(offer);
+}
+
+/// Number of open offers a maker has, counted up to the per-maker limit
+fn open_offer_count(maker: &Principal) -> usize {
+    principal_range(OPEN_BY_MAKER, maker, MAX_OPEN_OFFERS_PER_MAKER).len()
+}
+
+fn all_offers() -> Vec<OtcOffer> {
+    OTC_OFFERS.with(|o| {
+        o.borrow()
+            .as_ref()
+            .map(|map| map.iter().map(|(_, offer)| offer).collect())
+            .unwrap_or_default()
+    })
+}
+
+/// Get an offer by ID
+pub fn get_offer(id: u64) -> Option<OtcOffer> {
+    OTC_OFFERS.with(|o| o.borrow().as_ref().and_then(|map| map.get(&id)))
+}
+
+/// Open offers `viewer` could fill (public ones and those addressed to them)
+pub fn list_open_offers(viewer: Principal, rune_id: Option<&str>, now: u64) -> Vec<OtcOffer> {
+    index_range(expiry_key(now.saturating_add(1), 0), expiry_key(u64::MAX, u64::MAX), usize::MAX)
+        .iter()
+        .filter_map(|key| get_offer(key_id(key)))
+        .filter(|o| o.status == OtcStatus::Open && now < o.expires_at)
+        .filter(|o| rune_id.is_none_or(|r| o.rune_id == r))
+        .filter(|o| o.counterparty.is_none_or(|c| c == viewer) || o.maker == viewer)
+        .collect()
+}
+
+/// Every offer a principal made or filled (closed ones until they are pruned)
+pub fn get_user_offers(user: Principal) -> Vec<OtcOffer> {
+    principal_range(BY_PARTY, &user, usize::MAX)
+        .iter()
+        .filter_map(|key| get_offer(key_id(key)))
+        .collect()
+}
+
+/// Lock `amount` of a rune and publish an offer for it
+pub fn create_offer(maker: Principal, params: CreateOtcOfferParams, now: u64) -> Result<OtcOffer, EngineError> {
+    if maker == Principal::anonymous() {
+        return Err(EngineError::Unauthorized(
+            "Anonymous principals cannot create offers".to_string()
+        ));
+    }
+    if params.amount == 0 || params.price == 0 {
+        return Err(EngineError::InvalidInput(
+            "Amount and price must be greater than 0".to_string()
+        ));
+    }
+    if params.counterparty == Some(maker) {
+        return Err(EngineError::InvalidInput(
+            "Counterparty cannot be the maker".to_string()
+        ));
+    }
+    let ttl = params.expires_in_secs.unwrap_or(DEFAULT_OFFER_TTL_SECS);
+    if !(MIN_OFFER_TTL_SECS..=MAX_OFFER_TTL_SECS).contains(&ttl) {
+        return Err(EngineError::InvalidInput(format!(
+            "Offer lifetime must be between {} and {} seconds",
+            MIN_OFFER_TTL_SECS, MAX_OFFER_TTL_SECS
+        )));
+    }
+
+    if open_offer_count(&maker) >= MAX_OPEN_OFFERS_PER_MAKER {
+        return Err(EngineError::InvalidState(format!(
+            "At most {} open offers per maker",
+            MAX_OPEN_OFFERS_PER_MAKER
+        )));
+    }
+
+    let id = OFFER_COUNTER.with(|c| {
+        let mut c = c.borrow_mut();
+        let cell = c.as_mut().expect("OTC storage not initialized");
+        let id = cell.get() + 1;
+        let _ = cell.set(id);
+        id
+    });
+
+    accounting::post(
+        Asset::Rune(params.rune_id.clone()),
+        LedgerAccount::Available(maker),
+        LedgerAccount::Locked(maker),
+        params.amount,
+        EntryReason::Lock,
+        offer_ref(id),
+    )
+    .map_err(EngineError::InvalidState)?;
+
+    let offer = OtcOffer {
+        id,
+        maker,
+        rune_id: params.rune_id,
+        amount: params.amount,
+        quote: params.quote,
+        price: params.price,
+        counterparty: params.counterparty,
+        created_at: now,
+        expires_at: now.saturating_add(ttl.saturating_mul(NS_PER_SEC)),
+        status: OtcStatus::Open,
+        fill_created_at: None,
+    };
+    store_offer(&offer);
+    Ok(offer)
+}
+
+/// Check that `taker` may fill an open offer now
+fn check_fillable(offer: &OtcOffer, taker: Principal, now: u64) -> Result<(), EngineError> {
+    if offer.status != OtcStatus::Open {
+        return Err(EngineError::InvalidState(format!("Offer is not open: {:?}", offer.status)));
+    }
+    if now >= offer.expires_at {
+        return Err(EngineError::InvalidState("Offer has expired".to_string()));
+    }
+    if taker == offer.maker || taker == Principal::anonymous() {
+        return Err(EngineError::Unauthorized("Cannot fill this offer".to_string()));
+    }
+    if offer.counterparty.is_some_and(|c| c != taker) {
+        return Err(EngineError::Unauthorized(
+            "Offer is reserved for another counterparty".to_string()
+        ));
+    }
+    Ok(())
+}
+
+/// Move an offer's locked runes to `taker` and mark it filled
+fn release_to_taker(mut offer: OtcOffer, taker: Principal, now: u64) -> Result<OtcOffer, EngineError> {
+    accounting::post(
+        Asset::Rune(offer.rune_id.clone()),
+        LedgerAccount::Locked(offer.maker),
+        LedgerAccount::Available(taker),
+        offer.amount,
+        EntryReason::Trade,
+        offer_ref(offer.id),
+    )
+    .map_err(EngineError::InvalidState)?;
+
+    offer.status = OtcStatus::Filled { taker, filled_at: now };
+    store_offer(&offer);
+    Ok(offer)
+}
+
+/// Fill a whole ICP-priced offer: the taker pays the price and receives the runes
+///
+/// Runs without awaits, so both legs settle in the same message or not at all.
+/// ckBTC-priced offers go through `fill_ckbtc_offer`.
+pub fn fill_offer(taker: Principal, id: u64, now: u64) -> Result<OtcOffer, EngineError> {
+    let offer = get_offer(id).ok_or_else(|| EngineError::NotFound(format!("Offer not found: {}", id)))?;
+    check_fillable(&offer, taker, now)?;
+
+    let Some(quote) = offer.quote.internal_asset() else {
+        return Err(EngineError::InvalidInput(
+            "ckBTC-priced offers settle through the ckBTC ledger".to_string()
+        ));
+    };
+    let (available, _) = accounting::user_balance(taker, &quote);
+    if available < offer.price {
+        return Err(EngineError::InvalidState(format!(
+            "Insufficient {:?} balance: have {}, need {}",
+            offer.quote, available, offer.price
+        )));
+    }
+
+    accounting::post(
+        quote.clone(),
+        LedgerAccount::Available(taker),
+        LedgerAccount::Available(offer.maker),
+        offer.price,
+        EntryReason::Trade,
+        offer_ref(id),
+    )
+    .map_err(EngineError::InvalidState)?;
+
+    let (maker, price) = (offer.maker, offer.price);
+    release_to_taker(offer, taker, now).or_else(|e| {
+        // Undo the payment so the fill stays all-or-nothing
+        accounting::post(
+            quote,
+            LedgerAccount::Available(maker),
+            LedgerAccount::Available(taker),
+            price,
+            EntryReason::Trade,
+            offer_ref(id),
+        )
+        .map_err(|revert| EngineError::InternalError(format!("{}; revert failed: {}", e, revert)))?;
+        Err(e)
+    })
+}
+
+/// Memo identifying an offer's ckBTC payment on the ledger
+fn fill_memo(id: u64) -> Vec<u8> {
+    format!("otc:{}", id).into_bytes()
+}
+
+/// Reserve a ckBTC-priced offer for `taker` and pin the draw's `created_at_time`
+///
+/// A taker whose earlier draw ended ambiguously may call again: the offer is
+/// still reserved for them and the pinned time is reused, so the ledger
+/// resolves the retry to the original payment.
+fn begin_ckbtc_fill(taker: Principal, id: u64, now: u64) -> Result<(OtcOffer, u64), EngineError> {
+    let mut offer = get_offer(id).ok_or_else(|| EngineError::NotFound(format!("Offer not found: {}", id)))?;
+    if offer.quote != OtcQuoteAsset::CkBtc {
+        return Err(EngineError::InvalidInput("Offer is not priced in ckBTC".to_string()));
+    }
+
+    let created_at = match offer.status {
+        OtcStatus::Filling { taker: pending, .. } if pending == taker => match offer.fill_created_at {
+            Some(at) if now.saturating_sub(at) < FILL_DEDUP_WINDOW_NS => at,
+            _ => {
+                return Err(EngineError::InvalidState(
+                    "Fill needs manual review: deduplication window expired".to_string()
+                ))
+            }
+        },
+        _ => {
+            check_fillable(&offer, taker, now)?;
+            now
+        }
+    };
+
+    offer.status = OtcStatus::Filling { taker, started_at: created_at };
+    offer.fill_created_at = Some(created_at);
+    store_offer(&offer);
+    Ok((offer, created_at))
+}
+
+/// Settle a reserved ckBTC fill with the outcome of the draw
+///
+/// A block index releases the runes and a ledger refusal reopens the offer.
+/// A failed call leaves the outcome unknown, so the offer stays reserved for
+/// a retry by the same taker.
+fn finish_ckbtc_fill(
+    id: u64,
+    outcome: Result<Result<u64, String>, String>,
+    now: u64,
+) -> Result<OtcOffer, EngineError> {
+    let mut offer = get_offer(id).ok_or_else(|| EngineError::NotFound(format!("Offer not found: {}", id)))?;
+    let OtcStatus::Filling { taker, .. } = offer.status else {
+        return Err(EngineError::InternalError(format!("Offer {} is not being filled", id)));
+    };
+
+    match outcome {
+        Ok(Ok(_)) => release_to_taker(offer, taker, now).map_err(|e| {
+            EngineError::InternalError(format!("CRITICAL: ckBTC paid for offer {} but runes not released: {}", id, e))
+        }),
+        Ok(Err(reason)) => {
+            offer.status = OtcStatus::Open;
+            offer.fill_created_at = None;
+            store_offer(&offer);
+            Err(EngineError::InvalidState(format!("ckBTC payment failed: {}", reason)))
+        }
+        Err(e) => Err(EngineError::ExternalCall(format!(
+            "ckBTC payment outcome unknown, retry the fill: {}",
+            e
+        ))),
+    }
+}
+
+/// Fill a whole ckBTC-priced offer: the price is drawn from the taker's
+/// ICRC-2 allowance to bitcoin-integration and paid straight to the maker
+///
+/// The taker approves bitcoin-integration for the price plus the ledger fee
+/// before calling.
+pub async fn fill_ckbtc_offer(taker: Principal, id: u64) -> Result<OtcOffer, EngineError> {
+    let btc_canister_id = crate::get_bitcoin_integration_id().map_err(EngineError::InvalidState)?;
+    let (offer, created_at) = begin_ckbtc_fill(taker, id, time())?;
+
+    let outcome = ic_cdk::call::<_, (Result<u64, String>,)>(
+        btc_canister_id,
+        "pay_ckbtc_from",
+        (taker, offer.maker, offer.price, Some(fill_memo(id)), Some(created_at)),
+    )
+    .await
+    .map(|(result,)| result)
+    .map_err(|(code, msg)| format!("{:?} - {}", code, msg));
+
+    finish_ckbtc_fill(id, outcome, time())
+}
+
+/// Return an offer's runes to its maker and close it with `status`
+fn close_offer(mut offer: OtcOffer, status: OtcStatus) -> Result<OtcOffer, EngineError> {
+    accounting::post(
+        Asset::Rune(offer.rune_id.clone()),
+        LedgerAccount::Locked(offer.maker),
+        LedgerAccount::Available(offer.maker),
+        offer.amount,
+        EntryReason::Unlock,
+        offer_ref(offer.id),
+    )
+    .map_err(EngineError::InternalError)?;
+
+    offer.status = status;
+    store_offer(&offer);
+    Ok(offer)
+}
+
+/// Cancel an open offer (maker only)
+pub fn cancel_offer(caller: Principal, id: u64, now: u64) -> Result<OtcOffer, EngineError> {
+    let offer = get_offer(id).ok_or_else(|| EngineError::NotFound(format!("Offer not found: {}", id)))?;
+
+    if offer.maker != caller {
+        return Err(EngineError::Unauthorized("Only the maker can cancel".to_string()));
+    }
+    if offer.status != OtcStatus::Open {
+        return Err(EngineError::InvalidState(format!("Offer is not open: {:?}", offer.status)));
+    }
+
+    close_offer(offer, OtcStatus::Cancelled { cancelled_at: now })
+}
+
+/// Expire open offers past their deadline, oldest first and at most
+/// `MAX_OFFERS_PER_RUN`; returns how many were closed
+pub fn expire_offers(now: u64) -> u64 {
+    let expired = index_range(expiry_key(0, 0), expiry_key(now, u64::MAX), MAX_OFFERS_PER_RUN);
+
+    let mut closed = 0;
+    for key in expired {
+        let id = key_id(&key);
+        let Some(offer) = get_offer(id).filter(|o| o.status == OtcStatus::Open) else {
+            continue;
+        };
+        match close_offer(offer, OtcStatus::Expired { expired_at: now }) {
+            Ok(_) => closed += 1,
+            Err(e) => ic_cdk::println!("Failed to expire OTC offer {}: {}", id, e),
+        }
+    }
+    closed
+}
+
+/// Remove offers closed more than `CLOSED_OFFER_RETENTION_SECS` ago, at most
+/// `MAX_OFFERS_PER_RUN`; returns how many were removed
+pub fn prune_closed_offers(now: u64) -> u64 {
+    let cutoff = now.saturating_sub(CLOSED_OFFER_RETENTION_SECS.saturating_mul(NS_PER_SEC));
+    let stale = index_range(closed_key(0, 0), closed_key(cutoff, u64::MAX), MAX_OFFERS_PER_RUN);
+
+    for key in &stale {
+        let removed = OTC_OFFERS.with(|o| o.borrow_mut().as_mut().and_then(|map| map.remove(&key_id(key))));
+        OFFER_INDEX.with(|i| {
+            if let Some(ref mut map) = *i.borrow_mut() {
+                map.remove(key);
+                for party in removed.iter().flat_map(OtcOffer::parties) {
+                    map.remove(&principal_key(BY_PARTY, &party, key_id(key)));
+                }
+            }
+        });
+    }
+    stale.len() as u64
+}
+
+// ============================================================================
+// Timer
+// ============================================================================
+
+/// Start the periodic expiry of unfilled offers
+pub fn start_offer_expiry() {
+    stop_offer_expiry();
+
+    let timer_id = ic_cdk_timers::set_timer_interval(Duration::from_secs(EXPIRY_INTERVAL_SECS), || {
+        let now = time();
+        let expired = expire_offers(now);
+        if expired > 0 {
+            ic_cdk::println!("Expired {} OTC offers", expired);
+        }
+        let pruned = prune_closed_offers(now);
+        if pruned > 0 {
+            ic_cdk::println!("Pruned {} closed OTC offers", pruned);
+        }
+    });
+
+    TIMER_ID.with(|t| *t.borrow_mut() = Some(timer_id));
+}
+
+/// Stop the offer expiry timer
+pub fn stop_offer_expiry() {
+    TIMER_ID.with(|t| {
+        if let Some(timer_id) = t.borrow_mut().take() {
+            ic_cdk_timers::clear_timer(timer_id);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+
+    fn setup() -> (Principal, Principal, Principal) {
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        accounting::init_accounting_storage(manager.get(MemoryId::new(0)), manager.get(MemoryId::new(1)));
+        init_otc_storage(manager.get(MemoryId::new(2)), manager.get(MemoryId::new(3)), manager.get(MemoryId::new(4)));
+
+        let maker = Principal::from_slice(&[1; 29]);
+        let taker = Principal::from_slice(&[2; 29]);
+        let other = Principal::from_slice(&[3; 29]);
+
+        accounting::post(
+            Asset::Rune("WHALE".to_string()),
+            LedgerAccount::Custody,
+            LedgerAccount::Available(maker),
+            1_000_000,
+            EntryReason::ManualCredit,
+            None,
+        )
+        .unwrap();
+        for user in [taker, other] {
+            accounting::post(Asset::Icp, LedgerAccount::Custody, LedgerAccount::Available(user), 500, EntryReason::Deposit, None)
+                .unwrap();
+        }
+        (maker, taker, other)
+    }
+
+    fn params(counterparty: Option<Principal>) -> CreateOtcOfferParams {
+        CreateOtcOfferParams {
+            rune_id: "WHALE".to_string(),
+            amount: 600_000,
+            quote: OtcQuoteAsset::Icp,
+            price: 400,
+            counterparty,
+            expires_in_secs: None,
+        }
+    }
+
+    #[test]
+    fn test_fill_settles_both_legs() {
+        let (maker, taker, other) = setup();
+        let rune = Asset::Rune("WHALE".to_string());
+
+        let offer = create_offer(maker, params(Some(taker)), 0).unwrap();
+        assert_eq!(accounting::user_balance(maker, &rune), (400_000, 600_000));
+
+        // Reserved offers are invisible to and unfillable by others
+        assert!(list_open_offers(other, None, 0).is_empty());
+        assert!(fill_offer(other, offer.id, 0).is_err());
+
+        let filled = fill_offer(taker, offer.id, 0).unwrap();
+        assert!(matches!(filled.status, OtcStatus::Filled { .. }));
+        assert_eq!(accounting::user_balance(maker, &rune), (400_000, 0));
+        assert_eq!(accounting::user_balance(taker, &rune), (600_000, 0));
+        assert_eq!(accounting::user_balance(maker, &Asset::Icp), (400, 0));
+        assert_eq!(accounting::user_balance(taker, &Asset::Icp), (100, 0));
+
+        assert!(fill_offer(taker, offer.id, 0).is_err());
+
+        // Both sides see the trade in their history
+        assert_eq!(get_user_offers(maker).len(), 1);
+        assert_eq!(get_user_offers(taker)[0].id, offer.id);
+        assert!(get_user_offers(other).is_empty());
+    }
+
+    #[test]
+    fn test_unfilled_offer_expires_to_maker() {
+        let (maker, taker, _) = setup();
+        let rune = Asset::Rune("WHALE".to_string());
+
+        let offer = create_offer(maker, params(None), 0).unwrap();
+
+        // An underfunded taker leaves everything untouched
+        let mut pricey = params(None);
+        pricey.price = 501;
+        pricey.amount = 1;
+        let pricey = create_offer(maker, pricey, 0).unwrap();
+        assert!(fill_offer(taker, pricey.id, 0).is_err());
+        assert_eq!(accounting::user_balance(taker, &Asset::Icp), (500, 0));
+
+        assert_eq!(expire_offers(offer.expires_at - 1), 0);
+        assert!(fill_offer(taker, offer.id, offer.expires_at).is_err());
+        assert_eq!(expire_offers(offer.expires_at), 2);
+        assert_eq!(accounting::user_balance(maker, &rune), (1_000_000, 0));
+        assert!(matches!(get_offer(offer.id).unwrap().status, OtcStatus::Expired { .. }));
+
+        // Cancelling is maker-only and returns the runes too
+        let offer = create_offer(maker, params(None), 0).unwrap();
+        assert!(cancel_offer(taker, offer.id, 0).is_err());
+        cancel_offer(maker, offer.id, 0).unwrap();
+        assert_eq!(accounting::user_balance(maker, &rune), (1_000_000, 0));
+    }
+
+    #[test]
+    fn test_ckbtc_fill_reserves_until_the_draw_settles() {
+        let (maker, taker, other) = setup();
+        let rune = Asset::Rune("WHALE".to_string());
+
+        let mut ckbtc = params(None);
+        ckbtc.quote = OtcQuoteAsset::CkBtc;
+        let offer = create_offer(maker, ckbtc, 0).unwrap();
+        assert!(fill_offer(taker, offer.id, 0).is_err());
+
+        // A refused draw reopens the offer for anyone
+        let (_, pinned) = begin_ckbtc_fill(taker, offer.id, 5).unwrap();
+        assert_eq!(pinned, 5);
+        assert!(begin_ckbtc_fill(other, offer.id, 6).is_err());
+        assert!(cancel_offer(maker, offer.id, 6).is_err());
+        assert!(finish_ckbtc_fill(offer.id, Ok(Err("InsufficientAllowance".to_string())), 6).is_err());
+        assert_eq!(get_offer(offer.id).unwrap().status, OtcStatus::Open);
+
+        // An unknown outcome keeps it reserved, and the retry reuses the pin
+        let (_, pinned) = begin_ckbtc_fill(taker, offer.id, 10).unwrap();
+        assert!(finish_ckbtc_fill(offer.id, Err("call rejected".to_string()), 11).is_err());
+        assert!(begin_ckbtc_fill(other, offer.id, 12).is_err());
+        assert_eq!(begin_ckbtc_fill(taker, offer.id, 20).unwrap().1, pinned);
+        assert!(begin_ckbtc_fill(taker, offer.id, pinned + FILL_DEDUP_WINDOW_NS).is_err());
+
+        let filled = finish_ckbtc_fill(offer.id, Ok(Ok(42)), 21).unwrap();
+        assert!(matches!(filled.status, OtcStatus::Filled { taker: t, .. } if t == taker));
+        assert_eq!(accounting::user_balance(maker, &rune), (400_000, 0));
+        assert_eq!(accounting::user_balance(taker, &rune), (600_000, 0));
+        // ckBTC never touches internal balances
+        assert_eq!(accounting::user_balance(taker, &Asset::Icp), (500, 0));
+    }
+
+    #[test]
+    fn test_index_tracks_open_offers_and_prunes_closed() {
+        let (maker, taker, _) = setup();
+
+        let mut short = params(None);
+        short.amount = 1;
+        short.expires_in_secs = Some(MIN_OFFER_TTL_SECS);
+        let short = create_offer(maker, short, 0).unwrap();
+        let long = create_offer(maker, params(None), 0).unwrap();
+        assert_eq!(open_offer_count(&maker), 2);
+        assert_eq!(open_offer_count(&taker), 0);
+
+        // Only offers past their deadline are touched
+        assert_eq!(expire_offers(short.expires_at), 1);
+        assert_eq!(open_offer_count(&maker), 1);
+        let open: Vec<u64> = list_open_offers(taker, None, short.expires_at).iter().map(|o| o.id).collect();
+        assert_eq!(open, vec![long.id]);
+
+        // Closed offers stay visible for the retention period, then go
+        let retention = CLOSED_OFFER_RETENTION_SECS * NS_PER_SEC;
+        assert_eq!(prune_closed_offers(short.expires_at + retention - 1), 0);
+        assert!(get_offer(short.id).is_some());
+        assert_eq!(prune_closed_offers(short.expires_at + retention), 1);
+        assert!(get_offer(short.id).is_none());
+        assert_eq!(get_user_offers(maker).len(), 1);
+
+        // The index can be rebuilt from the offers alone
+        OFFER_INDEX.with(|i| {
+            let mut i = i.borrow_mut();
+            let map = i.as_mut().unwrap();
+            let keys: Vec<Vec<u8>> = map.iter().map(|(k, _)| k).collect();
+            for key in keys {
+                map.remove(&key);
+            }
+        });
+        assert_eq!(rebuild_offer_index(), 1);
+        assert_eq!(open_offer_count(&maker), 1);
+        assert_eq!(rebuild_offer_index(), 0);
+    }
+}
@@ -0,0 +1,469 @@
+// ============================================================================
+// Solvency Module - ICP Reconciliation Against the Ledger
+// ============================================================================
+//
+// Periodically compares everything the engine owes in ICP with what the
+// canister actually holds on the ICP ledger.
+//
+// Liabilities:
+// - User available + locked balances (accounting ledger)
+// - Trading V2 pool `icp_reserve`s and `protocol_fees_pending`
+// - Legacy (V1) pool reserves, escrows and anything else the accounting
+//   ledger holds in ICP
+//
+// Key Features:
+// - Hourly timer plus on-demand admin checks
+// - Every report persisted in stable memory (oldest pruned past a cap)
+// - `logging::log_error` alert on any shortfall beyond the tolerance
+// - Optional auto-pause of ICP withdrawals on shortfall; an admin resumes
+//
+// Outgoing ICP transfers (withdrawals, treasury collections) deduct the
+// ledger fee from the amount sent, so the ledger balance falls by exactly
+// what is debited internally and fees cause no drift. A surplus is expected
+// (deposits not yet credited, ICP sent to the canister directly); only a
+// shortfall means an accounting bug or a loss.
+//
+// ============================================================================
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::memory_manager::VirtualMemory;
+use ic_stable_structures::{storable::Bound, DefaultMemoryImpl, StableBTreeMap, StableCell, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::accounting::{self, Asset, LedgerAccount};
+use crate::{ledger, logging};
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Interval between scheduled reconciliations
+const CHECK_INTERVAL_SECS: u64 = 3600;
+
+/// Maximum number of reports kept in stable memory
+const MAX_REPORTS: u64 = 2_000;
+
+// ============================================================================
+// Types
+// ============================================================================
+
+/// Solvency settings and withdrawal pause state
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct SolvencyConfig {
+    /// Pause ICP withdrawals automatically when a shortfall is detected
+    pub auto_pause_withdrawals: bool,
+    /// Shortfall (e8s) tolerated before alerting
+    pub shortfall_tolerance: u64,
+    /// Whether ICP withdrawals are currently paused
+    pub withdrawals_paused: bool,
+    /// Why withdrawals were paused
+    pub pause_reason: Option<String>,
+    /// Last update timestamp
+    pub updated_at: u64,
+    /// Principal that last updated the config (None for automatic pauses)
+    pub updated_by: Option<Principal>,
+}
+
+impl Storable for SolvencyConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to encode SolvencyConfig: {}", e))
+        }))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to decode SolvencyConfig: {}", e))
+        })
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Internal ICP liabilities by category (e8s)
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct IcpLiabilities {
+    pub user_available: u64,
+    pub user_locked: u64,
+    /// ICP held by pools: reserves plus LP fees not yet claimed
+    pub pool_reserves: u64,
+    pub protocol_fees_pending: u64,
+    /// Legacy pools, escrows and other ledger accounts holding ICP
+    pub other: u64,
+}
+
+impl IcpLiabilities {
+    pub fn total(&self) -> u64 {
+        self.user_available
+            .saturating_add(self.user_locked)
+            .saturating_add(self.pool_reserves)
+            .saturating_add(self.protocol_fees_pending)
+            .saturating_add(self.other)
+    }
+}
+
+/// Outcome of a reconciliation
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum SolvencyStatus {
+    /// Ledger balance covers all liabilities
+    Solvent,
+    /// Ledger balance is short, but within the configured tolerance
+    WithinTolerance,
+    /// Ledger balance is short beyond the tolerance
+    Shortfall,
+}
+
+/// Persisted reconciliation report
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SolvencyReport {
+    /// Sequential report ID
+    pub id: u64,
+    pub liabilities: IcpLiabilities,
+    pub total_liabilities: u64,
+    /// Canister's ICRC-1 balance on the ICP ledger (main account)
+    pub ledger_balance: u64,
+    /// ledger_balance - total_liabilities, when positive
+    pub surplus: u64,
+    /// total_liabilities - ledger_balance, when positive
+    pub shortfall: u64,
+    pub status: SolvencyStatus,
+    /// Whether this check paused withdrawals
+    pub paused_withdrawals: bool,
+    pub timestamp: u64,
+}
+
+impl Storable for SolvencyReport {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to encode SolvencyReport: {}", e))
+        }))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to decode SolvencyReport: {}", e))
+        })
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// ============================================================================
+// Storage
+// ============================================================================
+
+thread_local! {
+    static CONFIG: RefCell<Option<StableCell<SolvencyConfig, Memory>>> = const { RefCell::new(None) };
+
+    static REPORTS: RefCell<Option<StableBTreeMap<u64, SolvencyReport, Memory>>> = const { RefCell::new(None) };
+
+    static TIMER_ID: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+}
+
+/// Initialize solvency storage
+pub fn init_solvency_storage(config_memory: Memory, reports_memory: Memory) {
+    CONFIG.with(|c| {
+        *c.borrow_mut() = Some(
+            StableCell::init(config_memory, SolvencyConfig::default())
+                .expect("Failed to initialize solvency config"),
+        );
+    });
+    REPORTS.with(|r| {
+        *r.borrow_mut() = Some(StableBTreeMap::init(reports_memory));
+    });
+}
+
+/// Current solvency config
+pub fn get_config() -> SolvencyConfig {
+    CONFIG.with(|c| c.borrow().as_ref().map(|cell| cell.get().clone()).unwrap_or_default())
+}
+
+fn update_config(update: impl FnOnce(&mut SolvencyConfig)) -> Result<SolvencyConfig, String> {
+    CONFIG.with(|c| {
+        let mut c = c.borrow_mut();
+        let cell = c.as_mut().ok_or("Solvency storage not initialized")?;
+        let mut config = cell.get().clone();
+        update(&mut config);
+        config.updated_at = ic_cdk::api::time();
+        cell.set(config.clone())
+            .map_err(|e| format!("Failed to save solvency config: {:?}", e))?;
+        Ok(config)
+    })
+}
+
+/// Set auto-pause and tolerance
+pub fn set_config(
+    auto_pause_withdrawals: bool,
+    shortfall_tolerance: u64,
+    caller: Principal,
+) -> Result<SolvencyConfig, String> {
+    update_config(|config| {
+        config.auto_pause_withdrawals = auto_pause_withdrawals;
+        config.shortfall_tolerance = shortfall_tolerance;
+        config.updated_by = Some(caller);
+    })
+}
+
+/// Whether ICP withdrawals are paused
+pub fn withdrawals_paused() -> bool {
+    get_config().withdrawals_paused
+}
+
+/// Pause or resume ICP withdrawals
+pub fn set_withdrawals_paused(
+    paused: bool,
+    reason: Option<String>,
+    caller: Option<Principal>,
+) -> Result<SolvencyConfig, String> {
+    update_config(|config| {
+        config.withdrawals_paused = paused;
+        config.pause_reason = if paused { reason } else { None };
+        config.updated_by = caller;
+    })
+}
+
+/// Reports, most recent first
+pub fn get_reports(offset: u64, limit: u64) -> Vec<SolvencyReport> {
+    REPORTS.with(|r| {
+        if let Some(ref map) = *r.borrow() {
+            map.iter()
+                .rev()
+                .skip(offset as usize)
+                .take(limit as usize)
+                .map(|(_, report)| report)
+                .collect()
+        } else {
+            vec![]
+        }
+    })
+}
+
+/// Most recent report
+pub fn get_latest_report() -> Option<SolvencyReport> {
+    REPORTS.with(|r| {
+        r.borrow()
+            .as_ref()
+            .and_then(|map| map.last_key_value().map(|(_, report)| report))
+    })
+}
+
+fn store_report(report: &SolvencyReport) {
+    REPORTS.with(|r| {
+        if let Some(ref mut map) = *r.borrow_mut() {
+            map.insert(report.id, report.clone());
+            while map.len() > MAX_REPORTS {
+                if map.pop_first().is_none() {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+fn next_report_id() -> u64 {
+    REPORTS.with(|r| {
+        r.borrow()
+            .as_ref()
+            .and_then(|map| map.last_key_value().map(|(id, _)| id + 1))
+            .unwrap_or(0)
+    })
+}
+
+// ============================================================================
+// Reconciliation
+// ============================================================================
+
+/// Sum all internal ICP liabilities
+///
+/// Pools are counted by their ledger balance rather than `icp_reserve`, so
+/// LP fees that providers have not claimed yet are included.
+pub fn compute_liabilities() -> IcpLiabilities {
+    let mut liabilities = IcpLiabilities::default();
+
+    for (account, balance) in accounting::asset_holders(&Asset::Icp) {
+        match account {
+            LedgerAccount::Available(_) => {
+                liabilities.user_available = liabilities.user_available.saturating_add(balance);
+            }
+            LedgerAccount::Locked(_) => {
+                liabilities.user_locked = liabilities.user_locked.saturating_add(balance);
+            }
+            LedgerAccount::Pool(_) => {
+                liabilities.pool_reserves = liabilities.pool_reserves.saturating_add(balance);
+            }
+            LedgerAccount::ProtocolFees => {
+                liabilities.protocol_fees_pending = liabilities.protocol_fees_pending.saturating_add(balance);
+            }
+            LedgerAccount::Custody => {}
+            LedgerAccount::LegacyPool(_) | LedgerAccount::Escrow(_) | LedgerAccount::Subaccount(..) => {
+                liabilities.other = liabilities.other.saturating_add(balance);
+            }
+        }
+    }
+
+    liabilities
+}
+
+/// Build a report from liabilities and the observed ledger balance
+pub fn build_report(
+    id: u64,
+    liabilities: IcpLiabilities,
+    ledger_balance: u64,
+    shortfall_tolerance: u64,
+    timestamp: u64,
+) -> SolvencyReport {
+    let total_liabilities = liabilities.total();
+    let surplus = ledger_balance.saturating_sub(total_liabilities);
+    let shortfall = total_liabilities.saturating_sub(ledger_balance);
+    let status = if shortfall == 0 {
+        SolvencyStatus::Solvent
+    } else if shortfall <= shortfall_tolerance {
+        SolvencyStatus::WithinTolerance
+    } else {
+        SolvencyStatus::Shortfall
+    };
+
+    SolvencyReport {
+        id,
+        liabilities,
+        total_liabilities,
+        ledger_balance,
+        surplus,
+        shortfall,
+        status,
+        paused_withdrawals: false,
+        timestamp,
+    }
+}
+
+/// Reconcile liabilities with the ledger balance and persist the report
+pub async fn run_check() -> Result<SolvencyReport, String> {
+    // Liabilities move while the balance call is in flight: a deposit credited
+    // or a withdrawal sent in the meantime would look like a shortfall on one
+    // side or the other. Read them before and after and use the lower figure.
+    let before = compute_liabilities();
+    let ledger_balance = ledger::get_icrc1_balance(ic_cdk::api::id(), None).await?;
+    let after = compute_liabilities();
+    let liabilities = if after.total() < before.total() { after } else { before };
+
+    let config = get_config();
+    let mut report = build_report(
+        next_report_id(),
+        liabilities,
+        ledger_balance,
+        config.shortfall_tolerance,
+        ic_cdk::api::time(),
+    );
+
+    if report.status == SolvencyStatus::Shortfall {
+        logging::log_error(
+            "solvency",
+            format!(
+                "ICP shortfall of {} e8s: liabilities {} e8s, ledger balance {} e8s",
+                report.shortfall, report.total_liabilities, report.ledger_balance
+            ),
+            Some(format!("report_id={}", report.id)),
+        );
+
+        if config.auto_pause_withdrawals && !config.withdrawals_paused {
+            let reason = format!("Solvency report {} found a {} e8s shortfall", report.id, report.shortfall);
+            match set_withdrawals_paused(true, Some(reason), None) {
+                Ok(_) => report.paused_withdrawals = true,
+                Err(e) => logging::log_error(
+                    "solvency",
+                    format!("Failed to pause withdrawals: {}", e),
+                    None,
+                ),
+            }
+        }
+    }
+
+    store_report(&report);
+    Ok(report)
+}
+
+// ============================================================================
+// Timer
+// ============================================================================
+
+/// Start the periodic reconciliation timer
+pub fn start_solvency_monitor() {
+    stop_solvency_monitor();
+
+    let timer_id = ic_cdk_timers::set_timer_interval(Duration::from_secs(CHECK_INTERVAL_SECS), || {
+        ic_cdk::spawn(async {
+            if let Err(e) = run_check().await {
+                logging::log_error("solvency", format!("Solvency check failed: {}", e), None);
+            }
+        });
+    });
+
+    TIMER_ID.with(|t| *t.borrow_mut() = Some(timer_id));
+}
+
+/// Stop the reconciliation timer
+pub fn stop_solvency_monitor() {
+    TIMER_ID.with(|t| {
+        if let Some(timer_id) = t.borrow_mut().take() {
+            ic_cdk_timers::clear_timer(timer_id);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn liabilities() -> IcpLiabilities {
+        IcpLiabilities {
+            user_available: 1_000,
+            user_locked: 200,
+            pool_reserves: 5_000,
+            protocol_fees_pending: 50,
+            other: 0,
+        }
+    }
+
+    #[test]
+    fn test_report_status() {
+        assert_eq!(liabilities().total(), 6_250);
+
+        let report = build_report(0, liabilities(), 6_300, 0, 0);
+        assert_eq!(report.status, SolvencyStatus::Solvent);
+        assert_eq!((report.surplus, report.shortfall), (50, 0));
+
+        let report = build_report(1, liabilities(), 6_240, 10, 0);
+        assert_eq!(report.status, SolvencyStatus::WithinTolerance);
+        assert_eq!((report.surplus, report.shortfall), (0, 10));
+
+        let report = build_report(2, liabilities(), 6_239, 10, 0);
+        assert_eq!(report.status, SolvencyStatus::Shortfall);
+        assert_eq!(report.shortfall, 11);
+    }
+
+    #[test]
+    fn test_liabilities_include_unclaimed_lp_fees() {
+        use crate::accounting::EntryReason;
+        use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        accounting::init_accounting_storage(manager.get(MemoryId::new(0)), manager.get(MemoryId::new(1)));
+
+        let trader = Principal::from_slice(&[1; 29]);
+        let pool = LedgerAccount::Pool("840000:1".to_string());
+        let post = |from, to, amount, reason| accounting::post(Asset::Icp, from, to, amount, reason, None).unwrap();
+        post(LedgerAccount::Custody, LedgerAccount::Available(trader), 10_000, EntryReason::Deposit);
+        post(LedgerAccount::Available(trader), pool.clone(), 5_000, EntryReason::Trade);
+        // A trade's LP fee stays in the pool account outside `icp_reserve`
+        post(LedgerAccount::Available(trader), pool, 30, EntryReason::Trade);
+        post(LedgerAccount::Available(trader), LedgerAccount::ProtocolFees, 10, EntryReason::ProtocolFee);
+
+        let liabilities = compute_liabilities();
+        assert_eq!(liabilities.pool_reserves, 5_030);
+        assert_eq!(liabilities.protocol_fees_pending, 10);
+        assert_eq!(liabilities.user_available, 4_960);
+        // Every deposited e8 is owed to someone
+        assert_eq!(liabilities.total(), 10_000);
+    }
+}
@@ -34,6 +34,8 @@ pub struct VirtualRune {
     pub status: VirtualRuneStatus,
     pub created_at: u64,
     pub updated_at: u64,
+    /// On-chain rune ID ("block:tx") once the etching is confirmed
+    pub onchain_id: Option<String>,
 }
 
 impl VirtualRune {
@@ -46,6 +48,7 @@ impl VirtualRune {
             status: VirtualRuneStatus::Virtual,
             created_at: now,
             updated_at: now,
+            onchain_id: None,
         }
     }
 
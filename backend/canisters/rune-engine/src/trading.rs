@@ -19,13 +19,12 @@ use std::collections::HashMap;
 
 use crate::state::VirtualRune;
 use crate::balances::{
-    self, BalanceChangeType, RuneBalance,
+    BalanceChangeType, RuneBalance,
     credit_balance, debit_balance, get_balance,
 };
-use crate::ledger::{
-    self, get_user_trading_balance, credit_user_icp, debit_user_icp,
-    ICP_TRANSFER_FEE,
-};
+use crate::accounting::{EntryReason, LedgerAccount};
+use crate::ledger::{self, ICP_TRANSFER_FEE};
+use crate::trading_v2;
 
 /// Trading pool for a Virtual Rune
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -289,7 +288,7 @@ pub fn execute_buy(
     }
 
     // 1. Verify user has sufficient ICP balance
-    let user_icp_balance = get_user_trading_balance(trader);
+    let user_icp_balance = get_user_icp_balance(trader);
     if user_icp_balance < icp_amount {
         return Err(format!(
             "Insufficient ICP balance: have {}, need {}. Please deposit ICP first.",
@@ -316,7 +315,12 @@ pub fn execute_buy(
     }
 
     // 3. Debit ICP from user's trading balance
-    debit_user_icp(trader, icp_amount)?;
+    trading_v2::debit_user_icp(
+        trader,
+        icp_amount,
+        LedgerAccount::LegacyPool(rune_id.to_string()),
+        EntryReason::Trade,
+    )?;
 
     // 4. Credit runes to user's balance
     let trade_id = next_trade_id();
@@ -325,6 +329,7 @@ pub fn execute_buy(
         rune_id,
         rune_out,
         BalanceChangeType::Buy,
+        LedgerAccount::LegacyPool(rune_id.to_string()),
         Some(format!("trade:{}", trade_id)),
     )?;
 
@@ -418,11 +423,17 @@ pub fn execute_sell(
         rune_id,
         rune_amount,
         BalanceChangeType::Sell,
+        LedgerAccount::LegacyPool(rune_id.to_string()),
         Some(format!("trade:{}", trade_id)),
     )?;
 
     // 4. Credit ICP to user's trading balance
-    credit_user_icp(trader, icp_out);
+    trading_v2::credit_user_icp(
+        trader,
+        icp_out,
+        LedgerAccount::LegacyPool(rune_id.to_string()),
+        EntryReason::Trade,
+    )?;
 
     // 5. Update pool state
     pool.icp_reserve = new_icp_reserve + fee; // Fee stays in pool
@@ -573,14 +584,9 @@ pub fn get_user_rune_balance(user: Principal, rune_id: &str) -> RuneBalance {
     get_balance(user, rune_id)
 }
 
-/// Get all rune balances for a user
-pub fn get_user_all_rune_balances(user: Principal) -> Vec<(String, RuneBalance)> {
-    balances::get_user_balances(user)
-}
-
 /// Get user's ICP trading balance
 pub fn get_user_icp_balance(user: Principal) -> u64 {
-    get_user_trading_balance(user)
+    trading_v2::get_user_icp_balance(user).available
 }
 
 #[cfg(test)]
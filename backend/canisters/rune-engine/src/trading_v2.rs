@@ -8,8 +8,12 @@
  * - Bonding curve AMM (constant product x * y = k)
  * - Enhanced bonding curve with graduation (pump.fun style)
  * - LP Token support for liquidity providers
+ * - Per-LP fee accounting (fee growth per LP token)
+ * - Launch protections (start delay, per-principal buy cap and cooldown)
+ * - Trade deadlines and signed quotes pinning reserves and fees
  * - Persistent storage across upgrades
  * - Event sourcing for complete audit trail
+ * - Admin-managed fee tiers, selected per pool
  * - Real ICP integration via ICRC-1
  *
  * Architecture:
@@ -21,33 +25,43 @@
 
 use candid::{CandidType, Decode, Deserialize, Encode, Principal};
 use ic_stable_structures::memory_manager::VirtualMemory;
-use ic_stable_structures::{storable::Bound, DefaultMemoryImpl, StableBTreeMap, Storable};
+use ic_stable_structures::{storable::Bound, DefaultMemoryImpl, StableBTreeMap, StableCell, Storable};
 use std::borrow::Cow;
 use std::cell::RefCell;
 
+use crate::accounting::{self, Asset, EntryReason, LedgerAccount};
+use crate::balances;
+use crate::icrc::{self, DedupError};
+
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
 // ============================================================================
 // CONSTANTS
 // ============================================================================
 
-/// Trading fee in basis points (30 = 0.3%)
+/// Default trading fee in basis points (30 = 0.3%) - used by the Standard tier
 pub const TRADING_FEE_BPS: u64 = 30;
 
-/// Protocol fee in basis points (10 = 0.1%) - goes to treasury
+/// Default protocol fee in basis points (10 = 0.1%) - goes to treasury
 pub const PROTOCOL_FEE_BPS: u64 = 10;
 
-/// LP fee in basis points (20 = 0.2%) - stays in pool for LPs
+/// Default LP fee in basis points (20 = 0.2%) - stays in pool for LPs
 pub const LP_FEE_BPS: u64 = 20;
 
+/// ID of the built-in Standard fee tier (cannot be removed)
+pub const DEFAULT_FEE_TIER_ID: u32 = 0;
+
+/// Maximum trading fee a tier may charge (10%)
+pub const MAX_TRADING_FEE_BPS: u64 = 1_000;
+
 /// Minimum liquidity to create pool (0.001 ICP in e8s)
 pub const MIN_LIQUIDITY_ICP: u64 = 100_000;
 
 /// Graduation threshold in ICP e8s (equivalent to ~$69k market cap)
-pub const GRADUATION_THRESHOLD_ICP: u64 = 85_00_000_000; // 85 ICP
+pub const GRADUATION_THRESHOLD_ICP: u64 = 8_500_000_000; // 85 ICP
 
 /// Virtual reserves for initial bonding curve
-pub const VIRTUAL_ICP_RESERVE: u64 = 30_00_000_000; // 30 ICP virtual
+pub const VIRTUAL_ICP_RESERVE: u64 = 3_000_000_000; // 30 ICP virtual
 pub const VIRTUAL_RUNE_RESERVE: u64 = 800_000_000; // 800M virtual tokens
 
 /// Price impact warning threshold (5%)
@@ -56,6 +70,23 @@ pub const PRICE_IMPACT_WARNING_BPS: u64 = 500;
 /// Maximum rune ID length
 const MAX_RUNE_ID_LENGTH: usize = 64;
 
+/// Fractional bits of the fee growth accumulators (Q64.64 fixed point)
+const FEE_GROWTH_FRACTION_BITS: u32 = 64;
+
+/// Maximum launch start delay (24 hours)
+pub const MAX_LAUNCH_START_DELAY_SECS: u64 = 86_400;
+
+/// Maximum launch protection window (24 hours)
+pub const MAX_LAUNCH_WINDOW_MINUTES: u64 = 1_440;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// How long a signed quote stays valid (60 seconds)
+pub const QUOTE_TTL_NANOS: u64 = 60 * NANOS_PER_SEC;
+
+/// Length of the quote authentication tag
+const QUOTE_TAG_LEN: usize = 16;
+
 // ============================================================================
 // POOL ID - Bounded Key Type
 // ============================================================================
@@ -180,6 +211,20 @@ pub struct TradingPool {
     pub fees_collected_runes: u64,
     /// Protocol fees pending withdrawal
     pub protocol_fees_pending: u64,
+    /// Fee tier in effect (None = Standard tier, for pools created before tiers)
+    pub fee_tier_id: Option<u32>,
+    /// Fees copied from the tier when it was assigned (None: read from the tier, for older pools)
+    pub fee_schedule: Option<PoolFees>,
+    /// Fees the pool moves to on graduation, copied from the tier's graduated tier
+    pub graduated_fees: Option<PoolFees>,
+    /// Cumulative LP fees in ICP per LP token (Q64.64, wrapping)
+    pub fee_growth_global_icp: Option<u128>,
+    /// Cumulative LP fees in runes per LP token (Q64.64, wrapping)
+    pub fee_growth_global_runes: Option<u128>,
+
+    // === Launch ===
+    /// Anti-sniping rules applied to buys after creation
+    pub launch_rules: Option<LaunchRules>,
 
     // === Statistics ===
     /// Total volume in ICP (e8s)
@@ -220,6 +265,12 @@ impl Default for TradingPool {
             fees_collected_icp: 0,
             fees_collected_runes: 0,
             protocol_fees_pending: 0,
+            fee_tier_id: None,
+            fee_schedule: None,
+            graduated_fees: None,
+            fee_growth_global_icp: None,
+            fee_growth_global_runes: None,
+            launch_rules: None,
             total_volume_icp: 0,
             total_trades: 0,
             unique_traders: 0,
@@ -231,6 +282,67 @@ impl Default for TradingPool {
     }
 }
 
+impl TradingPool {
+    /// Fee tier ID in effect for this pool
+    pub fn fee_tier_id(&self) -> u32 {
+        self.fee_tier_id.unwrap_or(DEFAULT_FEE_TIER_ID)
+    }
+
+    /// Resolve the fees currently charged by this pool
+    pub fn fees(&self) -> PoolFees {
+        if let Some(fees) = &self.fee_schedule {
+            return fees.clone();
+        }
+        get_fee_tier(self.fee_tier_id())
+            .map(|tier| PoolFees::from(&tier))
+            .unwrap_or_else(PoolFees::standard)
+    }
+
+    /// Copy a tier's fees (and those of its graduated tier) into the pool
+    fn assign_fees(&mut self, fees: PoolFees, graduated_fees: Option<PoolFees>) {
+        self.fee_tier_id = Some(fees.tier_id);
+        self.fee_schedule = Some(fees);
+        self.graduated_fees = graduated_fees;
+    }
+
+    /// Switch to the fees copied for the graduated phase, if any
+    ///
+    /// Returns false for pools created before fees were copied.
+    fn enter_graduated_fees(&mut self) -> bool {
+        if self.fee_schedule.is_none() {
+            return false;
+        }
+        if let Some(fees) = self.graduated_fees.take() {
+            self.assign_fees(fees, None);
+        }
+        true
+    }
+
+    /// Cumulative ICP fees per LP token
+    pub fn fee_growth_icp(&self) -> u128 {
+        self.fee_growth_global_icp.unwrap_or(0)
+    }
+
+    /// Cumulative rune fees per LP token
+    pub fn fee_growth_runes(&self) -> u128 {
+        self.fee_growth_global_runes.unwrap_or(0)
+    }
+
+    /// Record LP fees and distribute them pro rata over the current LP supply
+    fn record_lp_fees(&mut self, icp_fee: u64, rune_fee: u64) {
+        self.fees_collected_icp = self.fees_collected_icp.saturating_add(icp_fee);
+        self.fees_collected_runes = self.fees_collected_runes.saturating_add(rune_fee);
+        self.fee_growth_global_icp = Some(
+            self.fee_growth_icp()
+                .wrapping_add(fee_growth_delta(icp_fee, self.total_lp_supply)),
+        );
+        self.fee_growth_global_runes = Some(
+            self.fee_growth_runes()
+                .wrapping_add(fee_growth_delta(rune_fee, self.total_lp_supply)),
+        );
+    }
+}
+
 impl Storable for TradingPool {
     fn to_bytes(&self) -> Cow<[u8]> {
         Cow::Owned(Encode!(self).expect("Failed to encode TradingPool"))
@@ -243,6 +355,238 @@ impl Storable for TradingPool {
     const BOUND: Bound = Bound::Unbounded;
 }
 
+// ============================================================================
+// FEE TIERS - Admin-managed fee schedules
+// ============================================================================
+
+/// A fee schedule that pools can be assigned to
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct FeeTier {
+    /// Tier ID
+    pub id: u32,
+    /// Display name (e.g. "Bonding", "Graduated")
+    pub name: String,
+    /// Total trading fee in basis points
+    pub trading_fee_bps: u64,
+    /// Share of the trading fee that goes to the treasury, in basis points
+    pub protocol_fee_bps: u64,
+    /// Tier the pool switches to when it graduates to AMM
+    pub graduated_tier_id: Option<u32>,
+}
+
+impl FeeTier {
+    /// Built-in Standard tier matching the default fee constants
+    pub fn standard() -> Self {
+        Self {
+            id: DEFAULT_FEE_TIER_ID,
+            name: "Standard".to_string(),
+            trading_fee_bps: TRADING_FEE_BPS,
+            protocol_fee_bps: PROTOCOL_FEE_BPS,
+            graduated_tier_id: None,
+        }
+    }
+
+    /// LP share of the trading fee in basis points
+    pub fn lp_fee_bps(&self) -> u64 {
+        self.trading_fee_bps.saturating_sub(self.protocol_fee_bps)
+    }
+
+    /// Validate fee parameters
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() || self.name.len() > 32 {
+            return Err("Fee tier name must be 1-32 characters".to_string());
+        }
+        if self.trading_fee_bps > MAX_TRADING_FEE_BPS {
+            return Err(format!(
+                "Trading fee {} bps exceeds maximum {} bps",
+                self.trading_fee_bps, MAX_TRADING_FEE_BPS
+            ));
+        }
+        if self.protocol_fee_bps > self.trading_fee_bps {
+            return Err("Protocol fee cannot exceed the trading fee".to_string());
+        }
+        if self.graduated_tier_id == Some(self.id) {
+            return Err("A tier cannot graduate into itself".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Storable for FeeTier {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).expect("Failed to encode FeeTier"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).expect("Failed to decode FeeTier")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Fees applied to a single trade (snapshot of the tier in effect)
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct PoolFees {
+    pub tier_id: u32,
+    pub trading_fee_bps: u64,
+    pub protocol_fee_bps: u64,
+}
+
+impl PoolFees {
+    /// Fees of the built-in Standard tier
+    pub fn standard() -> Self {
+        Self::from(&FeeTier::standard())
+    }
+
+    /// LP share of the trading fee in basis points
+    pub fn lp_fee_bps(&self) -> u64 {
+        self.trading_fee_bps.saturating_sub(self.protocol_fee_bps)
+    }
+
+    /// Split an amount into (total fee, protocol fee, LP fee)
+    pub fn split(&self, amount: u64) -> (u64, u64, u64) {
+        let total_fee = ((amount as u128 * self.trading_fee_bps as u128) / 10_000) as u64;
+        let protocol_fee = ((amount as u128 * self.protocol_fee_bps as u128) / 10_000) as u64;
+        let lp_fee = total_fee.saturating_sub(protocol_fee);
+        (total_fee, protocol_fee, lp_fee)
+    }
+}
+
+impl From<&FeeTier> for PoolFees {
+    fn from(tier: &FeeTier) -> Self {
+        Self {
+            tier_id: tier.id,
+            trading_fee_bps: tier.trading_fee_bps,
+            protocol_fee_bps: tier.protocol_fee_bps,
+        }
+    }
+}
+
+// ============================================================================
+// LAUNCH RULES - Anti-sniping protections
+// ============================================================================
+
+/// Launch rules set at pool creation (0 disables a rule)
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct LaunchRules {
+    /// Trading opens this many seconds after pool creation
+    pub start_delay_secs: u64,
+    /// Length of the protection window, counted from trading start
+    pub protection_window_minutes: u64,
+    /// Max ICP (e8s) a principal may spend on buys during the window
+    pub max_buy_icp_per_principal: u64,
+    /// Minimum seconds between buys by the same principal during the window
+    pub buy_cooldown_secs: u64,
+}
+
+impl LaunchRules {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.start_delay_secs > MAX_LAUNCH_START_DELAY_SECS {
+            return Err(format!(
+                "Start delay cannot exceed {} seconds",
+                MAX_LAUNCH_START_DELAY_SECS
+            ));
+        }
+        if self.protection_window_minutes > MAX_LAUNCH_WINDOW_MINUTES {
+            return Err(format!(
+                "Protection window cannot exceed {} minutes",
+                MAX_LAUNCH_WINDOW_MINUTES
+            ));
+        }
+        if self.protection_window_minutes == 0
+            && (self.max_buy_icp_per_principal > 0 || self.buy_cooldown_secs > 0)
+        {
+            return Err("Buy cap and cooldown require a protection window".to_string());
+        }
+        if self.buy_cooldown_secs > self.protection_window_minutes * 60 {
+            return Err("Buy cooldown cannot exceed the protection window".to_string());
+        }
+        Ok(())
+    }
+
+    /// Timestamp at which trading opens
+    pub fn trading_starts_at(&self, created_at: u64) -> u64 {
+        created_at.saturating_add(self.start_delay_secs * NANOS_PER_SEC)
+    }
+
+    /// Timestamp at which the per-principal limits stop applying
+    pub fn protection_ends_at(&self, created_at: u64) -> u64 {
+        self.trading_starts_at(created_at)
+            .saturating_add(self.protection_window_minutes * 60 * NANOS_PER_SEC)
+    }
+
+    /// Check a buy against the rules, given the buyer's activity during the window
+    pub fn check_buy(
+        &self,
+        created_at: u64,
+        now: u64,
+        icp_amount: u64,
+        prior: Option<&LaunchBuyState>,
+    ) -> Result<(), String> {
+        let starts_at = self.trading_starts_at(created_at);
+        if now < starts_at {
+            return Err(format!(
+                "Trading opens in {} seconds",
+                (starts_at - now).div_ceil(NANOS_PER_SEC)
+            ));
+        }
+        if now >= self.protection_ends_at(created_at) {
+            return Ok(());
+        }
+
+        let Some(prior) = prior else {
+            return self.check_cap(0, icp_amount);
+        };
+
+        if self.buy_cooldown_secs > 0 {
+            let next_buy_at = prior
+                .last_buy_at
+                .saturating_add(self.buy_cooldown_secs * NANOS_PER_SEC);
+            if now < next_buy_at {
+                return Err(format!(
+                    "Buy cooldown active: wait {} seconds",
+                    (next_buy_at - now).div_ceil(NANOS_PER_SEC)
+                ));
+            }
+        }
+        self.check_cap(prior.icp_spent, icp_amount)
+    }
+
+    fn check_cap(&self, icp_spent: u64, icp_amount: u64) -> Result<(), String> {
+        if self.max_buy_icp_per_principal > 0
+            && icp_spent.saturating_add(icp_amount) > self.max_buy_icp_per_principal
+        {
+            return Err(format!(
+                "Launch buy limit exceeded: {} e8s remaining of {} e8s",
+                self.max_buy_icp_per_principal.saturating_sub(icp_spent),
+                self.max_buy_icp_per_principal
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A principal's buys during a pool's launch protection window
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct LaunchBuyState {
+    /// ICP spent on buys during the window (e8s)
+    pub icp_spent: u64,
+    /// Timestamp of the last buy
+    pub last_buy_at: u64,
+}
+
+impl Storable for LaunchBuyState {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).expect("Failed to encode LaunchBuyState"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).expect("Failed to decode LaunchBuyState")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
 // ============================================================================
 // LP POSITION - Liquidity Provider Tracking
 // ============================================================================
@@ -286,18 +630,46 @@ impl Storable for LPPositionKey {
 pub struct LPPosition {
     /// LP token balance
     pub lp_balance: u64,
-    /// ICP deposited (original)
+    /// ICP deposited (cost basis of the current LP balance)
     pub icp_deposited: u64,
-    /// Runes deposited (original)
+    /// Runes deposited (cost basis of the current LP balance)
     pub runes_deposited: u64,
-    /// Rewards earned (ICP e8s)
+    /// LP fees earned in ICP (e8s), accrued up to the last snapshot
     pub rewards_earned: u64,
-    /// Last reward claim timestamp
+    /// Last time accrued LP fees were paid out
     pub last_reward_claim: u64,
     /// First deposit timestamp
     pub created_at: u64,
     /// Last update timestamp
     pub updated_at: u64,
+    /// LP fees earned in runes, accrued up to the last snapshot
+    pub rune_rewards_earned: Option<u64>,
+    /// Pool ICP fee growth at the last snapshot
+    pub fee_growth_snapshot_icp: Option<u128>,
+    /// Pool rune fee growth at the last snapshot
+    pub fee_growth_snapshot_runes: Option<u128>,
+}
+
+impl LPPosition {
+    /// Move fees earned since the last snapshot into the position's totals
+    ///
+    /// Must run before `lp_balance` changes so fees are credited at the old balance.
+    pub fn accrue_fees(&mut self, pool: &TradingPool) {
+        let icp = accrued_fees(
+            self.lp_balance,
+            pool.fee_growth_icp(),
+            self.fee_growth_snapshot_icp.unwrap_or(0),
+        );
+        let runes = accrued_fees(
+            self.lp_balance,
+            pool.fee_growth_runes(),
+            self.fee_growth_snapshot_runes.unwrap_or(0),
+        );
+        self.rewards_earned = self.rewards_earned.saturating_add(icp);
+        self.rune_rewards_earned = Some(self.rune_rewards_earned.unwrap_or(0).saturating_add(runes));
+        self.fee_growth_snapshot_icp = Some(pool.fee_growth_icp());
+        self.fee_growth_snapshot_runes = Some(pool.fee_growth_runes());
+    }
 }
 
 impl Storable for LPPosition {
@@ -342,6 +714,8 @@ impl Storable for EventId {
 pub enum TradeType {
     Buy,
     Sell,
+    /// User-to-user rune transfer (trader sends to counterparty)
+    Transfer,
 }
 
 /// Trade event for event sourcing
@@ -365,6 +739,8 @@ pub struct TradeEvent {
     pub price_per_rune: u64,
     /// Fee paid (e8s)
     pub fee: u64,
+    /// Fee tier in effect for this trade (None for trades before fee tiers)
+    pub fees: Option<PoolFees>,
     /// Price impact (basis points)
     pub price_impact_bps: u16,
     /// Pool ICP reserve after trade
@@ -373,6 +749,10 @@ pub struct TradeEvent {
     pub pool_rune_reserve_after: u64,
     /// Timestamp
     pub timestamp: u64,
+    /// Recipient of a transfer
+    pub counterparty: Option<Principal>,
+    /// Memo attached to a transfer
+    pub memo: Option<Vec<u8>>,
 }
 
 impl Storable for TradeEvent {
@@ -440,7 +820,48 @@ impl Storable for BalanceKey {
     };
 }
 
+/// Key for the user -> rune index: (Principal, rune_id)
+///
+/// Encoded principal-first like `BalanceKey`, but keeps the rune ID in clear
+/// so a user's runes can be listed with a prefix range.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UserRuneKey {
+    pub user: Principal,
+    pub rune_id: String,
+}
+
+impl UserRuneKey {
+    /// Encoded principal shared by every key of that user
+    fn user_prefix(user: &Principal) -> Vec<u8> {
+        let principal_bytes = user.as_slice();
+        let mut bytes = Vec::with_capacity(1 + principal_bytes.len());
+        bytes.push(principal_bytes.len() as u8);
+        bytes.extend_from_slice(principal_bytes);
+        bytes
+    }
+}
+
+impl Storable for UserRuneKey {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut bytes = Self::user_prefix(&self.user);
+        bytes.extend_from_slice(self.rune_id.as_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let len = bytes[0] as usize;
+        let user = Principal::from_slice(&bytes[1..1 + len]);
+        let rune_id = String::from_utf8_lossy(&bytes[1 + len..]).into_owned();
+        Self { user, rune_id }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
 /// User balance for a rune
+///
+/// Stored entries only keep lifetime stats; `available` and `locked` are
+/// filled in from the accounting ledger on read.
 #[derive(CandidType, Deserialize, Clone, Debug, Default)]
 pub struct UserBalance {
     /// Available balance
@@ -474,6 +895,9 @@ impl Storable for UserBalance {
 }
 
 /// User ICP trading balance
+///
+/// Stored entries only keep lifetime stats; `available` and `locked` are
+/// filled in from the accounting ledger on read.
 #[derive(CandidType, Deserialize, Clone, Debug, Default)]
 pub struct ICPBalance {
     /// Available for trading
@@ -531,6 +955,18 @@ thread_local! {
 
     /// Rune ID to Pool ID mapping (for reverse lookup)
     static RUNE_TO_POOL: RefCell<Option<StableBTreeMap<[u8; 32], PoolId, Memory>>> = const { RefCell::new(None) };
+
+    /// Fee tiers
+    static FEE_TIERS: RefCell<Option<StableBTreeMap<u32, FeeTier, Memory>>> = const { RefCell::new(None) };
+
+    /// Buys during launch protection windows, keyed by (pool, buyer)
+    static LAUNCH_BUYS: RefCell<Option<StableBTreeMap<LPPositionKey, LaunchBuyState, Memory>>> = const { RefCell::new(None) };
+
+    /// Secret used to sign quotes (all zeros until generated)
+    static QUOTE_SIGNING_KEY: RefCell<Option<StableCell<[u8; 32], Memory>>> = const { RefCell::new(None) };
+
+    /// Runes each user holds a balance in, keyed principal-first
+    static USER_RUNE_INDEX: RefCell<Option<StableBTreeMap<UserRuneKey, (), Memory>>> = const { RefCell::new(None) };
 }
 
 // ============================================================================
@@ -586,20 +1022,200 @@ pub fn reinit_trading_storage(
     );
 }
 
+/// Initialize fee tier storage, seeding the Standard tier on first use
+pub fn init_fee_tier_storage(fee_tiers_memory: Memory) {
+    FEE_TIERS.with(|t| {
+        let mut map = StableBTreeMap::init(fee_tiers_memory);
+        if !map.contains_key(&DEFAULT_FEE_TIER_ID) {
+            map.insert(DEFAULT_FEE_TIER_ID, FeeTier::standard());
+        }
+        *t.borrow_mut() = Some(map);
+    });
+}
+
+/// Initialize launch protection storage
+pub fn init_launch_storage(launch_buys_memory: Memory) {
+    LAUNCH_BUYS.with(|l| {
+        *l.borrow_mut() = Some(StableBTreeMap::init(launch_buys_memory));
+    });
+}
+
+/// Initialize quote signing key storage
+pub fn init_quote_key_storage(quote_key_memory: Memory) {
+    QUOTE_SIGNING_KEY.with(|k| {
+        *k.borrow_mut() = Some(
+            StableCell::init(quote_key_memory, [0u8; 32])
+                .expect("Failed to initialize quote signing key"),
+        );
+    });
+}
+
+/// Initialize the user -> rune index
+pub fn init_user_rune_index_storage(user_rune_index_memory: Memory) {
+    USER_RUNE_INDEX.with(|i| {
+        *i.borrow_mut() = Some(StableBTreeMap::init(user_rune_index_memory));
+    });
+}
+
+/// Generate the quote signing key if it has not been generated yet
+///
+/// Needs `raw_rand`, so it runs from a timer rather than init/post_upgrade.
+pub async fn ensure_quote_signing_key() -> Result<(), String> {
+    if quote_signing_key().is_some() {
+        return Ok(());
+    }
+
+    let random_bytes = ic_cdk::api::management_canister::main::raw_rand()
+        .await
+        .map_err(|(code, msg)| format!("Failed to generate random bytes: {:?} - {}", code, msg))?
+        .0;
+    if random_bytes.len() < 32 {
+        return Err(format!(
+            "Insufficient random bytes: expected 32, got {}",
+            random_bytes.len()
+        ));
+    }
+
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&random_bytes[0..32]);
+    QUOTE_SIGNING_KEY.with(|k| {
+        if let Some(ref mut cell) = *k.borrow_mut() {
+            cell.set(key)
+                .map(|_| ())
+                .map_err(|e| format!("Failed to store quote signing key: {:?}", e))
+        } else {
+            Err("Quote key storage not initialized".to_string())
+        }
+    })
+}
+
+fn quote_signing_key() -> Option<[u8; 32]> {
+    QUOTE_SIGNING_KEY.with(|k| {
+        k.borrow()
+            .as_ref()
+            .map(|cell| *cell.get())
+            .filter(|key| *key != [0u8; 32])
+    })
+}
+
+// ============================================================================
+// FEE TIER OPERATIONS
+// ============================================================================
+
+/// Get a fee tier by ID
+pub fn get_fee_tier(tier_id: u32) -> Option<FeeTier> {
+    FEE_TIERS.with(|t| t.borrow().as_ref().and_then(|map| map.get(&tier_id)))
+}
+
+/// List all fee tiers
+pub fn list_fee_tiers() -> Vec<FeeTier> {
+    FEE_TIERS.with(|t| {
+        if let Some(ref map) = *t.borrow() {
+            map.iter().map(|(_, tier)| tier).collect()
+        } else {
+            vec![]
+        }
+    })
+}
+
+/// Create or replace a fee tier (Admin only - call from lib.rs with RBAC check)
+///
+/// Pools copy their tier's fees when it is assigned, so edits only apply to
+/// pools created or moved to the tier afterwards.
+pub fn upsert_fee_tier(tier: FeeTier) -> Result<(), String> {
+    tier.validate()?;
+    if let Some(graduated_id) = tier.graduated_tier_id {
+        if get_fee_tier(graduated_id).is_none() {
+            return Err(format!("Graduated fee tier {} not found", graduated_id));
+        }
+    }
+
+    FEE_TIERS.with(|t| {
+        if let Some(ref mut map) = *t.borrow_mut() {
+            map.insert(tier.id, tier);
+            Ok(())
+        } else {
+            Err("Fee tier storage not initialized".to_string())
+        }
+    })
+}
+
+/// Remove a fee tier that no pool or tier references (Admin only)
+pub fn remove_fee_tier(tier_id: u32) -> Result<(), String> {
+    if tier_id == DEFAULT_FEE_TIER_ID {
+        return Err("The Standard fee tier cannot be removed".to_string());
+    }
+    if list_pools(0, u64::MAX).iter().any(|p| p.fee_tier_id() == tier_id) {
+        return Err(format!("Fee tier {} is still assigned to pools", tier_id));
+    }
+    if list_fee_tiers().iter().any(|t| t.graduated_tier_id == Some(tier_id)) {
+        return Err(format!("Fee tier {} is the graduation target of another tier", tier_id));
+    }
+
+    FEE_TIERS.with(|t| {
+        if let Some(ref mut map) = *t.borrow_mut() {
+            map.remove(&tier_id)
+                .map(|_| ())
+                .ok_or_else(|| format!("Fee tier {} not found", tier_id))
+        } else {
+            Err("Fee tier storage not initialized".to_string())
+        }
+    })
+}
+
+/// Copy a tier's current fees, and those of its graduated tier
+fn tier_fees(tier_id: u32) -> Result<(PoolFees, Option<PoolFees>), String> {
+    let tier = get_fee_tier(tier_id).ok_or_else(|| format!("Fee tier {} not found", tier_id))?;
+    let graduated = tier
+        .graduated_tier_id
+        .and_then(get_fee_tier)
+        .map(|t| PoolFees::from(&t));
+    Ok((PoolFees::from(&tier), graduated))
+}
+
+/// Change the fee tier of a pool (Admin only)
+///
+/// The pool takes the tier's current fees; a bonding pool also takes those of
+/// the tier it graduates into.
+pub fn set_pool_fee_tier(rune_id: &str, tier_id: u32) -> Result<TradingPool, String> {
+    let (fees, graduated_fees) = tier_fees(tier_id)?;
+    let mut pool = get_pool_by_rune_id(rune_id).ok_or("Pool not found")?;
+    let graduated_fees = graduated_fees.filter(|_| pool.pool_type == PoolType::Bonding);
+    pool.assign_fees(fees, graduated_fees);
+    save_pool(&pool)?;
+    Ok(pool)
+}
+
 // ============================================================================
 // POOL OPERATIONS
 // ============================================================================
 
 /// Get next event ID
+///
+/// The counter lives on the heap, so after an upgrade it resumes from the
+/// last stored event instead of overwriting existing events.
 fn next_event_id() -> u64 {
     EVENT_COUNTER.with(|c| {
-        let id = *c.borrow();
+        let mut id = *c.borrow();
+        if id == 0 {
+            id = TRADE_EVENTS.with(|e| {
+                e.borrow()
+                    .as_ref()
+                    .and_then(|map| map.last_key_value())
+                    .map(|(last, _)| last.0 + 1)
+                    .unwrap_or(0)
+            });
+        }
         *c.borrow_mut() = id + 1;
         id
     })
 }
 
 /// Create a new trading pool with bonding curve
+///
+/// New pools are in the bonding phase and start on `fee_tier_id` (the
+/// Standard tier if None), moving to its graduated tier (if any) when they
+/// graduate. The fees of both are copied into the pool.
 pub fn create_pool(
     rune_id: &str,
     rune_name: &str,
@@ -609,6 +1225,8 @@ pub fn create_pool(
     initial_icp: u64,
     initial_runes: u64,
     creator: Principal,
+    launch_rules: Option<LaunchRules>,
+    fee_tier_id: Option<u32>,
 ) -> Result<TradingPool, String> {
     // Validate inputs
     if rune_id.is_empty() || rune_id.len() > MAX_RUNE_ID_LENGTH {
@@ -620,6 +1238,10 @@ pub fn create_pool(
     if initial_runes == 0 {
         return Err("Initial runes must be > 0".to_string());
     }
+    let (fees, graduated_fees) = tier_fees(fee_tier_id.unwrap_or(DEFAULT_FEE_TIER_ID))?;
+    if let Some(ref rules) = launch_rules {
+        rules.validate()?;
+    }
 
     let pool_id = PoolId::from_rune_id(rune_id);
 
@@ -657,6 +1279,12 @@ pub fn create_pool(
         fees_collected_icp: 0,
         fees_collected_runes: 0,
         protocol_fees_pending: 0,
+        fee_tier_id: Some(fees.tier_id),
+        fee_schedule: Some(fees),
+        graduated_fees,
+        fee_growth_global_icp: Some(0),
+        fee_growth_global_runes: Some(0),
+        launch_rules,
         total_volume_icp: 0,
         total_trades: 0,
         unique_traders: 0,
@@ -682,6 +1310,9 @@ pub fn create_pool(
         last_reward_claim: now,
         created_at: now,
         updated_at: now,
+        rune_rewards_earned: Some(0),
+        fee_growth_snapshot_icp: Some(0),
+        fee_growth_snapshot_runes: Some(0),
     };
     save_lp_position(&lp_key, &lp_position)?;
 
@@ -825,6 +1456,7 @@ pub struct TradeQuote {
     pub fee: u64,
     pub protocol_fee: u64,
     pub lp_fee: u64,
+    pub fees: PoolFees,
     pub price_impact_bps: u16,
     pub minimum_output: u64,
     pub pool_icp_reserve: u64,
@@ -844,10 +1476,9 @@ pub fn calculate_buy_quote(
         return Err("Pool is not active".to_string());
     }
 
-    // Calculate fees
-    let total_fee = (icp_amount * TRADING_FEE_BPS) / 10_000;
-    let protocol_fee = (icp_amount * PROTOCOL_FEE_BPS) / 10_000;
-    let lp_fee = total_fee.saturating_sub(protocol_fee);
+    // Calculate fees from the pool's tier
+    let fees = pool.fees();
+    let (total_fee, protocol_fee, lp_fee) = fees.split(icp_amount);
     let icp_after_fee = icp_amount.saturating_sub(total_fee);
 
     // Calculate effective reserves (real + virtual for bonding curve)
@@ -894,6 +1525,7 @@ pub fn calculate_buy_quote(
         fee: total_fee,
         protocol_fee,
         lp_fee,
+        fees,
         price_impact_bps: price_impact,
         minimum_output: min_output,
         pool_icp_reserve: pool.icp_reserve,
@@ -929,10 +1561,9 @@ pub fn calculate_sell_quote(
     let new_icp = (k / new_runes as u128) as u64;
     let icp_out_before_fee = effective_icp.saturating_sub(new_icp);
 
-    // Calculate fees
-    let total_fee = (icp_out_before_fee * TRADING_FEE_BPS) / 10_000;
-    let protocol_fee = (icp_out_before_fee * PROTOCOL_FEE_BPS) / 10_000;
-    let lp_fee = total_fee.saturating_sub(protocol_fee);
+    // Calculate fees from the pool's tier
+    let fees = pool.fees();
+    let (total_fee, protocol_fee, lp_fee) = fees.split(icp_out_before_fee);
     let icp_out = icp_out_before_fee.saturating_sub(total_fee);
 
     if icp_out == 0 {
@@ -963,6 +1594,7 @@ pub fn calculate_sell_quote(
         fee: total_fee,
         protocol_fee,
         lp_fee,
+        fees,
         price_impact_bps: price_impact,
         minimum_output: min_output,
         pool_icp_reserve: pool.icp_reserve,
@@ -971,50 +1603,337 @@ pub fn calculate_sell_quote(
     })
 }
 
-/// Execute a buy trade
-pub fn execute_buy(
-    rune_id: &str,
-    icp_amount: u64,
-    min_runes_out: u64,
-    trader: Principal,
-) -> Result<TradeEvent, String> {
-    // Get quote first
-    let quote = calculate_buy_quote(rune_id, icp_amount, 0)?;
+// ============================================================================
+// SIGNED QUOTES & TRADE GUARDS
+// ============================================================================
 
-    // Check slippage
-    if quote.output_amount < min_runes_out {
-        return Err(format!(
-            "Slippage exceeded: got {} runes, expected at least {}",
-            quote.output_amount, min_runes_out
-        ));
-    }
+/// Typed trade execution error
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum TradeError {
+    /// The caller's deadline passed before execution
+    DeadlineExceeded { deadline: u64, now: u64 },
+    /// The quote is older than `QUOTE_TTL_NANOS`
+    QuoteExpired { expires_at: u64, now: u64 },
+    /// The quote ID is malformed or was not issued by this canister
+    InvalidQuote { reason: String },
+    /// The quote was issued for a different pool, side, amount or fee schedule
+    QuoteMismatch { reason: String },
+    /// The pool moved beyond the quote's tolerance
+    PriceDrift {
+        quoted_output: u64,
+        current_output: u64,
+        tolerance_bps: u64,
+    },
+    /// Output fell below the caller's minimum
+    SlippageExceeded { output: u64, min_output: u64 },
+    /// Any other rejection (balance, pool state, launch rules, ...)
+    Rejected(String),
+}
 
-    // Verify user has enough ICP
-    let user_icp = get_user_icp_balance(trader);
-    if user_icp.available < icp_amount {
-        return Err(format!(
-            "Insufficient ICP balance: have {}, need {}",
-            user_icp.available, icp_amount
-        ));
+impl std::fmt::Display for TradeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TradeError::DeadlineExceeded { deadline, now } => {
+                write!(f, "Deadline exceeded: deadline {}, now {}", deadline, now)
+            }
+            TradeError::QuoteExpired { expires_at, now } => {
+                write!(f, "Quote expired at {}, now {}", expires_at, now)
+            }
+            TradeError::InvalidQuote { reason } => write!(f, "Invalid quote: {}", reason),
+            TradeError::QuoteMismatch { reason } => write!(f, "Quote mismatch: {}", reason),
+            TradeError::PriceDrift { quoted_output, current_output, tolerance_bps } => write!(
+                f,
+                "Price drifted: quoted {}, now {} (tolerance {} bps)",
+                quoted_output, current_output, tolerance_bps
+            ),
+            TradeError::SlippageExceeded { output, min_output } => write!(
+                f,
+                "Slippage exceeded: got {}, expected at least {}",
+                output, min_output
+            ),
+            TradeError::Rejected(reason) => write!(f, "{}", reason),
+        }
     }
+}
 
-    // Debit ICP from user
-    debit_user_icp(trader, icp_amount)?;
+impl From<String> for TradeError {
+    fn from(reason: String) -> Self {
+        TradeError::Rejected(reason)
+    }
+}
 
-    // Credit runes to user
-    credit_user_runes(trader, rune_id, quote.output_amount)?;
+impl From<&str> for TradeError {
+    fn from(reason: &str) -> Self {
+        TradeError::Rejected(reason.to_string())
+    }
+}
 
-    // Update pool state
-    let mut pool = get_pool_by_rune_id(rune_id).ok_or("Pool not found")?;
-    let now = ic_cdk::api::time();
+/// Quote state pinned into a signed quote ID
+#[derive(Clone, Debug, PartialEq)]
+pub struct PinnedQuote {
+    pub pool_id: PoolId,
+    pub trade_type: TradeType,
+    pub input_amount: u64,
+    pub output_amount: u64,
+    pub pool_icp_reserve: u64,
+    pub pool_rune_reserve: u64,
+    pub fees: PoolFees,
+    pub tolerance_bps: u64,
+    pub expires_at: u64,
+}
 
-    pool.icp_reserve = pool.icp_reserve.saturating_add(icp_amount - quote.fee);
-    pool.rune_reserve = pool.rune_reserve.saturating_sub(quote.output_amount);
-    pool.fees_collected_icp = pool.fees_collected_icp.saturating_add(quote.lp_fee);
-    pool.protocol_fees_pending = pool.protocol_fees_pending.saturating_add(quote.protocol_fee);
-    pool.total_volume_icp = pool.total_volume_icp.saturating_add(icp_amount as u128);
-    pool.total_trades += 1;
-    pool.last_trade_at = now;
+impl PinnedQuote {
+    const ENCODED_LEN: usize = 32 + 1 + 8 * 4 + 4 + 8 * 4;
+
+    pub fn from_quote(quote: &TradeQuote, tolerance_bps: u64, now: u64) -> Self {
+        Self {
+            pool_id: PoolId::from_rune_id(&quote.rune_id),
+            trade_type: quote.trade_type.clone(),
+            input_amount: quote.input_amount,
+            output_amount: quote.output_amount,
+            pool_icp_reserve: quote.pool_icp_reserve,
+            pool_rune_reserve: quote.pool_rune_reserve,
+            fees: quote.fees.clone(),
+            tolerance_bps: tolerance_bps.min(10_000),
+            expires_at: now.saturating_add(QUOTE_TTL_NANOS),
+        }
+    }
+
+    fn encode_fields(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::ENCODED_LEN);
+        bytes.extend_from_slice(&self.pool_id.0);
+        bytes.push(match self.trade_type {
+            TradeType::Buy => 0,
+            TradeType::Sell => 1,
+            TradeType::Transfer => 2,
+        });
+        bytes.extend_from_slice(&self.input_amount.to_be_bytes());
+        bytes.extend_from_slice(&self.output_amount.to_be_bytes());
+        bytes.extend_from_slice(&self.pool_icp_reserve.to_be_bytes());
+        bytes.extend_from_slice(&self.pool_rune_reserve.to_be_bytes());
+        bytes.extend_from_slice(&self.fees.tier_id.to_be_bytes());
+        bytes.extend_from_slice(&self.fees.trading_fee_bps.to_be_bytes());
+        bytes.extend_from_slice(&self.fees.protocol_fee_bps.to_be_bytes());
+        bytes.extend_from_slice(&self.tolerance_bps.to_be_bytes());
+        bytes.extend_from_slice(&self.expires_at.to_be_bytes());
+        bytes
+    }
+
+    fn decode_fields(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::ENCODED_LEN {
+            return None;
+        }
+        let u64_at = |offset: usize| {
+            let mut arr = [0u8; 8];
+            arr.copy_from_slice(&bytes[offset..offset + 8]);
+            u64::from_be_bytes(arr)
+        };
+        let mut pool_id = [0u8; 32];
+        pool_id.copy_from_slice(&bytes[0..32]);
+        let trade_type = match bytes[32] {
+            0 => TradeType::Buy,
+            1 => TradeType::Sell,
+            _ => return None,
+        };
+        let mut tier_id = [0u8; 4];
+        tier_id.copy_from_slice(&bytes[65..69]);
+
+        Some(Self {
+            pool_id: PoolId(pool_id),
+            trade_type,
+            input_amount: u64_at(33),
+            output_amount: u64_at(41),
+            pool_icp_reserve: u64_at(49),
+            pool_rune_reserve: u64_at(57),
+            fees: PoolFees {
+                tier_id: u32::from_be_bytes(tier_id),
+                trading_fee_bps: u64_at(69),
+                protocol_fee_bps: u64_at(77),
+            },
+            tolerance_bps: u64_at(85),
+            expires_at: u64_at(93),
+        })
+    }
+
+    /// Encode as a hex quote ID authenticated with `key`
+    pub fn sign(&self, key: &[u8; 32]) -> String {
+        let mut bytes = self.encode_fields();
+        let tag = quote_tag(key, &bytes);
+        bytes.extend_from_slice(&tag);
+        hex::encode(bytes)
+    }
+
+    /// Decode a quote ID, rejecting it unless it was signed with `key`
+    pub fn verify(quote_id: &str, key: &[u8; 32]) -> Result<Self, TradeError> {
+        let invalid = |reason: &str| TradeError::InvalidQuote { reason: reason.to_string() };
+
+        let bytes = hex::decode(quote_id).map_err(|_| invalid("not hex encoded"))?;
+        if bytes.len() != Self::ENCODED_LEN + QUOTE_TAG_LEN {
+            return Err(invalid("unexpected length"));
+        }
+        let (fields, tag) = bytes.split_at(Self::ENCODED_LEN);
+        if quote_tag(key, fields) != tag {
+            return Err(invalid("signature mismatch"));
+        }
+        Self::decode_fields(fields).ok_or_else(|| invalid("malformed fields"))
+    }
+
+    /// Check that a trade about to execute still matches this quote
+    pub fn check(&self, current: &TradeQuote, now: u64) -> Result<(), TradeError> {
+        if now > self.expires_at {
+            return Err(TradeError::QuoteExpired { expires_at: self.expires_at, now });
+        }
+
+        let mismatch = |reason: &str| TradeError::QuoteMismatch { reason: reason.to_string() };
+        if self.pool_id != PoolId::from_rune_id(&current.rune_id) {
+            return Err(mismatch("quote is for a different pool"));
+        }
+        if self.trade_type != current.trade_type {
+            return Err(mismatch("quote is for the other trade side"));
+        }
+        if self.input_amount != current.input_amount {
+            return Err(mismatch("quote is for a different input amount"));
+        }
+        if self.fees != current.fees {
+            return Err(mismatch("pool fees changed since the quote"));
+        }
+
+        let min_output = (self.output_amount as u128 * (10_000 - self.tolerance_bps) as u128 / 10_000) as u64;
+        if current.output_amount < min_output {
+            return Err(TradeError::PriceDrift {
+                quoted_output: self.output_amount,
+                current_output: current.output_amount,
+                tolerance_bps: self.tolerance_bps,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn quote_tag(key: &[u8; 32], fields: &[u8]) -> [u8; QUOTE_TAG_LEN] {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    hasher.update(key);
+    hasher.update(fields);
+    let digest = hasher.finalize();
+    let mut tag = [0u8; QUOTE_TAG_LEN];
+    tag.copy_from_slice(&digest[..QUOTE_TAG_LEN]);
+    tag
+}
+
+/// Sign a quote so it can be passed back on execution
+///
+/// Returns `(quote_id, expires_at)`, or None while the signing key is not yet generated.
+pub fn sign_quote(quote: &TradeQuote, tolerance_bps: u64, now: u64) -> Option<(String, u64)> {
+    let key = quote_signing_key()?;
+    let pinned = PinnedQuote::from_quote(quote, tolerance_bps, now);
+    Some((pinned.sign(&key), pinned.expires_at))
+}
+
+/// Check the caller's deadline and quote before executing a trade
+fn check_trade_guards(
+    current: &TradeQuote,
+    deadline: Option<u64>,
+    quote_id: Option<&str>,
+    now: u64,
+) -> Result<(), TradeError> {
+    if let Some(deadline) = deadline {
+        if now > deadline {
+            return Err(TradeError::DeadlineExceeded { deadline, now });
+        }
+    }
+    if let Some(quote_id) = quote_id {
+        let key = quote_signing_key().ok_or_else(|| TradeError::InvalidQuote {
+            reason: "quote signing key not available".to_string(),
+        })?;
+        PinnedQuote::verify(quote_id, &key)?.check(current, now)?;
+    }
+    Ok(())
+}
+
+/// Unwrap a step of a trade that runs after its first posting
+///
+/// Trades validate everything before posting, so a failure here is a bug;
+/// trapping rolls back the postings already made instead of leaving half a
+/// trade in the ledger.
+fn committed<T, E: std::fmt::Display>(result: Result<T, E>) -> T {
+    result.unwrap_or_else(|e| ic_cdk::trap(&format!("CRITICAL: trade failed after posting: {}", e)))
+}
+
+/// Execute a buy trade
+pub fn execute_buy(
+    rune_id: &str,
+    icp_amount: u64,
+    min_runes_out: u64,
+    deadline: Option<u64>,
+    quote_id: Option<&str>,
+    trader: Principal,
+) -> Result<TradeEvent, TradeError> {
+    let now = ic_cdk::api::time();
+
+    // Enforce launch protections
+    let pool = get_pool_by_rune_id(rune_id).ok_or("Pool not found")?;
+    if let Some(ref rules) = pool.launch_rules {
+        let prior = get_launch_buy_state(&pool.id, trader);
+        rules.check_buy(pool.created_at, now, icp_amount, prior.as_ref())?;
+    }
+
+    // Get quote first
+    let quote = calculate_buy_quote(rune_id, icp_amount, 0)?;
+
+    // Check deadline and signed quote
+    check_trade_guards(&quote, deadline, quote_id, now)?;
+
+    // Check slippage
+    if quote.output_amount < min_runes_out {
+        return Err(TradeError::SlippageExceeded {
+            output: quote.output_amount,
+            min_output: min_runes_out,
+        });
+    }
+
+    // Verify user has enough ICP
+    let user_icp = get_user_icp_balance(trader);
+    if user_icp.available < icp_amount {
+        return Err(format!(
+            "Insufficient ICP balance: have {}, need {}",
+            user_icp.available, icp_amount
+        )
+        .into());
+    }
+
+    // The pool must hold the runes it pays out
+    let pool_account = LedgerAccount::Pool(rune_id.to_string());
+    let pool_runes = accounting::balance_of(&pool_account, &Asset::Rune(rune_id.to_string()));
+    if pool_runes < quote.output_amount {
+        return Err(format!(
+            "Insufficient pool liquidity: have {}, need {}",
+            pool_runes, quote.output_amount
+        )
+        .into());
+    }
+
+    // Everything is validated; from the first posting on, a failure traps so
+    // the trade rolls back as a whole
+    committed(debit_user_icp(
+        trader,
+        icp_amount - quote.protocol_fee,
+        pool_account.clone(),
+        EntryReason::Trade,
+    ));
+    committed(debit_user_icp(trader, quote.protocol_fee, LedgerAccount::ProtocolFees, EntryReason::ProtocolFee));
+    committed(credit_user_runes(trader, rune_id, quote.output_amount, pool_account, EntryReason::Trade));
+
+    // Update pool state
+    let mut pool = committed(get_pool_by_rune_id(rune_id).ok_or("Pool not found"));
+
+    pool.icp_reserve = pool.icp_reserve.saturating_add(icp_amount - quote.fee);
+    pool.rune_reserve = pool.rune_reserve.saturating_sub(quote.output_amount);
+    pool.record_lp_fees(quote.lp_fee, 0);
+    pool.protocol_fees_pending = pool.protocol_fees_pending.saturating_add(quote.protocol_fee);
+    pool.total_volume_icp = pool.total_volume_icp.saturating_add(icp_amount as u128);
+    pool.total_trades += 1;
+    pool.last_trade_at = now;
 
     // Update k constant
     let (effective_icp, effective_runes) = match pool.pool_type {
@@ -1027,9 +1946,16 @@ pub fn execute_buy(
     pool.k_constant = (effective_icp as u128) * (effective_runes as u128);
 
     // Check for graduation
-    check_and_graduate_pool(&mut pool)?;
+    committed(check_and_graduate_pool(&mut pool));
 
-    save_pool(&pool)?;
+    committed(save_pool(&pool));
+
+    // Track the buyer's activity while launch protections apply
+    if let Some(ref rules) = pool.launch_rules {
+        if now < rules.protection_ends_at(pool.created_at) {
+            committed(record_launch_buy(&pool.id, trader, icp_amount, now));
+        }
+    }
 
     // Create and store event
     let event = TradeEvent {
@@ -1042,13 +1968,16 @@ pub fn execute_buy(
         rune_amount: quote.output_amount,
         price_per_rune: quote.price_per_rune,
         fee: quote.fee,
+        fees: Some(quote.fees.clone()),
         price_impact_bps: quote.price_impact_bps,
         pool_icp_reserve_after: pool.icp_reserve,
         pool_rune_reserve_after: pool.rune_reserve,
         timestamp: now,
+        counterparty: None,
+        memo: None,
     };
 
-    store_trade_event(&event)?;
+    committed(store_trade_event(&event));
 
     Ok(event)
 }
@@ -1058,17 +1987,24 @@ pub fn execute_sell(
     rune_id: &str,
     rune_amount: u64,
     min_icp_out: u64,
+    deadline: Option<u64>,
+    quote_id: Option<&str>,
     trader: Principal,
-) -> Result<TradeEvent, String> {
+) -> Result<TradeEvent, TradeError> {
+    let now = ic_cdk::api::time();
+
     // Get quote first
     let quote = calculate_sell_quote(rune_id, rune_amount, 0)?;
 
+    // Check deadline and signed quote
+    check_trade_guards(&quote, deadline, quote_id, now)?;
+
     // Check slippage
     if quote.output_amount < min_icp_out {
-        return Err(format!(
-            "Slippage exceeded: got {} ICP, expected at least {}",
-            quote.output_amount, min_icp_out
-        ));
+        return Err(TradeError::SlippageExceeded {
+            output: quote.output_amount,
+            min_output: min_icp_out,
+        });
     }
 
     // Verify user has enough runes
@@ -1077,22 +2013,37 @@ pub fn execute_sell(
         return Err(format!(
             "Insufficient rune balance: have {}, need {}",
             user_balance.available, rune_amount
-        ));
+        )
+        .into());
     }
 
-    // Debit runes from user
-    debit_user_runes(trader, rune_id, rune_amount)?;
+    // The pool must hold the ICP it pays out, protocol fee included
+    let pool_account = LedgerAccount::Pool(rune_id.to_string());
+    let pool_icp = accounting::balance_of(&pool_account, &Asset::Icp);
+    let icp_out = quote.output_amount.saturating_add(quote.protocol_fee);
+    if pool_icp < icp_out {
+        return Err(format!("Insufficient pool liquidity: have {}, need {}", pool_icp, icp_out).into());
+    }
 
-    // Credit ICP to user
-    credit_user_icp(trader, quote.output_amount)?;
+    // Everything is validated; from the first posting on, a failure traps so
+    // the trade rolls back as a whole
+    committed(debit_user_runes(trader, rune_id, rune_amount, pool_account.clone(), EntryReason::Trade));
+    committed(credit_user_icp(trader, quote.output_amount, pool_account.clone(), EntryReason::Trade));
+    committed(accounting::post(
+        Asset::Icp,
+        pool_account,
+        LedgerAccount::ProtocolFees,
+        quote.protocol_fee,
+        EntryReason::ProtocolFee,
+        None,
+    ));
 
     // Update pool state
-    let mut pool = get_pool_by_rune_id(rune_id).ok_or("Pool not found")?;
-    let now = ic_cdk::api::time();
+    let mut pool = committed(get_pool_by_rune_id(rune_id).ok_or("Pool not found"));
 
     pool.rune_reserve = pool.rune_reserve.saturating_add(rune_amount);
     pool.icp_reserve = pool.icp_reserve.saturating_sub(quote.output_amount + quote.fee);
-    pool.fees_collected_icp = pool.fees_collected_icp.saturating_add(quote.lp_fee);
+    pool.record_lp_fees(quote.lp_fee, 0);
     pool.protocol_fees_pending = pool.protocol_fees_pending.saturating_add(quote.protocol_fee);
     pool.total_volume_icp = pool.total_volume_icp.saturating_add(quote.output_amount as u128);
     pool.total_trades += 1;
@@ -1108,7 +2059,7 @@ pub fn execute_sell(
     };
     pool.k_constant = (effective_icp as u128) * (effective_runes as u128);
 
-    save_pool(&pool)?;
+    committed(save_pool(&pool));
 
     // Create and store event
     let event = TradeEvent {
@@ -1121,13 +2072,16 @@ pub fn execute_sell(
         rune_amount,
         price_per_rune: quote.price_per_rune,
         fee: quote.fee,
+        fees: Some(quote.fees.clone()),
         price_impact_bps: quote.price_impact_bps,
         pool_icp_reserve_after: pool.icp_reserve,
         pool_rune_reserve_after: pool.rune_reserve,
         timestamp: now,
+        counterparty: None,
+        memo: None,
     };
 
-    store_trade_event(&event)?;
+    committed(store_trade_event(&event));
 
     Ok(event)
 }
@@ -1155,6 +2109,15 @@ fn check_and_graduate_pool(pool: &mut TradingPool) -> Result<(), String> {
         liquidity_burned: 0, // LP tokens are not burned, just locked
     };
 
+    // Move to the graduated fees copied at creation; older pools look up the tier
+    if !pool.enter_graduated_fees() {
+        if let Some(graduated_tier_id) = get_fee_tier(pool.fee_tier_id()).and_then(|t| t.graduated_tier_id) {
+            if get_fee_tier(graduated_tier_id).is_some() {
+                pool.fee_tier_id = Some(graduated_tier_id);
+            }
+        }
+    }
+
     // Recalculate k constant without virtual reserves
     pool.k_constant = (pool.icp_reserve as u128) * (pool.rune_reserve as u128);
 
@@ -1190,119 +2153,446 @@ pub fn get_pool_price(pool: &TradingPool) -> u64 {
     effective_icp / effective_runes
 }
 
+// ============================================================================
+// PROTOCOL FEES
+// ============================================================================
+
+/// Take all pending protocol fees from a pool, resetting the pending amount to zero.
+///
+/// Fees are taken before the treasury transfer is awaited so a concurrent
+/// collection can never withdraw the same fees twice.
+pub fn take_protocol_fees(pool_id: &PoolId) -> Result<u64, String> {
+    let mut pool = get_pool(pool_id).ok_or("Pool not found")?;
+    let amount = pool.protocol_fees_pending;
+    if amount > 0 {
+        accounting::post(
+            Asset::Icp,
+            LedgerAccount::ProtocolFees,
+            LedgerAccount::Custody,
+            amount,
+            EntryReason::FeeCollection,
+            Some(pool.rune_id.clone()),
+        )?;
+        pool.protocol_fees_pending = 0;
+        save_pool(&pool)?;
+    }
+    Ok(amount)
+}
+
+/// Return previously taken protocol fees to a pool (used when a treasury transfer fails)
+pub fn restore_protocol_fees(pool_id: &PoolId, amount: u64) -> Result<(), String> {
+    let mut pool = get_pool(pool_id).ok_or("Pool not found")?;
+    accounting::post(
+        Asset::Icp,
+        LedgerAccount::Custody,
+        LedgerAccount::ProtocolFees,
+        amount,
+        EntryReason::FeeCollectionRefund,
+        Some(pool.rune_id.clone()),
+    )?;
+    pool.protocol_fees_pending = pool.protocol_fees_pending.saturating_add(amount);
+    save_pool(&pool)
+}
+
 // ============================================================================
 // USER BALANCE OPERATIONS
 // ============================================================================
 
 /// Get user's ICP balance
+///
+/// Amounts come from the accounting ledger; `ICP_BALANCES` keeps lifetime stats.
 pub fn get_user_icp_balance(user: Principal) -> ICPBalance {
-    ICP_BALANCES.with(|b| {
+    let mut balance = ICP_BALANCES.with(|b| {
         if let Some(ref map) = *b.borrow() {
             map.get(&user).unwrap_or_default()
         } else {
             ICPBalance::default()
         }
-    })
+    });
+    let (available, locked) = accounting::user_balance(user, &Asset::Icp);
+    balance.available = available;
+    balance.locked = locked;
+    balance
 }
 
-/// Credit ICP to user
-pub fn credit_user_icp(user: Principal, amount: u64) -> Result<u64, String> {
-    ICP_BALANCES.with(|b| {
-        if let Some(ref mut map) = *b.borrow_mut() {
-            let mut balance = map.get(&user).unwrap_or_default();
-            balance.available = balance.available.saturating_add(amount);
-            balance.updated_at = ic_cdk::api::time();
-            map.insert(user, balance.clone());
-            Ok(balance.available)
-        } else {
-            Err("ICP balance storage not initialized".to_string())
+/// Credit ICP to user's available balance from `from`
+pub fn credit_user_icp(
+    user: Principal,
+    amount: u64,
+    from: LedgerAccount,
+    reason: EntryReason,
+) -> Result<u64, String> {
+    accounting::post(Asset::Icp, from, LedgerAccount::Available(user), amount, reason.clone(), None)?;
+    update_icp_stats(user, |stats| match reason {
+        EntryReason::Deposit | EntryReason::ManualCredit => {
+            stats.total_deposited = stats.total_deposited.saturating_add(amount);
         }
-    })
+        EntryReason::WithdrawalRefund => {
+            stats.total_withdrawn = stats.total_withdrawn.saturating_sub(amount);
+        }
+        _ => {}
+    });
+    Ok(accounting::balance_of(&LedgerAccount::Available(user), &Asset::Icp))
+}
+
+/// Debit ICP from user's available balance to `to`
+pub fn debit_user_icp(
+    user: Principal,
+    amount: u64,
+    to: LedgerAccount,
+    reason: EntryReason,
+) -> Result<u64, String> {
+    accounting::post(Asset::Icp, LedgerAccount::Available(user), to, amount, reason.clone(), None)?;
+    update_icp_stats(user, |stats| {
+        if reason == EntryReason::Withdrawal {
+            stats.total_withdrawn = stats.total_withdrawn.saturating_add(amount);
+        }
+    });
+    Ok(accounting::balance_of(&LedgerAccount::Available(user), &Asset::Icp))
 }
 
-/// Debit ICP from user
-pub fn debit_user_icp(user: Principal, amount: u64) -> Result<u64, String> {
+fn update_icp_stats(user: Principal, update: impl FnOnce(&mut ICPBalance)) {
     ICP_BALANCES.with(|b| {
         if let Some(ref mut map) = *b.borrow_mut() {
-            let mut balance = map.get(&user).unwrap_or_default();
-            if balance.available < amount {
-                return Err(format!(
-                    "Insufficient ICP: have {}, need {}",
-                    balance.available, amount
-                ));
-            }
-            balance.available = balance.available.saturating_sub(amount);
-            balance.updated_at = ic_cdk::api::time();
-            map.insert(user, balance.clone());
-            Ok(balance.available)
-        } else {
-            Err("ICP balance storage not initialized".to_string())
+            let mut stats = map.get(&user).unwrap_or_default();
+            update(&mut stats);
+            stats.available = 0;
+            stats.locked = 0;
+            stats.updated_at = ic_cdk::api::time();
+            map.insert(user, stats);
         }
-    })
+    });
 }
 
 /// Get user's rune balance
+///
+/// Amounts come from the accounting ledger; `USER_BALANCES` keeps lifetime stats.
 pub fn get_user_rune_balance(user: Principal, rune_id: &str) -> UserBalance {
     let key = BalanceKey::new(user, rune_id);
-    USER_BALANCES.with(|b| {
+    let mut balance = USER_BALANCES.with(|b| {
         if let Some(ref map) = *b.borrow() {
             map.get(&key).unwrap_or_default()
         } else {
             UserBalance::default()
         }
-    })
+    });
+    let (available, locked) = accounting::user_balance(user, &Asset::Rune(rune_id.to_string()));
+    balance.available = available;
+    balance.locked = locked;
+    balance
+}
+
+/// Credit runes to user's available balance from `from`
+pub fn credit_user_runes(
+    user: Principal,
+    rune_id: &str,
+    amount: u64,
+    from: LedgerAccount,
+    reason: EntryReason,
+) -> Result<u64, String> {
+    let asset = Asset::Rune(rune_id.to_string());
+    accounting::post(asset.clone(), from, LedgerAccount::Available(user), amount, reason, None)?;
+    update_rune_stats(user, rune_id, |stats| {
+        stats.total_bought = stats.total_bought.saturating_add(amount);
+    });
+    Ok(accounting::balance_of(&LedgerAccount::Available(user), &asset))
+}
+
+/// Debit runes from user's available balance to `to`
+pub fn debit_user_runes(
+    user: Principal,
+    rune_id: &str,
+    amount: u64,
+    to: LedgerAccount,
+    reason: EntryReason,
+) -> Result<u64, String> {
+    let asset = Asset::Rune(rune_id.to_string());
+    accounting::post(asset.clone(), LedgerAccount::Available(user), to, amount, reason, None)?;
+    update_rune_stats(user, rune_id, |stats| {
+        stats.total_sold = stats.total_sold.saturating_add(amount);
+    });
+    Ok(accounting::balance_of(&LedgerAccount::Available(user), &asset))
 }
 
-/// Credit runes to user
-pub fn credit_user_runes(user: Principal, rune_id: &str, amount: u64) -> Result<u64, String> {
+fn update_rune_stats(user: Principal, rune_id: &str, update: impl FnOnce(&mut UserBalance)) {
     let key = BalanceKey::new(user, rune_id);
     USER_BALANCES.with(|b| {
         if let Some(ref mut map) = *b.borrow_mut() {
-            let mut balance = map.get(&key).unwrap_or_default();
-            balance.available = balance.available.saturating_add(amount);
-            balance.total_bought = balance.total_bought.saturating_add(amount);
-            balance.updated_at = ic_cdk::api::time();
-            map.insert(key, balance.clone());
-            Ok(balance.available)
-        } else {
-            Err("User balance storage not initialized".to_string())
+            let mut stats = map.get(&key).unwrap_or_default();
+            update(&mut stats);
+            stats.available = 0;
+            stats.locked = 0;
+            stats.updated_at = ic_cdk::api::time();
+            map.insert(key, stats);
         }
-    })
+    });
 }
 
-/// Debit runes from user
-pub fn debit_user_runes(user: Principal, rune_id: &str, amount: u64) -> Result<u64, String> {
-    let key = BalanceKey::new(user, rune_id);
-    USER_BALANCES.with(|b| {
-        if let Some(ref mut map) = *b.borrow_mut() {
-            let mut balance = map.get(&key).unwrap_or_default();
-            if balance.available < amount {
-                return Err(format!(
-                    "Insufficient runes: have {}, need {}",
-                    balance.available, amount
-                ));
+// ============================================================================
+// TRANSFERS
+// ============================================================================
+
+/// Maximum memo length for rune transfers (bytes)
+pub const MAX_TRANSFER_MEMO_LENGTH: usize = 32;
+
+/// Transfer runes from one user's available balance to another's
+///
+/// With `created_at_time` set, a retry with the same (sender, created_at_time,
+/// memo) inside the ICRC dedup window returns the original transfer event
+/// instead of moving the runes again.
+pub fn transfer_runes(
+    from: Principal,
+    to: Principal,
+    rune_id: &str,
+    amount: u64,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+) -> Result<TradeEvent, String> {
+    let now = ic_cdk::api::time();
+
+    if memo.as_ref().map(|m| m.len() > MAX_TRANSFER_MEMO_LENGTH).unwrap_or(false) {
+        return Err(format!("Memo exceeds {} bytes", MAX_TRANSFER_MEMO_LENGTH));
+    }
+
+    let dedup_key = match created_at_time {
+        Some(created_at) => {
+            let tx_hash = transfer_dedup_hash(from, created_at, memo.as_deref());
+            match icrc::check_recent_transaction(created_at, tx_hash, now) {
+                Ok(key) => Some(key),
+                Err(DedupError::Duplicate(event_id)) => {
+                    return get_trade_event(event_id)
+                        .ok_or_else(|| format!("Duplicate of transfer {}", event_id));
+                }
+                Err(DedupError::TooOld) => {
+                    return Err("created_at_time is too old".to_string());
+                }
+                Err(DedupError::CreatedInFuture(ledger_time)) => {
+                    return Err(format!("created_at_time is in the future (ledger time {})", ledger_time));
+                }
             }
-            balance.available = balance.available.saturating_sub(amount);
-            balance.total_sold = balance.total_sold.saturating_add(amount);
-            balance.updated_at = ic_cdk::api::time();
-            map.insert(key, balance.clone());
-            Ok(balance.available)
+        }
+        None => None,
+    };
+
+    let event_id = next_event_id();
+    balances::transfer_runes(from, to, rune_id, amount, Some(format!("transfer:{}", event_id)))?;
+
+    let pool = get_pool_by_rune_id(rune_id);
+    let event = TradeEvent {
+        id: event_id,
+        pool_id: pool.as_ref().map(|p| p.id.clone()).unwrap_or_else(|| PoolId::from_rune_id(rune_id)),
+        rune_id: rune_id.to_string(),
+        trader: from,
+        trade_type: TradeType::Transfer,
+        icp_amount: 0,
+        rune_amount: amount,
+        price_per_rune: 0,
+        fee: 0,
+        fees: None,
+        price_impact_bps: 0,
+        pool_icp_reserve_after: pool.as_ref().map(|p| p.icp_reserve).unwrap_or(0),
+        pool_rune_reserve_after: pool.as_ref().map(|p| p.rune_reserve).unwrap_or(0),
+        timestamp: now,
+        counterparty: Some(to),
+        memo,
+    };
+    store_trade_event(&event)?;
+    icrc::record_recent_transaction(dedup_key, event_id);
+
+    Ok(event)
+}
+
+/// Dedup hash for a transfer: (sender, created_at_time, memo)
+fn transfer_dedup_hash(from: Principal, created_at: u64, memo: Option<&[u8]>) -> [u8; 32] {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    hasher.update(b"transfer_virtual_rune");
+    hasher.update([from.as_slice().len() as u8]);
+    hasher.update(from.as_slice());
+    hasher.update(created_at.to_be_bytes());
+    if let Some(memo) = memo {
+        hasher.update([1u8]);
+        hasher.update(memo);
+    }
+    hasher.finalize().into()
+}
+
+/// Rune IDs by the hash used in balance keys and pool IDs
+fn known_rune_ids() -> std::collections::HashMap<[u8; 32], String> {
+    let mut rune_ids = std::collections::HashMap::new();
+    for rune in crate::state::get_all_virtual_runes(0, u64::MAX) {
+        rune_ids.insert(PoolId::from_rune_id(&rune.id).0, rune.id);
+    }
+    for pool in list_pools(0, u64::MAX) {
+        rune_ids.insert(pool.id.0, pool.rune_id);
+    }
+    rune_ids
+}
+
+/// Move pool reserves and pending protocol fees into the accounting ledger
+///
+/// Runs once, on the first upgrade that introduces the ledger; both are
+/// recorded as held by the engine.
+pub fn migrate_pools_to_accounting() -> Result<u64, String> {
+    let mut posted = 0u64;
+    for pool in list_pools(0, u64::MAX) {
+        let account = LedgerAccount::Pool(pool.rune_id.clone());
+        posted += post_migration(Asset::Icp, account.clone(), pool.icp_reserve)?;
+        posted += post_migration(Asset::Rune(pool.rune_id.clone()), account, pool.rune_reserve)?;
+        posted += post_migration(Asset::Icp, LedgerAccount::ProtocolFees, pool.protocol_fees_pending)?;
+    }
+    Ok(posted)
+}
+
+/// Move user balances held in the V2 stable maps into the accounting ledger
+///
+/// Each record is zeroed once posted, so this is safe to run on every
+/// upgrade and resumes where an earlier run stopped. Balances whose rune ID
+/// can't be resolved are left in place for a later run; returns the number
+/// of postings and of records skipped.
+pub fn migrate_user_balances_to_accounting() -> Result<(u64, u64), String> {
+    let mut posted = 0u64;
+    let mut skipped = 0u64;
+    let rune_ids = known_rune_ids();
+
+    let icp_balances: Vec<(Principal, ICPBalance)> = ICP_BALANCES.with(|b| {
+        b.borrow().as_ref().map(|map| map.iter().collect()).unwrap_or_default()
+    });
+    for (user, mut balance) in icp_balances {
+        if balance.available == 0 && balance.locked == 0 {
+            continue;
+        }
+        posted += post_migration(Asset::Icp, LedgerAccount::Available(user), balance.available)?;
+        posted += post_migration(Asset::Icp, LedgerAccount::Locked(user), balance.locked)?;
+        balance.available = 0;
+        balance.locked = 0;
+        ICP_BALANCES.with(|b| {
+            if let Some(ref mut map) = *b.borrow_mut() {
+                map.insert(user, balance);
+            }
+        });
+    }
+
+    let rune_balances: Vec<(BalanceKey, UserBalance)> = USER_BALANCES.with(|b| {
+        b.borrow().as_ref().map(|map| map.iter().collect()).unwrap_or_default()
+    });
+    for (key, mut balance) in rune_balances {
+        if balance.available == 0 && balance.locked == 0 {
+            continue;
+        }
+        let Some(rune_id) = rune_ids.get(&key.rune_id_hash) else {
+            crate::logging::log_error(
+                "accounting",
+                format!(
+                    "Cannot migrate rune balance of {}: unknown rune hash {}",
+                    key.user,
+                    hex::encode(key.rune_id_hash)
+                ),
+                None,
+            );
+            skipped += 1;
+            continue;
+        };
+        let asset = Asset::Rune(rune_id.clone());
+        posted += post_migration(asset.clone(), LedgerAccount::Available(key.user), balance.available)?;
+        posted += post_migration(asset, LedgerAccount::Locked(key.user), balance.locked)?;
+        balance.available = 0;
+        balance.locked = 0;
+        USER_BALANCES.with(|b| {
+            if let Some(ref mut map) = *b.borrow_mut() {
+                map.insert(key, balance);
+            }
+        });
+    }
+
+    Ok((posted, skipped))
+}
+
+/// Post one migrated amount from custody; returns the number of postings
+fn post_migration(asset: Asset, to: LedgerAccount, amount: u64) -> Result<u64, String> {
+    if amount == 0 {
+        return Ok(0);
+    }
+    accounting::post(asset, LedgerAccount::Custody, to, amount, EntryReason::Migration, None)?;
+    Ok(1)
+}
+
+/// Get a page of a user's rune balances, ordered by rune ID
+pub fn get_user_all_rune_balances(user: Principal, offset: u64, limit: u64) -> Vec<(String, UserBalance)> {
+    let rune_ids: Vec<String> = USER_RUNE_INDEX.with(|i| {
+        if let Some(ref index) = *i.borrow() {
+            let start = UserRuneKey { user, rune_id: String::new() };
+            index
+                .range(start..)
+                .take_while(|(key, _)| key.user == user)
+                .skip(offset as usize)
+                .take(limit as usize)
+                .map(|(key, _)| key.rune_id)
+                .collect()
+        } else {
+            vec![]
+        }
+    });
+
+    rune_ids
+        .into_iter()
+        .map(|rune_id| {
+            let balance = get_user_rune_balance(user, &rune_id);
+            (rune_id, balance)
+        })
+        .collect()
+}
+
+/// Number of runes a user holds a balance in
+pub fn get_user_rune_count(user: Principal) -> u64 {
+    USER_RUNE_INDEX.with(|i| {
+        if let Some(ref index) = *i.borrow() {
+            let start = UserRuneKey { user, rune_id: String::new() };
+            index.range(start..).take_while(|(key, _)| key.user == user).count() as u64
         } else {
-            Err("User balance storage not initialized".to_string())
+            0
         }
     })
 }
 
-/// Get all rune balances for a user
-/// Note: This is expensive as it requires iterating all balances.
-/// In production, consider maintaining a separate user -> rune_ids index.
-pub fn get_user_all_rune_balances(_user: Principal) -> Vec<(String, UserBalance)> {
-    // TODO: Implement with a separate index mapping user -> list of rune_ids
-    // For now, we return an empty list as we don't store rune_id in the balance
-    USER_BALANCES.with(|_b| {
-        // We can't efficiently filter by principal without a secondary index
-        // since BalanceKey includes both user AND rune_id_hash
-        vec![]
+/// Keep the user -> rune index in step with the user's ledger balance
+///
+/// Called by the accounting ledger whenever a user's available or locked
+/// rune balance changes, so every credit and debit path (trades, transfers,
+/// ICRC calls, locks) is covered. Runes with no remaining balance drop out.
+pub(crate) fn sync_user_rune_index(user: Principal, rune_id: &str) {
+    let (available, locked) = accounting::user_balance(user, &Asset::Rune(rune_id.to_string()));
+    let key = UserRuneKey { user, rune_id: rune_id.to_string() };
+    USER_RUNE_INDEX.with(|i| {
+        if let Some(ref mut index) = *i.borrow_mut() {
+            if available > 0 || locked > 0 {
+                index.insert(key, ());
+            } else {
+                index.remove(&key);
+            }
+        }
+    });
+}
+
+/// Build the user -> rune index from the accounting ledger
+///
+/// Runs on upgrade while the index is empty (i.e. the first upgrade that
+/// introduces it). Returns the number of entries indexed.
+pub fn rebuild_user_rune_index() -> u64 {
+    let is_empty = USER_RUNE_INDEX.with(|i| i.borrow().as_ref().map(|index| index.is_empty()).unwrap_or(false));
+    if !is_empty {
+        return 0;
+    }
+    let holdings = accounting::user_rune_holdings();
+    USER_RUNE_INDEX.with(|i| {
+        if let Some(ref mut index) = *i.borrow_mut() {
+            for (user, rune_id) in holdings {
+                index.insert(UserRuneKey { user, rune_id }, ());
+            }
+            index.len()
+        } else {
+            0
+        }
     })
 }
 
@@ -1338,12 +2628,12 @@ pub fn get_trade_events(rune_id: &str, limit: u64) -> Vec<TradeEvent> {
     })
 }
 
-/// Get trade events for a user
+/// Get trade events for a user (including transfers they received)
 pub fn get_user_trade_events(user: Principal, limit: u64) -> Vec<TradeEvent> {
     TRADE_EVENTS.with(|e| {
         if let Some(ref map) = *e.borrow() {
             map.iter()
-                .filter(|(_, event)| event.trader == user)
+                .filter(|(_, event)| event.trader == user || event.counterparty == Some(user))
                 .map(|(_, event)| event)
                 .rev()
                 .take(limit as usize)
@@ -1354,6 +2644,11 @@ pub fn get_user_trade_events(user: Principal, limit: u64) -> Vec<TradeEvent> {
     })
 }
 
+/// Get a trade event by ID
+pub fn get_trade_event(id: u64) -> Option<TradeEvent> {
+    TRADE_EVENTS.with(|e| e.borrow().as_ref().and_then(|map| map.get(&EventId(id))))
+}
+
 /// Get total event count
 pub fn get_event_count() -> u64 {
     TRADE_EVENTS.with(|e| {
@@ -1397,8 +2692,16 @@ pub fn add_liquidity(
     }
 
     // Verify and debit user balances
-    debit_user_icp(provider, icp_amount)?;
-    debit_user_runes(provider, rune_id, runes_needed)?;
+    let user_runes = get_user_rune_balance(provider, rune_id);
+    if user_runes.available < runes_needed {
+        return Err(format!(
+            "Insufficient runes: have {}, need {}",
+            user_runes.available, runes_needed
+        ));
+    }
+    let pool_account = LedgerAccount::Pool(rune_id.to_string());
+    debit_user_icp(provider, icp_amount, pool_account.clone(), EntryReason::LiquidityAdd)?;
+    debit_user_runes(provider, rune_id, runes_needed, pool_account, EntryReason::LiquidityAdd)?;
 
     // Calculate LP tokens to mint
     let lp_tokens = if pool.total_lp_supply == 0 {
@@ -1421,6 +2724,7 @@ pub fn add_liquidity(
         owner: provider,
     };
     let mut position = get_lp_position(&pool.id, provider).unwrap_or_default();
+    position.accrue_fees(&pool);
     position.lp_balance = position.lp_balance.saturating_add(lp_tokens);
     position.icp_deposited = position.icp_deposited.saturating_add(icp_amount);
     position.runes_deposited = position.runes_deposited.saturating_add(runes_needed);
@@ -1482,11 +2786,20 @@ pub fn remove_liquidity(
         owner: provider,
     };
     let mut updated_position = position.clone();
+    updated_position.accrue_fees(&pool);
+    // Reduce the cost basis in proportion to the LP tokens burned
+    updated_position.icp_deposited = updated_position.icp_deposited.saturating_sub(
+        (position.icp_deposited as u128 * lp_amount as u128 / position.lp_balance as u128) as u64,
+    );
+    updated_position.runes_deposited = updated_position.runes_deposited.saturating_sub(
+        (position.runes_deposited as u128 * lp_amount as u128 / position.lp_balance as u128) as u64,
+    );
     updated_position.lp_balance = updated_position.lp_balance.saturating_sub(lp_amount);
     updated_position.updated_at = now;
 
     if updated_position.lp_balance == 0 {
-        // Remove position entirely
+        // Pay out unclaimed fees, then remove position entirely
+        committed(pay_lp_fees(rune_id, provider, &mut updated_position, now));
         LP_POSITIONS.with(|l| {
             if let Some(ref mut map) = *l.borrow_mut() {
                 map.remove(&lp_key);
@@ -1497,12 +2810,209 @@ pub fn remove_liquidity(
     }
 
     // Credit user
-    credit_user_icp(provider, icp_out)?;
-    credit_user_runes(provider, rune_id, runes_out)?;
+    let pool_account = LedgerAccount::Pool(rune_id.to_string());
+    credit_user_icp(provider, icp_out, pool_account.clone(), EntryReason::LiquidityRemove)?;
+    credit_user_runes(provider, rune_id, runes_out, pool_account, EntryReason::LiquidityRemove)?;
 
     Ok((icp_out, runes_out))
 }
 
+/// Move a position's accrued LP fees from the pool account to the provider
+///
+/// LP fees stay in the pool account, outside the reserves, until paid out.
+/// The position must already be accrued against the pool.
+fn pay_lp_fees(
+    rune_id: &str,
+    provider: Principal,
+    position: &mut LPPosition,
+    now: u64,
+) -> Result<(u64, u64), String> {
+    let icp = position.rewards_earned;
+    let runes = position.rune_rewards_earned.unwrap_or(0);
+    let pool_account = LedgerAccount::Pool(rune_id.to_string());
+    let rune_asset = Asset::Rune(rune_id.to_string());
+    if accounting::balance_of(&pool_account, &Asset::Icp) < icp
+        || accounting::balance_of(&pool_account, &rune_asset) < runes
+    {
+        return Err("Pool does not hold the accrued LP fees".to_string());
+    }
+
+    if icp > 0 {
+        accounting::post(Asset::Icp, pool_account.clone(), LedgerAccount::Available(provider), icp, EntryReason::LpFeeClaim, None)?;
+    }
+    if runes > 0 {
+        committed(accounting::post(rune_asset, pool_account, LedgerAccount::Available(provider), runes, EntryReason::LpFeeClaim, None));
+    }
+    position.rewards_earned = 0;
+    position.rune_rewards_earned = Some(0);
+    position.last_reward_claim = now;
+    Ok((icp, runes))
+}
+
+/// Pay a provider's accrued LP fees into their available balances
+///
+/// Returns the ICP and runes paid out.
+pub fn claim_lp_fees(rune_id: &str, provider: Principal, now: u64) -> Result<(u64, u64), String> {
+    let pool = get_pool_by_rune_id(rune_id).ok_or("Pool not found")?;
+    let mut position = get_lp_position(&pool.id, provider).ok_or("No LP position found")?;
+    position.accrue_fees(&pool);
+    if position.rewards_earned == 0 && position.rune_rewards_earned.unwrap_or(0) == 0 {
+        return Err("No LP fees to claim".to_string());
+    }
+
+    let claimed = pay_lp_fees(rune_id, provider, &mut position, now)?;
+    position.updated_at = now;
+    let lp_key = LPPositionKey {
+        pool_id: pool.id.clone(),
+        owner: provider,
+    };
+    committed(save_lp_position(&lp_key, &position));
+    Ok(claimed)
+}
+
+// ============================================================================
+// LAUNCH PROTECTION TRACKING
+// ============================================================================
+
+/// Get a principal's buy activity during a pool's launch window
+pub fn get_launch_buy_state(pool_id: &PoolId, buyer: Principal) -> Option<LaunchBuyState> {
+    let key = LPPositionKey {
+        pool_id: pool_id.clone(),
+        owner: buyer,
+    };
+    LAUNCH_BUYS.with(|l| l.borrow().as_ref().and_then(|map| map.get(&key)))
+}
+
+/// Record a buy made during a pool's launch window
+fn record_launch_buy(pool_id: &PoolId, buyer: Principal, icp_amount: u64, now: u64) -> Result<(), String> {
+    let key = LPPositionKey {
+        pool_id: pool_id.clone(),
+        owner: buyer,
+    };
+    LAUNCH_BUYS.with(|l| {
+        if let Some(ref mut map) = *l.borrow_mut() {
+            let mut state = map.get(&key).unwrap_or_default();
+            state.icp_spent = state.icp_spent.saturating_add(icp_amount);
+            state.last_buy_at = now;
+            map.insert(key, state);
+            Ok(())
+        } else {
+            Err("Launch storage not initialized".to_string())
+        }
+    })
+}
+
+// ============================================================================
+// LP POSITION STATS
+// ============================================================================
+
+/// Earnings and performance of an LP position
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LPPositionStats {
+    pub rune_id: String,
+    pub lp_balance: u64,
+    /// Share of the pool's LP supply (basis points)
+    pub pool_share_bps: u64,
+    /// ICP currently redeemable for the LP balance (e8s)
+    pub underlying_icp: u64,
+    /// Runes currently redeemable for the LP balance
+    pub underlying_runes: u64,
+    /// Cost basis of the current LP balance
+    pub icp_deposited: u64,
+    pub runes_deposited: u64,
+    /// LP fees earned in ICP (e8s)
+    pub earned_fees_icp: u64,
+    /// LP fees earned in runes
+    pub earned_fees_runes: u64,
+    /// Value of the deposited amounts at the current price, had they been held (e8s)
+    pub hold_value_icp: u64,
+    /// Value of the underlying amounts at the current price, excluding fees (e8s)
+    pub position_value_icp: u64,
+    /// Impermanent loss against holding (basis points, positive = loss)
+    pub impermanent_loss_bps: i64,
+}
+
+/// Compute fee earnings, underlying amounts and impermanent loss for a position
+pub fn get_lp_position_stats(rune_id: &str, owner: Principal) -> Result<LPPositionStats, String> {
+    let pool = get_pool_by_rune_id(rune_id).ok_or("Pool not found")?;
+    let mut position = get_lp_position(&pool.id, owner).ok_or("No LP position found")?;
+    position.accrue_fees(&pool);
+
+    let (underlying_icp, underlying_runes, pool_share_bps) = if pool.total_lp_supply == 0 {
+        (0, 0, 0)
+    } else {
+        let supply = pool.total_lp_supply as u128;
+        let lp = position.lp_balance as u128;
+        (
+            (pool.icp_reserve as u128 * lp / supply) as u64,
+            (pool.rune_reserve as u128 * lp / supply) as u64,
+            (lp * 10_000 / supply) as u64,
+        )
+    };
+
+    let hold_value_icp = position
+        .icp_deposited
+        .saturating_add(runes_to_icp_value(&pool, position.runes_deposited));
+    let position_value_icp = underlying_icp.saturating_add(runes_to_icp_value(&pool, underlying_runes));
+
+    Ok(LPPositionStats {
+        rune_id: rune_id.to_string(),
+        lp_balance: position.lp_balance,
+        pool_share_bps,
+        underlying_icp,
+        underlying_runes,
+        icp_deposited: position.icp_deposited,
+        runes_deposited: position.runes_deposited,
+        earned_fees_icp: position.rewards_earned,
+        earned_fees_runes: position.rune_rewards_earned.unwrap_or(0),
+        hold_value_icp,
+        position_value_icp,
+        impermanent_loss_bps: impermanent_loss_bps(hold_value_icp, position_value_icp),
+    })
+}
+
+/// Value a rune amount in ICP e8s at the pool's current marginal price
+fn runes_to_icp_value(pool: &TradingPool, rune_amount: u64) -> u64 {
+    let (effective_icp, effective_runes) = match pool.pool_type {
+        PoolType::Bonding => (
+            pool.icp_reserve + pool.virtual_icp_reserve,
+            pool.rune_reserve + pool.virtual_rune_reserve,
+        ),
+        PoolType::AMM => (pool.icp_reserve, pool.rune_reserve),
+    };
+    if effective_runes == 0 {
+        return 0;
+    }
+    (rune_amount as u128 * effective_icp as u128 / effective_runes as u128).min(u64::MAX as u128) as u64
+}
+
+/// Fee growth per LP token for a fee distributed over `total_lp_supply`
+fn fee_growth_delta(fee: u64, total_lp_supply: u64) -> u128 {
+    if total_lp_supply == 0 {
+        return 0;
+    }
+    ((fee as u128) << FEE_GROWTH_FRACTION_BITS) / total_lp_supply as u128
+}
+
+/// Fees earned by `lp_balance` tokens between two fee growth readings
+fn accrued_fees(lp_balance: u64, growth_now: u128, growth_snapshot: u128) -> u64 {
+    let delta = growth_now.wrapping_sub(growth_snapshot);
+    let lp = lp_balance as u128;
+    // Split the multiplication so lp * delta cannot overflow u128
+    let whole = (delta >> FEE_GROWTH_FRACTION_BITS) * lp;
+    let fraction = ((delta & u64::MAX as u128) * lp) >> FEE_GROWTH_FRACTION_BITS;
+    whole.saturating_add(fraction).min(u64::MAX as u128) as u64
+}
+
+/// Loss of the position against holding, in basis points of the hold value
+fn impermanent_loss_bps(hold_value: u64, position_value: u64) -> i64 {
+    if hold_value == 0 {
+        return 0;
+    }
+    let diff = hold_value as i128 - position_value as i128;
+    (diff * 10_000 / hold_value as i128) as i64
+}
+
 // ============================================================================
 // UTILITY FUNCTIONS
 // ============================================================================
@@ -1548,7 +3058,7 @@ mod tests {
 
     #[test]
     fn test_fee_calculation() {
-        let amount = 1_00_000_000u64; // 1 ICP
+        let amount = 100_000_000u64; // 1 ICP
         let total_fee = (amount * TRADING_FEE_BPS) / 10_000;
         assert_eq!(total_fee, 300_000); // 0.3%
 
@@ -1559,6 +3069,364 @@ mod tests {
         assert_eq!(lp_fee, 200_000); // 0.2%
     }
 
+    #[test]
+    fn test_pool_fees_split() {
+        let fees = PoolFees::standard();
+        assert_eq!(fees.split(100_000_000), (300_000, 100_000, 200_000));
+        assert_eq!(fees.lp_fee_bps(), LP_FEE_BPS);
+
+        let bonding = PoolFees {
+            tier_id: 1,
+            trading_fee_bps: 100,
+            protocol_fee_bps: 40,
+        };
+        assert_eq!(bonding.split(100_000_000), (1_000_000, 400_000, 600_000));
+
+        // No overflow on large amounts
+        let (total, protocol, lp) = bonding.split(u64::MAX);
+        assert_eq!(total, protocol + lp);
+    }
+
+    #[test]
+    fn test_fee_tier_validation() {
+        assert!(FeeTier::standard().validate().is_ok());
+
+        let mut tier = FeeTier {
+            id: 1,
+            name: "Bonding".to_string(),
+            trading_fee_bps: 100,
+            protocol_fee_bps: 40,
+            graduated_tier_id: Some(DEFAULT_FEE_TIER_ID),
+        };
+        assert!(tier.validate().is_ok());
+        assert_eq!(tier.lp_fee_bps(), 60);
+
+        tier.protocol_fee_bps = 200;
+        assert!(tier.validate().is_err());
+
+        tier.protocol_fee_bps = 40;
+        tier.trading_fee_bps = MAX_TRADING_FEE_BPS + 1;
+        assert!(tier.validate().is_err());
+
+        tier.trading_fee_bps = 100;
+        tier.graduated_tier_id = Some(1);
+        assert!(tier.validate().is_err());
+    }
+
+    #[test]
+    fn test_pool_keeps_fees_copied_from_its_tier() {
+        let bonding = PoolFees {
+            tier_id: 1,
+            trading_fee_bps: 100,
+            protocol_fee_bps: 40,
+        };
+        let mut pool = TradingPool::default();
+        pool.assign_fees(bonding.clone(), Some(PoolFees::standard()));
+
+        // Fees come from the copy, not from the (uninitialized) tier store
+        assert_eq!(pool.fee_tier_id(), 1);
+        assert_eq!(pool.fees(), bonding);
+
+        // Graduation moves to the copied graduated fees, once
+        assert!(pool.enter_graduated_fees());
+        assert_eq!(pool.fees(), PoolFees::standard());
+        assert_eq!(pool.fee_tier_id(), DEFAULT_FEE_TIER_ID);
+        assert!(pool.graduated_fees.is_none());
+        assert!(pool.enter_graduated_fees());
+        assert_eq!(pool.fees(), PoolFees::standard());
+
+        // Pools from before fees were copied still resolve the tier
+        assert!(!TradingPool::default().enter_graduated_fees());
+    }
+
+    #[test]
+    fn test_fee_growth_accrual() {
+        // 1 ICP of LP fees over 1,000 LP tokens
+        let growth = fee_growth_delta(100_000_000, 1_000);
+        assert_eq!(accrued_fees(1_000, growth, 0), 100_000_000);
+        assert_eq!(accrued_fees(250, growth, 0), 25_000_000);
+
+        // A position that joined after the fee earns nothing from it
+        assert_eq!(accrued_fees(250, growth, growth), 0);
+
+        // No LP supply, no growth
+        assert_eq!(fee_growth_delta(100_000_000, 0), 0);
+
+        // Accumulator wrap-around still yields the correct delta
+        let snapshot = u128::MAX - 10;
+        let now = snapshot.wrapping_add(growth);
+        assert_eq!(accrued_fees(1_000, now, snapshot), 100_000_000);
+    }
+
+    #[test]
+    fn test_position_accrues_fees_before_balance_change() {
+        let mut pool = TradingPool {
+            total_lp_supply: 1_000,
+            ..Default::default()
+        };
+        let mut position = LPPosition {
+            lp_balance: 500,
+            ..Default::default()
+        };
+
+        pool.record_lp_fees(10_000, 0);
+        position.accrue_fees(&pool);
+        assert_eq!(position.rewards_earned, 5_000);
+        assert_eq!(pool.fees_collected_icp, 10_000);
+
+        // Accruing again without new fees changes nothing
+        position.accrue_fees(&pool);
+        assert_eq!(position.rewards_earned, 5_000);
+        assert_eq!(position.rune_rewards_earned, Some(0));
+    }
+
+    #[test]
+    fn test_impermanent_loss_bps() {
+        assert_eq!(impermanent_loss_bps(1_000, 1_000), 0);
+        assert_eq!(impermanent_loss_bps(1_000, 950), 500);
+        assert_eq!(impermanent_loss_bps(1_000, 1_100), -1_000);
+        assert_eq!(impermanent_loss_bps(0, 100), 0);
+    }
+
+    #[test]
+    fn test_launch_rules_validation() {
+        assert!(LaunchRules::default().validate().is_ok());
+        assert!(LaunchRules {
+            start_delay_secs: 60,
+            protection_window_minutes: 10,
+            max_buy_icp_per_principal: 100_000_000,
+            buy_cooldown_secs: 30,
+        }
+        .validate()
+        .is_ok());
+
+        // Cap without a window
+        assert!(LaunchRules {
+            max_buy_icp_per_principal: 100_000_000,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+
+        // Cooldown longer than the window
+        assert!(LaunchRules {
+            protection_window_minutes: 1,
+            buy_cooldown_secs: 120,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+
+        assert!(LaunchRules {
+            start_delay_secs: MAX_LAUNCH_START_DELAY_SECS + 1,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_launch_rules_check_buy() {
+        let sec = NANOS_PER_SEC;
+        let created_at = 1_000 * sec;
+        let rules = LaunchRules {
+            start_delay_secs: 60,
+            protection_window_minutes: 5,
+            max_buy_icp_per_principal: 1_000,
+            buy_cooldown_secs: 30,
+        };
+        let opens = created_at + 60 * sec;
+
+        // Before the start delay elapses
+        assert!(rules.check_buy(created_at, opens - 1, 100, None).is_err());
+        assert!(rules.check_buy(created_at, opens, 100, None).is_ok());
+
+        // Per-principal cap
+        assert!(rules.check_buy(created_at, opens, 1_001, None).is_err());
+        let prior = LaunchBuyState {
+            icp_spent: 900,
+            last_buy_at: opens,
+        };
+        assert!(rules.check_buy(created_at, opens + 30 * sec, 100, Some(&prior)).is_ok());
+        assert!(rules.check_buy(created_at, opens + 30 * sec, 101, Some(&prior)).is_err());
+
+        // Cooldown between buys
+        assert!(rules.check_buy(created_at, opens + 29 * sec, 10, Some(&prior)).is_err());
+
+        // Limits lift once the window ends
+        let window_end = rules.protection_ends_at(created_at);
+        assert_eq!(window_end, opens + 300 * sec);
+        assert!(rules.check_buy(created_at, window_end, 10_000, Some(&prior)).is_ok());
+    }
+
+    fn sample_quote() -> TradeQuote {
+        TradeQuote {
+            rune_id: "840000:1".to_string(),
+            trade_type: TradeType::Buy,
+            input_amount: 100_000_000,
+            output_amount: 1_000_000,
+            price_per_rune: 100,
+            fee: 300_000,
+            protocol_fee: 100_000,
+            lp_fee: 200_000,
+            fees: PoolFees::standard(),
+            price_impact_bps: 10,
+            minimum_output: 990_000,
+            pool_icp_reserve: 1_000_000_000,
+            pool_rune_reserve: 50_000_000,
+            effective_price: 100.0,
+        }
+    }
+
+    #[test]
+    fn test_signed_quote_roundtrip() {
+        let key = [7u8; 32];
+        let quote = sample_quote();
+        let pinned = PinnedQuote::from_quote(&quote, 100, 1_000);
+        let quote_id = pinned.sign(&key);
+
+        assert_eq!(PinnedQuote::verify(&quote_id, &key), Ok(pinned.clone()));
+        assert_eq!(pinned.expires_at, 1_000 + QUOTE_TTL_NANOS);
+
+        // Wrong key or tampered fields are rejected
+        assert!(matches!(
+            PinnedQuote::verify(&quote_id, &[8u8; 32]),
+            Err(TradeError::InvalidQuote { .. })
+        ));
+        let mut tampered = hex::decode(&quote_id).unwrap();
+        tampered[40] ^= 1;
+        assert!(matches!(
+            PinnedQuote::verify(&hex::encode(tampered), &key),
+            Err(TradeError::InvalidQuote { .. })
+        ));
+        assert!(PinnedQuote::verify("zz", &key).is_err());
+    }
+
+    #[test]
+    fn test_signed_quote_check() {
+        let quote = sample_quote();
+        let pinned = PinnedQuote::from_quote(&quote, 100, 0);
+        assert!(pinned.check(&quote, 1).is_ok());
+
+        // Expired
+        assert!(matches!(
+            pinned.check(&quote, QUOTE_TTL_NANOS + 1),
+            Err(TradeError::QuoteExpired { .. })
+        ));
+
+        // Within 1% drift tolerance
+        let mut moved = quote.clone();
+        moved.output_amount = 990_000;
+        assert!(pinned.check(&moved, 1).is_ok());
+
+        // Beyond tolerance
+        moved.output_amount = 989_999;
+        assert!(matches!(pinned.check(&moved, 1), Err(TradeError::PriceDrift { .. })));
+
+        // Different input amount or fees
+        let mut other = quote.clone();
+        other.input_amount += 1;
+        assert!(matches!(pinned.check(&other, 1), Err(TradeError::QuoteMismatch { .. })));
+        let mut other = quote;
+        other.fees.trading_fee_bps = 100;
+        assert!(matches!(pinned.check(&other, 1), Err(TradeError::QuoteMismatch { .. })));
+    }
+
+    #[test]
+    fn test_user_rune_index_follows_ledger() {
+        use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        accounting::init_accounting_storage(manager.get(MemoryId::new(0)), manager.get(MemoryId::new(1)));
+        init_user_rune_index_storage(manager.get(MemoryId::new(2)));
+
+        let alice = Principal::from_slice(&[1; 29]);
+        let bob = Principal::from_slice(&[2; 29]);
+        let rune = |id: &str| Asset::Rune(id.to_string());
+        for id in ["c", "a", "b"] {
+            accounting::post(rune(id), LedgerAccount::Custody, LedgerAccount::Available(alice), 10, EntryReason::Premine, None).unwrap();
+        }
+        accounting::post(rune("a"), LedgerAccount::Custody, LedgerAccount::Available(bob), 5, EntryReason::Premine, None).unwrap();
+
+        let ids = |page: Vec<(String, UserBalance)>| page.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+        assert_eq!(get_user_rune_count(alice), 3);
+        assert_eq!(ids(get_user_all_rune_balances(alice, 0, 2)), vec!["a", "b"]);
+        assert_eq!(ids(get_user_all_rune_balances(alice, 2, 2)), vec!["c"]);
+        assert_eq!(get_user_all_rune_balances(alice, 0, 1)[0].1.available, 10);
+
+        // Locked runes stay listed; emptied runes drop out
+        accounting::post(rune("b"), LedgerAccount::Available(alice), LedgerAccount::Locked(alice), 10, EntryReason::Lock, None).unwrap();
+        accounting::post(rune("a"), LedgerAccount::Available(alice), LedgerAccount::Available(bob), 10, EntryReason::Transfer, None).unwrap();
+        assert_eq!(ids(get_user_all_rune_balances(alice, 0, 10)), vec!["b", "c"]);
+        assert_eq!(get_user_all_rune_balances(bob, 0, 10)[0].1.available, 15);
+
+        // Rebuild only runs on an empty index
+        assert_eq!(rebuild_user_rune_index(), 0);
+    }
+
+    #[test]
+    fn test_claim_lp_fees_pays_provider() {
+        use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        accounting::init_accounting_storage(manager.get(MemoryId::new(0)), manager.get(MemoryId::new(1)));
+        init_trading_storage(
+            manager.get(MemoryId::new(2)),
+            manager.get(MemoryId::new(3)),
+            manager.get(MemoryId::new(4)),
+            manager.get(MemoryId::new(5)),
+            manager.get(MemoryId::new(6)),
+            manager.get(MemoryId::new(7)),
+        );
+
+        let alice = Principal::from_slice(&[1; 29]);
+        let bob = Principal::from_slice(&[2; 29]);
+        let rune_id = "lp-fees";
+        let pool_account = LedgerAccount::Pool(rune_id.to_string());
+        let mut pool = TradingPool {
+            id: PoolId::from_rune_id(rune_id),
+            rune_id: rune_id.to_string(),
+            icp_reserve: 1_000_000,
+            total_lp_supply: 1_000,
+            ..Default::default()
+        };
+        for (owner, lp_balance) in [(alice, 750), (bob, 250)] {
+            let key = LPPositionKey { pool_id: pool.id.clone(), owner };
+            let position = LPPosition { lp_balance, ..Default::default() };
+            save_lp_position(&key, &position).unwrap();
+        }
+
+        // A trade leaves 10_000 e8s of LP fees in the pool account, outside the reserves
+        accounting::post(Asset::Icp, LedgerAccount::Custody, pool_account.clone(), 1_010_000, EntryReason::PoolCreation, None).unwrap();
+        pool.record_lp_fees(10_000, 0);
+        save_pool(&pool).unwrap();
+
+        assert_eq!(claim_lp_fees(rune_id, alice, 7), Ok((7_500, 0)));
+        assert_eq!(accounting::balance_of(&LedgerAccount::Available(alice), &Asset::Icp), 7_500);
+        assert_eq!(accounting::balance_of(&pool_account, &Asset::Icp), 1_002_500);
+
+        let position = get_lp_position(&pool.id, alice).unwrap();
+        assert_eq!(position.rewards_earned, 0);
+        assert_eq!(position.last_reward_claim, 7);
+        assert!(claim_lp_fees(rune_id, alice, 8).is_err());
+
+        // Bob's share is still there for him
+        assert_eq!(get_lp_position_stats(rune_id, bob).unwrap().earned_fees_icp, 2_500);
+        assert_eq!(claim_lp_fees(rune_id, bob, 9), Ok((2_500, 0)));
+        assert_eq!(accounting::balance_of(&pool_account, &Asset::Icp), pool.icp_reserve);
+    }
+
+    #[test]
+    fn test_transfer_dedup_hash() {
+        let alice = Principal::from_slice(&[1; 29]);
+        let bob = Principal::from_slice(&[2; 29]);
+        let base = transfer_dedup_hash(alice, 100, Some(b"rent"));
+
+        assert_eq!(base, transfer_dedup_hash(alice, 100, Some(b"rent")));
+        assert_ne!(base, transfer_dedup_hash(bob, 100, Some(b"rent")));
+        assert_ne!(base, transfer_dedup_hash(alice, 101, Some(b"rent")));
+        assert_ne!(base, transfer_dedup_hash(alice, 100, Some(b"food")));
+        assert_ne!(transfer_dedup_hash(alice, 100, None), transfer_dedup_hash(alice, 100, Some(b"")));
+    }
+
     #[test]
     fn test_integer_sqrt() {
         assert_eq!(0u128.integer_sqrt(), 0);
@@ -0,0 +1,504 @@
+// ============================================================================
+// Treasury Module - Protocol Fee Collection
+// ============================================================================
+//
+// Moves the protocol share of trading fees (`protocol_fees_pending` on each
+// trading V2 pool) to the configured treasury account.
+//
+// Key Features:
+// - Treasury destination stored in StableCell (principal or ICRC-1 account)
+// - Collection of a single pool or all pools in one ledger transfer
+// - Auditable collection history in stable memory
+// - Per-pool lifetime totals
+//
+// Collections are admin-only; the RBAC check lives in lib.rs.
+//
+// ============================================================================
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::memory_manager::VirtualMemory;
+use ic_stable_structures::{storable::Bound, DefaultMemoryImpl, StableBTreeMap, StableCell, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use crate::ledger::{self, Account, ICP_TRANSFER_FEE};
+use crate::trading_v2::{self, PoolId};
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Memo attached to every treasury transfer
+const COLLECTION_MEMO: &[u8] = b"protocol-fees";
+
+// ============================================================================
+// Types
+// ============================================================================
+
+/// Which pools a collection applies to
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum CollectionScope {
+    /// A single pool, identified by rune ID
+    Pool(String),
+    /// Every pool with pending protocol fees
+    All,
+}
+
+/// Treasury configuration
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct TreasuryConfig {
+    /// Destination of collected fees (None until configured by an admin)
+    pub account: Option<Account>,
+    /// Last update timestamp
+    pub updated_at: u64,
+    /// Principal that last updated the config
+    pub updated_by: Option<Principal>,
+}
+
+impl Storable for TreasuryConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to encode TreasuryConfig: {}", e))
+        }))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to decode TreasuryConfig: {}", e))
+        })
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Amount taken from a single pool as part of a collection
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PoolFeeShare {
+    pub rune_id: String,
+    pub amount: u64,
+}
+
+/// Outcome of a collection
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum CollectionStatus {
+    /// Fees were transferred to the treasury
+    Completed { block_index: u64 },
+    /// Transfer failed and the fees were returned to their pools
+    Failed { reason: String },
+}
+
+/// Audit record of a protocol fee collection
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FeeCollection {
+    /// Sequential collection ID
+    pub id: u64,
+    /// Requested scope
+    pub scope: CollectionScope,
+    /// Per-pool breakdown
+    pub shares: Vec<PoolFeeShare>,
+    /// Sum of all shares (e8s)
+    pub total_amount: u64,
+    /// Ledger fee paid out of the total (e8s)
+    pub transfer_fee: u64,
+    /// Amount received by the treasury (e8s)
+    pub net_amount: u64,
+    /// Destination account
+    pub destination: Account,
+    /// Outcome
+    pub status: CollectionStatus,
+    /// Admin who triggered the collection
+    pub collected_by: Principal,
+    /// Timestamp
+    pub timestamp: u64,
+}
+
+impl Storable for FeeCollection {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to encode FeeCollection: {}", e))
+        }))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to decode FeeCollection: {}", e))
+        })
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Lifetime protocol fee totals for a pool
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct PoolFeeTotals {
+    /// Total protocol fees moved to the treasury (e8s, before ledger fee)
+    pub total_collected: u64,
+    /// Number of successful collections that included this pool
+    pub collection_count: u64,
+    /// Last successful collection timestamp
+    pub last_collected_at: u64,
+}
+
+impl Storable for PoolFeeTotals {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to encode PoolFeeTotals: {}", e))
+        }))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to decode PoolFeeTotals: {}", e))
+        })
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Per-pool protocol fee report
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PoolFeeReport {
+    pub rune_id: String,
+    pub pending: u64,
+    pub total_collected: u64,
+    pub collection_count: u64,
+    pub last_collected_at: u64,
+}
+
+// ============================================================================
+// Stable Storage
+// ============================================================================
+
+thread_local! {
+    /// Treasury config (MemoryId 19)
+    static TREASURY_CONFIG: RefCell<Option<StableCell<TreasuryConfig, Memory>>> =
+        const { RefCell::new(None) };
+
+    /// Collection history (MemoryId 20)
+    static COLLECTIONS: RefCell<Option<StableBTreeMap<u64, FeeCollection, Memory>>> =
+        const { RefCell::new(None) };
+
+    /// Per-pool totals (MemoryId 21)
+    static POOL_TOTALS: RefCell<Option<StableBTreeMap<PoolId, PoolFeeTotals, Memory>>> =
+        const { RefCell::new(None) };
+}
+
+/// Initialize treasury storage
+pub fn init_treasury_storage(config_memory: Memory, collections_memory: Memory, totals_memory: Memory) {
+    TREASURY_CONFIG.with(|c| {
+        *c.borrow_mut() = Some(
+            StableCell::init(config_memory, TreasuryConfig::default())
+                .unwrap_or_else(|e| ic_cdk::trap(&format!("Failed to initialize TreasuryConfig storage: {:?}", e))),
+        );
+    });
+    COLLECTIONS.with(|c| {
+        *c.borrow_mut() = Some(StableBTreeMap::init(collections_memory));
+    });
+    POOL_TOTALS.with(|t| {
+        *t.borrow_mut() = Some(StableBTreeMap::init(totals_memory));
+    });
+}
+
+/// Reinitialize treasury storage after upgrade
+pub fn reinit_treasury_storage(config_memory: Memory, collections_memory: Memory, totals_memory: Memory) {
+    init_treasury_storage(config_memory, collections_memory, totals_memory);
+}
+
+// ============================================================================
+// Treasury Config
+// ============================================================================
+
+/// Get the configured treasury account
+pub fn get_treasury_account() -> Option<Account> {
+    TREASURY_CONFIG.with(|c| c.borrow().as_ref().and_then(|cell| cell.get().account.clone()))
+}
+
+/// Set the treasury account (Admin only - call from lib.rs with RBAC check)
+pub fn set_treasury_account(account: Account, caller: Principal) -> Result<(), String> {
+    validate_account(&account)?;
+
+    TREASURY_CONFIG.with(|c| {
+        let mut cell_ref = c.borrow_mut();
+        if let Some(ref mut cell) = *cell_ref {
+            cell.set(TreasuryConfig {
+                account: Some(account),
+                updated_at: ic_cdk::api::time(),
+                updated_by: Some(caller),
+            })
+            .map_err(|e| format!("Failed to set TreasuryConfig: {:?}", e))?;
+            Ok(())
+        } else {
+            Err("Treasury storage not initialized".to_string())
+        }
+    })
+}
+
+fn validate_account(account: &Account) -> Result<(), String> {
+    if account.owner == Principal::anonymous() {
+        return Err("Treasury owner cannot be anonymous".to_string());
+    }
+    if let Some(ref subaccount) = account.subaccount {
+        if subaccount.len() != 32 {
+            return Err(format!(
+                "Subaccount must be 32 bytes, got {}",
+                subaccount.len()
+            ));
+        }
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Collection
+// ============================================================================
+
+/// Collect pending protocol fees and move them to the treasury
+///
+/// All selected pools are swept into a single ledger transfer. If the transfer
+/// fails, every pool gets its fees back and the failure is still recorded.
+pub async fn collect_protocol_fees(
+    scope: CollectionScope,
+    caller: Principal,
+) -> Result<FeeCollection, String> {
+    let destination = get_treasury_account().ok_or("Treasury account not configured")?;
+
+    let pools = match &scope {
+        CollectionScope::Pool(rune_id) => {
+            vec![trading_v2::get_pool_by_rune_id(rune_id).ok_or("Pool not found")?]
+        }
+        CollectionScope::All => trading_v2::list_pools(0, u64::MAX),
+    };
+
+    let pending_total: u64 = pools.iter().map(|p| p.protocol_fees_pending).sum();
+    if pending_total <= ICP_TRANSFER_FEE {
+        return Err(format!(
+            "Pending protocol fees {} do not cover the ledger fee {}",
+            pending_total, ICP_TRANSFER_FEE
+        ));
+    }
+
+    // Take fees before awaiting so concurrent calls cannot collect them twice
+    let mut taken: Vec<(PoolId, PoolFeeShare)> = Vec::new();
+    for pool in pools.iter().filter(|p| p.protocol_fees_pending > 0) {
+        let amount = trading_v2::take_protocol_fees(&pool.id)?;
+        if amount > 0 {
+            taken.push((
+                pool.id.clone(),
+                PoolFeeShare {
+                    rune_id: pool.rune_id.clone(),
+                    amount,
+                },
+            ));
+        }
+    }
+    let total_amount: u64 = taken.iter().map(|(_, share)| share.amount).sum();
+
+    let transfer_result = if destination.subaccount.is_none() {
+        ledger::transfer_icp_to_user(destination.owner, total_amount, Some(COLLECTION_MEMO.to_vec())).await
+    } else {
+        ledger::transfer_icp_to_account(destination.clone(), total_amount, Some(COLLECTION_MEMO.to_vec())).await
+    };
+
+    let now = ic_cdk::api::time();
+    let status = match transfer_result {
+        Ok(block_index) => {
+            for (pool_id, share) in &taken {
+                record_pool_collection(pool_id, share.amount, now);
+            }
+            CollectionStatus::Completed { block_index }
+        }
+        Err(e) => {
+            for (pool_id, share) in &taken {
+                if let Err(restore_err) = trading_v2::restore_protocol_fees(pool_id, share.amount) {
+                    crate::logging::log_error(
+                        "treasury",
+                        format!(
+                            "Failed to restore {} e8s of protocol fees to pool {}: {}",
+                            share.amount, share.rune_id, restore_err
+                        ),
+                        None,
+                    );
+                }
+            }
+            crate::logging::log_error(
+                "treasury",
+                format!("Protocol fee collection of {} e8s failed: {}", total_amount, e),
+                None,
+            );
+            CollectionStatus::Failed { reason: e }
+        }
+    };
+
+    let collection = FeeCollection {
+        id: next_collection_id(),
+        scope,
+        shares: taken.into_iter().map(|(_, share)| share).collect(),
+        total_amount,
+        transfer_fee: ICP_TRANSFER_FEE,
+        net_amount: net_amount(total_amount),
+        destination,
+        status: status.clone(),
+        collected_by: caller,
+        timestamp: now,
+    };
+    store_collection(&collection)?;
+
+    match status {
+        CollectionStatus::Completed { .. } => Ok(collection),
+        CollectionStatus::Failed { reason } => Err(format!("Protocol fee collection failed: {}", reason)),
+    }
+}
+
+/// Amount the treasury receives after the ledger fee
+fn net_amount(total_amount: u64) -> u64 {
+    total_amount.saturating_sub(ICP_TRANSFER_FEE)
+}
+
+fn next_collection_id() -> u64 {
+    COLLECTIONS.with(|c| {
+        c.borrow()
+            .as_ref()
+            .and_then(|map| map.last_key_value())
+            .map(|(id, _)| id + 1)
+            .unwrap_or(0)
+    })
+}
+
+fn store_collection(collection: &FeeCollection) -> Result<(), String> {
+    COLLECTIONS.with(|c| {
+        if let Some(ref mut map) = *c.borrow_mut() {
+            map.insert(collection.id, collection.clone());
+            Ok(())
+        } else {
+            Err("Treasury storage not initialized".to_string())
+        }
+    })
+}
+
+fn record_pool_collection(pool_id: &PoolId, amount: u64, now: u64) {
+    POOL_TOTALS.with(|t| {
+        if let Some(ref mut map) = *t.borrow_mut() {
+            let mut totals = map.get(pool_id).unwrap_or_default();
+            totals.total_collected = totals.total_collected.saturating_add(amount);
+            totals.collection_count += 1;
+            totals.last_collected_at = now;
+            map.insert(pool_id.clone(), totals);
+        }
+    });
+}
+
+// ============================================================================
+// Queries
+// ============================================================================
+
+/// Get collection history (most recent first)
+pub fn get_collections(offset: u64, limit: u64) -> Vec<FeeCollection> {
+    COLLECTIONS.with(|c| {
+        if let Some(ref map) = *c.borrow() {
+            map.iter()
+                .map(|(_, collection)| collection)
+                .rev()
+                .skip(offset as usize)
+                .take(limit as usize)
+                .collect()
+        } else {
+            vec![]
+        }
+    })
+}
+
+/// Get lifetime totals for a pool
+pub fn get_pool_totals(pool_id: &PoolId) -> PoolFeeTotals {
+    POOL_TOTALS.with(|t| {
+        t.borrow()
+            .as_ref()
+            .and_then(|map| map.get(pool_id))
+            .unwrap_or_default()
+    })
+}
+
+/// Get pending and collected protocol fees for every pool
+pub fn get_pool_fee_reports() -> Vec<PoolFeeReport> {
+    trading_v2::list_pools(0, u64::MAX)
+        .into_iter()
+        .map(|pool| {
+            let totals = get_pool_totals(&pool.id);
+            PoolFeeReport {
+                rune_id: pool.rune_id,
+                pending: pool.protocol_fees_pending,
+                total_collected: totals.total_collected,
+                collection_count: totals.collection_count,
+                last_collected_at: totals.last_collected_at,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn treasury_owner() -> Principal {
+        Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap()
+    }
+
+    #[test]
+    fn test_validate_account() {
+        let plain = Account {
+            owner: treasury_owner(),
+            subaccount: None,
+        };
+        assert!(validate_account(&plain).is_ok());
+
+        let with_subaccount = Account {
+            owner: treasury_owner(),
+            subaccount: Some(vec![1u8; 32]),
+        };
+        assert!(validate_account(&with_subaccount).is_ok());
+
+        let bad_subaccount = Account {
+            owner: treasury_owner(),
+            subaccount: Some(vec![1u8; 16]),
+        };
+        assert!(validate_account(&bad_subaccount).is_err());
+
+        let anonymous = Account {
+            owner: Principal::anonymous(),
+            subaccount: None,
+        };
+        assert!(validate_account(&anonymous).is_err());
+    }
+
+    #[test]
+    fn test_net_amount() {
+        assert_eq!(net_amount(1_000_000), 1_000_000 - ICP_TRANSFER_FEE);
+        assert_eq!(net_amount(ICP_TRANSFER_FEE), 0);
+        assert_eq!(net_amount(0), 0);
+    }
+
+    #[test]
+    fn test_fee_collection_storable() {
+        let collection = FeeCollection {
+            id: 7,
+            scope: CollectionScope::All,
+            shares: vec![PoolFeeShare {
+                rune_id: "rune_1".to_string(),
+                amount: 500_000,
+            }],
+            total_amount: 500_000,
+            transfer_fee: ICP_TRANSFER_FEE,
+            net_amount: 490_000,
+            destination: Account {
+                owner: treasury_owner(),
+                subaccount: None,
+            },
+            status: CollectionStatus::Completed { block_index: 42 },
+            collected_by: treasury_owner(),
+            timestamp: 1,
+        };
+
+        let decoded = FeeCollection::from_bytes(collection.to_bytes());
+        assert_eq!(decoded.id, 7);
+        assert_eq!(decoded.scope, CollectionScope::All);
+        assert_eq!(decoded.shares.len(), 1);
+        assert_eq!(decoded.status, CollectionStatus::Completed { block_index: 42 });
+    }
+}
@@ -0,0 +1,708 @@
+// ============================================================================
+// Withdrawals Module - Velocity Limits, Address Book and Review Queue
+// ============================================================================
+//
+// Guards every ICP withdrawal before it reaches `ledger::withdraw_icp_to`.
+//
+// Key Features:
+// - Per-principal and global limits over a rolling window
+// - Address book: destinations other than the user's own principal must be
+//   added first and only unlock after a delay
+// - Withdrawals at or above a threshold are held (Available -> Locked) in a
+//   review queue until an admin approves or rejects them
+//
+// Limits and thresholds are admin-configured; the RBAC checks live in lib.rs.
+//
+// ============================================================================
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::memory_manager::VirtualMemory;
+use ic_stable_structures::{storable::Bound, DefaultMemoryImpl, StableBTreeMap, StableCell, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use crate::accounting::{self, Asset, EntryReason, LedgerAccount};
+use crate::ledger::{self, Account};
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Default rolling window and address unlock delay (24 hours)
+const DEFAULT_WINDOW_SECS: u64 = 24 * 3600;
+const DEFAULT_ADDRESS_DELAY_SECS: u64 = 24 * 3600;
+
+/// Maximum address book entries per user
+const MAX_ADDRESSES_PER_USER: usize = 20;
+
+/// Maximum address label length
+const MAX_LABEL_LENGTH: usize = 64;
+
+// ============================================================================
+// Types
+// ============================================================================
+
+/// Withdrawal limits configuration
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct WithdrawalLimits {
+    /// Rolling window for the limits below (seconds)
+    pub window_secs: u64,
+    /// Maximum withdrawn per principal per window (e8s, None = unlimited)
+    pub per_user_limit: Option<u64>,
+    /// Maximum withdrawn by all users per window (e8s, None = unlimited)
+    pub global_limit: Option<u64>,
+    /// Withdrawals at or above this amount need admin review (None = never)
+    pub review_threshold: Option<u64>,
+    /// Delay before a new address book destination can be used (seconds)
+    pub address_delay_secs: u64,
+    /// Last update timestamp
+    pub updated_at: u64,
+    /// Principal that last updated the limits
+    pub updated_by: Option<Principal>,
+}
+
+impl Default for WithdrawalLimits {
+    fn default() -> Self {
+        Self {
+            window_secs: DEFAULT_WINDOW_SECS,
+            per_user_limit: None,
+            global_limit: None,
+            review_threshold: None,
+            address_delay_secs: DEFAULT_ADDRESS_DELAY_SECS,
+            updated_at: 0,
+            updated_by: None,
+        }
+    }
+}
+
+impl Storable for WithdrawalLimits {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to encode WithdrawalLimits: {}", e))
+        }))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to decode WithdrawalLimits: {}", e))
+        })
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// A withdrawal counted against the rolling limits
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct WithdrawalUsage {
+    user: Principal,
+    amount: u64,
+}
+
+impl Storable for WithdrawalUsage {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to encode WithdrawalUsage: {}", e))
+        }))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to decode WithdrawalUsage: {}", e))
+        })
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Saved withdrawal destination
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AddressBookEntry {
+    pub account: Account,
+    pub label: Option<String>,
+    pub added_at: u64,
+    /// The destination can be used from this time on
+    pub unlocks_at: u64,
+}
+
+impl Storable for AddressBookEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to encode AddressBookEntry: {}", e))
+        }))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to decode AddressBookEntry: {}", e))
+        })
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Review status of a held withdrawal
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum ReviewStatus {
+    /// Funds locked, waiting for an admin
+    Pending,
+    /// Approved; ledger transfer in flight
+    Sending,
+    /// Approved and sent
+    Sent { block_index: u64 },
+    /// Approved, but the ledger transfer failed and the funds were refunded
+    Failed { reason: String },
+    /// Rejected; funds returned to the user's available balance
+    Rejected { reason: Option<String> },
+}
+
+/// A withdrawal held for admin review
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PendingWithdrawal {
+    pub id: u64,
+    pub user: Principal,
+    pub to: Account,
+    pub amount: u64,
+    pub status: ReviewStatus,
+    pub requested_at: u64,
+    pub reviewed_by: Option<Principal>,
+    pub reviewed_at: Option<u64>,
+    /// Rolling-window usage entry, released on rejection
+    usage_key: Vec<u8>,
+}
+
+impl Storable for PendingWithdrawal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to encode PendingWithdrawal: {}", e))
+        }))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("CRITICAL: Failed to decode PendingWithdrawal: {}", e))
+        })
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Result of a withdrawal request
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum WithdrawalOutcome {
+    /// Sent to the ledger
+    Sent { block_index: u64 },
+    /// Held for admin review
+    PendingReview { request_id: u64 },
+}
+
+// ============================================================================
+// Storage
+// ============================================================================
+
+thread_local! {
+    static LIMITS: RefCell<Option<StableCell<WithdrawalLimits, Memory>>> = const { RefCell::new(None) };
+
+    /// Rolling-window usage keyed by (timestamp, sequence)
+    static USAGE: RefCell<Option<StableBTreeMap<Vec<u8>, WithdrawalUsage, Memory>>> = const { RefCell::new(None) };
+
+    /// Address book keyed by (user, destination)
+    static ADDRESS_BOOK: RefCell<Option<StableBTreeMap<Vec<u8>, AddressBookEntry, Memory>>> = const { RefCell::new(None) };
+
+    /// Withdrawals held for review
+    static REVIEW_QUEUE: RefCell<Option<StableBTreeMap<u64, PendingWithdrawal, Memory>>> = const { RefCell::new(None) };
+}
+
+/// Initialize withdrawal storage
+pub fn init_withdrawal_storage(
+    limits_memory: Memory,
+    usage_memory: Memory,
+    address_book_memory: Memory,
+    review_queue_memory: Memory,
+) {
+    LIMITS.with(|l| {
+        *l.borrow_mut() = Some(
+            StableCell::init(limits_memory, WithdrawalLimits::default())
+                .expect("Failed to initialize withdrawal limits"),
+        );
+    });
+    USAGE.with(|u| {
+        *u.borrow_mut() = Some(StableBTreeMap::init(usage_memory));
+    });
+    ADDRESS_BOOK.with(|a| {
+        *a.borrow_mut() = Some(StableBTreeMap::init(address_book_memory));
+    });
+    REVIEW_QUEUE.with(|q| {
+        *q.borrow_mut() = Some(StableBTreeMap::init(review_queue_memory));
+    });
+}
+
+/// Current limits
+pub fn get_limits() -> WithdrawalLimits {
+    LIMITS.with(|l| l.borrow().as_ref().map(|cell| cell.get().clone()).unwrap_or_default())
+}
+
+/// Replace the limits
+pub fn set_limits(mut limits: WithdrawalLimits, caller: Principal, now: u64) -> Result<(), String> {
+    if limits.window_secs == 0 {
+        return Err("Window must be at least 1 second".to_string());
+    }
+    limits.updated_at = now;
+    limits.updated_by = Some(caller);
+    LIMITS.with(|l| {
+        let mut l = l.borrow_mut();
+        let cell = l.as_mut().ok_or("Withdrawal storage not initialized")?;
+        cell.set(limits)
+            .map_err(|e| format!("Failed to save withdrawal limits: {:?}", e))?;
+        Ok(())
+    })
+}
+
+// ============================================================================
+// Rolling Limits
+// ============================================================================
+
+fn window_start(now: u64, limits: &WithdrawalLimits) -> u64 {
+    now.saturating_sub(limits.window_secs.saturating_mul(NANOS_PER_SEC))
+}
+
+/// Amount withdrawn in the current window (by `user`, or by everyone)
+pub fn usage_in_window(user: Option<Principal>, now: u64) -> u64 {
+    let start = window_start(now, &get_limits()).to_be_bytes().to_vec();
+    USAGE.with(|u| {
+        if let Some(ref map) = *u.borrow() {
+            map.range(start..)
+                .filter(|(_, usage)| user.map(|user| usage.user == user).unwrap_or(true))
+                .fold(0u64, |total, (_, usage)| total.saturating_add(usage.amount))
+        } else {
+            0
+        }
+    })
+}
+
+fn check_limits(user: Principal, amount: u64, now: u64, limits: &WithdrawalLimits) -> Result<(), String> {
+    if let Some(limit) = limits.per_user_limit {
+        let used = usage_in_window(Some(user), now);
+        if used.saturating_add(amount) > limit {
+            return Err(format!(
+                "Withdrawal limit reached: {} of {} e8s used in the last {}s",
+                used, limit, limits.window_secs
+            ));
+        }
+    }
+    if let Some(limit) = limits.global_limit {
+        let used = usage_in_window(None, now);
+        if used.saturating_add(amount) > limit {
+            return Err("Global withdrawal limit reached; try again later".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Count a withdrawal against the limits, dropping entries outside the window
+fn record_usage(user: Principal, amount: u64, now: u64, limits: &WithdrawalLimits) -> Vec<u8> {
+    let start = window_start(now, limits).to_be_bytes().to_vec();
+    USAGE.with(|u| {
+        let mut u = u.borrow_mut();
+        let Some(map) = u.as_mut() else {
+            return vec![];
+        };
+        while map.first_key_value().map(|(key, _)| key < start).unwrap_or(false) {
+            map.pop_first();
+        }
+
+        let sequence = map.len();
+        let mut key = now.to_be_bytes().to_vec();
+        key.extend_from_slice(&sequence.to_be_bytes());
+        while map.contains_key(&key) {
+            key.push(0);
+        }
+        map.insert(key.clone(), WithdrawalUsage { user, amount });
+        key
+    })
+}
+
+fn release_usage(key: &Vec<u8>) {
+    USAGE.with(|u| {
+        if let Some(ref mut map) = *u.borrow_mut() {
+            map.remove(key);
+        }
+    });
+}
+
+// ============================================================================
+// Address Book
+// ============================================================================
+
+fn address_key(user: &Principal, account: &Account) -> Vec<u8> {
+    let mut key = address_prefix(user);
+    let owner = account.owner.as_slice();
+    key.push(owner.len() as u8);
+    key.extend_from_slice(owner);
+    // Default subaccount and None are the same destination
+    match account.subaccount {
+        Some(ref subaccount) if subaccount.iter().any(|b| *b != 0) => key.extend_from_slice(subaccount),
+        _ => {}
+    }
+    key
+}
+
+fn address_prefix(user: &Principal) -> Vec<u8> {
+    let bytes = user.as_slice();
+    let mut key = Vec::with_capacity(1 + bytes.len() + 62);
+    key.push(bytes.len() as u8);
+    key.extend_from_slice(bytes);
+    key
+}
+
+/// Add a destination; it unlocks after the configured delay
+pub fn add_address(
+    user: Principal,
+    account: Account,
+    label: Option<String>,
+    now: u64,
+) -> Result<AddressBookEntry, String> {
+    if account.owner == user {
+        return Err("Your own principal is always allowed".to_string());
+    }
+    if account.owner == Principal::anonymous() {
+        return Err("Cannot withdraw to the anonymous principal".to_string());
+    }
+    if account.subaccount.as_ref().map(|s| s.len() != 32).unwrap_or(false) {
+        return Err("Subaccount must be 32 bytes".to_string());
+    }
+    if label.as_ref().map(|l| l.len() > MAX_LABEL_LENGTH).unwrap_or(false) {
+        return Err(format!("Label exceeds {} characters", MAX_LABEL_LENGTH));
+    }
+    if list_addresses(user).len() >= MAX_ADDRESSES_PER_USER {
+        return Err(format!("Address book is full ({} entries)", MAX_ADDRESSES_PER_USER));
+    }
+
+    let key = address_key(&user, &account);
+    let entry = AddressBookEntry {
+        account,
+        label,
+        added_at: now,
+        unlocks_at: now.saturating_add(get_limits().address_delay_secs.saturating_mul(NANOS_PER_SEC)),
+    };
+    ADDRESS_BOOK.with(|a| {
+        let mut a = a.borrow_mut();
+        let map = a.as_mut().ok_or("Withdrawal storage not initialized")?;
+        if map.contains_key(&key) {
+            return Err("Destination is already in the address book".to_string());
+        }
+        map.insert(key, entry.clone());
+        Ok(entry)
+    })
+}
+
+/// Remove a destination
+pub fn remove_address(user: Principal, account: &Account) -> Result<(), String> {
+    let key = address_key(&user, account);
+    ADDRESS_BOOK.with(|a| {
+        a.borrow_mut()
+            .as_mut()
+            .and_then(|map| map.remove(&key))
+            .map(|_| ())
+            .ok_or_else(|| "Destination is not in the address book".to_string())
+    })
+}
+
+/// A user's address book
+pub fn list_addresses(user: Principal) -> Vec<AddressBookEntry> {
+    let prefix = address_prefix(&user);
+    ADDRESS_BOOK.with(|a| {
+        if let Some(ref map) = *a.borrow() {
+            map.range(prefix.clone()..)
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                .map(|(_, entry)| entry)
+                .collect()
+        } else {
+            vec![]
+        }
+    })
+}
+
+/// The user's own principal is always allowed; anything else must be an
+/// unlocked address book entry
+fn check_destination(user: Principal, to: &Account, now: u64) -> Result<(), String> {
+    if to.owner == user {
+        return Ok(());
+    }
+    let key = address_key(&user, to);
+    let entry = ADDRESS_BOOK
+        .with(|a| a.borrow().as_ref().and_then(|map| map.get(&key)))
+        .ok_or("Destination is not in your withdrawal address book")?;
+    if now < entry.unlocks_at {
+        return Err(format!(
+            "Destination unlocks in {}s",
+            (entry.unlocks_at - now).div_ceil(NANOS_PER_SEC)
+        ));
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Withdrawals
+// ============================================================================
+
+/// Check a withdrawal and either send it or hold it for review
+///
+/// With `allow_review` false, amounts at or above the review threshold are
+/// refused instead of queued.
+pub async fn request_withdrawal(
+    user: Principal,
+    to: Account,
+    amount: u64,
+    allow_review: bool,
+) -> Result<WithdrawalOutcome, String> {
+    let now = ic_cdk::api::time();
+    let limits = get_limits();
+
+    if crate::solvency::withdrawals_paused() {
+        return Err("ICP withdrawals are paused pending a solvency review".to_string());
+    }
+    if amount <= ledger::ICP_TRANSFER_FEE {
+        return Err(format!(
+            "Amount {} must be greater than fee {}",
+            amount,
+            ledger::ICP_TRANSFER_FEE
+        ));
+    }
+    check_destination(user, &to, now)?;
+    check_limits(user, amount, now, &limits)?;
+
+    if limits.review_threshold.map(|t| amount >= t).unwrap_or(false) {
+        if !allow_review {
+            return Err(format!(
+                "Withdrawals of {} e8s or more need admin review; use request_withdrawal",
+                limits.review_threshold.unwrap_or_default()
+            ));
+        }
+        let request_id = hold_for_review(user, to, amount, now, &limits)?;
+        return Ok(WithdrawalOutcome::PendingReview { request_id });
+    }
+
+    let usage_key = record_usage(user, amount, now, &limits);
+    match ledger::withdraw_icp_to(user, to, amount).await {
+        Ok(block_index) => Ok(WithdrawalOutcome::Sent { block_index }),
+        Err(e) => {
+            release_usage(&usage_key);
+            Err(e)
+        }
+    }
+}
+
+fn hold_for_review(
+    user: Principal,
+    to: Account,
+    amount: u64,
+    now: u64,
+    limits: &WithdrawalLimits,
+) -> Result<u64, String> {
+    let id = REVIEW_QUEUE.with(|q| {
+        q.borrow()
+            .as_ref()
+            .and_then(|map| map.last_key_value().map(|(id, _)| id + 1))
+            .unwrap_or(0)
+    });
+
+    accounting::post(
+        Asset::Icp,
+        LedgerAccount::Available(user),
+        LedgerAccount::Locked(user),
+        amount,
+        EntryReason::Lock,
+        Some(format!("withdrawal_review:{}", id)),
+    )?;
+
+    let usage_key = record_usage(user, amount, now, limits);
+    let pending = PendingWithdrawal {
+        id,
+        user,
+        to,
+        amount,
+        status: ReviewStatus::Pending,
+        requested_at: now,
+        reviewed_by: None,
+        reviewed_at: None,
+        usage_key,
+    };
+    store_pending(&pending);
+    Ok(id)
+}
+
+fn store_pending(pending: &PendingWithdrawal) {
+    REVIEW_QUEUE.with(|q| {
+        if let Some(ref mut map) = *q.borrow_mut() {
+            map.insert(pending.id, pending.clone());
+        }
+    });
+}
+
+/// Get a held withdrawal
+pub fn get_pending(id: u64) -> Option<PendingWithdrawal> {
+    REVIEW_QUEUE.with(|q| q.borrow().as_ref().and_then(|map| map.get(&id)))
+}
+
+/// Withdrawals in the review queue, newest first (optionally only pending ones)
+pub fn list_review_queue(pending_only: bool, offset: u64, limit: u64) -> Vec<PendingWithdrawal> {
+    REVIEW_QUEUE.with(|q| {
+        if let Some(ref map) = *q.borrow() {
+            map.iter()
+                .rev()
+                .map(|(_, pending)| pending)
+                .filter(|pending| !pending_only || pending.status == ReviewStatus::Pending)
+                .skip(offset as usize)
+                .take(limit as usize)
+                .collect()
+        } else {
+            vec![]
+        }
+    })
+}
+
+/// A user's held withdrawals, newest first
+pub fn get_user_reviews(user: Principal) -> Vec<PendingWithdrawal> {
+    REVIEW_QUEUE.with(|q| {
+        if let Some(ref map) = *q.borrow() {
+            map.iter()
+                .rev()
+                .map(|(_, pending)| pending)
+                .filter(|pending| pending.user == user)
+                .collect()
+        } else {
+            vec![]
+        }
+    })
+}
+
+fn take_pending(id: u64) -> Result<PendingWithdrawal, String> {
+    let pending = get_pending(id).ok_or_else(|| format!("Withdrawal request {} not found", id))?;
+    if pending.status != ReviewStatus::Pending {
+        return Err(format!("Withdrawal request {} is already {:?}", id, pending.status));
+    }
+    Ok(pending)
+}
+
+fn release_hold(pending: &PendingWithdrawal) -> Result<(), String> {
+    accounting::post(
+        Asset::Icp,
+        LedgerAccount::Locked(pending.user),
+        LedgerAccount::Available(pending.user),
+        pending.amount,
+        EntryReason::Unlock,
+        Some(format!("withdrawal_review:{}", pending.id)),
+    )
+}
+
+/// Approve a held withdrawal and send it
+pub async fn approve(id: u64, admin: Principal) -> Result<PendingWithdrawal, String> {
+    let mut pending = take_pending(id)?;
+
+    // Release the hold and mark reviewed before the ledger call, so the
+    // request cannot be approved twice while the transfer is in flight
+    release_hold(&pending)?;
+    pending.reviewed_by = Some(admin);
+    pending.reviewed_at = Some(ic_cdk::api::time());
+    pending.status = ReviewStatus::Sending;
+    store_pending(&pending);
+
+    pending.status = match ledger::withdraw_icp_to(pending.user, pending.to.clone(), pending.amount).await {
+        Ok(block_index) => ReviewStatus::Sent { block_index },
+        Err(reason) => {
+            release_usage(&pending.usage_key);
+            ReviewStatus::Failed { reason }
+        }
+    };
+    store_pending(&pending);
+    Ok(pending)
+}
+
+/// Reject a held withdrawal, returning the funds to the user
+pub fn reject(id: u64, admin: Principal, reason: Option<String>, now: u64) -> Result<PendingWithdrawal, String> {
+    let mut pending = take_pending(id)?;
+    release_hold(&pending)?;
+    release_usage(&pending.usage_key);
+
+    pending.reviewed_by = Some(admin);
+    pending.reviewed_at = Some(now);
+    pending.status = ReviewStatus::Rejected { reason };
+    store_pending(&pending);
+    Ok(pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+
+    const SEC: u64 = NANOS_PER_SEC;
+
+    fn init_test_storage() {
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        init_withdrawal_storage(
+            manager.get(MemoryId::new(0)),
+            manager.get(MemoryId::new(1)),
+            manager.get(MemoryId::new(2)),
+            manager.get(MemoryId::new(3)),
+        );
+    }
+
+    fn user() -> Principal {
+        Principal::from_slice(&[1; 29])
+    }
+
+    fn account(owner: Principal) -> Account {
+        Account { owner, subaccount: None }
+    }
+
+    #[test]
+    fn test_rolling_limits() {
+        init_test_storage();
+        let other = Principal::from_slice(&[2; 29]);
+        let limits = WithdrawalLimits {
+            window_secs: 100,
+            per_user_limit: Some(1_000),
+            global_limit: Some(1_500),
+            ..Default::default()
+        };
+        set_limits(limits.clone(), user(), 0).unwrap();
+
+        let now = 1_000 * SEC;
+        assert!(check_limits(user(), 1_000, now, &limits).is_ok());
+        record_usage(user(), 800, now, &limits);
+        assert!(check_limits(user(), 201, now, &limits).is_err());
+        assert!(check_limits(other, 700, now, &limits).is_ok());
+        assert!(check_limits(other, 701, now, &limits).is_err());
+
+        // Usage leaves the window
+        let later = now + 101 * SEC;
+        assert_eq!(usage_in_window(Some(user()), later), 0);
+        assert!(check_limits(user(), 1_000, later, &limits).is_ok());
+    }
+
+    #[test]
+    fn test_address_book_delay() {
+        init_test_storage();
+        let friend = Principal::from_slice(&[3; 29]);
+        let now = 1_000 * SEC;
+
+        // Own principal is always allowed, others only once unlocked
+        assert!(check_destination(user(), &account(user()), now).is_ok());
+        assert!(check_destination(user(), &account(friend), now).is_err());
+
+        let entry = add_address(user(), account(friend), Some("friend".to_string()), now).unwrap();
+        assert_eq!(entry.unlocks_at, now + DEFAULT_ADDRESS_DELAY_SECS * SEC);
+        assert!(add_address(user(), account(friend), None, now).is_err());
+        assert!(check_destination(user(), &account(friend), now + SEC).is_err());
+        assert!(check_destination(user(), &account(friend), entry.unlocks_at).is_ok());
+
+        // A zero subaccount is the same destination
+        let zero = Account { owner: friend, subaccount: Some(vec![0; 32]) };
+        assert!(check_destination(user(), &zero, entry.unlocks_at).is_ok());
+
+        remove_address(user(), &account(friend)).unwrap();
+        assert!(list_addresses(user()).is_empty());
+        assert!(check_destination(user(), &account(friend), entry.unlocks_at).is_err());
+    }
+}
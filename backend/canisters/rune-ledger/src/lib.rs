@@ -0,0 +1,376 @@
+//! Rune Ledger Canister
+//!
+//! Standard ICRC-1/ICRC-2 ledger for one virtual rune. Balances, allowances
+//! and the transaction log stay in the rune engine; this canister gives the
+//! rune its own canister ID so wallets and DEXes can treat it as any other
+//! ICRC token.
+//!
+//! Token metadata is copied from the engine after install (and on
+//! `refresh_metadata`), so name, symbol, decimals and fee are plain queries.
+//! Balances, supply and allowances are composite queries over the engine's
+//! `rune_token_*` queries; canisters that need them in an update call can
+//! query the engine's `rune_token_*` endpoints directly. Updates are forwarded to the engine's `rune_ledger_*` endpoints with the
+//! original caller, which the engine only accepts from the ledger registered
+//! for the rune.
+//!
+//! Deploy one instance per rune with init `(engine, rune_id)` on the engine's
+//! subnet, then register it with the engine's `set_rune_ledger`.
+
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::api::call::RejectionCode;
+use ic_cdk_macros::{init, post_upgrade, query, update};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, StableCell};
+use std::cell::RefCell;
+use std::time::Duration;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// ============================================================================
+// ICRC TYPES
+// ============================================================================
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum MetadataValue {
+    Nat(Nat),
+    Int(candid::Int),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct StandardRecord {
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ApproveArgs {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub spender: Account,
+    pub amount: Nat,
+    pub expected_allowance: Option<Nat>,
+    pub expires_at: Option<u64>,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ApproveError {
+    BadFee { expected_fee: Nat },
+    InsufficientFunds { balance: Nat },
+    AllowanceChanged { current_allowance: Nat },
+    Expired { ledger_time: u64 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AllowanceArgs {
+    pub account: Account,
+    pub spender: Account,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Allowance {
+    pub allowance: Nat,
+    pub expires_at: Option<u64>,
+}
+
+// ============================================================================
+// STATE
+// ============================================================================
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    /// Rune engine principal (MemoryId 0)
+    static ENGINE: RefCell<StableCell<Vec<u8>, Memory>> = RefCell::new(
+        StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))), Vec::new())
+            .expect("Failed to initialize engine cell")
+    );
+
+    /// Rune served by this ledger (MemoryId 1)
+    static RUNE_ID: RefCell<StableCell<String, Memory>> = RefCell::new(
+        StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1))), String::new())
+            .expect("Failed to initialize rune ID cell")
+    );
+
+    /// Candid-encoded token metadata copied from the engine (MemoryId 2)
+    static METADATA: RefCell<StableCell<Vec<u8>, Memory>> = RefCell::new(
+        StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2))), Vec::new())
+            .expect("Failed to initialize metadata cell")
+    );
+}
+
+#[init]
+fn init(engine: Principal, rune_id: String) {
+    ENGINE.with(|e| {
+        e.borrow_mut()
+            .set(engine.as_slice().to_vec())
+            .expect("Failed to store engine principal");
+    });
+    RUNE_ID.with(|r| {
+        r.borrow_mut().set(rune_id).expect("Failed to store rune ID");
+    });
+    schedule_metadata_refresh();
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    schedule_metadata_refresh();
+}
+
+/// Copy the metadata right after install (init cannot make calls)
+fn schedule_metadata_refresh() {
+    ic_cdk_timers::set_timer(Duration::from_secs(1), || {
+        ic_cdk::spawn(async {
+            if let Err(e) = refresh_metadata().await {
+                ic_cdk::println!("{}", e);
+            }
+        });
+    });
+}
+
+fn engine() -> Principal {
+    ENGINE.with(|e| Principal::try_from_slice(e.borrow().get()))
+        .unwrap_or_else(|_| ic_cdk::trap("Rune engine not configured"))
+}
+
+fn rune_id() -> String {
+    RUNE_ID.with(|r| r.borrow().get().clone())
+}
+
+/// Query the engine, trapping on failure (query results have no error case)
+async fn query_engine<A, R>(method: &str, args: A) -> R
+where
+    A: candid::utils::ArgumentEncoder,
+    R: for<'a> Deserialize<'a> + CandidType,
+{
+    match ic_cdk::call::<A, (R,)>(engine(), method, args).await {
+        Ok((result,)) => result,
+        Err((code, msg)) => ic_cdk::trap(&format!("Rune engine {} failed: {:?} - {}", method, code, msg)),
+    }
+}
+
+/// Cached metadata (empty until the first refresh)
+fn metadata() -> Vec<(String, MetadataValue)> {
+    METADATA.with(|m| candid::decode_one(m.borrow().get()).unwrap_or_default())
+}
+
+/// Metadata entry `key` of the rune
+fn metadata_entry(key: &str) -> Option<MetadataValue> {
+    metadata().into_iter().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+/// Message for a forwarded update the engine never answered
+fn unavailable(method: &str, code: RejectionCode, msg: String) -> String {
+    format!("Rune engine {} failed: {:?} - {}", method, code, msg)
+}
+
+// ============================================================================
+// ICRC-1
+// ============================================================================
+
+/// Copy the rune's metadata from the engine again
+#[update]
+async fn refresh_metadata() -> Result<(), String> {
+    let method = "rune_token_metadata";
+    let (entries,): (Vec<(String, MetadataValue)>,) = ic_cdk::call(engine(), method, (rune_id(),))
+        .await
+        .map_err(|(code, msg)| unavailable(method, code, msg))?;
+    if entries.is_empty() {
+        return Err(format!("Rune engine has no metadata for {}", rune_id()));
+    }
+    let bytes = candid::encode_one(&entries).map_err(|e| format!("Failed to encode metadata: {}", e))?;
+    METADATA.with(|m| m.borrow_mut().set(bytes).map(|_| ()).map_err(|e| format!("Failed to store metadata: {:?}", e)))
+}
+
+#[query]
+fn icrc1_metadata() -> Vec<(String, MetadataValue)> {
+    metadata()
+}
+
+#[query]
+fn icrc1_name() -> String {
+    match metadata_entry("icrc1:name") {
+        Some(MetadataValue::Text(name)) => name,
+        _ => rune_id(),
+    }
+}
+
+#[query]
+fn icrc1_symbol() -> String {
+    match metadata_entry("icrc1:symbol") {
+        Some(MetadataValue::Text(symbol)) => symbol,
+        _ => String::new(),
+    }
+}
+
+#[query]
+fn icrc1_decimals() -> u8 {
+    match metadata_entry("icrc1:decimals") {
+        Some(MetadataValue::Nat(decimals)) => u8::try_from(&decimals.0).unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Transfers of virtual runes are free
+#[query]
+fn icrc1_fee() -> Nat {
+    match metadata_entry("icrc1:fee") {
+        Some(MetadataValue::Nat(fee)) => fee,
+        _ => Nat::from(0u64),
+    }
+}
+
+#[query(composite = true)]
+async fn icrc1_total_supply() -> Nat {
+    query_engine("rune_token_total_supply", (rune_id(),)).await
+}
+
+/// Runes are minted and burned by the engine, not through a minting account
+#[query]
+fn icrc1_minting_account() -> Option<Account> {
+    None
+}
+
+#[query(composite = true)]
+async fn icrc1_balance_of(account: Account) -> Nat {
+    query_engine("rune_token_balance_of", (rune_id(), account)).await
+}
+
+#[update]
+async fn icrc1_transfer(arg: TransferArg) -> Result<Nat, TransferError> {
+    let method = "rune_ledger_transfer";
+    match ic_cdk::call::<_, (Result<Nat, TransferError>,)>(engine(), method, (ic_cdk::caller(), arg)).await {
+        Ok((result,)) => result,
+        Err((code, msg)) => Err(TransferError::GenericError {
+            error_code: Nat::from(0u64),
+            message: unavailable(method, code, msg),
+        }),
+    }
+}
+
+#[query]
+fn icrc1_supported_standards() -> Vec<StandardRecord> {
+    supported_standards()
+}
+
+// ============================================================================
+// ICRC-2
+// ============================================================================
+
+#[update]
+async fn icrc2_approve(args: ApproveArgs) -> Result<Nat, ApproveError> {
+    let method = "rune_ledger_approve";
+    match ic_cdk::call::<_, (Result<Nat, ApproveError>,)>(engine(), method, (ic_cdk::caller(), args)).await {
+        Ok((result,)) => result,
+        Err((code, msg)) => Err(ApproveError::GenericError {
+            error_code: Nat::from(0u64),
+            message: unavailable(method, code, msg),
+        }),
+    }
+}
+
+#[query(composite = true)]
+async fn icrc2_allowance(args: AllowanceArgs) -> Allowance {
+    query_engine("rune_token_allowance", (rune_id(), args)).await
+}
+
+#[update]
+async fn icrc2_transfer_from(args: TransferFromArgs) -> Result<Nat, TransferFromError> {
+    let method = "rune_ledger_transfer_from";
+    match ic_cdk::call::<_, (Result<Nat, TransferFromError>,)>(engine(), method, (ic_cdk::caller(), args)).await {
+        Ok((result,)) => result,
+        Err((code, msg)) => Err(TransferFromError::GenericError {
+            error_code: Nat::from(0u64),
+            message: unavailable(method, code, msg),
+        }),
+    }
+}
+
+// ============================================================================
+// ICRC-10
+// ============================================================================
+
+fn supported_standards() -> Vec<StandardRecord> {
+    vec![
+        StandardRecord {
+            name: "ICRC-1".to_string(),
+            url: "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-1".to_string(),
+        },
+        StandardRecord {
+            name: "ICRC-2".to_string(),
+            url: "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-2".to_string(),
+        },
+        StandardRecord {
+            name: "ICRC-10".to_string(),
+            url: "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-10".to_string(),
+        },
+    ]
+}
+
+#[query]
+fn icrc10_supported_standards() -> Vec<StandardRecord> {
+    supported_standards()
+}
+
+ic_cdk::export_candid!();
@@ -1,5 +1,5 @@
-use crate::Result;
-use quri_types::RuneEtching;
+use crate::{BitcoinUtilsError, Result};
+use quri_types::{RuneEtching, Utxo};
 use sha2::{Digest, Sha256};
 
 /// Build a Bitcoin transaction for Rune etching
@@ -65,6 +65,30 @@ pub fn estimate_fee(num_inputs: usize, num_outputs: usize, fee_rate: u64) -> u64
     (estimated_vsize as u64) * fee_rate
 }
 
+/// Actual fee paid by a signed transaction
+///
+/// `spent` are the candidate UTXOs; each transaction input must spend one of
+/// them. The fee is the value of the spent inputs minus all outputs.
+pub fn transaction_fee(tx: &[u8], spent: &[Utxo]) -> Result<u64> {
+    let tx: bitcoin::Transaction = bitcoin::consensus::deserialize(tx)
+        .map_err(|e| BitcoinUtilsError::TransactionError(format!("Invalid transaction: {}", e)))?;
+
+    let mut input_value = 0u64;
+    for input in &tx.input {
+        let prevout = &input.previous_output;
+        let utxo = spent
+            .iter()
+            .find(|u| u.outpoint.vout == prevout.vout && hex::encode(&u.outpoint.txid) == prevout.txid.to_string())
+            .ok_or_else(|| BitcoinUtilsError::TransactionError(format!("Unknown input {}", prevout)))?;
+        input_value = input_value.saturating_add(utxo.value);
+    }
+
+    let output_value: u64 = tx.output.iter().map(|o| o.value.to_sat()).sum();
+    input_value
+        .checked_sub(output_value)
+        .ok_or_else(|| BitcoinUtilsError::TransactionError("Outputs exceed inputs".to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -81,4 +105,32 @@ mod tests {
         let txid = calculate_txid(&tx);
         assert_eq!(txid.len(), 64); // SHA256 hash hex encoded
     }
+
+    #[test]
+    fn test_transaction_fee() {
+        use bitcoin::{absolute::LockTime, transaction::Version, Amount, OutPoint, ScriptBuf, Sequence, TxIn, TxOut, Txid, Witness};
+        use std::str::FromStr;
+
+        let txid_hex = "11".repeat(31) + "22";
+        let tx = bitcoin::Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint { txid: Txid::from_str(&txid_hex).unwrap(), vout: 1 },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut { value: Amount::from_sat(9_000), script_pubkey: ScriptBuf::new() }],
+        };
+        let bytes = bitcoin::consensus::serialize(&tx);
+
+        let utxo = |vout| Utxo {
+            outpoint: quri_types::OutPoint { txid: hex::decode(&txid_hex).unwrap(), vout },
+            value: 10_000,
+            height: 1,
+        };
+        assert_eq!(transaction_fee(&bytes, &[utxo(0), utxo(1)]).unwrap(), 1_000);
+        assert!(transaction_fee(&bytes, &[utxo(0)]).is_err());
+    }
 }
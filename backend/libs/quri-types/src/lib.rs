@@ -211,7 +211,7 @@ pub struct DeadManSwitch {
     pub id: u64,
     /// Owner's principal
     pub owner: Principal,
-    /// Beneficiary Bitcoin address (receives Runes on trigger)
+    /// Beneficiary principal or Bitcoin address (receives Runes on trigger)
     pub beneficiary: String,
     /// Rune identifier to transfer
     pub rune_id: String,
@@ -227,12 +227,81 @@ pub struct DeadManSwitch {
     pub created_at: u64,
     /// Optional message for beneficiary
     pub message: Option<String>,
+    /// Amount held in the owner's locked balance until release or cancel
+    pub locked_amount: Option<u64>,
+    /// Split between several beneficiaries (None: all to `beneficiary`)
+    pub beneficiaries: Option<Vec<SwitchBeneficiary>>,
+    /// Staged release schedule (None: everything at expiry)
+    pub stages: Option<Vec<ReleaseStage>>,
+    /// Warn this many days before expiry
+    pub warning_days: Option<Vec<u64>>,
+    /// One payout per stage and beneficiary, planned when the switch expires
+    pub payouts: Option<Vec<SwitchPayout>>,
+    /// Principals who can veto or confirm the release
+    pub guardians: Option<Vec<Principal>>,
+    /// Guardian votes needed for a veto or an early release (M of N)
+    pub guardian_threshold: Option<u8>,
+    /// Window after expiry in which guardians can veto (nanoseconds)
+    pub grace_period_ns: Option<u64>,
+    /// When guardians confirmed an early release
+    pub guardian_released_at: Option<u64>,
+}
+
+/// A beneficiary's share of a Dead Man's Switch
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct SwitchBeneficiary {
+    /// Principal (paid in virtual balance) or Bitcoin address
+    pub beneficiary: String,
+    /// Share in basis points (all shares sum to 10_000)
+    pub share_bps: u16,
+}
+
+/// One step of a staged release
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReleaseStage {
+    /// Days after expiry at which this stage is released
+    pub delay_days: u64,
+    /// Portion of the amount released, in basis points (all stages sum to 10_000)
+    pub release_bps: u16,
+}
+
+/// Progress of a Dead Man's Switch payout
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub enum SwitchPayoutStatus {
+    /// Waiting for the next attempt
+    Pending,
+    /// Bitcoin transfer broadcast, waiting for confirmations
+    Broadcast { txid: String },
+    /// Payout is final
+    Completed,
+    /// Retries exhausted; needs an admin retry
+    Failed,
+}
+
+/// Payout of one stage to one beneficiary
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct SwitchPayout {
+    /// Principal or Bitcoin address being paid
+    pub beneficiary: String,
+    /// Amount of this payout
+    pub amount: u64,
+    /// Not paid before this time (nanoseconds since epoch)
+    pub release_at: u64,
+    pub status: SwitchPayoutStatus,
+    /// Number of attempts made so far
+    pub attempts: u32,
+    /// Earliest time of the next attempt (nanoseconds since epoch)
+    pub next_attempt_at: u64,
+    /// Error from the last failed attempt
+    pub last_error: Option<String>,
+    /// When the payout became final
+    pub completed_at: Option<u64>,
 }
 
 /// Parameters for creating a Dead Man's Switch
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
 pub struct CreateDeadManSwitchParams {
-    /// Beneficiary Bitcoin address
+    /// Beneficiary principal (paid in virtual balance) or Bitcoin address
     pub beneficiary: String,
     /// Rune to transfer
     pub rune_id: String,
@@ -242,6 +311,18 @@ pub struct CreateDeadManSwitchParams {
     pub timeout_days: u64,
     /// Optional message for beneficiary
     pub message: Option<String>,
+    /// Split between several beneficiaries; replaces `beneficiary` when set
+    pub beneficiaries: Option<Vec<SwitchBeneficiary>>,
+    /// Staged release schedule
+    pub stages: Option<Vec<ReleaseStage>>,
+    /// Warn this many days before expiry (each below `timeout_days`)
+    pub warning_days: Option<Vec<u64>>,
+    /// Guardians who can veto or confirm the release
+    pub guardians: Option<Vec<Principal>>,
+    /// Guardian votes needed to act (defaults to a simple majority)
+    pub guardian_threshold: Option<u8>,
+    /// Days after expiry during which guardians can veto (default 7)
+    pub grace_days: Option<u64>,
 }
 
 /// Status of a Dead Man's Switch
@@ -266,6 +347,12 @@ pub struct DeadManSwitchInfo {
     pub time_remaining_ns: u64,
     /// Percentage of time elapsed
     pub elapsed_percentage: u8,
+    /// Tightest warning threshold (days before expiry) already crossed
+    pub warning_days: Option<u64>,
+    /// Guardians currently confirming a release
+    pub guardian_approvals: u32,
+    /// Guardians currently vetoing the release
+    pub guardian_vetoes: u32,
 }
 
 /// Summary statistics for Dead Man's Switches
@@ -296,6 +383,63 @@ pub struct EncryptedRuneMetadata {
     pub owner: Principal,
     /// Creation timestamp
     pub created_at: u64,
+    /// Dead Man's Switch whose beneficiaries can decrypt once it fires
+    pub switch_id: Option<u64>,
+    /// Item name within the rune ("default" for the legacy single item)
+    pub item_id: String,
+    /// Version of the item, starting at 1
+    pub version: u32,
+    /// Principals allowed to decrypt
+    pub readers: Vec<Principal>,
+    /// Holders of at least this much of the rune may decrypt
+    pub min_holding: Option<u64>,
+    /// Payload uploaded in chunks (`encrypted_data` is empty when set)
+    pub blob: Option<MetadataBlob>,
+    /// vetKD derivation ID this version is encrypted under (None: versions
+    /// stored before IDs were versioned, which use the item's legacy ID)
+    pub derivation_id: Option<Vec<u8>>,
+}
+
+/// A chunked encrypted payload, fetched with `get_chunk`
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct MetadataBlob {
+    pub blob_id: u64,
+    /// Total size in bytes
+    pub size: u64,
+    pub chunk_count: u32,
+    /// SHA-256 of the concatenated chunks
+    pub sha256: Vec<u8>,
+}
+
+/// Parameters for starting a chunked upload of encrypted metadata
+///
+/// Access fields mean the same as in `StoreEncryptedMetadataParams`.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct BeginUploadParams {
+    pub rune_id: String,
+    pub item_id: Option<String>,
+    /// Total payload size in bytes
+    pub size: u64,
+    /// Expected SHA-256 of the full payload
+    pub sha256: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub reveal_time: Option<u64>,
+    pub switch_id: Option<u64>,
+    pub readers: Option<Vec<Principal>>,
+    pub min_holding: Option<u64>,
+}
+
+/// An upload in progress
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct UploadInfo {
+    pub upload_id: u64,
+    /// Size every chunk but the last must have
+    pub chunk_size: u64,
+    pub chunk_count: u32,
+    /// Chunks received so far
+    pub received: u32,
+    /// Uploads not committed by then are discarded
+    pub expires_at: u64,
 }
 
 /// Parameters for storing encrypted metadata
@@ -305,4 +449,58 @@ pub struct StoreEncryptedMetadataParams {
     pub encrypted_data: Vec<u8>,
     pub nonce: Vec<u8>,
     pub reveal_time: Option<u64>,
+    /// Attach to one of the caller's Dead Man's Switches
+    pub switch_id: Option<u64>,
+    /// Item name; storing to an existing item adds a new version
+    pub item_id: Option<String>,
+    /// Principals allowed to decrypt
+    pub readers: Option<Vec<Principal>>,
+    /// Let holders of at least this much of the rune decrypt
+    pub min_holding: Option<u64>,
+}
+
+// ============================================
+// ICRC-3 Block Log Types
+// ============================================
+
+/// Generic ICRC-3 value (blocks are maps of these)
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum ICRC3Value {
+    Blob(#[serde(with = "serde_bytes")] Vec<u8>),
+    Text(String),
+    Nat(candid::Nat),
+    Int(candid::Int),
+    Array(Vec<ICRC3Value>),
+    Map(Vec<(String, ICRC3Value)>),
+}
+
+/// Range of blocks requested from `icrc3_get_blocks`
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct GetBlocksArgs {
+    pub start: candid::Nat,
+    pub length: candid::Nat,
+}
+
+/// A block with its index in the log
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct BlockWithId {
+    pub id: candid::Nat,
+    pub block: ICRC3Value,
+}
+
+candid::define_function!(pub GetBlocksCallback : (Vec<GetBlocksArgs>) -> (GetBlocksResult) query);
+
+/// Blocks that must be fetched from an archive canister
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchivedBlocks {
+    pub args: Vec<GetBlocksArgs>,
+    pub callback: GetBlocksCallback,
+}
+
+/// Response of `icrc3_get_blocks`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetBlocksResult {
+    pub log_length: candid::Nat,
+    pub blocks: Vec<BlockWithId>,
+    pub archived_blocks: Vec<ArchivedBlocks>,
 }
@@ -28,6 +28,13 @@ pub fn build_runestone(etching: &RuneEtching) -> Result<Vec<u8>> {
     runestone::build_etching_runestone(etching)
 }
 
+/// Build a runestone that transfers runes with edicts
+///
+/// `pointer` is the output that receives runes not assigned by an edict.
+pub fn build_transfer_runestone(edicts: &[Edict], pointer: Option<u32>) -> Result<Vec<u8>> {
+    runestone::build_transfer_runestone(edicts, pointer)
+}
+
 /// Parse a runestone from bytes
 pub fn parse_runestone(data: &[u8]) -> Result<Runestone> {
     runestone::parse_runestone(data)
@@ -83,6 +90,30 @@ impl RuneId {
     }
 }
 
+impl std::str::FromStr for RuneId {
+    type Err = RunesError;
+
+    /// Parse a rune ID in `block:tx` form (e.g. `840000:1`)
+    fn from_str(s: &str) -> Result<Self> {
+        let (block, tx) = s
+            .split_once(':')
+            .ok_or_else(|| RunesError::InvalidRunestone(format!("Invalid rune ID: {}", s)))?;
+        let block = block
+            .parse()
+            .map_err(|_| RunesError::InvalidRunestone(format!("Invalid rune ID block: {}", s)))?;
+        let tx = tx
+            .parse()
+            .map_err(|_| RunesError::InvalidRunestone(format!("Invalid rune ID tx: {}", s)))?;
+        Ok(Self { block, tx })
+    }
+}
+
+impl std::fmt::Display for RuneId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.block, self.tx)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -101,4 +132,13 @@ mod tests {
         let runestone = build_runestone(&etching);
         assert!(runestone.is_ok());
     }
+
+    #[test]
+    fn test_parse_rune_id() {
+        let id: RuneId = "840000:12".parse().unwrap();
+        assert_eq!(id, RuneId::new(840_000, 12));
+        assert_eq!(id.to_string(), "840000:12");
+        assert!("840000".parse::<RuneId>().is_err());
+        assert!("a:1".parse::<RuneId>().is_err());
+    }
 }
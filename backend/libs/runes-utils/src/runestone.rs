@@ -1,7 +1,7 @@
 // 🎓 LECCIÓN: Imports y Módulos
 // Importamos Tag desde crate (el root del package runes-utils)
 // porque lo re-exportamos en lib.rs con `pub use tag::Tag;`
-use crate::{Result, RunesError, Runestone, Tag};
+use crate::{Edict, Result, RunesError, Runestone, Tag};
 use quri_types::RuneEtching;
 use quri_utils::encoding::encode_leb128;
 
@@ -80,6 +80,55 @@ pub fn build_etching_runestone(etching: &RuneEtching) -> Result<Vec<u8>> {
     Ok(bytes)
 }
 
+/// Build runestone for an edict transfer
+///
+/// Edicts are sorted by rune ID and delta-encoded after the Body tag, as the
+/// Runes spec requires: `[block delta, tx (delta if same block), amount, output]`.
+pub fn build_transfer_runestone(edicts: &[Edict], pointer: Option<u32>) -> Result<Vec<u8>> {
+    if edicts.is_empty() {
+        return Err(RunesError::InvalidRunestone(
+            "Transfer needs at least one edict".to_string(),
+        ));
+    }
+
+    let mut integers: Vec<u128> = Vec::new();
+
+    // Unallocated runes go to the pointer output (default: first non-OP_RETURN)
+    if let Some(pointer) = pointer {
+        integers.push(Tag::Pointer.as_u128());
+        integers.push(pointer as u128);
+    }
+
+    integers.push(Tag::Body.as_u128());
+
+    let mut sorted: Vec<&Edict> = edicts.iter().collect();
+    sorted.sort_by_key(|edict| (edict.id.block, edict.id.tx));
+
+    let (mut last_block, mut last_tx) = (0u64, 0u32);
+    for edict in sorted {
+        let block_delta = edict.id.block - last_block;
+        let tx_delta = if block_delta == 0 {
+            edict.id.tx - last_tx
+        } else {
+            edict.id.tx
+        };
+        integers.push(block_delta as u128);
+        integers.push(tx_delta as u128);
+        integers.push(edict.amount);
+        integers.push(edict.output as u128);
+
+        last_block = edict.id.block;
+        last_tx = edict.id.tx;
+    }
+
+    let mut bytes = Vec::new();
+    for integer in integers {
+        bytes.extend_from_slice(&encode_leb128(integer));
+    }
+
+    Ok(bytes)
+}
+
 /// Encode a rune name as an integer
 fn encode_rune_name(name: &str) -> Result<u128> {
     let mut value: u128 = 0;
@@ -204,6 +253,31 @@ mod tests {
         assert_eq!(decode_rune_name(25), "Z");
     }
 
+    #[test]
+    fn test_build_transfer_runestone() {
+        use crate::RuneId;
+
+        let edicts = vec![
+            Edict { id: RuneId::new(840_001, 3), amount: 50, output: 1 },
+            Edict { id: RuneId::new(840_000, 7), amount: 1000, output: 1 },
+            Edict { id: RuneId::new(840_000, 9), amount: 5, output: 1 },
+        ];
+        let bytes = build_transfer_runestone(&edicts, Some(2)).unwrap();
+        let integers = parse_leb128_sequence(&bytes).unwrap();
+
+        assert_eq!(
+            integers,
+            vec![
+                12, 2, // pointer
+                0, // body
+                840_000, 7, 1000, 1,
+                0, 2, 5, 1, // same block: tx delta
+                1, 3, 50, 1, // next block: absolute tx
+            ]
+        );
+        assert!(build_transfer_runestone(&[], None).is_err());
+    }
+
     #[test]
     fn test_encode_decode_rune_name() {
         let names = vec!["A", "Z", "AA", "BITCOIN", "UNCOMMONGOODS"];
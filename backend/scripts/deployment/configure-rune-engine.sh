@@ -79,6 +79,19 @@ else
     exit 1
 fi
 
+# Let rune-engine move runes and ckBTC through bitcoin-integration
+# (refunds, fee draws, OTC fills, withdrawals); idempotent
+log_info "Authorizing rune-engine as a bitcoin-integration transfer caller..."
+
+if dfx canister call "$NEXT_PUBLIC_BITCOIN_INTEGRATION_CANISTER_ID" authorize_transfer_caller \
+    "(principal \"$NEXT_PUBLIC_RUNE_ENGINE_CANISTER_ID\")" \
+    --network "$NETWORK" 2>&1; then
+    log_success "rune-engine authorized"
+else
+    log_error "Failed to authorize rune-engine (run as a bitcoin-integration controller)"
+    exit 1
+fi
+
 # Update etching config (optional - defaults are usually fine)
 log_info "Setting default etching configuration..."
 
@@ -135,6 +135,10 @@ else
     log_warning "Inter-canister configuration may have failed (check manually)"
 fi
 
+log_info "Authorizing Rune Engine as a Bitcoin Integration transfer caller..."
+dfx canister call bitcoin-integration authorize_transfer_caller "(principal \"$(dfx canister id rune-engine --network $NETWORK)\")" --network $NETWORK \
+    || log_warning "Transfer caller authorization failed (check manually)"
+
 # Get Bitcoin address
 echo ""
 log_info "━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━"
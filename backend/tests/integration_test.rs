@@ -19,46 +19,13 @@
 //! cargo test --test integration_test test_dead_man_switch_creation
 //! ```
 
-use candid::{decode_one, CandidType, Encode, Principal};
+use candid::{decode_one, Encode, Principal};
 use pocket_ic::{PocketIc, WasmResult};
-use serde::Deserialize;
+use quri_types::{
+    CreateDeadManSwitchParams, DeadManSwitchInfo, DeadManSwitchStats, RuneEtching, SwitchStatus,
+};
 use std::time::Duration;
 
-// ============================================================================
-// Test Types
-// ============================================================================
-
-#[derive(CandidType, Deserialize, Debug)]
-struct CreateDeadManSwitchParams {
-    rune_id: String,
-    beneficiary: String,
-    amount: u64,
-    timeout_days: u64,
-    message: Option<String>,
-}
-
-#[derive(CandidType, Deserialize, Debug)]
-struct DeadManSwitchInfo {
-    id: u64,
-    owner: Principal,
-    beneficiary: String,
-    rune_id: String,
-    amount: u64,
-    status: String,
-    time_remaining_seconds: u64,
-    elapsed_percentage: u8,
-    created_at: u64,
-    message: Option<String>,
-}
-
-#[derive(CandidType, Deserialize, Debug)]
-struct DeadManSwitchStats {
-    total_switches: u64,
-    active_switches: u64,
-    triggered_switches: u64,
-    total_value_protected: u64,
-}
-
 // ============================================================================
 // Test Helpers
 // ============================================================================
@@ -109,6 +76,46 @@ fn test_user(id: u8) -> Principal {
     Principal::from_slice(&[id; 29])
 }
 
+/// Create a virtual rune owned by `user`; the premine is credited to their
+/// balance, so it can be locked by a switch
+fn fund_with_rune(pic: &PocketIc, rune_engine_id: Principal, user: Principal, rune_name: &str, premine: u64) -> String {
+    let etching = RuneEtching {
+        rune_name: rune_name.to_string(),
+        symbol: "T".to_string(),
+        divisibility: 0,
+        premine,
+        terms: None,
+    };
+
+    let args = Encode!(&etching).unwrap();
+    let result = pic.update_call(rune_engine_id, user, "create_rune", args)
+        .expect("Failed to call create_rune");
+
+    let WasmResult::Reply(response) = result else {
+        panic!("Expected reply from create_rune");
+    };
+    decode_one::<Result<String, String>>(&response)
+        .unwrap()
+        .expect("Should create rune")
+}
+
+/// Switch parameters paying everything to a single beneficiary
+fn switch_params(rune_id: &str, beneficiary: Principal, amount: u128, timeout_days: u64, message: Option<&str>) -> CreateDeadManSwitchParams {
+    CreateDeadManSwitchParams {
+        beneficiary: beneficiary.to_text(),
+        rune_id: rune_id.to_string(),
+        amount,
+        timeout_days,
+        message: message.map(str::to_string),
+        beneficiaries: None,
+        stages: None,
+        warning_days: None,
+        guardians: None,
+        guardian_threshold: None,
+        grace_days: None,
+    }
+}
+
 // ============================================================================
 // Dead Man's Switch Tests
 // ============================================================================
@@ -117,15 +124,11 @@ fn test_user(id: u8) -> Principal {
 fn test_dead_man_switch_creation() {
     let (pic, rune_engine_id, _, _) = setup_quri_environment();
     let alice = test_user(1);
+    let bob = test_user(2);
+    let rune_id = fund_with_rune(&pic, rune_engine_id, alice, "TEST•RUNE", 10_000);
 
     // Create a Dead Man's Switch
-    let params = CreateDeadManSwitchParams {
-        rune_id: "TEST•RUNE".to_string(),
-        beneficiary: "bc1q...beneficiary".to_string(),
-        amount: 1000,
-        timeout_days: 30,
-        message: Some("Emergency transfer to family".to_string()),
-    };
+    let params = switch_params(&rune_id, bob, 1000, 30, Some("Emergency transfer to family"));
 
     let args = Encode!(&params).unwrap();
     let result = pic.update_call(
@@ -139,7 +142,7 @@ fn test_dead_man_switch_creation() {
         panic!("Expected reply from create_dead_man_switch");
     };
     let switch_id: Result<u64, String> = decode_one(&response).unwrap();
-    assert!(switch_id.is_ok(), "Should create switch successfully");
+    assert!(switch_id.is_ok(), "Should create switch successfully: {:?}", switch_id);
 
     let id = switch_id.unwrap();
     println!("✅ Created Dead Man's Switch with ID: {}", id);
@@ -160,9 +163,10 @@ fn test_dead_man_switch_creation() {
     assert!(switch_info.is_some(), "Switch should exist");
 
     let info = switch_info.unwrap();
-    assert_eq!(info.amount, 1000);
-    assert_eq!(info.beneficiary, "bc1q...beneficiary");
-    assert_eq!(info.status, "Active");
+    assert_eq!(info.switch.amount, 1000);
+    assert_eq!(info.switch.beneficiary, bob.to_text());
+    assert_eq!(info.switch.locked_amount, Some(1000));
+    assert_eq!(info.status, SwitchStatus::Active);
     println!("✅ Verified switch: {:?}", info);
 }
 
@@ -170,15 +174,11 @@ fn test_dead_man_switch_creation() {
 fn test_dead_man_switch_checkin() {
     let (pic, rune_engine_id, _, _) = setup_quri_environment();
     let alice = test_user(1);
+    let bob = test_user(2);
+    let rune_id = fund_with_rune(&pic, rune_engine_id, alice, "TEST•RUNE", 10_000);
 
     // Create switch
-    let params = CreateDeadManSwitchParams {
-        rune_id: "TEST•RUNE".to_string(),
-        beneficiary: "bc1q...beneficiary".to_string(),
-        amount: 1000,
-        timeout_days: 30,
-        message: None,
-    };
+    let params = switch_params(&rune_id, bob, 1000, 30, None);
 
     let args = Encode!(&params).unwrap();
     let result = pic.update_call(rune_engine_id, alice, "create_dead_man_switch", args)
@@ -228,15 +228,11 @@ fn test_dead_man_switch_checkin() {
 fn test_dead_man_switch_expiration() {
     let (pic, rune_engine_id, _, _) = setup_quri_environment();
     let alice = test_user(1);
+    let bob = test_user(2);
+    let rune_id = fund_with_rune(&pic, rune_engine_id, alice, "TEST•RUNE", 10_000);
 
-    // Create switch with 1 day timeout
-    let params = CreateDeadManSwitchParams {
-        rune_id: "TEST•RUNE".to_string(),
-        beneficiary: "bc1q...beneficiary".to_string(),
-        amount: 1000,
-        timeout_days: 1, // 1 day for faster testing
-        message: Some("Test expiration".to_string()),
-    };
+    // Create switch with 1 day timeout (1 day for faster testing)
+    let params = switch_params(&rune_id, bob, 1000, 1, Some("Test expiration"));
 
     let args = Encode!(&params).unwrap();
     let result = pic.update_call(rune_engine_id, alice, "create_dead_man_switch", args)
@@ -262,7 +258,13 @@ fn test_dead_man_switch_expiration() {
     let info: Option<DeadManSwitchInfo> = decode_one(&get_response).unwrap();
     let info = info.unwrap();
 
-    assert_eq!(info.status, "Expired", "Switch should be expired");
+    // The switch processor may already have paid the beneficiary
+    assert!(
+        matches!(info.status, SwitchStatus::Expired | SwitchStatus::Triggered),
+        "Switch should be expired, got {:?}",
+        info.status
+    );
+    assert_eq!(info.time_remaining_ns, 0);
     assert_eq!(info.elapsed_percentage, 100, "Should be 100% elapsed");
     println!("✅ Switch expired as expected: {:?}", info);
 }
@@ -272,20 +274,23 @@ fn test_dead_man_switch_stats() {
     let (pic, rune_engine_id, _, _) = setup_quri_environment();
     let alice = test_user(1);
     let bob = test_user(2);
+    let carol = test_user(3);
 
     // Create multiple switches
-    for user in [alice, bob] {
+    for (user, rune_name) in [(alice, "ALICE•RUNE"), (bob, "BOB•RUNE")] {
+        let rune_id = fund_with_rune(&pic, rune_engine_id, user, rune_name, 10_000);
         for i in 0..3 {
-            let params = CreateDeadManSwitchParams {
-                rune_id: format!("RUNE{}", i),
-                beneficiary: "bc1q...".to_string(),
-                amount: 1000 * (i + 1),
-                timeout_days: 30,
-                message: None,
-            };
+            let params = switch_params(&rune_id, carol, 1000 * (i + 1), 30, None);
 
             let args = Encode!(&params).unwrap();
-            let _ = pic.update_call(rune_engine_id, user, "create_dead_man_switch", args);
+            let result = pic.update_call(rune_engine_id, user, "create_dead_man_switch", args)
+                .expect("Failed to create switch");
+            let WasmResult::Reply(response) = result else {
+                panic!("Expected reply from create_dead_man_switch");
+            };
+            decode_one::<Result<u64, String>>(&response)
+                .unwrap()
+                .expect("Should create switch");
         }
     }
 
@@ -305,11 +310,63 @@ fn test_dead_man_switch_stats() {
     assert_eq!(stats.total_switches, 6, "Should have 6 switches total");
     assert_eq!(stats.active_switches, 6, "All should be active");
     assert_eq!(stats.triggered_switches, 0, "None should be triggered yet");
-    assert_eq!(stats.total_value_protected, 6000 + 12000, "Total value: 1000+2000+3000 per user");
+    assert_eq!(stats.total_value_protected, 2 * 6000, "Total value: 1000+2000+3000 per user");
 
     println!("✅ Stats verified: {:?}", stats);
 }
 
+#[test]
+fn test_dead_man_switch_survives_upgrade() {
+    let (pic, rune_engine_id, _, _) = setup_quri_environment();
+    let alice = test_user(1);
+    let bob = test_user(2);
+    let rune_id = fund_with_rune(&pic, rune_engine_id, alice, "TEST•RUNE", 10_000);
+
+    // Create a switch before the upgrade
+    let params = switch_params(&rune_id, bob, 1000, 30, Some("Survive upgrades"));
+
+    let args = Encode!(&params).unwrap();
+    let result = pic.update_call(rune_engine_id, alice, "create_dead_man_switch", args)
+        .expect("Failed to create switch");
+
+    let WasmResult::Reply(response) = result else {
+        panic!("Expected reply from create_dead_man_switch");
+    };
+    let switch_id: u64 = decode_one::<Result<u64, String>>(&response).unwrap().unwrap();
+
+    // Upgrade the canister with the same WASM
+    let rune_engine_wasm = std::fs::read("../target/wasm32-unknown-unknown/release/rune_engine.wasm")
+        .expect("Failed to read rune-engine WASM");
+    pic.upgrade_canister(rune_engine_id, rune_engine_wasm, vec![], None)
+        .expect("Failed to upgrade rune-engine");
+
+    // The switch is still there
+    let get_args = Encode!(&switch_id).unwrap();
+    let get_result = pic.query_call(rune_engine_id, alice, "get_dead_man_switch", get_args)
+        .expect("Failed to get switch");
+
+    let WasmResult::Reply(get_response) = get_result else {
+        panic!("Expected reply from get_dead_man_switch");
+    };
+    let info: Option<DeadManSwitchInfo> = decode_one(&get_response).unwrap();
+    let info = info.expect("Switch should survive the upgrade");
+    assert_eq!(info.switch.amount, 1000);
+    assert_eq!(info.switch.beneficiary, bob.to_text());
+
+    // New switches don't reuse the old ID
+    let args = Encode!(&params).unwrap();
+    let result = pic.update_call(rune_engine_id, alice, "create_dead_man_switch", args)
+        .expect("Failed to create switch after upgrade");
+
+    let WasmResult::Reply(response) = result else {
+        panic!("Expected reply from create_dead_man_switch");
+    };
+    let next_id: u64 = decode_one::<Result<u64, String>>(&response).unwrap().unwrap();
+    assert!(next_id > switch_id, "Switch IDs must keep increasing across upgrades");
+
+    println!("✅ Switch {} survived the upgrade", switch_id);
+}
+
 // ============================================================================
 // Encrypted Metadata Tests (vetKeys)
 // ============================================================================
@@ -341,6 +398,7 @@ fn test_all_basic_flows() {
     test_dead_man_switch_checkin();
     test_dead_man_switch_expiration();
     test_dead_man_switch_stats();
+    test_dead_man_switch_survives_upgrade();
 
     println!("\n✅ All basic integration tests passed!");
 }
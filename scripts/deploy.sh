@@ -272,6 +272,13 @@ configure_canisters() {
         "(principal \"$BITCOIN_ID\", principal \"$REGISTRY_ID\")" \
         --network "$NETWORK" || print_warning "Failed to configure canister IDs"
 
+    # Let rune-engine move runes and ckBTC through bitcoin-integration
+    # (refunds, fee draws, OTC fills, withdrawals); idempotent
+    print_step "Autorizando rune-engine en bitcoin-integration..."
+    dfx canister call bitcoin-integration authorize_transfer_caller \
+        "(principal \"$RUNE_ENGINE_ID\")" \
+        --network "$NETWORK" || print_warning "Failed to authorize rune-engine as transfer caller"
+
     # Configure etching settings based on environment
     print_step "Configurando settings de etching..."
     if [ "$ENVIRONMENT" = "mainnet" ]; then